@@ -0,0 +1,319 @@
+use std::fs::{File, Metadata};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use md5::Md5;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256, Sha512};
+use tokio::task;
+
+use crate::utils::xattr;
+
+/// The digests are cached in the extended attributes like `user.sachima.sha256`
+const XATTR_PREFIX: &str = "user.sachima.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algo {
+    Md5,
+    Sha256,
+    Sha512,
+    Blake3,
+    Crc32c,
+}
+
+impl Algo {
+    pub const ALL: [Algo; 5] = [
+        Algo::Md5,
+        Algo::Sha256,
+        Algo::Sha512,
+        Algo::Blake3,
+        Algo::Crc32c,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algo::Md5 => "md5",
+            Algo::Sha256 => "sha256",
+            Algo::Sha512 => "sha512",
+            Algo::Blake3 => "blake3",
+            Algo::Crc32c => "crc32c",
+        }
+    }
+
+    /// The name in `Digest` and `Repr-Digest`, absent if it isn't registered
+    fn http_name(self) -> Option<&'static str> {
+        match self {
+            Algo::Md5 => Some("md5"),
+            Algo::Sha256 => Some("sha-256"),
+            Algo::Sha512 => Some("sha-512"),
+            Algo::Blake3 => None,
+            Algo::Crc32c => Some("crc32c"),
+        }
+    }
+}
+
+/// The names in `Digest` are accepted too, like `sha-256`
+impl FromStr for Algo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        Algo::ALL
+            .into_iter()
+            .find(|algo| algo.name() == s || algo.http_name() == Some(&s))
+            .ok_or_else(|| format!("unknown checksum algorithm {s}"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Algo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+    Crc32c(u32),
+}
+
+impl Hasher {
+    fn new(algo: Algo) -> Self {
+        match algo {
+            Algo::Md5 => Hasher::Md5(Md5::new()),
+            Algo::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algo::Sha512 => Hasher::Sha512(Sha512::new()),
+            Algo::Blake3 => Hasher::Blake3(Box::default()),
+            Algo::Crc32c => Hasher::Crc32c(0),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            // big-endian, as `Repr-Digest` expects
+            Hasher::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// The digests of the file in the order of algorithms,
+/// which are computed by streaming it unless they're cached.
+///
+/// The cache is keyed on the inode, size and modified time of the file,
+/// so the file replaced or changed isn't taken for the cached one.
+pub async fn checksums(path: &Path, algos: Vec<Algo>) -> io::Result<Vec<(Algo, Vec<u8>)>> {
+    let path = path.to_owned();
+    task::spawn_blocking(move || compute(&path, &algos))
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn compute(path: &Path, algos: &[Algo]) -> io::Result<Vec<(Algo, Vec<u8>)>> {
+    let mut fd = File::open(path)?;
+    let md = fd.metadata()?;
+
+    let mut digests: Vec<_> = algos
+        .iter()
+        .map(|&algo| (algo, read_cache(path, &md, algo)))
+        .collect();
+    let mut hashers: Vec<_> = digests
+        .iter()
+        .filter(|(_, cached)| cached.is_none())
+        .map(|&(algo, _)| (algo, Hasher::new(algo)))
+        .collect();
+
+    if !hashers.is_empty() {
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = fd.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+
+            for (_, hasher) in &mut hashers {
+                hasher.update(&buf[..n]);
+            }
+        }
+
+        // the file changed meanwhile, so the digests aren't worth caching
+        let unchanged = fd
+            .metadata()
+            .is_ok_and(|now| cache_key(&now) == cache_key(&md));
+
+        for (algo, hasher) in hashers {
+            let digest = hasher.finalize();
+            if unchanged {
+                write_cache(path, &md, algo, &digest);
+            }
+
+            for (found, cached) in &mut digests {
+                if *found == algo && cached.is_none() {
+                    *cached = Some(digest.clone());
+                }
+            }
+        }
+    }
+
+    Ok(digests
+        .into_iter()
+        .filter_map(|(algo, digest)| Some((algo, digest?)))
+        .collect())
+}
+
+/// The cached digests only, which are cheap to look up,
+/// and valid only for the file of the metadata.
+pub async fn cached(path: &Path, md: &Metadata) -> Vec<(Algo, Vec<u8>)> {
+    let path = path.to_owned();
+    let md = md.clone();
+    task::spawn_blocking(move || {
+        Algo::ALL
+            .into_iter()
+            .filter_map(|algo| Some((algo, read_cache(&path, &md, algo)?)))
+            .collect()
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// `Digest` and `Repr-Digest` of the registered algorithms
+pub fn digest_headers(digests: &[(Algo, Vec<u8>)]) -> Option<(String, String)> {
+    let registered: Vec<_> = digests
+        .iter()
+        .filter_map(|(algo, digest)| Some((algo.http_name()?, BASE64.encode(digest))))
+        .collect();
+    if registered.is_empty() {
+        return None;
+    }
+
+    let digest = registered
+        .iter()
+        .map(|(name, encoded)| format!("{name}={encoded}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let repr_digest = registered
+        .iter()
+        .map(|(name, encoded)| format!("{name}=:{encoded}:"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Some((digest, repr_digest))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn cache_key(md: &Metadata) -> String {
+    format!(
+        "{}:{}:{}.{}",
+        md.ino(),
+        md.len(),
+        md.mtime(),
+        md.mtime_nsec()
+    )
+}
+
+/// The value is `{inode}:{size}:{mtime}:{hex digest}`
+fn read_cache(path: &Path, md: &Metadata, algo: Algo) -> Option<Vec<u8>> {
+    let value = xattr::get(path, &format!("{XATTR_PREFIX}{}", algo.name())).ok()??;
+    let value = String::from_utf8(value).ok()?;
+    let (key, digest) = value.rsplit_once(':')?;
+    if key != cache_key(md) || digest.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..digest.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digest.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn write_cache(path: &Path, md: &Metadata, algo: Algo, digest: &[u8]) {
+    let value = format!("{}:{}", cache_key(md), hex(digest));
+    if let Err(e) = xattr::set(
+        path,
+        &format!("{XATTR_PREFIX}{}", algo.name()),
+        value.as_bytes(),
+    ) {
+        tracing::debug!(path = %path.display(), error = %e, "cannot cache the checksum");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cached, checksums, digest_headers, hex, Algo};
+    use crate::utils::tests::setup_workspace;
+    use std::io;
+    use tokio::fs;
+
+    #[tokio::test]
+    async fn test_checksums() -> io::Result<()> {
+        let (_tmp_dir, wk) = setup_workspace();
+        let path = wk.join("abc.txt");
+        fs::write(&path, "abc").await?;
+
+        let digests = checksums(&path, Algo::ALL.to_vec()).await?;
+        let hexes: Vec<_> = digests.iter().map(|(_, digest)| hex(digest)).collect();
+        assert_eq!(
+            hexes,
+            [
+                "900150983cd24fb0d6963f7d28e17f72",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+                "364b3fb7",
+            ]
+        );
+
+        // the cache is kept only where extended attributes are supported
+        let md = fs::metadata(&path).await?;
+        if !cached(&path, &md).await.is_empty() {
+            assert_eq!(cached(&path, &md).await, digests);
+
+            fs::write(&path, "abcd").await?;
+            assert!(cached(&path, &fs::metadata(&path).await?).await.is_empty());
+        }
+
+        let (digest, repr_digest) = digest_headers(&digests[1..2]).unwrap();
+        assert_eq!(
+            digest,
+            "sha-256=ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+        );
+        assert_eq!(
+            repr_digest,
+            "sha-256=:ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=:"
+        );
+        assert!(digest_headers(&digests[3..4]).is_none());
+
+        assert_eq!("SHA-512".parse(), Ok(Algo::Sha512));
+        assert!("rot13".parse::<Algo>().is_err());
+
+        Ok(())
+    }
+}
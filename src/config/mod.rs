@@ -1,28 +1,69 @@
 mod workspace;
 pub use workspace::Workspace;
 
+mod workspaces;
+pub use workspaces::{Policy, Workspaces};
+
 pub mod log_level;
 pub use log_level::LogLevel;
 
+mod tls;
+pub use tls::Tls;
+
+pub mod quota;
+pub use quota::Quota;
+
+mod search;
+pub use search::Search;
+
+pub mod s3;
+pub use s3::S3;
+
+pub mod source;
+pub use source::{ConfigError, ConfigSource, Overrides};
+
+pub mod check;
+
 use bytesize::ByteSize;
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     /// The deploying port
+    #[serde(default = "default_port")]
     pub port: u16,
 
     /// The database url
     pub database_url: String,
 
-    /// Log level of poem
+    /// Log level of poem and sachima
     pub poem_log_level: Option<LogLevel>,
 
     /// The serving directory
     pub workspace: Workspace,
 
+    /// The workspaces served by name
+    #[serde(default)]
+    pub workspaces: Workspaces,
+
+    /// Give every user a private home directory `~{username}` in the workspace
+    #[serde(default)]
+    pub homes: bool,
+
+    /// The users who may access every home directory
+    #[serde(default)]
+    pub admins: Vec<String>,
+
+    /// The storage quotas of the default workspace
+    pub quota: Option<Quota>,
+
+    /// The search over the workspaces
+    #[serde(default)]
+    pub search: Search,
+
     /// The max data size of single file
+    #[serde(default = "default_max_upload")]
     pub max_upload: ByteSize,
 
     /// The hash secret key for JWT
@@ -30,4 +71,30 @@ pub struct Config {
 
     /// The hash salt of user password
     pub password_salt: String,
+
+    /// Serve HTTPS instead of HTTP
+    pub tls: Option<Tls>,
+
+    /// Serve the S3 compatible API at `/s3`
+    pub s3: Option<S3>,
+
+    /// Serve the interactive docs of `/openapi.json` at `/docs`
+    #[serde(default)]
+    pub api_docs: bool,
+
+    /// The seconds waiting for the in-flight requests on shutdown
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: u64,
+}
+
+fn default_port() -> u16 {
+    8000
+}
+
+fn default_max_upload() -> ByteSize {
+    ByteSize::gib(2)
+}
+
+fn default_drain_timeout() -> u64 {
+    30
 }
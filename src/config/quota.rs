@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::path::{Component, PathBuf};
+
+use bytesize::ByteSize;
+use serde::Deserialize;
+
+/// The upper limits of a directory tree
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Limit {
+    /// The total size of files
+    pub bytes: Option<ByteSize>,
+
+    /// The number of files, directories aren't counted
+    pub files: Option<u64>,
+}
+
+/// The storage quotas of a workspace
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "RawQuota")]
+pub struct Quota {
+    /// Of the whole workspace
+    pub limit: Limit,
+
+    /// Of every home directory
+    pub home: Option<Limit>,
+
+    /// Directory relative to the workspace root => its limit
+    pub dirs: HashMap<PathBuf, Limit>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RawQuota {
+    bytes: Option<ByteSize>,
+    files: Option<u64>,
+    home: Option<Limit>,
+    #[serde(default)]
+    dirs: HashMap<PathBuf, Limit>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("quota directory {0:?} should be relative to the workspace and not contain `..`")]
+pub struct InvalidDir(PathBuf);
+
+impl TryFrom<RawQuota> for Quota {
+    type Error = InvalidDir;
+
+    fn try_from(raw: RawQuota) -> Result<Self, Self::Error> {
+        for dir in raw.dirs.keys() {
+            if !dir.components().all(|c| matches!(c, Component::Normal(_))) {
+                return Err(InvalidDir(dir.clone()));
+            }
+        }
+
+        Ok(Self {
+            limit: Limit {
+                bytes: raw.bytes,
+                files: raw.files,
+            },
+            home: raw.home,
+            dirs: raw.dirs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quota;
+    use bytesize::ByteSize;
+    use std::path::Path;
+
+    #[test]
+    fn test_quota() {
+        let quota: Quota = toml::from_str(
+            r#"
+            bytes = "100G"
+            home = { bytes = "5G", files = 1000 }
+            dirs = { "shared/videos" = { bytes = "20G" } }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(quota.limit.bytes, Some(ByteSize::gb(100)));
+        assert_eq!(quota.limit.files, None);
+        assert_eq!(quota.home.unwrap().files, Some(1000));
+        assert_eq!(
+            quota.dirs[Path::new("shared/videos")].bytes,
+            Some(ByteSize::gb(20))
+        );
+
+        assert!(toml::from_str::<Quota>(r#"dirs = { "../etc" = { files = 1 } }"#).is_err());
+        assert!(toml::from_str::<Quota>(r#"dirs = { "/etc" = { files = 1 } }"#).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// The S3 compatible API at `/s3` over the default workspace
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct S3 {
+    /// The region in the credential scope of signatures
+    #[serde(default = "default_region")]
+    pub region: String,
+
+    /// Access key id => the key
+    #[serde(default)]
+    pub keys: HashMap<String, AccessKey>,
+}
+
+/// An access key signing the requests as a user
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AccessKey {
+    pub secret: String,
+
+    /// The username the requests are made as
+    pub user: String,
+}
+
+impl Default for S3 {
+    fn default() -> Self {
+        Self {
+            region: default_region(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+fn default_region() -> String {
+    "us-east-1".to_owned()
+}
@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use bytesize::ByteSize;
+use serde::{Deserialize, Deserializer};
+
+/// The search over the workspaces
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Search {
+    /// Where the indexes are saved,
+    /// they're rebuilt from scratch on every start if absent.
+    #[serde(default, deserialize_with = "expand_tilde")]
+    pub index_dir: Option<PathBuf>,
+
+    /// Index the words of text-like files
+    #[serde(default)]
+    pub full_text: bool,
+
+    /// The larger files are indexed by name only
+    #[serde(default = "default_max_text_size")]
+    pub max_text_size: ByteSize,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self {
+            index_dir: None,
+            full_text: false,
+            max_text_size: default_max_text_size(),
+        }
+    }
+}
+
+fn default_max_text_size() -> ByteSize {
+    ByteSize::mib(1)
+}
+
+fn expand_tilde<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+    let path = Option::<String>::deserialize(deserializer)?;
+    Ok(path.map(|path| PathBuf::from(shellexpand::tilde(&path).as_ref())))
+}
@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use super::Config;
+use crate::error::FileSysError;
+
+/// The type of a config value given as plain text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Integer,
+    Boolean,
+    String,
+    /// An inline TOML table or array
+    Inline,
+}
+
+/// All the config keys, the nested ones are joined by `.`
+const KEYS: &[(&str, Kind)] = &[
+    ("port", Kind::Integer),
+    ("database-url", Kind::String),
+    ("poem-log-level", Kind::String),
+    ("workspace", Kind::String),
+    ("workspaces", Kind::Inline),
+    ("homes", Kind::Boolean),
+    ("admins", Kind::Inline),
+    ("quota", Kind::Inline),
+    ("search", Kind::Inline),
+    ("max-upload", Kind::String),
+    ("jwt-secret-key", Kind::String),
+    ("password-salt", Kind::String),
+    ("tls.cert", Kind::String),
+    ("tls.key", Kind::String),
+    ("tls.client-ca", Kind::String),
+    ("tls.client-auth-required", Kind::Boolean),
+    ("tls.client-users", Kind::Inline),
+    ("s3.region", Kind::String),
+    ("s3.keys", Kind::Inline),
+    ("api-docs", Kind::Boolean),
+    ("drain-timeout", Kind::Integer),
+];
+
+/// The prefix of environment variables overriding the config
+pub const ENV_PREFIX: &str = "SACHIMA_";
+
+/// Override the config by command line flags,
+/// which take precedence over everything else.
+#[derive(Debug, Default, Clone, clap::Args)]
+pub struct Overrides {
+    /// Override `port`
+    #[arg(long, value_name = "PORT", global = true)]
+    pub port: Option<String>,
+
+    /// Override `database-url`
+    #[arg(long, value_name = "URL", global = true)]
+    pub database_url: Option<String>,
+
+    /// Override `poem-log-level`
+    #[arg(long, value_name = "LEVEL", global = true)]
+    pub poem_log_level: Option<String>,
+
+    /// Override `workspace`
+    #[arg(long, value_name = "PATH", global = true)]
+    pub workspace: Option<String>,
+
+    /// Override `workspaces` by an inline array of tables
+    #[arg(long, value_name = "ARRAY", global = true)]
+    pub workspaces: Option<String>,
+
+    /// Override `homes`
+    #[arg(long, value_name = "BOOL", global = true)]
+    pub homes: Option<String>,
+
+    /// Override `admins` by an inline array
+    #[arg(long, value_name = "ARRAY", global = true)]
+    pub admins: Option<String>,
+
+    /// Override `quota` by an inline table
+    #[arg(long, value_name = "TABLE", global = true)]
+    pub quota: Option<String>,
+
+    /// Override `search` by an inline table
+    #[arg(long, value_name = "TABLE", global = true)]
+    pub search: Option<String>,
+
+    /// Override `max-upload`
+    #[arg(long, value_name = "SIZE", global = true)]
+    pub max_upload: Option<String>,
+
+    /// Override `jwt-secret-key`
+    #[arg(long, value_name = "KEY", global = true)]
+    pub jwt_secret_key: Option<String>,
+
+    /// Override `password-salt`
+    #[arg(long, value_name = "SALT", global = true)]
+    pub password_salt: Option<String>,
+
+    /// Override `tls.cert`
+    #[arg(long, value_name = "PATH", global = true)]
+    pub tls_cert: Option<String>,
+
+    /// Override `tls.key`
+    #[arg(long, value_name = "PATH", global = true)]
+    pub tls_key: Option<String>,
+
+    /// Override `tls.client-ca`
+    #[arg(long, value_name = "PATH", global = true)]
+    pub tls_client_ca: Option<String>,
+
+    /// Override `tls.client-auth-required`
+    #[arg(long, value_name = "BOOL", global = true)]
+    pub tls_client_auth_required: Option<String>,
+
+    /// Override `tls.client-users` by an inline table
+    #[arg(long, value_name = "TABLE", global = true)]
+    pub tls_client_users: Option<String>,
+
+    /// Override `s3.region`
+    #[arg(long, value_name = "REGION", global = true)]
+    pub s3_region: Option<String>,
+
+    /// Override `s3.keys` by an inline table
+    #[arg(long, value_name = "TABLE", global = true)]
+    pub s3_keys: Option<String>,
+
+    /// Override `api-docs`
+    #[arg(long, value_name = "BOOL", global = true)]
+    pub api_docs: Option<String>,
+
+    /// Override `drain-timeout`
+    #[arg(long, value_name = "SECONDS", global = true)]
+    pub drain_timeout: Option<String>,
+}
+
+impl Overrides {
+    fn get(&self, key: &str) -> Option<&str> {
+        match key {
+            "port" => self.port.as_deref(),
+            "database-url" => self.database_url.as_deref(),
+            "poem-log-level" => self.poem_log_level.as_deref(),
+            "workspace" => self.workspace.as_deref(),
+            "workspaces" => self.workspaces.as_deref(),
+            "homes" => self.homes.as_deref(),
+            "admins" => self.admins.as_deref(),
+            "quota" => self.quota.as_deref(),
+            "search" => self.search.as_deref(),
+            "max-upload" => self.max_upload.as_deref(),
+            "jwt-secret-key" => self.jwt_secret_key.as_deref(),
+            "password-salt" => self.password_salt.as_deref(),
+            "tls.cert" => self.tls_cert.as_deref(),
+            "tls.key" => self.tls_key.as_deref(),
+            "tls.client-ca" => self.tls_client_ca.as_deref(),
+            "tls.client-auth-required" => self.tls_client_auth_required.as_deref(),
+            "tls.client-users" => self.tls_client_users.as_deref(),
+            "s3.region" => self.s3_region.as_deref(),
+            "s3.keys" => self.s3_keys.as_deref(),
+            "api-docs" => self.api_docs.as_deref(),
+            "drain-timeout" => self.drain_timeout.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Where a config value comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    File(PathBuf),
+    Env(String),
+    Flag(String),
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "file {path:?}"),
+            Self::Env(var) => write!(f, "environment variable {var}"),
+            Self::Flag(flag) => write!(f, "flag --{flag}"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Read(#[from] FileSysError),
+
+    #[error("{path:?}: {source}")]
+    Syntax {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("both {0} and {0}_FILE are set")]
+    Ambiguous(String),
+
+    #[error("`{key}`{}: {msg}", .origin.as_ref().map(|o| format!(" from {o}")).unwrap_or_default())]
+    InvalidValue {
+        key: String,
+        origin: Option<Origin>,
+        msg: String,
+    },
+
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// The config merged from the file, environment variables and flags,
+/// values of the latter layer take precedence.
+#[derive(Debug, Default)]
+pub struct Layered {
+    pub table: Table,
+    pub origins: HashMap<String, Origin>,
+}
+
+impl Layered {
+    pub fn load(file: Option<&Path>, overrides: &Overrides) -> Result<Self, ConfigError> {
+        Self::load_with(file, overrides, |var| env::var(var).ok())
+    }
+
+    fn load_with<F>(file: Option<&Path>, overrides: &Overrides, env: F) -> Result<Self, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut layered = Self::default();
+
+        if let Some(path) = file {
+            let content = fs::read_to_string(path).map_err(|e| FileSysError {
+                path: path.to_owned(),
+                source: e,
+            })?;
+            layered.table = toml::from_str(&content).map_err(|e| ConfigError::Syntax {
+                path: path.to_owned(),
+                source: e,
+            })?;
+
+            for key in KEYS.iter().map(|(key, _)| *key) {
+                if lookup(&layered.table, key).is_some() {
+                    layered
+                        .origins
+                        .insert(key.to_owned(), Origin::File(path.to_owned()));
+                }
+            }
+        }
+
+        for &(key, kind) in KEYS {
+            let var = env_var(key);
+            let var_file = format!("{var}_FILE");
+
+            match (env(&var), env(&var_file)) {
+                (Some(_), Some(_)) => return Err(ConfigError::Ambiguous(var)),
+                (Some(raw), None) => layered.set(key, kind, &raw, Origin::Env(var))?,
+                (None, Some(secret)) => {
+                    let raw = fs::read_to_string(&secret).map_err(|e| FileSysError {
+                        path: secret.into(),
+                        source: e,
+                    })?;
+                    let raw = raw.trim_end_matches(['\n', '\r']);
+                    layered.set(key, kind, raw, Origin::Env(var_file))?;
+                }
+                (None, None) => (),
+            }
+        }
+
+        for &(key, kind) in KEYS {
+            if let Some(raw) = overrides.get(key) {
+                layered.set(key, kind, raw, Origin::Flag(key.replace('.', "-")))?;
+            }
+        }
+
+        Ok(layered)
+    }
+
+    fn set(&mut self, key: &str, kind: Kind, raw: &str, origin: Origin) -> Result<(), ConfigError> {
+        let value = parse(kind, raw).map_err(|msg| ConfigError::InvalidValue {
+            key: key.to_owned(),
+            origin: Some(origin.clone()),
+            msg,
+        })?;
+
+        let mut table = &mut self.table;
+        let mut segments = key.split('.').peekable();
+        while let Some(segment) = segments.next() {
+            if segments.peek().is_none() {
+                table.insert(segment.to_owned(), value);
+                break;
+            }
+
+            let entry = table
+                .entry(segment)
+                .or_insert_with(|| Value::Table(Table::new()));
+            let Value::Table(inner) = entry else {
+                return Err(ConfigError::InvalidValue {
+                    key: key.to_owned(),
+                    origin: Some(origin),
+                    msg: format!("`{segment}` isn't a table"),
+                });
+            };
+            table = inner;
+        }
+
+        self.origins.insert(key.to_owned(), origin);
+        Ok(())
+    }
+
+    /// Deserialize the merged table into [`Config`]
+    pub fn parse(&self) -> Result<Config, ConfigError> {
+        serde_path_to_error::deserialize(Value::Table(self.table.clone())).map_err(|e| {
+            let key = e.path().to_string();
+            let msg = e.into_inner().message().to_owned();
+
+            // missing fields are reported on the root
+            if key == "." {
+                return ConfigError::Invalid(msg);
+            }
+
+            ConfigError::InvalidValue {
+                origin: self.origin(&key).cloned(),
+                msg,
+                key,
+            }
+        })
+    }
+
+    /// The value of a key joined by `.`
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        lookup(&self.table, key)
+    }
+
+    /// The keys which sachima doesn't know
+    pub fn unknown_keys(&self) -> Vec<String> {
+        fn walk(table: &Table, prefix: &str, unknown: &mut Vec<String>) {
+            for (name, value) in table {
+                let key = if prefix.is_empty() {
+                    name.to_owned()
+                } else {
+                    format!("{prefix}.{name}")
+                };
+
+                if KEYS.iter().any(|(known, _)| *known == key) {
+                    continue;
+                }
+
+                let parent = format!("{key}.");
+                match value {
+                    Value::Table(inner)
+                        if KEYS.iter().any(|(known, _)| known.starts_with(&parent)) =>
+                    {
+                        walk(inner, &key, unknown)
+                    }
+                    _ => unknown.push(key),
+                }
+            }
+        }
+
+        let mut unknown = Vec::new();
+        walk(&self.table, "", &mut unknown);
+        unknown
+    }
+
+    /// The origin of the key or its closest ancestor
+    pub fn origin(&self, key: &str) -> Option<&Origin> {
+        let mut key = key;
+        loop {
+            if let Some(origin) = self.origins.get(key) {
+                return Some(origin);
+            }
+            key = &key[..key.rfind('.')?];
+        }
+    }
+}
+
+impl Config {
+    /// Load the config by the order:
+    /// defaults, the TOML file, `SACHIMA_*` environment variables, flags.
+    pub fn load(file: Option<&Path>, overrides: &Overrides) -> Result<Self, ConfigError> {
+        Layered::load(file, overrides)?.parse()
+    }
+}
+
+/// Where the config comes from, kept to reload the config
+#[derive(Debug, Default, Clone)]
+pub struct ConfigSource {
+    pub file: Option<PathBuf>,
+    pub overrides: Overrides,
+}
+
+impl ConfigSource {
+    #[inline]
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        Config::load(self.file.as_deref(), &self.overrides)
+    }
+}
+
+/// `tls.client-ca` => `SACHIMA_TLS_CLIENT_CA`
+pub fn env_var(key: &str) -> String {
+    format!(
+        "{ENV_PREFIX}{}",
+        key.replace(['-', '.'], "_").to_uppercase()
+    )
+}
+
+fn lookup<'t>(table: &'t Table, key: &str) -> Option<&'t Value> {
+    let (parent, last) = match key.rsplit_once('.') {
+        Some((parent, last)) => (lookup(table, parent)?.as_table()?, last),
+        None => (table, key),
+    };
+
+    parent.get(last)
+}
+
+fn parse(kind: Kind, raw: &str) -> Result<Value, String> {
+    match kind {
+        Kind::String => Ok(Value::String(raw.to_owned())),
+        Kind::Integer => raw
+            .trim()
+            .parse()
+            .map(Value::Integer)
+            .map_err(|e| format!("{e}")),
+        Kind::Boolean => raw
+            .trim()
+            .parse()
+            .map(Value::Boolean)
+            .map_err(|e| format!("{e}")),
+        Kind::Inline => {
+            #[derive(Deserialize)]
+            struct Inline {
+                v: Value,
+            }
+
+            toml::from_str::<Inline>(&format!("v = {raw}"))
+                .map(|inline| inline.v)
+                .map_err(|e| e.message().to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigError, Layered, Origin, Overrides};
+    use std::collections::HashMap;
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn load(
+        file: &str,
+        overrides: &Overrides,
+        vars: &[(&str, &str)],
+    ) -> (TempDir, Result<Layered, ConfigError>) {
+        let tmp_dir = TempDir::new("sachima").unwrap();
+        let path = tmp_dir.path().join("config.toml");
+        fs::write(&path, file).unwrap();
+
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let layered = Layered::load_with(Some(&path), overrides, |var| vars.get(var).cloned());
+
+        (tmp_dir, layered)
+    }
+
+    #[test]
+    fn test_precedence() {
+        let overrides = Overrides {
+            max_upload: Some("3G".to_owned()),
+            ..Default::default()
+        };
+        let (tmp_dir, layered) = load(
+            "port = 8000\nmax-upload = \"1G\"\n",
+            &overrides,
+            &[("SACHIMA_PORT", "9000"), ("SACHIMA_MAX_UPLOAD", "2G")],
+        );
+        let layered = layered.unwrap();
+
+        assert_eq!(layered.table["port"].as_integer(), Some(9000));
+        assert_eq!(layered.table["max-upload"].as_str(), Some("3G"));
+        assert_eq!(
+            layered.origins["port"],
+            Origin::Env("SACHIMA_PORT".to_owned())
+        );
+        assert_eq!(
+            layered.origins["max-upload"],
+            Origin::Flag("max-upload".to_owned())
+        );
+
+        drop(tmp_dir);
+    }
+
+    #[test]
+    fn test_secret_file() {
+        let (tmp_dir, _) = load("", &Overrides::default(), &[]);
+        let secret = tmp_dir.path().join("secret");
+        fs::write(&secret, "from a secret file\n").unwrap();
+
+        let (_tmp_dir, layered) = load(
+            "",
+            &Overrides::default(),
+            &[
+                ("SACHIMA_JWT_SECRET_KEY_FILE", secret.to_str().unwrap()),
+                ("SACHIMA_TLS_CLIENT_USERS", r#"{ "CN=alice" = "alice" }"#),
+            ],
+        );
+        let layered = layered.unwrap();
+
+        assert_eq!(
+            layered.table["jwt-secret-key"].as_str(),
+            Some("from a secret file")
+        );
+        assert_eq!(
+            layered.table["tls"]["client-users"]["CN=alice"].as_str(),
+            Some("alice")
+        );
+
+        let (_tmp_dir, layered) = load(
+            "",
+            &Overrides::default(),
+            &[
+                ("SACHIMA_PASSWORD_SALT", "salt"),
+                ("SACHIMA_PASSWORD_SALT_FILE", secret.to_str().unwrap()),
+            ],
+        );
+        assert!(matches!(layered, Err(ConfigError::Ambiguous(_))));
+    }
+
+    #[test]
+    fn test_offending_key() {
+        let (_tmp_dir, layered) = load("", &Overrides::default(), &[("SACHIMA_PORT", "http")]);
+        let Err(ConfigError::InvalidValue { key, origin, .. }) = layered else {
+            panic!("`port` should be invalid");
+        };
+        assert_eq!(key, "port");
+        assert_eq!(origin, Some(Origin::Env("SACHIMA_PORT".to_owned())));
+
+        let (_tmp_dir, layered) = load(
+            "port = 8000\nmax-upload = \"many\"\n",
+            &Overrides::default(),
+            &[],
+        );
+        let Err(ConfigError::InvalidValue { key, origin, .. }) = layered.unwrap().parse() else {
+            panic!("`max-upload` should be invalid");
+        };
+        assert_eq!(key, "max-upload");
+        assert!(matches!(origin, Some(Origin::File(_))));
+    }
+}
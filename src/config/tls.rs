@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Tls {
+    /// The PEM encoded certificate chain
+    pub cert: PathBuf,
+
+    /// The PEM encoded private key
+    pub key: PathBuf,
+
+    /// The PEM encoded CA certificates verifying the clients,
+    /// enables mutual TLS
+    pub client_ca: Option<PathBuf>,
+
+    /// Reject the clients without a trusted certificate
+    #[serde(default)]
+    pub client_auth_required: bool,
+
+    /// Map the subject of client certificate to a username
+    #[serde(default)]
+    pub client_users: HashMap<String, String>,
+}
@@ -7,7 +7,7 @@ use serde::Deserialize;
 
 use crate::error::FileSysError;
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 #[serde(try_from = "String")]
 pub struct Workspace(PathBuf);
 
@@ -15,6 +15,12 @@ impl Workspace {
     pub fn path(&self) -> &Path {
         &self.0
     }
+
+    /// Serve the directory without checking it,
+    /// which has been created by sachima itself.
+    pub(crate) fn new_unchecked(path: PathBuf) -> Self {
+        Workspace(path)
+    }
 }
 
 impl Deref for Workspace {
@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+use std::ops::Deref;
+
+use bytesize::ByteSize;
+use serde::Deserialize;
+
+use super::{Quota, Workspace};
+
+/// The route segments taken by the default workspace and the homes
+const RESERVED: &[&str] = &["r", "w", "home"];
+
+/// Who may access a workspace
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Policy {
+    /// Everyone, including the anonymous
+    Public,
+    /// The logged-in users
+    Authenticated,
+    /// No one
+    Nobody,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct NamedWorkspace {
+    /// Served at `/wk/{name}`
+    pub name: String,
+
+    /// The serving directory
+    pub path: Workspace,
+
+    /// Who may read the workspace
+    #[serde(default = "default_read")]
+    pub read: Policy,
+
+    /// Who may write the workspace
+    #[serde(default = "default_write")]
+    pub write: Policy,
+
+    /// Fall back to the global `max-upload`
+    pub max_upload: Option<ByteSize>,
+
+    /// The storage quotas, except those of home directories
+    pub quota: Option<Quota>,
+}
+
+/// The named workspaces, whose names are unique
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "Vec<NamedWorkspace>")]
+pub struct Workspaces(Vec<NamedWorkspace>);
+
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidName {
+    #[error("workspace name {0:?} should only contain ASCII letters, digits, '-' and '_'")]
+    Malformed(String),
+
+    #[error("workspace name {0:?} is reserved")]
+    Reserved(String),
+
+    #[error("workspace name {0:?} is duplicated")]
+    Duplicated(String),
+}
+
+impl TryFrom<Vec<NamedWorkspace>> for Workspaces {
+    type Error = InvalidName;
+
+    fn try_from(workspaces: Vec<NamedWorkspace>) -> Result<Self, Self::Error> {
+        let mut names = HashSet::new();
+
+        for wk in &workspaces {
+            let name = &wk.name;
+
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(InvalidName::Malformed(name.clone()));
+            }
+
+            if RESERVED.contains(&name.as_str()) {
+                return Err(InvalidName::Reserved(name.clone()));
+            }
+
+            if !names.insert(name) {
+                return Err(InvalidName::Duplicated(name.clone()));
+            }
+        }
+
+        Ok(Self(workspaces))
+    }
+}
+
+impl Deref for Workspaces {
+    type Target = [NamedWorkspace];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+fn default_read() -> Policy {
+    Policy::Public
+}
+
+fn default_write() -> Policy {
+    Policy::Authenticated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InvalidName, NamedWorkspace, Policy, Workspaces};
+    use crate::utils::tests::setup_workspace;
+
+    fn named(name: &str) -> NamedWorkspace {
+        let (tmp_dir, path) = setup_workspace();
+        // the path isn't touched by the name checks
+        drop(tmp_dir);
+
+        NamedWorkspace {
+            name: name.to_owned(),
+            path,
+            read: Policy::Public,
+            write: Policy::Authenticated,
+            max_upload: None,
+            quota: None,
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(Workspaces::try_from(vec![named("photos"), named("team_docs-2")]).is_ok());
+
+        assert!(matches!(
+            Workspaces::try_from(vec![named("a/b")]),
+            Err(InvalidName::Malformed(_))
+        ));
+        assert!(matches!(
+            Workspaces::try_from(vec![named("")]),
+            Err(InvalidName::Malformed(_))
+        ));
+        assert!(matches!(
+            Workspaces::try_from(vec![named("w")]),
+            Err(InvalidName::Reserved(_))
+        ));
+        assert!(matches!(
+            Workspaces::try_from(vec![named("home")]),
+            Err(InvalidName::Reserved(_))
+        ));
+        assert!(matches!(
+            Workspaces::try_from(vec![named("photos"), named("photos")]),
+            Err(InvalidName::Duplicated(_))
+        ));
+    }
+}
@@ -8,3 +8,9 @@ pub struct FileSysError {
     #[source]
     pub source: io::Error,
 }
+
+impl From<FileSysError> for io::Error {
+    fn from(e: FileSysError) -> Self {
+        io::Error::new(e.source.kind(), e)
+    }
+}
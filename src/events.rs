@@ -0,0 +1,239 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast;
+
+/// How many past events are kept for the clients resuming
+const HISTORY: usize = 4096;
+
+/// The changes reported by the watcher so soon after a handler made them are duplicates
+const DEDUP_WINDOW: Duration = Duration::from_secs(2);
+
+/// A change of the file system, the paths are absolute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Renamed { from: PathBuf, to: PathBuf },
+    Removed(PathBuf),
+}
+
+impl Change {
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        let (first, second) = match self {
+            Change::Created(path) | Change::Modified(path) | Change::Removed(path) => (path, None),
+            Change::Renamed { from, to } => (from, Some(to)),
+        };
+
+        std::iter::once(first).chain(second)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// Increasing, even across restarts
+    pub id: u64,
+    pub change: Change,
+}
+
+/// The events in order
+#[derive(Debug)]
+pub struct Subscription {
+    /// Those after the resumed id
+    pub missed: Vec<Arc<Event>>,
+    /// Some events after the resumed id have been forgotten,
+    /// the client should start over from this id.
+    pub reset: Option<u64>,
+    pub receiver: broadcast::Receiver<Arc<Event>>,
+}
+
+/// What a handler did to a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Done {
+    Created,
+    Modified,
+    RenamedFrom,
+    RenamedTo,
+    Removed,
+}
+
+impl Done {
+    fn of(change: &Change) -> Vec<(&PathBuf, Done)> {
+        match change {
+            Change::Created(path) => vec![(path, Done::Created)],
+            Change::Modified(path) => vec![(path, Done::Modified)],
+            Change::Renamed { from, to } => vec![(from, Done::RenamedFrom), (to, Done::RenamedTo)],
+            Change::Removed(path) => vec![(path, Done::Removed)],
+        }
+    }
+
+    /// The watcher reports `self` for what a handler has done,
+    /// the files replaced by a rename are created for it, so is half of a rename.
+    fn reports(self, done: Done) -> bool {
+        match self {
+            Done::Created => matches!(done, Done::Created | Done::Modified | Done::RenamedTo),
+            Done::Removed => matches!(done, Done::Removed | Done::RenamedFrom),
+            _ => self == done,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Hub {
+    next_id: u64,
+    history: VecDeque<Arc<Event>>,
+    /// Path => what a handler did to it and when
+    recent: HashMap<PathBuf, (Done, Instant)>,
+    sender: broadcast::Sender<Arc<Event>>,
+}
+
+static HUB: Lazy<Mutex<Hub>> = Lazy::new(|| {
+    // ids of the previous run are older, so they're known to be lost
+    let next_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_micros() as u64);
+
+    Mutex::new(Hub {
+        next_id,
+        history: VecDeque::with_capacity(HISTORY),
+        recent: HashMap::new(),
+        sender: broadcast::channel(HISTORY).0,
+    })
+});
+
+impl Hub {
+    fn push(&mut self, change: Change) {
+        let event = Arc::new(Event {
+            id: self.next_id,
+            change,
+        });
+        self.next_id += 1;
+
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(event.clone());
+        // no one is watching
+        self.sender.send(event).ok();
+    }
+}
+
+/// Publish a change made by a handler
+pub fn publish(change: Change) {
+    let mut hub = HUB.lock().unwrap();
+    let now = Instant::now();
+
+    if hub.recent.len() >= HISTORY {
+        hub.recent
+            .retain(|_, (_, changed)| now.duration_since(*changed) < DEDUP_WINDOW);
+    }
+    for (path, done) in Done::of(&change) {
+        hub.recent.insert(path.clone(), (done, now));
+    }
+
+    hub.push(change);
+}
+
+/// Publish a change found by the watcher, unless a handler has published it,
+/// which is taken as the duplicate only once.
+pub fn publish_external(change: Change) {
+    let mut hub = HUB.lock().unwrap();
+
+    let reported = Done::of(&change);
+    let duplicate = reported.iter().all(|(path, reported)| {
+        hub.recent.get(*path).is_some_and(|(done, changed)| {
+            changed.elapsed() < DEDUP_WINDOW && reported.reports(*done)
+        })
+    });
+    if duplicate {
+        for (path, _) in reported {
+            hub.recent.remove(path);
+        }
+    } else {
+        hub.push(change);
+    }
+}
+
+/// Receive the events after the id, or the new ones
+pub fn subscribe(after: Option<u64>) -> Subscription {
+    let hub = HUB.lock().unwrap();
+    let receiver = hub.sender.subscribe();
+
+    let Some(after) = after else {
+        return Subscription {
+            missed: Vec::new(),
+            reset: None,
+            receiver,
+        };
+    };
+
+    let oldest = hub.history.front().map_or(hub.next_id, |event| event.id);
+    if after.saturating_add(1) < oldest || after >= hub.next_id {
+        return Subscription {
+            missed: Vec::new(),
+            reset: Some(hub.next_id - 1),
+            receiver,
+        };
+    }
+
+    Subscription {
+        missed: hub
+            .history
+            .iter()
+            .filter(|event| event.id > after)
+            .cloned()
+            .collect(),
+        reset: None,
+        receiver,
+    }
+}
+
+/// The id of the last event
+pub fn latest() -> u64 {
+    HUB.lock().unwrap().next_id - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{publish, publish_external, subscribe, Change};
+    use crate::utils::tests::setup_workspace;
+
+    #[tokio::test]
+    async fn test_events() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let mut sub = subscribe(None);
+
+        publish(Change::Created(wk.join("a.txt")));
+        // reported by the watcher again
+        publish_external(Change::Created(wk.join("a.txt")));
+        publish_external(Change::Removed(wk.join("b.txt")));
+        // edited by another program right after
+        publish_external(Change::Modified(wk.join("a.txt")));
+
+        // other tests may publish at the same time
+        let mut mine = Vec::new();
+        while mine.len() < 3 {
+            let event = sub.receiver.recv().await.unwrap();
+            if event.change.paths().all(|path| path.starts_with(&*wk)) {
+                mine.push(event);
+            }
+        }
+        assert_eq!(mine[0].change, Change::Created(wk.join("a.txt")));
+        assert_eq!(mine[1].change, Change::Removed(wk.join("b.txt")));
+        assert_eq!(mine[2].change, Change::Modified(wk.join("a.txt")));
+
+        let resumed = subscribe(Some(mine[0].id));
+        assert_eq!(resumed.reset, None);
+        assert!(resumed.missed.contains(&mine[1]));
+        assert!(!resumed.missed.contains(&mine[0]));
+
+        // from the previous run
+        let lost = subscribe(Some(0));
+        assert!(lost.reset.is_some_and(|id| id >= mine[1].id));
+        assert!(lost.missed.is_empty());
+        assert!(subscribe(Some(u64::MAX)).reset.is_some());
+    }
+}
@@ -0,0 +1,33 @@
+use poem::handler;
+use poem::web::{Html, Json};
+use serde_json::Value;
+
+use crate::openapi::DOCUMENT;
+
+/// The page rendering `/openapi.json` by Scalar,
+/// whose version is pinned so the page doesn't change under it
+const PAGE: &str = r#"<!doctype html>
+<html>
+  <head>
+    <title>Sachima API</title>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+  </head>
+  <body>
+    <script id="api-reference" data-url="/openapi.json"></script>
+    <script src="https://cdn.jsdelivr.net/npm/@scalar/api-reference@1.25.0"></script>
+  </body>
+</html>
+"#;
+
+/// **The OpenAPI 3 document of the routes**
+#[handler]
+pub fn openapi() -> Json<&'static Value> {
+    Json(&DOCUMENT)
+}
+
+/// **The interactive docs of the document**
+#[handler]
+pub fn page() -> Html<&'static str> {
+    Html(PAGE)
+}
@@ -0,0 +1,383 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use futures_util::{stream, StreamExt};
+use poem::handler;
+use poem::web::{Data, Json};
+use serde::Deserialize;
+use tokio::fs;
+use tokio::task;
+
+use super::{check_owner, check_relative, is_file_name, make_dir, move_path, record, remove_path};
+use crate::config::Workspace;
+use crate::events::Change;
+use crate::models::fs::{Batched, Done};
+use crate::models::permission::User;
+use crate::quota::{self, Quotas, Usage};
+use crate::reply::{ReplyData, ReplyError};
+use crate::search::SearchIndex;
+use crate::settings::HomeOwnership;
+use crate::utils::atomic;
+
+/// How many operations of a batch run at the same time
+const CONCURRENCY: usize = 8;
+
+/// The paths are relative to the workspace root
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum Operation {
+    Remove {
+        path: PathBuf,
+    },
+    /// Rename in the same directory
+    Rename {
+        path: PathBuf,
+        name: String,
+    },
+    /// Move to another path
+    Move {
+        path: PathBuf,
+        to: PathBuf,
+    },
+    Copy {
+        path: PathBuf,
+        to: PathBuf,
+    },
+    Mkdir {
+        path: PathBuf,
+        #[serde(default)]
+        parents: bool,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchParam {
+    ops: Vec<Operation>,
+    /// Run the operations in order and stop at the first failure,
+    /// which undoes the done ones.
+    #[serde(default)]
+    transactional: bool,
+}
+
+/// **Run the file operations in a batch**
+/// - Ok: return the reply of every operation, like that of a single request
+///
+/// The operations run concurrently unless it's transactional,
+/// so they shouldn't depend on each other.
+#[handler]
+pub async fn batch(
+    Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    ownership: Option<Data<&HomeOwnership>>,
+    user: Option<Data<&User>>,
+    Json(BatchParam { ops, transactional }): Json<BatchParam>,
+) -> Result<ReplyData<Batched>, ReplyError> {
+    let ctx = Context {
+        workspace,
+        quotas,
+        index,
+        ownership: ownership.map(|Data(ownership)| ownership),
+        user: user.map(|Data(user)| user),
+    };
+
+    if !transactional {
+        let results = stream::iter(ops)
+            .map(|op| ctx.run(op, false))
+            .buffered(CONCURRENCY)
+            .map(|res| res.map(|(done, _)| done).into())
+            .collect()
+            .await;
+
+        return Ok(ReplyData(Batched {
+            results,
+            rolled_back: false,
+        }));
+    }
+
+    let mut results = Vec::with_capacity(ops.len());
+    let mut undos = Vec::with_capacity(ops.len());
+    for op in ops {
+        match ctx.run(op, true).await {
+            Ok((done, undo)) => {
+                results.push(Ok(done).into());
+                undos.extend(undo);
+            }
+            Err(e) => {
+                results.push(Err(e).into());
+                let rolled_back = ctx.undo(undos).await;
+                return Ok(ReplyData(Batched {
+                    results,
+                    rolled_back,
+                }));
+            }
+        }
+    }
+    ctx.commit(undos).await;
+
+    Ok(ReplyData(Batched {
+        results,
+        rolled_back: false,
+    }))
+}
+
+/// How to undo a done operation
+#[derive(Debug)]
+enum Undo {
+    /// Move the entry staged for removal back,
+    /// it's removed for good once the batch succeeds.
+    Restore {
+        staged: PathBuf,
+        path: PathBuf,
+    },
+    MoveBack {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Remove(PathBuf),
+    /// Remove the made directories from the innermost
+    RemoveDirs(Vec<PathBuf>),
+}
+
+struct Context<'a> {
+    workspace: &'a Workspace,
+    quotas: &'a Quotas,
+    index: &'a SearchIndex,
+    ownership: Option<&'a HomeOwnership>,
+    user: Option<&'a User>,
+}
+
+impl Context<'_> {
+    /// The absolute path, checked like the path of a single write request
+    fn resolve(&self, path: &Path) -> Result<PathBuf, ReplyError> {
+        check_relative(path)?;
+
+        if path.components().all(|c| c == Component::CurDir) {
+            return Err(ReplyError::WorkspaceRoot);
+        }
+
+        if let Some(ownership) = self.ownership {
+            check_owner(path, ownership, self.user)?;
+        }
+
+        Ok(self.workspace.join(path))
+    }
+
+    /// Resolve the source which must exist, and the free destination
+    async fn resolve_pair(&self, path: &Path, to: &Path) -> Result<(PathBuf, PathBuf), ReplyError> {
+        let src = self.resolve(path)?;
+        let dest = self.resolve(to)?;
+
+        if !fs::try_exists(&src).await? {
+            return Err(ReplyError::NotFound);
+        }
+
+        if dest != src && dest.starts_with(&src) {
+            return Err(ReplyError::IntoItself);
+        }
+
+        if !fs::try_exists(dest.parent().unwrap()).await? {
+            return Err(ReplyError::MissingParent);
+        }
+
+        Ok((src, dest))
+    }
+
+    /// The removal is staged to be undone if it's transactional
+    async fn run(
+        &self,
+        op: Operation,
+        transactional: bool,
+    ) -> Result<(Done, Option<Undo>), ReplyError> {
+        let undo = match op {
+            Operation::Remove { path } => {
+                let path = self.resolve(&path)?;
+                if !fs::try_exists(&path).await? {
+                    return Err(ReplyError::NotFound);
+                }
+
+                if transactional {
+                    let staged = atomic::temp_path(path.parent().unwrap());
+                    fs::rename(&path, &staged).await?;
+                    record(self.index, Change::Removed(path.clone())).await;
+                    Undo::Restore { staged, path }
+                } else {
+                    remove_path(&path, self.quotas).await?;
+                    record(self.index, Change::Removed(path)).await;
+                    return Ok((Done::Nothing, None));
+                }
+            }
+            Operation::Rename { path, name } => {
+                if !is_file_name(&name) {
+                    return Err(ReplyError::OutsideWorkspace);
+                }
+
+                let (src, dest) = self.resolve_pair(&path, &path.with_file_name(name)).await?;
+                move_path(&src, &dest, self.quotas).await?;
+                let (from, to) = (src.clone(), dest.clone());
+                record(self.index, Change::Renamed { from, to }).await;
+                Undo::MoveBack {
+                    from: dest,
+                    to: src,
+                }
+            }
+            Operation::Move { path, to } => {
+                let (src, dest) = self.resolve_pair(&path, &to).await?;
+                move_path(&src, &dest, self.quotas).await?;
+                let (from, to) = (src.clone(), dest.clone());
+                record(self.index, Change::Renamed { from, to }).await;
+                Undo::MoveBack {
+                    from: dest,
+                    to: src,
+                }
+            }
+            Operation::Copy { path, to } => {
+                let (src, dest) = self.resolve_pair(&path, &to).await?;
+                copy_path(&src, &dest, self.quotas).await?;
+                record(self.index, Change::Created(dest.clone())).await;
+                Undo::Remove(dest)
+            }
+            Operation::Mkdir { path, parents } => {
+                let path = self.resolve(&path)?;
+                let created = make_dir(self.workspace, &path, parents).await?;
+                if let Some(dir) = created.first() {
+                    record(self.index, Change::Created(self.workspace.join(dir))).await;
+                }
+                let dirs = created
+                    .iter()
+                    .rev()
+                    .map(|dir| self.workspace.join(dir))
+                    .collect();
+                return Ok((Done::Created(created), Some(Undo::RemoveDirs(dirs))));
+            }
+        };
+
+        Ok((Done::Nothing, Some(undo)))
+    }
+
+    /// Undo the done operations from the last,
+    /// return whether all of them are undone.
+    async fn undo(&self, undos: Vec<Undo>) -> bool {
+        let mut rolled_back = true;
+
+        for undo in undos.into_iter().rev() {
+            let res = match &undo {
+                Undo::Restore { staged, path } => fs::rename(staged, path)
+                    .await
+                    .map(|()| Some(Change::Created(path.clone())))
+                    .map_err(Into::into),
+                Undo::MoveBack { from, to } => move_path(from, to, self.quotas).await.map(|()| {
+                    Some(Change::Renamed {
+                        from: from.clone(),
+                        to: to.clone(),
+                    })
+                }),
+                Undo::Remove(path) => remove_path(path, self.quotas)
+                    .await
+                    .map(|()| Some(Change::Removed(path.clone()))),
+                // the outermost one is the last
+                Undo::RemoveDirs(dirs) => remove_dirs(dirs)
+                    .await
+                    .map(|()| dirs.last().cloned().map(Change::Removed))
+                    .map_err(Into::into),
+            };
+
+            match res {
+                Ok(change) => {
+                    if let Some(change) = change {
+                        record(self.index, change).await;
+                    }
+                }
+                Err(e) => {
+                    tracing::error!(?undo, error = %e, "cannot undo the batch operation");
+                    rolled_back = false;
+                }
+            }
+        }
+
+        rolled_back
+    }
+
+    /// Remove the staged entries once the batch succeeds
+    async fn commit(&self, undos: Vec<Undo>) {
+        for undo in undos {
+            if let Undo::Restore { staged, .. } = undo {
+                if let Err(e) = remove_path(&staged, self.quotas).await {
+                    tracing::warn!(staged = %staged.display(), error = %e, "cannot remove the staged entry");
+                }
+            }
+        }
+    }
+}
+
+async fn remove_dirs(dirs: &[PathBuf]) -> io::Result<()> {
+    for dir in dirs {
+        fs::remove_dir(dir).await?;
+    }
+
+    Ok(())
+}
+
+/// Copy a file or directory tree to the free path
+pub(super) async fn copy_path(src: &Path, dest: &Path, quotas: &Quotas) -> Result<(), ReplyError> {
+    let copied = if quotas.is_empty() {
+        Usage::default()
+    } else {
+        quota::measure(src).await?
+    };
+    quotas.reserve(dest, copied).await?;
+
+    let (from, to) = (src.to_owned(), dest.to_owned());
+    let res = task::spawn_blocking(move || {
+        let res = copy_tree(&from, &to);
+        // leave nothing half copied, unless the path is taken by others
+        if res
+            .as_ref()
+            .is_err_and(|e| e.kind() != io::ErrorKind::AlreadyExists)
+        {
+            std::fs::remove_dir_all(&to)
+                .or_else(|_| std::fs::remove_file(&to))
+                .ok();
+        }
+        res
+    })
+    .await
+    .map_err(io::Error::other)?;
+
+    if let Err(e) = res {
+        quotas.release(dest, copied);
+        return Err(match e.kind() {
+            io::ErrorKind::AlreadyExists => ReplyError::AlreadyExists,
+            _ => e.into(),
+        });
+    }
+
+    Ok(())
+}
+
+/// The uploads in progress and the staged entries aren't copied
+fn copy_tree(src: &Path, dest: &Path) -> io::Result<()> {
+    let md = src.symlink_metadata()?;
+
+    if md.is_dir() {
+        std::fs::create_dir(dest)?;
+        for entry in src.read_dir()? {
+            let entry = entry?;
+            if !atomic::is_temp(&entry.file_name().to_string_lossy()) {
+                copy_tree(&entry.path(), &dest.join(entry.file_name()))?;
+            }
+        }
+    } else if md.is_symlink() {
+        std::os::unix::fs::symlink(src.read_link()?, dest)?;
+    } else {
+        let mut from = std::fs::File::open(src)?;
+        let mut to = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dest)?;
+        io::copy(&mut from, &mut to)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+use once_cell::sync::Lazy;
+use poem::web::headers::{ETag, IfMatch};
+use serde::Deserialize;
+use tokio::fs;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::models::fs::Uploaded;
+use crate::quota::{Quotas, Usage};
+use crate::reply::ReplyError;
+use crate::utils::atomic::AtomicFile;
+use crate::utils::etag::etag;
+
+/// Give up looking for a free name after so many tries
+const MAX_RENAMES: usize = 10_000;
+
+/// Destination => the lock of the uploads placing there,
+/// so `If-Match` is checked and the file is placed without another upload in between.
+static PLACING: Lazy<Mutex<HashMap<PathBuf, Weak<tokio::sync::Mutex<()>>>>> =
+    Lazy::new(Mutex::default);
+
+/// Hold the destination until the guard is dropped
+async fn hold(dest: &Path) -> OwnedMutexGuard<()> {
+    let lock = {
+        let mut placing = PLACING.lock().unwrap();
+        placing.retain(|_, lock| lock.strong_count() > 0);
+        match placing.get(dest).and_then(Weak::upgrade) {
+            Some(lock) => lock,
+            None => {
+                let lock = Arc::new(tokio::sync::Mutex::new(()));
+                placing.insert(dest.to_owned(), Arc::downgrade(&lock));
+                lock
+            }
+        }
+    };
+
+    lock.lock_owned().await
+}
+
+/// `If-Match` passes only if the existing file matches
+fn precondition_passes(existing: Option<&Metadata>, if_match: Option<&IfMatch>) -> bool {
+    let Some(if_match) = if_match else {
+        return true;
+    };
+
+    existing.is_some_and(|md| {
+        etag(md)
+            .parse::<ETag>()
+            .is_ok_and(|etag| if_match.precondition_passes(&etag))
+    })
+}
+
+async fn check_precondition(dest: &Path, if_match: Option<&IfMatch>) -> Result<(), ReplyError> {
+    if if_match.is_none() {
+        return Ok(());
+    }
+
+    let existing = match fs::metadata(dest).await {
+        Ok(md) => Some(md),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    if !precondition_passes(existing.as_ref(), if_match) {
+        return Err(ReplyError::PreconditionFailed);
+    }
+
+    Ok(())
+}
+
+/// What to do when the name has been taken
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnConflict {
+    /// Fail with `ReplyError::AlreadyExists`
+    #[default]
+    Fail,
+    /// Replace the existing file
+    Overwrite,
+    /// Use a free name like `name (1).ext`
+    Rename,
+    /// Keep the existing file and discard the uploaded one
+    Skip,
+}
+
+/// Settle the conflict before receiving the content if possible,
+/// `Some` means the upload is skipped.
+pub async fn settle_early(
+    dest: &Path,
+    on_conflict: OnConflict,
+    if_match: Option<&IfMatch>,
+) -> Result<Option<Uploaded>, ReplyError> {
+    check_precondition(dest, if_match).await?;
+
+    match on_conflict {
+        OnConflict::Fail if fs::try_exists(dest).await? => Err(ReplyError::AlreadyExists),
+        OnConflict::Skip if fs::try_exists(dest).await? => Ok(Some(Uploaded::skipped(dest))),
+        _ => Ok(None),
+    }
+}
+
+/// Move the uploaded file into place following the conflict policy,
+/// `If-Match` is checked whatever the policy is.
+pub async fn place(
+    file: AtomicFile,
+    dest: &Path,
+    size: u64,
+    on_conflict: OnConflict,
+    if_match: Option<&IfMatch>,
+    quotas: &Quotas,
+) -> Result<Uploaded, ReplyError> {
+    let _placing = hold(dest).await;
+
+    if on_conflict == OnConflict::Overwrite {
+        return overwrite(file, dest, size, if_match, quotas).await;
+    }
+    check_precondition(dest, if_match).await?;
+
+    if on_conflict == OnConflict::Skip && fs::try_exists(dest).await? {
+        return Ok(Uploaded::skipped(dest));
+    }
+
+    let added = Usage {
+        bytes: size,
+        files: 1,
+    };
+    quotas.reserve(dest, added).await?;
+
+    let placed = match on_conflict {
+        OnConflict::Rename => persist_renamed(file, dest).await,
+        _ => file.persist(dest).await.map(|()| dest.to_owned()),
+    };
+
+    match placed {
+        Ok(placed) => Ok(Uploaded::new(&placed)),
+        Err(e) => {
+            quotas.release(dest, added);
+            match e.kind() {
+                // taken by a concurrent upload
+                io::ErrorKind::AlreadyExists if on_conflict == OnConflict::Skip => {
+                    Ok(Uploaded::skipped(dest))
+                }
+                io::ErrorKind::AlreadyExists => Err(ReplyError::AlreadyExists),
+                _ => Err(e.into()),
+            }
+        }
+    }
+}
+
+async fn overwrite(
+    file: AtomicFile,
+    dest: &Path,
+    size: u64,
+    if_match: Option<&IfMatch>,
+    quotas: &Quotas,
+) -> Result<Uploaded, ReplyError> {
+    let existing = match fs::metadata(dest).await {
+        Ok(md) if md.is_dir() => return Err(ReplyError::IsADirectory),
+        Ok(md) => Some(md),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    if !precondition_passes(existing.as_ref(), if_match) {
+        return Err(ReplyError::PreconditionFailed);
+    }
+
+    // only the growth is accounted
+    let old = existing.map(|md| md.len());
+    let (grown, shrunk) = match old {
+        Some(old) => (size.saturating_sub(old), old.saturating_sub(size)),
+        None => (size, 0),
+    };
+    let added = Usage {
+        bytes: grown,
+        files: old.is_none().into(),
+    };
+    quotas.reserve(dest, added).await?;
+
+    if let Err(e) = file.persist_overwrite(dest).await {
+        quotas.release(dest, added);
+        return Err(e.into());
+    }
+    quotas.release(
+        dest,
+        Usage {
+            bytes: shrunk,
+            files: 0,
+        },
+    );
+
+    Ok(Uploaded::new(dest))
+}
+
+/// Try `name.ext`, `name (1).ext`, `name (2).ext` and so on
+async fn persist_renamed(mut file: AtomicFile, dest: &Path) -> io::Result<PathBuf> {
+    for n in 0..MAX_RENAMES {
+        let candidate = if n == 0 {
+            dest.to_owned()
+        } else {
+            numbered(dest, n)
+        };
+
+        match file.try_persist(&candidate).await {
+            Ok(()) => return Ok(candidate),
+            Err((returned, e)) if e.kind() == io::ErrorKind::AlreadyExists => file = returned,
+            Err((_, e)) => return Err(e),
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "no free name is found",
+    ))
+}
+
+/// `name.ext` => `name (n).ext`
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem} ({n}).{}", ext.to_string_lossy()),
+        None => format!("{stem} ({n})"),
+    };
+
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{numbered, place, OnConflict};
+    use crate::quota::Quotas;
+    use crate::reply::ReplyError;
+    use crate::utils::atomic::AtomicFile;
+    use crate::utils::etag::etag;
+    use crate::utils::tests::setup_workspace;
+    use poem::web::headers::{ETag, IfMatch};
+    use std::path::Path;
+    use tokio::fs;
+    use tokio::io::AsyncWriteExt;
+
+    async fn staged(dir: &Path, content: &str) -> AtomicFile {
+        let mut file = AtomicFile::create(dir).await.unwrap();
+        file.file().write_all(content.as_bytes()).await.unwrap();
+        file
+    }
+
+    #[tokio::test]
+    async fn test_if_match() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let dest = wk.join("notes.txt");
+        fs::write(&dest, "v1").await.unwrap();
+        let if_match = IfMatch::from(
+            etag(&fs::metadata(&dest).await.unwrap())
+                .parse::<ETag>()
+                .unwrap(),
+        );
+        let quotas = Quotas::default();
+
+        // the editors holding the same version, only one of them wins
+        let (first, second) = tokio::join!(
+            async {
+                let file = staged(&wk, "v2 by alice").await;
+                place(
+                    file,
+                    &dest,
+                    11,
+                    OnConflict::Overwrite,
+                    Some(&if_match),
+                    &quotas,
+                )
+                .await
+            },
+            async {
+                // unlike "v2", a size differing from "v1" keeps the ETags apart
+                // even when the staged file gets the same coarse mtime
+                let file = staged(&wk, "v2 by bob").await;
+                place(
+                    file,
+                    &dest,
+                    9,
+                    OnConflict::Overwrite,
+                    Some(&if_match),
+                    &quotas,
+                )
+                .await
+            },
+        );
+        assert!(first.is_ok() != second.is_ok());
+        assert!(matches!(
+            first.and(second),
+            Err(ReplyError::PreconditionFailed)
+        ));
+
+        // stale whatever the policy is
+        for on_conflict in [OnConflict::Fail, OnConflict::Rename, OnConflict::Skip] {
+            let file = staged(&wk, "v3").await;
+            assert!(matches!(
+                place(file, &dest, 2, on_conflict, Some(&if_match), &quotas).await,
+                Err(ReplyError::PreconditionFailed)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_numbered() {
+        assert_eq!(
+            numbered(Path::new("a/report.pdf"), 1),
+            Path::new("a/report (1).pdf")
+        );
+        assert_eq!(numbered(Path::new("README"), 2), Path::new("README (2)"));
+        assert_eq!(numbered(Path::new(".bashrc"), 1), Path::new(".bashrc (1)"));
+        assert_eq!(
+            numbered(Path::new("backup.tar.gz"), 3),
+            Path::new("backup.tar (3).gz")
+        );
+    }
+}
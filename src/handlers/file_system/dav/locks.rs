@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+/// The longest a lock lasts without being refreshed
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// A write lock, which is lost on restart
+///
+/// It's advisory: only the WebDAV handlers check it,
+/// and `/wk`, S3 and the batches write regardless of it.
+#[derive(Debug, Clone)]
+pub struct Lock {
+    pub token: String,
+    /// Absolute
+    pub path: PathBuf,
+    /// Covering the subtree instead of the resource only
+    pub infinite: bool,
+    pub exclusive: bool,
+    /// The inner XML of `owner` given by the client
+    pub owner: Option<String>,
+    /// Who has locked it, the anonymous writers share their locks
+    pub user: Option<String>,
+    pub timeout: Duration,
+    expires: Instant,
+}
+
+impl Lock {
+    /// Whether writing the path needs the lock
+    fn covers(&self, path: &Path) -> bool {
+        self.path == path || (self.infinite && path.starts_with(&self.path))
+    }
+
+    /// Whether the lock is held by the request
+    fn held(&self, tokens: &[String], user: Option<&str>) -> bool {
+        tokens.contains(&self.token) && self.user.as_deref() == user
+    }
+}
+
+/// Token => lock
+static LOCKS: Lazy<Mutex<HashMap<String, Lock>>> = Lazy::new(Default::default);
+
+fn locks() -> std::sync::MutexGuard<'static, HashMap<String, Lock>> {
+    let mut locks = LOCKS.lock().unwrap();
+    let now = Instant::now();
+    locks.retain(|_, lock| lock.expires > now);
+
+    locks
+}
+
+/// Lock the path, `None` if it conflicts with an existing lock
+pub fn lock(
+    path: &Path,
+    infinite: bool,
+    exclusive: bool,
+    owner: Option<String>,
+    user: Option<&str>,
+    timeout: Duration,
+) -> Option<Lock> {
+    let mut locks = locks();
+
+    let conflicted = locks.values().any(|lock| {
+        (lock.covers(path) || (infinite && lock.path.starts_with(path)))
+            && (lock.exclusive || exclusive)
+    });
+    if conflicted {
+        return None;
+    }
+
+    let lock = Lock {
+        token: format!("opaquelocktoken:{}", uuid::Uuid::new_v4()),
+        path: path.to_owned(),
+        infinite,
+        exclusive,
+        owner,
+        user: user.map(ToOwned::to_owned),
+        timeout,
+        expires: Instant::now() + timeout,
+    };
+    locks.insert(lock.token.clone(), lock.clone());
+
+    Some(lock)
+}
+
+/// Extend the held lock covering the path
+pub fn refresh(
+    path: &Path,
+    tokens: &[String],
+    user: Option<&str>,
+    timeout: Duration,
+) -> Option<Lock> {
+    let mut locks = locks();
+    let lock = locks
+        .values_mut()
+        .find(|lock| lock.covers(path) && lock.held(tokens, user))?;
+
+    lock.timeout = timeout;
+    lock.expires = Instant::now() + timeout;
+
+    Some(lock.clone())
+}
+
+/// Remove the lock covering the path, `false` if it isn't held
+pub fn unlock(path: &Path, token: &str, user: Option<&str>) -> bool {
+    let mut locks = locks();
+
+    match locks.get(token) {
+        Some(lock) if lock.covers(path) && lock.user.as_deref() == user => {
+            locks.remove(token);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The locks covering the path
+pub fn discover(path: &Path) -> Vec<Lock> {
+    locks()
+        .values()
+        .filter(|lock| lock.covers(path))
+        .cloned()
+        .collect()
+}
+
+/// Whether the request may write the path,
+/// or remove it with its subtree if it's `deep`.
+pub fn may_write(path: &Path, deep: bool, tokens: &[String], user: Option<&str>) -> bool {
+    locks().values().all(|lock| {
+        let needed = lock.covers(path) || (deep && lock.path.starts_with(path));
+        !needed || lock.held(tokens, user)
+    })
+}
+
+/// Drop the locks of the removed path and its subtree
+pub fn release(path: &Path) {
+    locks().retain(|_, lock| !lock.path.starts_with(path));
+}
+
+/// The lock tokens submitted in the `If` header
+pub fn tokens(header: &str) -> Vec<String> {
+    header
+        .split('<')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('>'))
+        .map(|(url, _)| url.to_owned())
+        .filter(|url| url.starts_with("opaquelocktoken:"))
+        .collect()
+}
+
+/// The timeout asked by the `Timeout` header, within `MAX_TIMEOUT`
+pub fn timeout(header: Option<&str>) -> Duration {
+    header
+        .into_iter()
+        .flat_map(|header| header.split(','))
+        .find_map(|timeout| match timeout.trim() {
+            "Infinite" => Some(MAX_TIMEOUT),
+            timeout => timeout
+                .strip_prefix("Second-")
+                .and_then(|secs| secs.parse().ok())
+                .map(Duration::from_secs),
+        })
+        .map_or(MAX_TIMEOUT, |timeout| timeout.min(MAX_TIMEOUT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{discover, lock, may_write, refresh, release, timeout, tokens, unlock};
+    use crate::utils::tests::setup_workspace;
+    use std::time::Duration;
+
+    #[test]
+    fn test_locks() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let hour = Duration::from_secs(3600);
+        let alice = Some("alice");
+
+        let dir = lock(&wk.join("dir"), true, true, None, alice, hour).unwrap();
+        // inside the locked subtree
+        assert!(lock(&wk.join("dir/a.txt"), false, false, None, alice, hour).is_none());
+        assert!(lock(&wk.join("b.txt"), false, false, None, alice, hour).is_some());
+        assert!(lock(&wk.join("b.txt"), false, false, None, alice, hour).is_some());
+        assert!(lock(&wk.join("b.txt"), false, true, None, alice, hour).is_none());
+
+        let held = [dir.token.clone()];
+        assert!(!may_write(&wk.join("dir/a.txt"), false, &[], alice));
+        assert!(may_write(&wk.join("dir/a.txt"), false, &held, alice));
+        assert!(!may_write(&wk.join("dir/a.txt"), false, &held, Some("bob")));
+        // removing the workspace root removes the locked directory
+        assert!(may_write(&wk, false, &[], alice));
+        assert!(!may_write(&wk, true, &[], alice));
+
+        assert_eq!(discover(&wk.join("dir/a.txt")).len(), 1);
+        assert!(refresh(&wk.join("dir"), &held, alice, hour).is_some());
+        assert!(!unlock(&wk.join("dir"), &dir.token, Some("bob")));
+        assert!(unlock(&wk.join("dir"), &dir.token, alice));
+        assert!(discover(&wk.join("dir")).is_empty());
+
+        release(&wk);
+        assert!(discover(&wk.join("b.txt")).is_empty());
+
+        assert_eq!(
+            tokens("(<opaquelocktoken:a>) <http://host/b> (Not <opaquelocktoken:b>)"),
+            ["opaquelocktoken:a", "opaquelocktoken:b"]
+        );
+        assert_eq!(
+            timeout(Some("Second-60, Infinite")),
+            Duration::from_secs(60)
+        );
+        assert_eq!(timeout(Some("Second-999999")), hour);
+        assert_eq!(timeout(None), hour);
+    }
+}
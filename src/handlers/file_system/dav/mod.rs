@@ -0,0 +1,958 @@
+#[cfg(test)]
+mod tests;
+
+mod locks;
+pub(super) mod xml;
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytesize::ByteSize;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use poem::http::header::{ALLOW, ETAG};
+use poem::http::{HeaderMap, Method, StatusCode, Uri};
+use poem::web::headers::IfMatch;
+use poem::web::{Data, Path as PathParam, TypedHeader};
+use poem::{handler, Body, IntoResponse, Request, Response};
+use quick_xml::escape::escape;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::io::AsyncReadExt;
+use tokio::{fs, task};
+
+use super::batch::copy_path;
+use super::conflict::{self, OnConflict};
+use super::digest::Verifier;
+use super::{check_owner, check_relative, move_path, receive, record, remove_path};
+use crate::config::Workspace;
+use crate::events::Change;
+use crate::models::permission::User;
+use crate::quota::{Quotas, Usage};
+use crate::reply::ReplyError;
+use crate::search::SearchIndex;
+use crate::settings::{HomeOwnership, MaxUpload};
+use crate::utils::atomic;
+use crate::utils::etag::etag;
+use crate::utils::xattr;
+use locks::Lock;
+use xml::{MultiStatus, PropFind, PropName, PropUpdate};
+
+/// Where it's served, the hrefs and destinations are inside
+pub const MOUNT: &str = "/dav";
+
+pub const PROPFIND: &[u8] = b"PROPFIND";
+pub const PROPPATCH: &[u8] = b"PROPPATCH";
+pub const MKCOL: &[u8] = b"MKCOL";
+pub const COPY: &[u8] = b"COPY";
+pub const MOVE: &[u8] = b"MOVE";
+pub const LOCK: &[u8] = b"LOCK";
+pub const UNLOCK: &[u8] = b"UNLOCK";
+
+const METHODS: &str =
+    "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, PROPPATCH, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+
+const DAV: &str = "dav";
+const DEPTH: &str = "depth";
+const DESTINATION: &str = "destination";
+const OVERWRITE: &str = "overwrite";
+const IF: &str = "if";
+const LOCK_TOKEN: &str = "lock-token";
+const TIMEOUT: &str = "timeout";
+
+/// The largest XML body read
+const MAX_XML: u64 = 1024 * 1024;
+
+/// The dead properties set by PROPPATCH
+const PROPS_XATTR: &str = "user.sachima.props";
+
+/// The characters escaped in a segment of href
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+/// The live properties in the order of `allprop`
+const LIVE_PROPS: [&str; 9] = [
+    "resourcetype",
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getetag",
+    "getlastmodified",
+    "creationdate",
+    "supportedlock",
+    "lockdiscovery",
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DeadProp {
+    #[serde(flatten)]
+    name: PropName,
+    /// The inner XML
+    value: String,
+}
+
+/// What is known about the requesting client
+struct Client<'a> {
+    workspace: &'a Workspace,
+    user: Option<&'a User>,
+    /// Submitted in `If`
+    tokens: Vec<String>,
+}
+
+impl<'a> Client<'a> {
+    fn new(req: &Request, workspace: &'a Workspace, user: Option<&'a User>) -> Self {
+        Client {
+            workspace,
+            user,
+            tokens: header(req.headers(), IF).map_or_else(Vec::new, locks::tokens),
+        }
+    }
+
+    fn user(&self) -> Option<&str> {
+        self.user.map(|user| user.name.as_str())
+    }
+
+    fn href(&self, path: &Path, dir: bool) -> String {
+        let relative = path.strip_prefix(self.workspace.path()).unwrap_or(path);
+        let mut href = format!("{MOUNT}/");
+        for (i, name) in relative.iter().enumerate() {
+            if i > 0 {
+                href.push('/');
+            }
+            href.extend(utf8_percent_encode(&name.to_string_lossy(), SEGMENT));
+        }
+        if dir && !href.ends_with('/') {
+            href.push('/');
+        }
+
+        href
+    }
+
+    /// The path relative to the workspace root in `Destination`
+    fn destination(&self, headers: &HeaderMap) -> Result<PathBuf, ReplyError> {
+        let invalid = || ReplyError::InvalidHeader(DESTINATION.to_owned());
+
+        let uri: Uri = header(headers, DESTINATION)
+            .and_then(|dest| dest.parse().ok())
+            .ok_or_else(invalid)?;
+        let path = percent_decode_str(uri.path())
+            .decode_utf8()
+            .map_err(|_| invalid())?;
+
+        match path.strip_prefix(MOUNT) {
+            Some("") => Ok(PathBuf::new()),
+            Some(relative) => relative
+                .strip_prefix('/')
+                .map(PathBuf::from)
+                .ok_or_else(invalid),
+            None => Err(invalid()),
+        }
+    }
+
+    fn may_write(&self, path: &Path, deep: bool) -> bool {
+        locks::may_write(path, deep, &self.tokens, self.user())
+    }
+}
+
+fn header<'h>(headers: &'h HeaderMap, name: &str) -> Option<&'h str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// `Depth` is `0`, `1` or `infinity`, which is the default
+fn depth(headers: &HeaderMap) -> Result<Option<usize>, ReplyError> {
+    match header(headers, DEPTH).map(str::trim) {
+        None => Ok(None),
+        Some(depth) if depth.eq_ignore_ascii_case("infinity") => Ok(None),
+        Some("0") => Ok(Some(0)),
+        Some("1") => Ok(Some(1)),
+        Some(_) => Err(ReplyError::InvalidHeader(DEPTH.to_owned())),
+    }
+}
+
+fn locked() -> Response {
+    xml::error_response(StatusCode::LOCKED, "lock-token-submitted")
+}
+
+fn status(status: StatusCode) -> Response {
+    status.into_response()
+}
+
+/// The metadata following symlinks, `None` if it doesn't exist
+async fn metadata(path: &Path) -> Result<Option<std::fs::Metadata>, ReplyError> {
+    match fs::metadata(path).await {
+        Ok(md) => Ok(Some(md)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read the XML body up to `MAX_XML`
+async fn read_xml(body: Body) -> Result<String, ReplyError> {
+    let mut buf = Vec::new();
+    body.into_async_read()
+        .take(MAX_XML + 1)
+        .read_to_end(&mut buf)
+        .await?;
+    if buf.len() as u64 > MAX_XML {
+        return Err(ReplyError::ResourceTooLarge(ByteSize::b(MAX_XML)));
+    }
+
+    String::from_utf8(buf).map_err(|_| ReplyError::InvalidParam("body".to_owned()))
+}
+
+/// **Tell the WebDAV clients what is supported**
+#[handler]
+pub fn options() -> Response {
+    Response::builder()
+        .header(DAV, "1, 2")
+        .header(ALLOW, METHODS)
+        .header("ms-author-via", "DAV")
+        .finish()
+}
+
+/// **Read the properties of a resource and its children with `Depth: 1`**
+/// - Ok: 207 with the properties found, the missing ones are in a 404 `propstat`
+/// - Err:
+///   - resource doesn't exist => 404
+///   - `Depth: infinity` => 403
+///   - malformed body => 400
+///   - body is larger than `MAX_XML` => 413
+#[handler]
+pub async fn propfind(
+    req: &Request,
+    Data(workspace): Data<&Arc<Workspace>>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+    body: Body,
+) -> Result<Response, ReplyError> {
+    let client = Client::new(req, workspace, user.map(|Data(user)| user));
+    let path = workspace.join(&org);
+
+    let Some(depth) = depth(req.headers())? else {
+        return Ok(xml::error_response(
+            StatusCode::FORBIDDEN,
+            "propfind-finite-depth",
+        ));
+    };
+    let body = read_xml(body).await?;
+    let find = xml::propfind(&body).ok_or_else(|| ReplyError::InvalidParam("body".to_owned()))?;
+    let md = metadata(&path).await?.ok_or(ReplyError::NotFound)?;
+
+    let mut multi = MultiStatus::new();
+    describe(&client, &path, &md, &find, &mut multi).await;
+
+    if depth == 1 && md.is_dir() {
+        let mut children = Vec::new();
+        let mut dir = fs::read_dir(&path).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name();
+            if !atomic::is_temp(&name.to_string_lossy()) {
+                children.push(entry.path());
+            }
+        }
+        children.sort();
+
+        for child in children {
+            // broken symlinks
+            if let Some(md) = metadata(&child).await? {
+                describe(&client, &child, &md, &find, &mut multi).await;
+            }
+        }
+    }
+
+    Ok(multi.finish())
+}
+
+/// Add the properties of the resource to the response
+async fn describe(
+    client: &Client<'_>,
+    path: &Path,
+    md: &std::fs::Metadata,
+    find: &PropFind,
+    multi: &mut MultiStatus,
+) {
+    let href = client.href(path, md.is_dir());
+    let dead = dead_props(path).await;
+
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    match find {
+        PropFind::AllProp => {
+            for name in LIVE_PROPS {
+                if let Some(value) = live_prop(client, path, md, name) {
+                    found.push(PropName::dav(name).element(Some(&value)));
+                }
+            }
+            for prop in &dead {
+                found.push(prop.name.element(Some(&prop.value)));
+            }
+        }
+        PropFind::PropName => {
+            for name in LIVE_PROPS {
+                if live_prop(client, path, md, name).is_some() {
+                    found.push(PropName::dav(name).element(None));
+                }
+            }
+            for prop in &dead {
+                found.push(prop.name.element(None));
+            }
+        }
+        PropFind::Prop(names) => {
+            for name in names {
+                let value = if name.ns == xml::DAV_NS {
+                    live_prop(client, path, md, &name.name)
+                } else {
+                    None
+                };
+                let value = value.or_else(|| {
+                    dead.iter()
+                        .find(|prop| prop.name == *name)
+                        .map(|prop| prop.value.clone())
+                });
+
+                match value {
+                    Some(value) => found.push(name.element(Some(&value))),
+                    None => missing.push(name.element(None)),
+                }
+            }
+        }
+    }
+
+    multi.propstat(
+        &href,
+        &[(StatusCode::OK, found), (StatusCode::NOT_FOUND, missing)],
+    );
+}
+
+/// The inner XML of a live property, `None` if the resource doesn't have it
+fn live_prop(client: &Client, path: &Path, md: &std::fs::Metadata, name: &str) -> Option<String> {
+    let http_date = |time: SystemTime| httpdate::fmt_http_date(time);
+
+    match name {
+        "resourcetype" if md.is_dir() => Some("<D:collection/>".to_owned()),
+        "resourcetype" => Some(String::new()),
+        "displayname" => path
+            .file_name()
+            .map(|name| escape(&name.to_string_lossy()).into_owned()),
+        "getcontentlength" if md.is_file() => Some(md.len().to_string()),
+        "getcontenttype" if md.is_file() => Some(
+            mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string(),
+        ),
+        "getetag" if md.is_file() => Some(escape(&etag(md)).into_owned()),
+        "getlastmodified" => md.modified().ok().map(http_date),
+        "creationdate" => md
+            .created()
+            .ok()
+            .and_then(|time| OffsetDateTime::from(time).format(&Rfc3339).ok()),
+        "supportedlock" => Some(
+            ["exclusive", "shared"]
+                .map(|scope| {
+                    format!(
+                        "<D:lockentry><D:lockscope><D:{scope}/></D:lockscope>\
+                         <D:locktype><D:write/></D:locktype></D:lockentry>"
+                    )
+                })
+                .concat(),
+        ),
+        "lockdiscovery" => Some(
+            locks::discover(path)
+                .iter()
+                .map(|active| active_lock(client, active))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn active_lock(client: &Client, active: &Lock) -> String {
+    format!(
+        "<D:activelock><D:locktype><D:write/></D:locktype>\
+         <D:lockscope><D:{}/></D:lockscope><D:depth>{}</D:depth>{}\
+         <D:timeout>Second-{}</D:timeout>\
+         <D:locktoken><D:href>{}</D:href></D:locktoken>\
+         <D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+        if active.exclusive {
+            "exclusive"
+        } else {
+            "shared"
+        },
+        if active.infinite { "infinity" } else { "0" },
+        active
+            .owner
+            .as_ref()
+            .map(|owner| format!("<D:owner>{owner}</D:owner>"))
+            .unwrap_or_default(),
+        active.timeout.as_secs(),
+        active.token,
+        escape(&client.href(&active.path, active.path.is_dir())),
+    )
+}
+
+async fn dead_props(path: &Path) -> Vec<DeadProp> {
+    let path = path.to_owned();
+    let value = task::spawn_blocking(move || xattr::get(&path, PROPS_XATTR))
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .flatten();
+
+    value
+        .and_then(|value| serde_json::from_slice(&value).ok())
+        .unwrap_or_default()
+}
+
+/// **Set or remove the dead properties**
+/// They are kept in an extended attribute of the file,
+/// and all of them fail if any fails.
+///
+/// - Ok: 207 with the status of every property
+/// - Err:
+///   - resource doesn't exist => 404
+///   - resource is locked by others => 423
+///   - malformed body => 400
+///   - body is larger than `MAX_XML` => 413
+#[handler]
+pub async fn proppatch(
+    req: &Request,
+    Data(workspace): Data<&Arc<Workspace>>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+    body: Body,
+) -> Result<Response, ReplyError> {
+    let client = Client::new(req, workspace, user.map(|Data(user)| user));
+    let path = workspace.join(&org);
+
+    let body = read_xml(body).await?;
+    let updates =
+        xml::propertyupdate(&body).ok_or_else(|| ReplyError::InvalidParam("body".to_owned()))?;
+    let md = metadata(&path).await?.ok_or(ReplyError::NotFound)?;
+    if !client.may_write(&path, false) {
+        return Ok(locked());
+    }
+
+    let names: Vec<_> = updates
+        .iter()
+        .map(|update| match update {
+            PropUpdate::Set(name, _) | PropUpdate::Remove(name) => name.element(None),
+        })
+        .collect();
+    let protected = updates.iter().any(|update| match update {
+        PropUpdate::Set(name, _) | PropUpdate::Remove(name) => name.ns == xml::DAV_NS,
+    });
+
+    let mut multi = MultiStatus::new();
+    let href = client.href(&path, md.is_dir());
+    if protected {
+        let (live, dead): (Vec<_>, Vec<_>) =
+            names.into_iter().partition(|name| name.starts_with("<D:"));
+        multi.propstat(
+            &href,
+            &[
+                (StatusCode::FORBIDDEN, live),
+                (StatusCode::FAILED_DEPENDENCY, dead),
+            ],
+        );
+        return Ok(multi.finish());
+    }
+
+    let mut props = dead_props(&path).await;
+    for update in updates {
+        match update {
+            PropUpdate::Set(name, value) => match props.iter_mut().find(|prop| prop.name == name) {
+                Some(prop) => prop.value = value,
+                None => props.push(DeadProp { name, value }),
+            },
+            PropUpdate::Remove(name) => props.retain(|prop| prop.name != name),
+        }
+    }
+
+    let value = serde_json::to_vec(&props).map_err(|e| ReplyError::Internal(e.into()))?;
+    let saved = {
+        let path = path.clone();
+        task::spawn_blocking(move || xattr::set(&path, PROPS_XATTR, &value))
+            .await
+            .map_err(io::Error::other)?
+    };
+    let status = match saved {
+        Ok(()) => StatusCode::OK,
+        Err(e) => {
+            tracing::warn!(path = %path.display(), error = %e, "cannot save the properties");
+            StatusCode::INSUFFICIENT_STORAGE
+        }
+    };
+
+    multi.propstat(&href, &[(status, names)]);
+    Ok(multi.finish())
+}
+
+/// **Make a collection**
+/// - Ok: 201
+/// - Err:
+///   - parent directory doesn't exist => 409
+///   - path has existed => 405
+///   - request has a body => 415
+///   - parent directory is locked by others => 423
+#[handler]
+pub async fn mkcol(
+    req: &Request,
+    Data(workspace): Data<&Arc<Workspace>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+    body: Body,
+) -> Result<Response, ReplyError> {
+    let client = Client::new(req, workspace, user.map(|Data(user)| user));
+    let path = workspace.join(&org);
+
+    let mut head = Vec::new();
+    body.into_async_read()
+        .take(1)
+        .read_to_end(&mut head)
+        .await?;
+    if !head.is_empty() {
+        return Ok(status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+    }
+
+    if fs::try_exists(&path).await? {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    if !client.may_write(&path, false) {
+        return Ok(locked());
+    }
+
+    if !path.parent().unwrap().is_dir() {
+        return Ok(status(StatusCode::CONFLICT));
+    }
+
+    match fs::create_dir(&path).await {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Ok(status(StatusCode::METHOD_NOT_ALLOWED))
+        }
+        Err(e) => return Err(e.into()),
+    }
+    record(index, Change::Created(path)).await;
+
+    Ok(status(StatusCode::CREATED))
+}
+
+/// **Write the request body as the file**
+/// - Ok: 201 if it's created, 204 if it's replaced
+/// - Err:
+///   - parent directory doesn't exist => 409
+///   - path is a directory => 405
+///   - file is locked by others => 423
+///   - the same as `put_file` otherwise
+#[allow(clippy::too_many_arguments)]
+#[handler]
+pub async fn put(
+    req: &Request,
+    Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    Data(&MaxUpload(max_upload)): Data<&MaxUpload>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+    if_match: Option<TypedHeader<IfMatch>>,
+    body: Body,
+) -> Result<Response, ReplyError> {
+    let client = Client::new(req, workspace, user.map(|Data(user)| user));
+    let path = workspace.join(&org);
+    let parent = path.parent().unwrap();
+
+    if path == workspace.path() || path.is_dir() {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    if !client.may_write(&path, false) {
+        return Ok(locked());
+    }
+
+    if !parent.is_dir() {
+        return Ok(status(StatusCode::CONFLICT));
+    }
+
+    let mut verifier = Verifier::from_headers(req.headers())?;
+    let (file, size) = receive(parent, body, max_upload, &mut verifier).await?;
+    verifier.verify()?;
+
+    let replaced = fs::try_exists(&path).await?;
+    conflict::place(
+        file,
+        &path,
+        size,
+        OnConflict::Overwrite,
+        if_match.as_deref(),
+        quotas,
+    )
+    .await?;
+    let change = if replaced {
+        Change::Modified(path.clone())
+    } else {
+        Change::Created(path.clone())
+    };
+    record(index, change).await;
+
+    let etag = etag(&fs::metadata(&path).await?);
+    Ok(Response::builder()
+        .status(if replaced {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::CREATED
+        })
+        .header(ETAG, etag)
+        .finish())
+}
+
+/// **Remove a file or collection**
+/// - Ok: 204
+/// - Err:
+///   - workspace root => 403
+///   - resource doesn't exist => 404
+///   - resource or its subtree is locked by others => 423
+#[handler]
+pub async fn delete(
+    req: &Request,
+    Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+) -> Result<Response, ReplyError> {
+    let client = Client::new(req, workspace, user.map(|Data(user)| user));
+    let path = workspace.join(&org);
+
+    if path == workspace.path() {
+        return Err(ReplyError::WorkspaceRoot);
+    }
+
+    if fs::symlink_metadata(&path).await.is_err() {
+        return Err(ReplyError::NotFound);
+    }
+
+    if !client.may_write(&path, true) {
+        return Ok(locked());
+    }
+
+    remove_path(&path, quotas).await?;
+    locks::release(&path);
+    record(index, Change::Removed(path)).await;
+
+    Ok(status(StatusCode::NO_CONTENT))
+}
+
+/// **Copy a file or collection to `Destination`**
+/// A collection is copied with its subtree unless `Depth: 0`.
+///
+/// - Ok: 201 if the destination is created, 204 if it's replaced
+/// - Err:
+///   - the same as `move_to`
+#[allow(clippy::too_many_arguments)]
+#[handler]
+pub async fn copy(
+    req: &Request,
+    Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    ownership: Option<Data<&HomeOwnership>>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+) -> Result<Response, ReplyError> {
+    let ownership = ownership.map(|Data(ownership)| ownership);
+    let client = Client::new(req, workspace, user.map(|Data(user)| user));
+    transfer(req, &client, quotas, index, ownership, &org, false).await
+}
+
+/// **Move a file or collection to `Destination`**
+/// - Ok: 201 if the destination is created, 204 if it's replaced
+/// - Err:
+///   - workspace root, or moved into itself => 403
+///   - resource doesn't exist => 404
+///   - destination has existed with `Overwrite: F` => 412
+///   - parent directory of the destination doesn't exist => 409
+///   - destination outside the mount, or malformed headers => 400
+///   - either side is locked by others => 423
+#[allow(clippy::too_many_arguments)]
+#[handler]
+pub async fn move_to(
+    req: &Request,
+    Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    ownership: Option<Data<&HomeOwnership>>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+) -> Result<Response, ReplyError> {
+    let ownership = ownership.map(|Data(ownership)| ownership);
+    let client = Client::new(req, workspace, user.map(|Data(user)| user));
+    transfer(req, &client, quotas, index, ownership, &org, true).await
+}
+
+async fn transfer(
+    req: &Request,
+    client: &Client<'_>,
+    quotas: &Quotas,
+    index: &SearchIndex,
+    ownership: Option<&HomeOwnership>,
+    org: &Path,
+    moving: bool,
+) -> Result<Response, ReplyError> {
+    let workspace = client.workspace;
+    let src = workspace.join(org);
+
+    // the destination is checked like the path
+    let relative = client.destination(req.headers())?;
+    check_relative(&relative)?;
+    if let Some(ownership) = ownership {
+        check_owner(&relative, ownership, client.user)?;
+    }
+    let dest = workspace.join(&relative);
+
+    if src == workspace.path() || dest == workspace.path() {
+        return Err(ReplyError::WorkspaceRoot);
+    }
+
+    let md = fs::symlink_metadata(&src)
+        .await
+        .map_err(|_| ReplyError::NotFound)?;
+
+    if dest.starts_with(&src) {
+        return Err(ReplyError::IntoItself);
+    }
+
+    let overwrite = header(req.headers(), OVERWRITE).is_none_or(|value| value.trim() != "F");
+    let existed = fs::symlink_metadata(&dest).await.is_ok();
+    if existed && !overwrite {
+        return Ok(status(StatusCode::PRECONDITION_FAILED));
+    }
+
+    if (moving && !client.may_write(&src, true)) || !client.may_write(&dest, true) {
+        return Ok(locked());
+    }
+
+    if !dest.parent().unwrap().is_dir() {
+        return Ok(status(StatusCode::CONFLICT));
+    }
+
+    let shallow = !moving && md.is_dir() && depth(req.headers())? == Some(0);
+    // an existing destination is replaced only once the new one is in place beside it
+    let target = if existed {
+        atomic::temp_path(dest.parent().unwrap())
+    } else {
+        dest.clone()
+    };
+    if moving {
+        move_path(&src, &target, quotas).await?;
+    } else if shallow {
+        fs::create_dir(&target).await?;
+    } else {
+        copy_path(&src, &target, quotas).await?;
+    }
+
+    if existed {
+        if let Err(e) = replace(&target, &dest, quotas).await {
+            let undone = if moving {
+                move_path(&target, &src, quotas).await
+            } else {
+                remove_path(&target, quotas).await
+            };
+            if let Err(e) = undone {
+                tracing::warn!(path = %target.display(), error = %e, "cannot undo the transfer");
+            }
+            return Err(e);
+        }
+        locks::release(&dest);
+        record(index, Change::Removed(dest.clone())).await;
+    }
+
+    if moving {
+        locks::release(&src);
+        record(
+            index,
+            Change::Renamed {
+                from: src,
+                to: dest,
+            },
+        )
+        .await;
+    } else {
+        record(index, Change::Created(dest)).await;
+    }
+
+    Ok(status(if existed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    }))
+}
+
+/// Put the transferred `temp` in place of the existing `dest`,
+/// whose old content is removed after that succeeds.
+async fn replace(temp: &Path, dest: &Path, quotas: &Quotas) -> Result<(), ReplyError> {
+    let staged = atomic::temp_path(dest.parent().unwrap());
+    fs::rename(dest, &staged).await?;
+    if let Err(e) = fs::rename(temp, dest).await {
+        fs::rename(&staged, dest).await.ok();
+        return Err(e.into());
+    }
+
+    if let Err(e) = remove_path(&staged, quotas).await {
+        tracing::warn!(staged = %staged.display(), error = %e, "cannot remove the replaced entry");
+    }
+    Ok(())
+}
+
+/// **Lock a resource for writing, or refresh a lock without a body**
+/// An empty file is created if the resource doesn't exist.
+///
+/// - Ok: 200 with `lockdiscovery` and `Lock-Token`, 201 if the file is created
+/// - Err:
+///   - resource or its subtree is locked by others => 423
+///   - refreshing without the lock in `If` => 412
+///   - parent directory doesn't exist => 409
+///   - malformed body => 400
+///   - body is larger than `MAX_XML` => 413
+#[allow(clippy::too_many_arguments)]
+#[handler]
+pub async fn lock(
+    req: &Request,
+    Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+    body: Body,
+) -> Result<Response, ReplyError> {
+    let client = Client::new(req, workspace, user.map(|Data(user)| user));
+    let path = workspace.join(&org);
+    let timeout = locks::timeout(header(req.headers(), TIMEOUT));
+
+    let body = read_xml(body).await?;
+    if body.trim().is_empty() {
+        let Some(active) = locks::refresh(&path, &client.tokens, client.user(), timeout) else {
+            return Ok(xml::error_response(
+                StatusCode::PRECONDITION_FAILED,
+                "lock-token-matches-request-uri",
+            ));
+        };
+        let discovery = format!(
+            "<D:lockdiscovery>{}</D:lockdiscovery>",
+            active_lock(&client, &active)
+        );
+        return Ok(xml::prop_response(StatusCode::OK, &discovery));
+    }
+
+    let info = xml::lockinfo(&body).ok_or_else(|| ReplyError::InvalidParam("body".to_owned()))?;
+    let infinite = match depth(req.headers())? {
+        None => true,
+        Some(0) => false,
+        Some(_) => return Err(ReplyError::InvalidHeader(DEPTH.to_owned())),
+    };
+
+    let created = !fs::try_exists(&path).await?;
+    if created && !path.parent().unwrap().is_dir() {
+        return Ok(status(StatusCode::CONFLICT));
+    }
+
+    let Some(active) = locks::lock(
+        &path,
+        infinite,
+        info.exclusive,
+        info.owner,
+        client.user(),
+        timeout,
+    ) else {
+        return Ok(xml::error_response(
+            StatusCode::LOCKED,
+            "no-conflicting-lock",
+        ));
+    };
+
+    // a locked empty resource
+    if created {
+        let added = Usage { bytes: 0, files: 1 };
+        let res = match quotas.reserve(&path, added).await {
+            Ok(()) => fs::File::create(&path).await.map_err(|e| {
+                quotas.release(&path, added);
+                e.into()
+            }),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            locks::release(&path);
+            return Err(e);
+        }
+        record(index, Change::Created(path.clone())).await;
+    }
+
+    let discovery = format!(
+        "<D:lockdiscovery>{}</D:lockdiscovery>",
+        active_lock(&client, &active)
+    );
+    let mut resp = xml::prop_response(
+        if created {
+            StatusCode::CREATED
+        } else {
+            StatusCode::OK
+        },
+        &discovery,
+    );
+    if let Ok(token) = format!("<{}>", active.token).parse() {
+        resp.headers_mut().insert(LOCK_TOKEN, token);
+    }
+
+    Ok(resp)
+}
+
+/// **Remove the lock in `Lock-Token`**
+/// - Ok: 204
+/// - Err:
+///   - the lock doesn't cover the resource or isn't held => 409
+///   - missing `Lock-Token` => 400
+#[handler]
+pub async fn unlock(
+    req: &Request,
+    Data(workspace): Data<&Arc<Workspace>>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+) -> Result<Response, ReplyError> {
+    let client = Client::new(req, workspace, user.map(|Data(user)| user));
+    let path = workspace.join(&org);
+
+    let token = header(req.headers(), LOCK_TOKEN)
+        .map(|token| token.trim().trim_start_matches('<').trim_end_matches('>'))
+        .ok_or_else(|| ReplyError::InvalidHeader(LOCK_TOKEN.to_owned()))?;
+
+    if locks::unlock(&path, token, client.user()) {
+        Ok(status(StatusCode::NO_CONTENT))
+    } else {
+        Ok(xml::error_response(
+            StatusCode::CONFLICT,
+            "lock-token-matches-request-uri",
+        ))
+    }
+}
+
+/// The method names of WebDAV
+pub fn method(name: &'static [u8]) -> Method {
+    Method::from_bytes(name).unwrap()
+}
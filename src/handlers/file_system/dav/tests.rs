@@ -0,0 +1,307 @@
+use std::io;
+use std::sync::Arc;
+
+use bytesize::ByteSize;
+use poem::http::{Method, StatusCode};
+use poem::test::{TestClient, TestResponse};
+use poem::{Endpoint, EndpointExt, Route, RouteMethod};
+use tokio::fs;
+
+use super::method;
+use crate::config::Search;
+use crate::quota::Quotas;
+use crate::router::dav_error;
+use crate::search::SearchIndex;
+use crate::settings::MaxUpload;
+use crate::utils::tests::*;
+
+fn dav_app(wk: crate::config::Workspace) -> impl Endpoint {
+    let methods = RouteMethod::new()
+        .options(super::options)
+        .get(super::super::download)
+        .method(method(super::PROPFIND), super::propfind)
+        .method(method(super::PROPPATCH), super::proppatch)
+        .method(method(super::MKCOL), super::mkcol)
+        .put(super::put)
+        .delete(super::delete)
+        .method(method(super::COPY), super::copy)
+        .method(method(super::MOVE), super::move_to)
+        .method(method(super::LOCK), super::lock)
+        .method(method(super::UNLOCK), super::unlock);
+
+    Route::new()
+        .nest(super::MOUNT, Route::new().at("/*path", methods))
+        .catch_error(dav_error)
+        .data(Arc::new(SearchIndex::new(&wk, &Search::default())))
+        .data(Arc::new(wk))
+        .data(Arc::new(Quotas::default()))
+        .data(MaxUpload(ByteSize::mib(1)))
+}
+
+async fn text(resp: TestResponse) -> String {
+    resp.0.into_body().into_string().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_dav() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    let client = TestClient::new(dav_app(wk));
+    let dav = |name: &'static [u8], uri: &str| client.request(method(name), uri);
+
+    let resp = client.request(Method::OPTIONS, "/dav/").send().await;
+    resp.assert_status_is_ok();
+    resp.assert_header("dav", "1, 2");
+
+    dav(super::MKCOL, "/dav/docs")
+        .send()
+        .await
+        .assert_status(StatusCode::CREATED);
+    dav(super::MKCOL, "/dav/docs")
+        .send()
+        .await
+        .assert_status(StatusCode::METHOD_NOT_ALLOWED);
+    dav(super::MKCOL, "/dav/missing/docs")
+        .send()
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    client
+        .put("/dav/docs/a%20b.txt")
+        .body("hello")
+        .send()
+        .await
+        .assert_status(StatusCode::CREATED);
+    client
+        .put("/dav/docs/a%20b.txt")
+        .body("hello dav")
+        .send()
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    assert_eq!(
+        fs::read_to_string(root.join("docs/a b.txt")).await?,
+        "hello dav"
+    );
+    client
+        .get("/dav/docs/a%20b.txt")
+        .send()
+        .await
+        .assert_text("hello dav")
+        .await;
+
+    let resp = dav(super::PROPFIND, "/dav/docs")
+        .header("depth", "1")
+        .send()
+        .await;
+    resp.assert_status(StatusCode::MULTI_STATUS);
+    let body = text(resp).await;
+    assert!(body.contains("<D:href>/dav/docs/</D:href>"));
+    assert!(body.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+    assert!(body.contains("<D:href>/dav/docs/a%20b.txt</D:href>"));
+    assert!(body.contains("<D:getcontentlength>9</D:getcontentlength>"));
+    dav(super::PROPFIND, "/dav/docs")
+        .send()
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    // dead properties
+    let resp = dav(super::PROPPATCH, "/dav/docs/a%20b.txt")
+        .body(
+            r#"<D:propertyupdate xmlns:D="DAV:" xmlns:x="urn:x">
+              <D:set><D:prop><x:color><x:shade>red</x:shade></x:color></D:prop></D:set>
+            </D:propertyupdate>"#,
+        )
+        .send()
+        .await;
+    resp.assert_status(StatusCode::MULTI_STATUS);
+    let saved = text(resp).await.contains("HTTP/1.1 200 OK");
+    let resp = dav(super::PROPFIND, "/dav/docs/a%20b.txt")
+        .header("depth", "0")
+        .body(
+            r#"<propfind xmlns="DAV:" xmlns:x="urn:x">
+              <prop><x:color/><x:size/><getetag/></prop>
+            </propfind>"#,
+        )
+        .send()
+        .await;
+    let body = text(resp).await;
+    // unless the file system doesn't support the extended attributes
+    if saved {
+        assert!(
+            body.contains(r#"<P:color xmlns:P="urn:x"><shade xmlns="urn:x">red</shade></P:color>"#)
+        );
+    }
+    assert!(body.contains(r#"<P:size xmlns:P="urn:x"/>"#));
+    assert!(body.contains("HTTP/1.1 404 Not Found"));
+    assert!(body.contains("<D:getetag>"));
+    let resp = dav(super::PROPPATCH, "/dav/docs")
+        .body(
+            r#"<D:propertyupdate xmlns:D="DAV:">
+              <D:set><D:prop><D:getetag>x</D:getetag></D:prop></D:set>
+            </D:propertyupdate>"#,
+        )
+        .send()
+        .await;
+    assert!(text(resp).await.contains("HTTP/1.1 403 Forbidden"));
+
+    dav(super::COPY, "/dav/docs/a%20b.txt")
+        .header("destination", "http://localhost/dav/docs/c.txt")
+        .send()
+        .await
+        .assert_status(StatusCode::CREATED);
+    dav(super::MOVE, "/dav/docs/a%20b.txt")
+        .header("destination", "/dav/docs/c.txt")
+        .header("overwrite", "F")
+        .send()
+        .await
+        .assert_status(StatusCode::PRECONDITION_FAILED);
+    dav(super::MOVE, "/dav/docs/a%20b.txt")
+        .header("destination", "/dav/docs/c.txt")
+        .send()
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    assert!(!root.join("docs/a b.txt").exists());
+    dav(super::MOVE, "/dav/docs/c.txt")
+        .header("destination", "/dav/../c.txt")
+        .send()
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    dav(super::MOVE, "/dav/docs")
+        .header("destination", "/dav/docs/inner")
+        .send()
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    // locks
+    let resp = dav(super::LOCK, "/dav/docs/c.txt")
+        .body(
+            r#"<D:lockinfo xmlns:D="DAV:">
+              <D:lockscope><D:exclusive/></D:lockscope>
+              <D:locktype><D:write/></D:locktype>
+            </D:lockinfo>"#,
+        )
+        .send()
+        .await;
+    resp.assert_status_is_ok();
+    let token = resp.0.headers()["lock-token"].to_str().unwrap().to_owned();
+    assert!(text(resp)
+        .await
+        .contains("<D:lockroot><D:href>/dav/docs/c.txt"));
+
+    client
+        .put("/dav/docs/c.txt")
+        .body("stolen")
+        .send()
+        .await
+        .assert_status(StatusCode::LOCKED);
+    client
+        .delete("/dav/docs")
+        .send()
+        .await
+        .assert_status(StatusCode::LOCKED);
+    client
+        .put("/dav/docs/c.txt")
+        .header("if", format!("({token})"))
+        .body("mine")
+        .send()
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    dav(super::UNLOCK, "/dav/docs/c.txt")
+        .header("lock-token", &token)
+        .send()
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    dav(super::UNLOCK, "/dav/docs/c.txt")
+        .header("lock-token", &token)
+        .send()
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    client
+        .delete("/dav/docs")
+        .send()
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    dav(super::PROPFIND, "/dav/docs")
+        .header("depth", "0")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    client
+        .delete("/dav/")
+        .send()
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_overwrite() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    let client = TestClient::new(dav_app(wk));
+    let dav = |name: &'static [u8], uri: &str| client.request(method(name), uri);
+
+    fs::create_dir_all(root.join("old")).await?;
+    fs::write(root.join("old/stale.txt"), "stale").await?;
+    fs::create_dir_all(root.join("new")).await?;
+    fs::write(root.join("new/fresh.txt"), "fresh").await?;
+
+    dav(super::COPY, "/dav/new")
+        .header("destination", "/dav/old")
+        .send()
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    assert!(!root.join("old/stale.txt").exists());
+    assert_eq!(
+        fs::read_to_string(root.join("old/fresh.txt")).await?,
+        "fresh"
+    );
+
+    // into the destination containing it
+    dav(super::MOVE, "/dav/old/fresh.txt")
+        .header("destination", "/dav/old")
+        .send()
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    assert_eq!(fs::read_to_string(root.join("old")).await?, "fresh");
+
+    let mut names = Vec::new();
+    let mut dir = fs::read_dir(root).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        names.push(entry.file_name());
+    }
+    names.sort();
+    assert_eq!(names, ["new", "old"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_body_limit() {
+    let (_tmp_dir, wk) = setup_workspace();
+    let client = TestClient::new(dav_app(wk));
+    let dav = |name: &'static [u8], uri: &str| client.request(method(name), uri);
+
+    let huge = format!(
+        r#"<propfind xmlns="DAV:"><prop>{}</prop></propfind>"#,
+        "<getetag/>".repeat(super::MAX_XML as usize / 10)
+    );
+    dav(super::PROPFIND, "/dav/")
+        .header("depth", "0")
+        .body(huge.clone())
+        .send()
+        .await
+        .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    dav(super::LOCK, "/dav/a.txt")
+        .body(huge)
+        .send()
+        .await
+        .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    dav(super::MKCOL, "/dav/docs")
+        .body("<x/>")
+        .send()
+        .await
+        .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
@@ -0,0 +1,371 @@
+use std::fmt::Write;
+
+use poem::http::header::CONTENT_TYPE;
+use poem::http::StatusCode;
+use poem::{Body, Response};
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::name::ResolveResult;
+use quick_xml::NsReader;
+use serde::{Deserialize, Serialize};
+
+pub const DAV_NS: &str = "DAV:";
+
+const XML_TYPE: &str = "application/xml; charset=utf-8";
+
+/// A property named `{ns}name`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PropName {
+    pub ns: String,
+    pub name: String,
+}
+
+impl PropName {
+    pub fn dav(name: &str) -> Self {
+        PropName {
+            ns: DAV_NS.to_owned(),
+            name: name.to_owned(),
+        }
+    }
+
+    /// The element with the inner XML, empty if it's `None`
+    pub fn element(&self, inner: Option<&str>) -> String {
+        let (tag, xmlns) = if self.ns == DAV_NS {
+            (format!("D:{}", self.name), String::new())
+        } else if self.ns.is_empty() {
+            (self.name.clone(), r#" xmlns="""#.to_owned())
+        } else {
+            (
+                format!("P:{}", self.name),
+                format!(r#" xmlns:P="{}""#, escape(&self.ns)),
+            )
+        };
+
+        match inner {
+            Some(inner) if !inner.is_empty() => format!("<{tag}{xmlns}>{inner}</{tag}>"),
+            _ => format!("<{tag}{xmlns}/>"),
+        }
+    }
+}
+
+/// An element of the request body
+#[derive(Debug, Default)]
+pub struct Element {
+    pub ns: String,
+    pub name: String,
+    pub children: Vec<Element>,
+    /// The content between the tags,
+    /// whose elements declare their own namespaces to stand alone
+    pub inner: String,
+}
+
+impl Element {
+    pub fn is(&self, name: &str) -> bool {
+        self.ns == DAV_NS && self.name == name
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.is(name))
+    }
+
+    pub fn prop_name(&self) -> PropName {
+        PropName {
+            ns: self.ns.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+/// Parse the root element of the body
+pub fn parse(body: &str) -> Option<Element> {
+    let lossy = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+
+    let mut reader = NsReader::from_str(body);
+    // the body written again, where the prefixes are resolved
+    let mut out = String::new();
+    // (element, where its content starts in `out`)
+    let mut stack: Vec<(Element, usize)> = Vec::new();
+
+    loop {
+        let (ns, event) = reader.read_resolved_event().ok()?;
+        let ns = match ns {
+            ResolveResult::Bound(ns) => lossy(ns.as_ref()),
+            _ => String::new(),
+        };
+
+        let closed = match event {
+            Event::Start(ref start) | Event::Empty(ref start) => {
+                let element = Element {
+                    ns,
+                    name: lossy(start.local_name().as_ref()),
+                    ..Default::default()
+                };
+                write!(out, r#"<{} xmlns="{}""#, element.name, escape(&element.ns)).unwrap();
+                for (i, attr) in start.attributes().enumerate() {
+                    let attr = attr.ok()?;
+                    if attr.key.as_namespace_binding().is_some() {
+                        continue;
+                    }
+                    let value = lossy(&attr.value).replace('"', "&quot;");
+                    match reader.resolve_attribute(attr.key) {
+                        // `xml:` is bound without being declared
+                        (ResolveResult::Bound(ns), local)
+                            if attr.key.prefix().is_none_or(|p| p.as_ref() != b"xml") =>
+                        {
+                            let (ns, local) = (lossy(ns.as_ref()), lossy(local.as_ref()));
+                            write!(
+                                out,
+                                r#" xmlns:a{i}="{}" a{i}:{local}="{value}""#,
+                                escape(&ns)
+                            )
+                            .unwrap();
+                        }
+                        _ => write!(out, r#" {}="{value}""#, lossy(attr.key.as_ref())).unwrap(),
+                    }
+                }
+
+                if matches!(event, Event::Start(_)) {
+                    out.push('>');
+                    stack.push((element, out.len()));
+                    continue;
+                }
+                out.push_str("/>");
+                element
+            }
+            Event::End(_) => {
+                let (mut element, start) = stack.pop()?;
+                element.inner = out[start..].to_owned();
+                write!(out, "</{}>", element.name).unwrap();
+                element
+            }
+            Event::Text(text) => {
+                out.push_str(&lossy(&text));
+                continue;
+            }
+            Event::CData(data) => {
+                write!(out, "<![CDATA[{}]]>", lossy(&data)).unwrap();
+                continue;
+            }
+            Event::Eof => return None,
+            _ => continue,
+        };
+
+        match stack.last_mut() {
+            Some((parent, _)) => parent.children.push(closed),
+            None => return Some(closed),
+        }
+    }
+}
+
+/// What PROPFIND asks for
+#[derive(Debug, PartialEq, Eq)]
+pub enum PropFind {
+    AllProp,
+    PropName,
+    Prop(Vec<PropName>),
+}
+
+/// An empty body asks for all properties
+pub fn propfind(body: &str) -> Option<PropFind> {
+    if body.trim().is_empty() {
+        return Some(PropFind::AllProp);
+    }
+
+    let root = parse(body)?;
+    if !root.is("propfind") {
+        return None;
+    }
+
+    if let Some(prop) = root.child("prop") {
+        Some(PropFind::Prop(
+            prop.children.iter().map(Element::prop_name).collect(),
+        ))
+    } else if root.child("propname").is_some() {
+        Some(PropFind::PropName)
+    } else if root.child("allprop").is_some() {
+        Some(PropFind::AllProp)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PropUpdate {
+    /// The value is the inner XML
+    Set(PropName, String),
+    Remove(PropName),
+}
+
+/// The updates of PROPPATCH in order
+pub fn propertyupdate(body: &str) -> Option<Vec<PropUpdate>> {
+    let root = parse(body)?;
+    if !root.is("propertyupdate") {
+        return None;
+    }
+
+    let mut updates = Vec::new();
+    for op in &root.children {
+        let props = op.child("prop").map_or(&[][..], |prop| &prop.children);
+        for prop in props {
+            if op.is("set") {
+                updates.push(PropUpdate::Set(prop.prop_name(), prop.inner.clone()));
+            } else if op.is("remove") {
+                updates.push(PropUpdate::Remove(prop.prop_name()));
+            }
+        }
+    }
+
+    Some(updates)
+}
+
+/// What LOCK asks for
+#[derive(Debug, PartialEq, Eq)]
+pub struct LockInfo {
+    pub exclusive: bool,
+    /// The inner XML of `owner`
+    pub owner: Option<String>,
+}
+
+pub fn lockinfo(body: &str) -> Option<LockInfo> {
+    let root = parse(body)?;
+    if !root.is("lockinfo") {
+        return None;
+    }
+
+    let scope = root.child("lockscope")?;
+    Some(LockInfo {
+        exclusive: scope.child("exclusive").is_some(),
+        owner: root.child("owner").map(|owner| owner.inner.clone()),
+    })
+}
+
+/// The body of a 207 Multi-Status response
+#[derive(Debug)]
+pub struct MultiStatus(String);
+
+impl MultiStatus {
+    pub fn new() -> Self {
+        MultiStatus(
+            r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#.to_owned(),
+        )
+    }
+
+    /// The properties of a resource grouped by their status
+    pub fn propstat(&mut self, href: &str, groups: &[(StatusCode, Vec<String>)]) {
+        write!(self.0, "<D:response><D:href>{}</D:href>", escape(href)).unwrap();
+        for (status, props) in groups.iter().filter(|(_, props)| !props.is_empty()) {
+            self.0.push_str("<D:propstat><D:prop>");
+            props.iter().for_each(|prop| self.0.push_str(prop));
+            write!(self.0, "</D:prop>{}</D:propstat>", status_line(*status)).unwrap();
+        }
+        self.0.push_str("</D:response>");
+    }
+
+    pub fn finish(mut self) -> Response {
+        self.0.push_str("</D:multistatus>");
+        xml_response(StatusCode::MULTI_STATUS, self.0)
+    }
+}
+
+fn status_line(status: StatusCode) -> String {
+    format!(
+        "<D:status>HTTP/1.1 {} {}</D:status>",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )
+}
+
+/// The body of a single `prop` element
+pub fn prop_response(status: StatusCode, props: &str) -> Response {
+    xml_response(
+        status,
+        format!(r#"<?xml version="1.0" encoding="utf-8"?><D:prop xmlns:D="DAV:">{props}</D:prop>"#),
+    )
+}
+
+/// The body of an `error` with the precondition
+pub fn error_response(status: StatusCode, condition: &str) -> Response {
+    xml_response(
+        status,
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><D:error xmlns:D="DAV:"><D:{condition}/></D:error>"#
+        ),
+    )
+}
+
+fn xml_response(status: StatusCode, body: String) -> Response {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, XML_TYPE)
+        .body(Body::from_string(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lockinfo, propertyupdate, propfind, LockInfo, PropFind, PropName, PropUpdate};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(propfind(""), Some(PropFind::AllProp));
+        assert_eq!(
+            propfind(
+                r#"<?xml version="1.0"?>
+                <propfind xmlns="DAV:" xmlns:x="urn:x">
+                  <prop><getetag/><x:color/></prop>
+                </propfind>"#
+            ),
+            Some(PropFind::Prop(vec![
+                PropName::dav("getetag"),
+                PropName {
+                    ns: "urn:x".to_owned(),
+                    name: "color".to_owned()
+                },
+            ]))
+        );
+        assert_eq!(
+            propfind(r#"<D:propfind xmlns:D="DAV:"><D:propname/></D:propfind>"#),
+            Some(PropFind::PropName)
+        );
+        assert_eq!(propfind("<propfind>"), None);
+
+        let color = PropName {
+            ns: "urn:x".to_owned(),
+            name: "color".to_owned(),
+        };
+        assert_eq!(
+            propertyupdate(
+                r#"<D:propertyupdate xmlns:D="DAV:" xmlns:x="urn:x">
+                  <D:set><D:prop><x:color>red &amp; <b>blue</b><x:shade D:by="me"/></x:color></D:prop></D:set>
+                  <D:remove><D:prop><x:color/></D:prop></D:remove>
+                </D:propertyupdate>"#
+            ),
+            Some(vec![
+                PropUpdate::Set(
+                    color.clone(),
+                    r#"red &amp; <b xmlns="">blue</b><shade xmlns="urn:x" xmlns:a0="DAV:" a0:by="me"/>"#
+                        .to_owned()
+                ),
+                PropUpdate::Remove(color.clone()),
+            ])
+        );
+        assert_eq!(
+            color.element(Some("red")),
+            r#"<P:color xmlns:P="urn:x">red</P:color>"#
+        );
+        assert_eq!(PropName::dav("getetag").element(None), "<D:getetag/>");
+
+        assert_eq!(
+            lockinfo(
+                r#"<D:lockinfo xmlns:D="DAV:">
+                  <D:lockscope><D:exclusive/></D:lockscope>
+                  <D:locktype><D:write/></D:locktype>
+                  <D:owner><D:href>mailto:alice</D:href></D:owner>
+                </D:lockinfo>"#
+            ),
+            Some(LockInfo {
+                exclusive: true,
+                owner: Some(r#"<href xmlns="DAV:">mailto:alice</href>"#.to_owned()),
+            })
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use md5::Md5;
+use poem::http::HeaderMap;
+use sha2::digest::DynDigest;
+use sha2::{Sha256, Sha512};
+
+use crate::reply::ReplyError;
+
+const CONTENT_MD5: &str = "content-md5";
+
+/// Verify the content by `Content-MD5` and `Digest`,
+/// the unknown algorithms in `Digest` are ignored.
+#[derive(Default)]
+pub struct Verifier {
+    digests: Vec<(Box<dyn DynDigest + Send>, Vec<u8>)>,
+}
+
+impl Verifier {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, ReplyError> {
+        let mut digests = Vec::new();
+
+        if let Some(value) = headers.get(CONTENT_MD5) {
+            let expected = value
+                .to_str()
+                .ok()
+                .and_then(|value| BASE64.decode(value.trim()).ok())
+                .ok_or_else(|| ReplyError::InvalidHeader(CONTENT_MD5.to_owned()))?;
+            digests.push((new_digest("md5").unwrap(), expected));
+        }
+
+        if let Some(value) = headers.get("digest") {
+            let value = value
+                .to_str()
+                .map_err(|_| ReplyError::InvalidHeader("digest".to_owned()))?;
+
+            // e.g. `sha-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=, md5=...`
+            for item in value.split(',') {
+                let Some((algo, encoded)) = item.trim().split_once('=') else {
+                    return Err(ReplyError::InvalidHeader("digest".to_owned()));
+                };
+                let Some(digest) = new_digest(&algo.to_ascii_lowercase()) else {
+                    continue;
+                };
+                let expected = BASE64
+                    .decode(encoded)
+                    .map_err(|_| ReplyError::InvalidHeader("digest".to_owned()))?;
+                digests.push((digest, expected));
+            }
+        }
+
+        Ok(Self { digests })
+    }
+
+    /// Expect the digest told by other means, like the signature of S3
+    pub fn expect(&mut self, algo: &str, expected: Vec<u8>) {
+        if let Some(digest) = new_digest(algo) {
+            self.digests.push((digest, expected));
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for (digest, _) in &mut self.digests {
+            digest.update(data);
+        }
+    }
+
+    pub fn verify(self) -> Result<(), ReplyError> {
+        for (digest, expected) in self.digests {
+            if *digest.finalize() != *expected {
+                return Err(ReplyError::DigestMismatch);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn new_digest(algo: &str) -> Option<Box<dyn DynDigest + Send>> {
+    Some(match algo {
+        "md5" => Box::<Md5>::default(),
+        "sha-256" => Box::<Sha256>::default(),
+        "sha-512" => Box::<Sha512>::default(),
+        _ => return None,
+    })
+}
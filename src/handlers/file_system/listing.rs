@@ -0,0 +1,268 @@
+use std::cmp::Ordering;
+use std::fs::{FileType, Metadata};
+use std::time::UNIX_EPOCH;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine;
+use glob::Pattern;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::reply::ReplyError;
+use crate::utils::natural::natural_cmp;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Where an entry is placed in the listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key {
+    dir: bool,
+    /// The size or modified time in nanoseconds
+    value: u128,
+    name: String,
+}
+
+/// The directories come first in either direction,
+/// the names break the ties naturally.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Order {
+    pub sort: Sort,
+    pub direction: Direction,
+}
+
+impl Order {
+    /// The size of a directory is its recursive total if it's known
+    pub fn key(&self, name: &str, md: &Metadata, dir_bytes: Option<u64>) -> Key {
+        let value = match self.sort {
+            Sort::Name => 0,
+            Sort::Size if md.is_dir() => dir_bytes.unwrap_or_default().into(),
+            Sort::Size => md.len().into(),
+            Sort::Modified => md
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_nanos()),
+        };
+
+        Key {
+            dir: md.is_dir(),
+            value,
+            name: name.to_owned(),
+        }
+    }
+
+    pub fn cmp(&self, a: &Key, b: &Key) -> Ordering {
+        b.dir.cmp(&a.dir).then_with(|| {
+            let ordering = a
+                .value
+                .cmp(&b.value)
+                .then_with(|| natural_cmp(&a.name, &b.name))
+                .then_with(|| a.name.cmp(&b.name));
+
+            match self.direction {
+                Direction::Asc => ordering,
+                Direction::Desc => ordering.reverse(),
+            }
+        })
+    }
+
+    /// An opaque cursor after the entry,
+    /// which is only valid for the same order.
+    pub fn cursor(&self, key: &Key) -> String {
+        BASE64.encode(format!(
+            "{}:{}:{}:{}/{}",
+            self.tag(),
+            key.dir as u8,
+            key.value,
+            key.name.len(),
+            key.name
+        ))
+    }
+
+    pub fn parse_cursor(&self, cursor: &str) -> Result<Key, ReplyError> {
+        let invalid = || ReplyError::InvalidParam("cursor".to_owned());
+
+        let decoded = BASE64
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+        let (head, name) = decoded.split_once('/').ok_or_else(invalid)?;
+
+        match head.split(':').collect::<Vec<_>>()[..] {
+            [tag, dir, value, len] if tag == self.tag() && len.parse() == Ok(name.len()) => {
+                Ok(Key {
+                    dir: dir == "1",
+                    value: value.parse().map_err(|_| invalid())?,
+                    name: name.to_owned(),
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    fn tag(&self) -> String {
+        let sort = match self.sort {
+            Sort::Name => 'n',
+            Sort::Size => 's',
+            Sort::Modified => 'm',
+        };
+        let direction = match self.direction {
+            Direction::Asc => 'a',
+            Direction::Desc => 'd',
+        };
+
+        format!("{sort}{direction}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Dir,
+    File,
+    Symlink,
+}
+
+impl From<FileType> for Kind {
+    fn from(file_type: FileType) -> Self {
+        if file_type.is_dir() {
+            Kind::Dir
+        } else if file_type.is_symlink() {
+            Kind::Symlink
+        } else {
+            Kind::File
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NameFilter {
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+impl NameFilter {
+    pub fn new(filter: &str, regex: bool) -> Result<Self, ReplyError> {
+        let invalid = || ReplyError::InvalidParam("filter".to_owned());
+
+        Ok(if regex {
+            Self::Regex(Regex::new(filter).map_err(|_| invalid())?)
+        } else {
+            Self::Glob(Pattern::new(filter).map_err(|_| invalid())?)
+        })
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NameFilter::Glob(pattern) => pattern.matches(name),
+            NameFilter::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Which entries are listed, decided by the names and types only
+#[derive(Debug)]
+pub struct Filter {
+    pub name: Option<NameFilter>,
+    pub kind: Option<Kind>,
+    /// List the entries whose names start with `.`
+    pub hidden: bool,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            name: None,
+            kind: None,
+            hidden: true,
+        }
+    }
+}
+
+impl Filter {
+    pub fn passes(&self, name: &str, kind: Kind) -> bool {
+        (self.hidden || !name.starts_with('.'))
+            && self.kind.is_none_or(|expected| expected == kind)
+            && self.name.as_ref().is_none_or(|filter| filter.matches(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Direction, Key, Order, Sort};
+    use std::cmp::Ordering;
+
+    #[test]
+    fn test_order() {
+        let key = |dir, value, name: &str| Key {
+            dir,
+            value,
+            name: name.to_owned(),
+        };
+        let order = Order {
+            sort: Sort::Size,
+            direction: Direction::Desc,
+        };
+
+        // the directories come first
+        assert_eq!(
+            order.cmp(&key(true, 0, "z"), &key(false, 9, "a")),
+            Ordering::Less
+        );
+        assert_eq!(
+            order.cmp(&key(false, 9, "b"), &key(false, 1, "a")),
+            Ordering::Less
+        );
+
+        let after = key(false, 42, "a/b: 10.txt");
+        let cursor = order.cursor(&after);
+        assert_eq!(order.parse_cursor(&cursor).unwrap(), after);
+        // another order
+        assert!(Order::default().parse_cursor(&cursor).is_err());
+        assert!(order.parse_cursor("garbage").is_err());
+    }
+
+    /// By default the directories come first, then the names in either kind
+    #[test]
+    fn test_kind_then_name() {
+        let key = |dir, name: &str| Key {
+            dir,
+            value: 0,
+            name: name.to_owned(),
+        };
+        let order = Order::default();
+
+        let mut keys = vec![
+            key(false, "b.txt"),
+            key(true, "z"),
+            key(false, "a.txt"),
+            key(true, "c"),
+        ];
+        keys.sort_by(|a, b| order.cmp(a, b));
+        assert_eq!(
+            keys,
+            [
+                key(true, "c"),
+                key(true, "z"),
+                key(false, "a.txt"),
+                key(false, "b.txt"),
+            ]
+        );
+    }
+}
@@ -1,43 +1,135 @@
 #[cfg(test)]
 mod tests;
 
-use std::path::PathBuf;
+pub mod batch;
+mod conflict;
+pub mod dav;
+mod digest;
+mod listing;
+pub mod s3;
+pub mod search;
+mod tree;
+pub mod watch;
+
+use std::any::type_name;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Component, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 
 use bytesize::ByteSize;
+use fs_set_times::SystemTimeSpec;
+use futures_util::StreamExt;
+use poem::error::GetDataError;
 use poem::handler;
-use poem::http::header::{CONTENT_DISPOSITION, CONTENT_LENGTH};
+use poem::http::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG};
+use poem::http::HeaderMap;
+use poem::web::headers::{IfMatch, LastModified};
 use poem::web::Data;
-use poem::web::Multipart;
 use poem::web::Path;
 use poem::web::Query;
+use poem::web::TypedHeader;
+use poem::web::{Field, Multipart};
 use poem::Body;
 use poem::IntoResponse;
 use poem::Request;
+use poem::Response;
+use poem::ResponseBuilder;
 use serde::Deserialize;
 use tokio::fs;
-use tokio::fs::{File, OpenOptions};
+use tokio::fs::File;
 use tokio::io::BufReader;
-use tokio::io::{AsyncWriteExt, BufWriter};
-use tokio_stream::wrappers::ReadDirStream;
-use tokio_stream::{Stream, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::task;
 
+use crate::checksum::{cached, checksums, digest_headers, hex, Algo};
 use crate::config::Workspace;
-use crate::models::fs::{Directory, FsEntry};
+use crate::events::{self, Change};
+use crate::home;
+use crate::models::fs::{Checksum, Directory, FsEntry, Stat, Uploaded};
+use crate::models::permission::User;
+use crate::quota;
+use crate::quota::{Quotas, Usage};
 use crate::reply::ReplyData;
-use crate::reply::ReplyError;
+use crate::reply::{ApiVersion, ReplyError};
+use crate::search::SearchIndex;
+use crate::settings::{HomeOwnership, MaxUpload};
+use crate::utils::atomic::AtomicFile;
+use crate::utils::etag::etag;
+use crate::utils::time::TimeFormat;
+use conflict::OnConflict;
+use digest::Verifier;
+use listing::{Direction, Filter, Kind, NameFilter, Order, Sort};
+use tree::Lister;
+
+const DIGEST: &str = "digest";
+const REPR_DIGEST: &str = "repr-digest";
+/// Set the modification time of the put file, in unix seconds
+const X_MTIME: &str = "x-mtime";
+
+/// The entries in a page of directory if `limit` is absent,
+/// v1 lists the whole directory unless `limit` or `cursor` is given
+const DEFAULT_LIMIT: usize = 1_000;
+
+/// The most entries in a page of directory
+const MAX_LIMIT: usize = 10_000;
 
 /// Limit operations to the workspace
 pub async fn ensure_relative(req: Request) -> poem::Result<Request> {
     let path: PathBuf = req.path_params()?;
+    check_relative(&path)?;
 
+    Ok(req)
+}
+
+fn check_relative(path: &std::path::Path) -> Result<(), ReplyError> {
     if path.is_absolute() {
-        return Err(ReplyError::IsAbsolute.into());
+        return Err(ReplyError::IsAbsolute);
+    }
+
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(ReplyError::OutsideWorkspace);
+    }
+
+    Ok(())
+}
+
+/// Keep the home directories private to their owners and the admins
+pub async fn ensure_owner(req: Request) -> poem::Result<Request> {
+    let Some(ownership) = req.data::<HomeOwnership>() else {
+        return Ok(req);
+    };
+
+    let path: PathBuf = req.path_params()?;
+    // renaming into a home directory is writing it
+    let dest = req
+        .params::<RenameParam>()
+        .ok()
+        .map(|RenameParam { name }| path.with_file_name(name));
+
+    for path in [Some(&path), dest.as_ref()].into_iter().flatten() {
+        check_owner(path, ownership, req.data::<User>())?;
     }
 
     Ok(req)
 }
 
+fn check_owner(
+    path: &std::path::Path,
+    HomeOwnership { admins }: &HomeOwnership,
+    user: Option<&User>,
+) -> Result<(), ReplyError> {
+    let Some(owner) = home::owner(path) else {
+        return Ok(());
+    };
+
+    match user {
+        Some(user) if user.name == owner || admins.contains(&user.name) => Ok(()),
+        _ => Err(ReplyError::PermissionDenied),
+    }
+}
+
 /// Protect the workspace root
 pub async fn ensure_not_root(req: Request) -> poem::Result<Request> {
     let path: String = req.path_params()?;
@@ -50,7 +142,7 @@ pub async fn ensure_not_root(req: Request) -> poem::Result<Request> {
 }
 
 /// **Download a file**
-/// Because the mime of response is "application/octet-stream",
+/// Because the response is the file itself rather than JSON,
 /// so it uses the HTTP status code to handler errors.
 ///
 /// - Ok: return the bytes of file
@@ -78,67 +170,468 @@ pub async fn download(
     }
 
     let fd = File::open(&path).await?;
-    let filename = path.file_name().and_then(|s| s.to_str()).unwrap();
+    let md = fd.metadata().await?;
 
-    Ok(Body::from_async_read(BufReader::new(fd)).with_header(
-        CONTENT_DISPOSITION,
-        format!(r#"attachment; filename="{filename}""#),
-    ))
+    // of the opened file, even if the path is replaced meanwhile
+    Ok(file_headers(&path, &md)
+        .await?
+        .body(Body::from_async_read(BufReader::new(fd))))
 }
 
-pub fn limit_size(req: Request, max_upload: ByteSize) -> poem::Result<Request> {
-    if ByteSize(
-        req.header(CONTENT_LENGTH)
-            .and_then(|s| s.parse().ok())
-            .expect("content-length error"),
-    ) > max_upload
-    {
-        return Err(ReplyError::ResourceTooLarge(max_upload).into());
+/// **Get the headers of a file download without the content**
+/// - Ok: the same headers as `download`
+/// - Err: the same as `download`
+#[handler]
+pub async fn head_file(
+    Data(workspace): Data<&Arc<Workspace>>,
+    Path(path): Path<PathBuf>,
+) -> Result<Response, ReplyError> {
+    let path = workspace.join(path);
+
+    if path == workspace.path() {
+        return Err(ReplyError::WorkspaceRoot);
+    }
+
+    let md = match fs::metadata(&path).await {
+        Ok(md) => md,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(ReplyError::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+
+    if md.is_dir() {
+        return Err(ReplyError::IsADirectory);
+    }
+
+    Ok(file_headers(&path, &md).await?.finish())
+}
+
+/// The headers of a file download, which HEAD replies as well
+async fn file_headers(path: &std::path::Path, md: &Metadata) -> io::Result<ResponseBuilder> {
+    let filename = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+
+    let builder = Response::builder()
+        .header(CONTENT_LENGTH, md.len())
+        .header(CONTENT_TYPE, mime.as_ref())
+        .header(
+            CONTENT_DISPOSITION,
+            format!(r#"attachment; filename="{filename}""#),
+        )
+        .header(ETAG, etag(md))
+        .typed_header(LastModified::from(md.modified()?));
+
+    // only the cached digests, the file isn't hashed for a download
+    Ok(match digest_headers(&cached(path, md).await) {
+        Some((digest, repr_digest)) => builder
+            .header(DIGEST, digest)
+            .header(REPR_DIGEST, repr_digest),
+        None => builder,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChecksumParam {
+    algo: Algo,
+}
+
+/// **Get the checksum of a file**
+/// It's computed by streaming the file and cached until the file changes,
+/// then the downloads carry it in `Digest` and `Repr-Digest`.
+///
+/// - Ok: return the digest in lowercase hex
+/// - Err:
+///   - workspace root => ReplyError::WorkspaceRoot
+///   - file doesn't exist => ReplyError::NotFound
+///   - it's a directory => ReplyError::IsADirectory
+#[handler]
+pub async fn checksum(
+    Data(workspace): Data<&Arc<Workspace>>,
+    Path(path): Path<PathBuf>,
+    Query(ChecksumParam { algo }): Query<ChecksumParam>,
+) -> Result<ReplyData<Checksum>, ReplyError> {
+    let path = workspace.join(path);
+
+    if path == workspace.path() {
+        return Err(ReplyError::WorkspaceRoot);
+    }
+
+    let md = match fs::metadata(&path).await {
+        Ok(md) => md,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(ReplyError::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+
+    if md.is_dir() {
+        return Err(ReplyError::IsADirectory);
+    }
+
+    let digests = checksums(&path, vec![algo]).await?;
+
+    Ok(ReplyData(Checksum {
+        algo: algo.name(),
+        digest: digests
+            .first()
+            .map(|(_, digest)| hex(digest))
+            .unwrap_or_default(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StatParam {
+    /// Comma-separated algorithms like `md5,sha-256`
+    checksum: Option<String>,
+    #[serde(default)]
+    time_format: TimeFormat,
+}
+
+/// **Get the metadata of a file or directory**
+/// - Ok: return the entry with its ETag and the requested checksums of a file
+/// - Err:
+///   - workspace root => ReplyError::WorkspaceRoot
+///   - file doesn't exist => ReplyError::NotFound
+///   - checksums of a directory => ReplyError::IsADirectory
+///   - unknown checksum algorithm => ReplyError::InvalidParam
+#[handler]
+pub async fn stat(
+    Data(workspace): Data<&Arc<Workspace>>,
+    Path(path): Path<PathBuf>,
+    Query(StatParam {
+        checksum: algos,
+        time_format,
+    }): Query<StatParam>,
+) -> Result<ReplyData<Stat>, ReplyError> {
+    let path = workspace.join(path);
+
+    if path == workspace.path() {
+        return Err(ReplyError::WorkspaceRoot);
+    }
+
+    // symlinks aren't followed, like `read_dir`
+    let md = match fs::symlink_metadata(&path).await {
+        Ok(md) => md,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(ReplyError::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+
+    let checksums = match algos {
+        Some(_) if md.is_dir() => return Err(ReplyError::IsADirectory),
+        Some(algos) if md.is_file() => {
+            let requested = algos
+                .split(',')
+                .map(|name| {
+                    let name = name.trim().to_ascii_lowercase();
+                    name.parse::<Algo>()
+                        .map(|algo| (name, algo))
+                        .map_err(|_| ReplyError::InvalidParam("checksum".to_owned()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let algos = requested.iter().map(|&(_, algo)| algo).collect();
+            let digests = checksums(&path, algos).await?;
+
+            // keyed by the names requested
+            requested
+                .into_iter()
+                .zip(digests)
+                .map(|((name, _), (_, digest))| (name, hex(&digest)))
+                .collect()
+        }
+        _ => Default::default(),
+    };
+
+    Ok(ReplyData(Stat {
+        entry: FsEntry::new(&path, &md, time_format).await?,
+        etag: etag(&md),
+        checksums,
+    }))
+}
+
+/// Reject an upload early by its `Content-Length`; a body without one, e.g. chunked,
+/// is checked against the limit while `upload` reads it
+pub async fn limit_size(req: Request) -> poem::Result<Request> {
+    let MaxUpload(max_upload) = *req
+        .data()
+        .ok_or_else(|| GetDataError(type_name::<MaxUpload>()))?;
+
+    if let Some(length) = req.header(CONTENT_LENGTH) {
+        let length = length
+            .parse()
+            .map_err(|_| ReplyError::InvalidHeader(CONTENT_LENGTH.to_string()))?;
+        if ByteSize(length) > max_upload {
+            return Err(ReplyError::ResourceTooLarge(max_upload).into());
+        }
     }
 
     Ok(req)
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UploadParam {
+    #[serde(default)]
+    on_conflict: OnConflict,
+    /// Create the missing parent directories
+    #[serde(default)]
+    parents: bool,
+}
+
 /// **Upload a file to the parent directory**
-/// - Ok
+/// - Ok: return the final name, which differs if renamed for a conflict
 /// - Err:
-///   - parent directory doesn't exist => ReplyError::MissingParent
+///   - parent directory doesn't exist without `parents` => ReplyError::MissingParent
+///   - parent path has a file => ReplyError::NotADirectory
 ///   - file has already existed => ReplyError::AlreadyExists
 ///   - multipart has no file => ReplyError::FileExpected
 ///   - file field has no file name => ReplyError::MissingFileName
+///   - file name contains a path => ReplyError::OutsideWorkspace
+///   - file exceeds a storage quota => ReplyError::QuotaExceeded
+///   - `If-Match` doesn't match the existing file => ReplyError::PreconditionFailed
+///   - file is larger than the upload limit => ReplyError::ResourceTooLarge
+#[allow(clippy::too_many_arguments)]
 #[handler]
 pub async fn upload(
     Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    Data(&MaxUpload(max_upload)): Data<&MaxUpload>,
     Path(parent): Path<PathBuf>,
+    Query(UploadParam {
+        on_conflict,
+        parents,
+    }): Query<UploadParam>,
+    if_match: Option<TypedHeader<IfMatch>>,
     mut mltp: Multipart,
-) -> Result<ReplyData<()>, ReplyError> {
+) -> Result<ReplyData<Uploaded>, ReplyError> {
     let parent = workspace.join(parent);
 
-    if !fs::try_exists(&parent).await? {
-        return Err(ReplyError::MissingParent);
+    let Some(field) = mltp.next_field().await? else {
+        return Err(ReplyError::FileExpected);
+    };
+
+    let name = field.file_name().ok_or(ReplyError::MissingFileName)?;
+    if !is_file_name(name) {
+        return Err(ReplyError::OutsideWorkspace);
     }
 
-    let Some(file) = mltp.next_field().await? else {
-        return Err(ReplyError::FileExpected);
+    let created = ensure_parent(workspace, &parent, parents).await?;
+
+    let path = parent.join(name);
+    let placed = async {
+        if let Some(skipped) =
+            conflict::settle_early(&path, on_conflict, if_match.as_deref()).await?
+        {
+            return Ok((skipped, false));
+        }
+        let (file, size) = receive_field(&parent, field, max_upload).await?;
+
+        let replaced = on_conflict == OnConflict::Overwrite && fs::try_exists(&path).await?;
+
+        let uploaded =
+            conflict::place(file, &path, size, on_conflict, if_match.as_deref(), quotas).await?;
+        Ok::<_, ReplyError>((uploaded, replaced))
+    }
+    .await;
+
+    let (uploaded, replaced) = match placed {
+        Ok(placed) => placed,
+        Err(e) => {
+            remove_created(workspace, &created).await;
+            return Err(e);
+        }
+    };
+    let uploaded = Uploaded {
+        created,
+        ..uploaded
     };
+    record_upload(index, workspace, &parent, &uploaded, replaced).await;
 
-    let path = parent.join(file.file_name().ok_or(ReplyError::MissingFileName)?);
-    if fs::try_exists(&path).await? {
-        return Err(ReplyError::AlreadyExists);
+    Ok(ReplyData(uploaded))
+}
+
+/// **Upload the request body as the file at the path**
+/// - Ok: return the final name, which differs if renamed for a conflict
+/// - Err:
+///   - parent directory doesn't exist without `parents` => ReplyError::MissingParent
+///   - parent path has a file => ReplyError::NotADirectory
+///   - file has already existed => ReplyError::AlreadyExists
+///   - body is larger than the upload limit => ReplyError::ResourceTooLarge
+///   - body doesn't match `Content-MD5` or `Digest` => ReplyError::DigestMismatch
+///   - malformed `Content-MD5`, `Digest` or `X-Mtime` => ReplyError::InvalidHeader
+///   - file exceeds a storage quota => ReplyError::QuotaExceeded
+///   - `If-Match` doesn't match the existing file => ReplyError::PreconditionFailed
+#[allow(clippy::too_many_arguments)]
+#[handler]
+pub async fn put_file(
+    Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    Data(&MaxUpload(max_upload)): Data<&MaxUpload>,
+    Path(path): Path<PathBuf>,
+    Query(UploadParam {
+        on_conflict,
+        parents,
+    }): Query<UploadParam>,
+    if_match: Option<TypedHeader<IfMatch>>,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<ReplyData<Uploaded>, ReplyError> {
+    let path = workspace.join(path);
+    let parent = path.parent().unwrap();
+
+    let mut verifier = Verifier::from_headers(headers)?;
+    let mtime = headers
+        .get(X_MTIME)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                .ok_or_else(|| ReplyError::InvalidHeader(X_MTIME.to_owned()))
+        })
+        .transpose()?;
+
+    let created = ensure_parent(workspace, parent, parents).await?;
+
+    let placed = async {
+        if let Some(skipped) =
+            conflict::settle_early(&path, on_conflict, if_match.as_deref()).await?
+        {
+            return Ok((skipped, false));
+        }
+
+        let (file, size) = receive(parent, body, max_upload, &mut verifier).await?;
+        verifier.verify()?;
+
+        if let Some(mtime) = mtime {
+            let temp = file.path().to_owned();
+            task::spawn_blocking(move || {
+                fs_set_times::set_mtime(temp, SystemTimeSpec::Absolute(mtime))
+            })
+            .await
+            .map_err(io::Error::other)??;
+        }
+
+        let replaced = on_conflict == OnConflict::Overwrite && fs::try_exists(&path).await?;
+        let uploaded =
+            conflict::place(file, &path, size, on_conflict, if_match.as_deref(), quotas).await?;
+        Ok::<_, ReplyError>((uploaded, replaced))
     }
-    let bytes = file.bytes().await?;
+    .await;
+
+    let (uploaded, replaced) = match placed {
+        Ok(placed) => placed,
+        Err(e) => {
+            remove_created(workspace, &created).await;
+            return Err(e);
+        }
+    };
+    let uploaded = Uploaded {
+        created,
+        ..uploaded
+    };
+    record_upload(index, workspace, parent, &uploaded, replaced).await;
 
-    let mut fd = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(path)
-        .await?;
+    Ok(ReplyData(uploaded))
+}
 
-    let mut fd = BufWriter::new(&mut fd);
-    fd.write_all(&bytes).await?;
+/// Stream the body into a temp file in the directory, return it with the size
+async fn receive(
+    dir: &std::path::Path,
+    body: Body,
+    max_upload: ByteSize,
+    verifier: &mut Verifier,
+) -> Result<(AtomicFile, u64), ReplyError> {
+    let mut file = AtomicFile::create(dir).await?;
+    let mut size = 0;
+    // unlike reading it, the stream keeps the errors of the body as the sources
+    let mut body = body.into_bytes_stream();
+    let mut fd = BufWriter::new(file.file());
+
+    while let Some(data) = body.next().await {
+        let data = data?;
+
+        size += data.len() as u64;
+        if size > max_upload.as_u64() {
+            return Err(ReplyError::ResourceTooLarge(max_upload));
+        }
+
+        verifier.update(&data);
+        fd.write_all(&data).await?;
+    }
     fd.flush().await?;
+    drop(fd);
 
-    Ok(ReplyData(()))
+    Ok((file, size))
+}
+
+/// Stream a multipart field into an atomic file in `dir`, holding it to `max_upload`
+async fn receive_field(
+    dir: &std::path::Path,
+    field: Field,
+    max_upload: ByteSize,
+) -> Result<(AtomicFile, u64), ReplyError> {
+    let mut file = AtomicFile::create(dir).await?;
+    let mut size = 0;
+    let mut reader = field.into_async_read();
+    let mut fd = BufWriter::new(file.file());
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        // the reader only fails on a malformed or cut multipart body
+        let n = reader
+            .read(&mut buf)
+            .await
+            .map_err(|_| ReplyError::InvalidParam("body".to_owned()))?;
+        if n == 0 {
+            break;
+        }
+
+        size += n as u64;
+        if size > max_upload.as_u64() {
+            return Err(ReplyError::ResourceTooLarge(max_upload));
+        }
+
+        fd.write_all(&buf[..n]).await?;
+    }
+    fd.flush().await?;
+    drop(fd);
+
+    Ok((file, size))
+}
+
+/// Record the uploaded file, or the outermost directory created for it
+async fn record_upload(
+    index: &SearchIndex,
+    workspace: &Workspace,
+    parent: &std::path::Path,
+    uploaded: &Uploaded,
+    replaced: bool,
+) {
+    if uploaded.skipped {
+        return;
+    }
+
+    let change = match uploaded.created.first() {
+        Some(dir) => Change::Created(workspace.join(dir)),
+        None if replaced => Change::Modified(parent.join(&uploaded.name)),
+        None => Change::Created(parent.join(&uploaded.name)),
+    };
+    record(index, change).await;
+}
+
+/// Keep the search index up to date and notify the watching clients
+async fn record(index: &SearchIndex, change: Change) {
+    match &change {
+        Change::Created(path) | Change::Modified(path) => index.refresh(path).await,
+        Change::Renamed { from, to } => index.moved(from, to),
+        Change::Removed(path) => index.remove(path),
+    }
+
+    events::publish(change);
 }
 
 #[derive(Debug, Deserialize)]
@@ -151,12 +644,20 @@ pub struct RenameParam {
 /// - Err:
 ///   - file doesn't exist => ReplyError::NotFound
 ///   - new name has been used => ReplyError::AlreadyExists
+///   - new name isn't a file name => ReplyError::OutsideWorkspace
+///   - moved into a full directory => ReplyError::QuotaExceeded
 #[handler]
 pub async fn rename(
     Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
     Path(path): Path<PathBuf>,
     Query(RenameParam { name }): Query<RenameParam>,
 ) -> Result<ReplyData<()>, ReplyError> {
+    if !is_file_name(&name) {
+        return Err(ReplyError::OutsideWorkspace);
+    }
+
     let src = workspace.join(path);
 
     if !fs::try_exists(&src).await? {
@@ -164,12 +665,42 @@ pub async fn rename(
     }
 
     let dest = src.with_file_name(name);
-    if fs::try_exists(&dest).await? {
+    move_path(&src, &dest, quotas).await?;
+    record(
+        index,
+        Change::Renamed {
+            from: src,
+            to: dest,
+        },
+    )
+    .await;
+
+    Ok(ReplyData(()))
+}
+
+/// Move a file or directory to the free path
+async fn move_path(
+    src: &std::path::Path,
+    dest: &std::path::Path,
+    quotas: &Quotas,
+) -> Result<(), ReplyError> {
+    if fs::try_exists(dest).await? {
         return Err(ReplyError::AlreadyExists);
     }
-    fs::rename(src, dest).await?;
 
-    Ok(ReplyData(()))
+    if quotas.is_empty() {
+        fs::rename(src, dest).await?;
+    } else {
+        // a moved directory may enter the limited one with the new path
+        let moved = quota::measure(src).await?;
+        quotas.relocate(src, dest, moved).await?;
+        if let Err(e) = fs::rename(src, dest).await {
+            quotas.relocate(dest, src, moved).await.ok();
+            return Err(e.into());
+        }
+    }
+
+    Ok(())
 }
 
 /// **Remove a file or directory**
@@ -179,6 +710,8 @@ pub async fn rename(
 #[handler]
 pub async fn remove(
     Data(workspace): Data<&Arc<Workspace>>,
+    Data(quotas): Data<&Arc<Quotas>>,
+    Data(index): Data<&Arc<SearchIndex>>,
     Path(path): Path<PathBuf>,
 ) -> Result<ReplyData<()>, ReplyError> {
     let path = workspace.join(path);
@@ -187,24 +720,81 @@ pub async fn remove(
         return Err(ReplyError::NotFound);
     }
 
+    remove_path(&path, quotas).await?;
+    record(index, Change::Removed(path)).await;
+
+    Ok(ReplyData(()))
+}
+
+async fn remove_path(path: &std::path::Path, quotas: &Quotas) -> Result<(), ReplyError> {
+    let removed = if quotas.is_empty() {
+        Usage::default()
+    } else {
+        quota::measure(path).await?
+    };
+
     if path.is_dir() {
         fs::remove_dir_all(path).await?;
     } else {
         fs::remove_file(path).await?;
     }
+    quotas.release(path, removed);
 
-    Ok(ReplyData(()))
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReadDirParam {
+    /// Levels of the nested tree, or `unlimited` up to `tree::MAX_DEPTH`
+    #[serde(default = "one_level", deserialize_with = "tree::depth")]
+    depth: usize,
+    /// Sum up the bytes and items of the directories recursively
+    #[serde(default)]
+    with_size: bool,
+    #[serde(default)]
+    sort: Sort,
+    #[serde(default)]
+    order: Direction,
+    /// A glob of the names, or a regex with `regex=true`
+    filter: Option<String>,
+    #[serde(default)]
+    regex: bool,
+    kind: Option<Kind>,
+    /// List the hidden entries
+    #[serde(default = "listed")]
+    hidden: bool,
+    /// Entries per page
+    limit: Option<usize>,
+    /// Where the page starts, from `next_cursor` of the previous page
+    cursor: Option<String>,
+    #[serde(default)]
+    time_format: TimeFormat,
+}
+
+fn one_level() -> usize {
+    1
+}
+
+fn listed() -> bool {
+    true
 }
 
 /// **Read a directory**
-/// - Ok: return the entry array
+/// - Ok: return a page of the entry array,
+///   nested in the directories within `depth`
 /// - Err:
 ///   - directory doesn't exist => ReplyError::NotFound
 ///   - path isn't directory => ReplyError::NotADirectory
+///   - malformed `filter`, `limit` or `cursor` => ReplyError::InvalidParam
 #[handler]
 pub async fn read_dir(
     Data(workspace): Data<&Arc<Workspace>>,
+    ownership: Option<Data<&HomeOwnership>>,
+    user: Option<Data<&User>>,
     Path(org): Path<PathBuf>,
+    Query(param): Query<ReadDirParam>,
+    req: &Request,
 ) -> Result<ReplyData<Directory>, ReplyError> {
     let path = workspace.join(&org);
 
@@ -216,46 +806,200 @@ pub async fn read_dir(
         return Err(ReplyError::NotADirectory);
     }
 
-    let mut rd = ReadDirStream::new(fs::read_dir(&path).await?);
-    let mut entries = Vec::with_capacity(rd.size_hint().0);
+    let limit = match param.limit {
+        Some(0) => return Err(ReplyError::InvalidParam("limit".to_owned())),
+        Some(limit) => Some(limit.min(MAX_LIMIT)),
+        // v1 predates the paging, so its clients expect the whole directory
+        None if param.cursor.is_none() && ApiVersion::of(req) == ApiVersion::V1 => None,
+        None => Some(DEFAULT_LIMIT),
+    };
 
-    while let Some(entry) = rd.next().await {
-        let entry = entry?;
-        let entry = if entry.file_type().await?.is_dir() {
-            FsEntry::dir(&entry).await?
-        } else {
-            FsEntry::file(&entry).await?
-        };
-        entries.push(entry);
-    }
-    entries.sort();
+    let order = Order {
+        sort: param.sort,
+        direction: param.order,
+    };
+    let after = param
+        .cursor
+        .map(|cursor| order.parse_cursor(&cursor))
+        .transpose()?;
+
+    let lister = Lister {
+        workspace,
+        with_size: param.with_size,
+        ownership: ownership.map(|Data(ownership)| ownership),
+        user: user.map(|Data(user)| user),
+        order,
+        filter: Filter {
+            name: param
+                .filter
+                .map(|filter| NameFilter::new(&filter, param.regex))
+                .transpose()?,
+            kind: param.kind,
+            hidden: param.hidden,
+        },
+        time_format: param.time_format,
+    };
+    let (entries, next) = lister
+        .page(&path, param.depth, after.as_ref(), limit)
+        .await?;
 
     let parent = (path != workspace.path()).then(|| org.to_string_lossy().into_owned());
 
-    Ok(ReplyData(Directory { parent, entries }))
+    Ok(ReplyData(Directory {
+        parent,
+        entries,
+        next_cursor: next.map(|last| order.cursor(&last)),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MkdirParam {
+    /// Create the missing parent directories like `mkdir -p`
+    #[serde(default)]
+    parents: bool,
 }
 
 /// **Make a directory**
-/// - Ok
+/// - Ok: return the directories created, relative to the workspace root
 /// - Err:
-///   - parent directory doesn't exist => ReplyError::MissingParent
-///   - directory has already existed => ReplyError::AlreadyExists
+///   - parent directory doesn't exist without `parents` => ReplyError::MissingParent
+///   - directory has already existed without `parents` => ReplyError::AlreadyExists
+///   - path has a file => ReplyError::NotADirectory
 #[handler]
 pub async fn mkdir(
     Data(workspace): Data<&Arc<Workspace>>,
+    Data(index): Data<&Arc<SearchIndex>>,
     Path(path): Path<PathBuf>,
-) -> Result<ReplyData<()>, ReplyError> {
+    Query(MkdirParam { parents }): Query<MkdirParam>,
+) -> Result<ReplyData<Vec<String>>, ReplyError> {
     let path = workspace.join(path);
 
+    let created = make_dir(workspace, &path, parents).await?;
+    if let Some(dir) = created.first() {
+        record(index, Change::Created(workspace.join(dir))).await;
+    }
+
+    Ok(ReplyData(created))
+}
+
+async fn make_dir(
+    workspace: &Workspace,
+    path: &std::path::Path,
+    parents: bool,
+) -> Result<Vec<String>, ReplyError> {
+    if parents {
+        return create_dirs(workspace, path).await;
+    }
+
     if !fs::try_exists(path.parent().unwrap()).await? {
         return Err(ReplyError::MissingParent);
     }
 
-    if fs::try_exists(&path).await? {
+    if fs::try_exists(path).await? {
         return Err(ReplyError::AlreadyExists);
     }
 
     fs::create_dir(path).await?;
 
-    Ok(ReplyData(()))
+    Ok(vec![relative(workspace, path)])
+}
+
+/// Make sure the parent directory exists,
+/// return the directories created for it with `parents`.
+async fn ensure_parent(
+    workspace: &Workspace,
+    parent: &std::path::Path,
+    parents: bool,
+) -> Result<Vec<String>, ReplyError> {
+    if parents {
+        create_dirs(workspace, parent).await
+    } else if fs::try_exists(parent).await? {
+        Ok(Vec::new())
+    } else {
+        Err(ReplyError::MissingParent)
+    }
+}
+
+/// Create the directory and its missing ancestors inside the workspace,
+/// return those created from the outermost, relative to the workspace root.
+async fn create_dirs(
+    workspace: &Workspace,
+    dir: &std::path::Path,
+) -> Result<Vec<String>, ReplyError> {
+    let mut created = Vec::new();
+    let mut current = workspace.path().to_owned();
+
+    // from the top, so a file in the path is found before descending into it
+    for component in dir
+        .strip_prefix(workspace.path())
+        .unwrap_or(dir)
+        .components()
+    {
+        current.push(component);
+
+        match fs::symlink_metadata(&current).await {
+            Ok(md) if md.is_dir() => continue,
+            Ok(md) if md.is_symlink() => {
+                linked_dir(workspace, &current).await?;
+                continue;
+            }
+            Ok(_) => return Err(ReplyError::NotADirectory),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        match fs::create_dir(&current).await {
+            Ok(()) => created.push(relative(workspace, &current)),
+            // made by a concurrent request
+            Err(e)
+                if e.kind() == io::ErrorKind::AlreadyExists
+                    && current.symlink_metadata().is_ok_and(|md| md.is_dir()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(ReplyError::NotADirectory)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(created)
+}
+
+/// Descend into a symlink only if it links to a directory inside the workspace
+async fn linked_dir(workspace: &Workspace, link: &std::path::Path) -> Result<(), ReplyError> {
+    let target = match fs::canonicalize(link).await {
+        Ok(target) => target,
+        // broken
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(ReplyError::NotADirectory),
+        Err(e) => return Err(e.into()),
+    };
+    if !target.starts_with(fs::canonicalize(workspace.path()).await?) {
+        return Err(ReplyError::OutsideWorkspace);
+    }
+    if !fs::metadata(&target).await?.is_dir() {
+        return Err(ReplyError::NotADirectory);
+    }
+
+    Ok(())
+}
+
+/// Remove the directories created for a failed upload from the innermost,
+/// those filled meanwhile are kept.
+async fn remove_created(workspace: &Workspace, created: &[String]) {
+    for dir in created.iter().rev() {
+        if fs::remove_dir(workspace.join(dir)).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn relative(workspace: &Workspace, path: &std::path::Path) -> String {
+    path.strip_prefix(workspace.path())
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// A single path component, which cannot point outside its directory
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0'])
 }
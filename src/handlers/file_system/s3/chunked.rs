@@ -0,0 +1,128 @@
+use std::io;
+
+use bytes::Bytes;
+use futures_util::stream;
+use poem::Body;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+
+use crate::middlewares::ChunkSigner;
+
+/// The largest chunk accepted, the clients send 64 KiB to 8 MiB
+const MAX_CHUNK: usize = 16 * 1024 * 1024;
+
+/// The longest line of chunk size and extensions
+const MAX_LINE: u64 = 4096;
+
+/// Why the `aws-chunked` body is rejected
+#[derive(Debug, thiserror::Error)]
+pub enum ChunkError {
+    #[error("chunk signature doesn't match")]
+    Signature,
+
+    #[error("malformed chunk")]
+    Malformed,
+}
+
+impl From<ChunkError> for io::Error {
+    fn from(e: ChunkError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+struct Decoder<R> {
+    reader: BufReader<R>,
+    signer: Option<ChunkSigner>,
+    /// The signature of the previous chunk, or the seed one
+    previous: String,
+}
+
+impl<R: AsyncRead + Unpin> Decoder<R> {
+    /// The data of next chunk, empty at the end
+    async fn chunk(&mut self) -> io::Result<Bytes> {
+        let mut line = Vec::new();
+        (&mut self.reader)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut line)
+            .await?;
+        let line = std::str::from_utf8(&line)
+            .ok()
+            .and_then(|line| line.strip_suffix("\r\n"))
+            .ok_or(ChunkError::Malformed)?;
+
+        // `{hex size};chunk-signature={signature}`
+        let (size, signature) = match line.split_once(';') {
+            Some((size, ext)) => (size, ext.strip_prefix("chunk-signature=")),
+            None => (line, None),
+        };
+        let size = usize::from_str_radix(size, 16)
+            .ok()
+            .filter(|&size| size <= MAX_CHUNK)
+            .ok_or(ChunkError::Malformed)?;
+
+        let mut data = vec![0; size];
+        self.reader.read_exact(&mut data).await?;
+        // the last chunk is followed by the trailers
+        if size > 0 {
+            let mut crlf = [0; 2];
+            self.reader.read_exact(&mut crlf).await?;
+            if &crlf != b"\r\n" {
+                return Err(ChunkError::Malformed.into());
+            }
+        }
+
+        if let Some(signer) = &self.signer {
+            let expected = signer.sign(&self.previous, &data);
+            if signature != Some(expected.as_str()) {
+                return Err(ChunkError::Signature.into());
+            }
+            self.previous = expected;
+        }
+
+        Ok(data.into())
+    }
+}
+
+/// Decode the `aws-chunked` body, the chunks are verified if there's a signer
+pub fn decode(body: Body, signer: Option<ChunkSigner>) -> Body {
+    let decoder = Decoder {
+        reader: BufReader::new(body.into_async_read()),
+        previous: signer
+            .as_ref()
+            .map(|signer| signer.seed.clone())
+            .unwrap_or_default(),
+        signer,
+    };
+
+    Body::from_bytes_stream(stream::try_unfold(Some(decoder), |decoder| async move {
+        let Some(mut decoder) = decoder else {
+            return Ok(None);
+        };
+
+        let data = decoder.chunk().await?;
+        if data.is_empty() {
+            Ok::<_, io::Error>(None)
+        } else {
+            Ok(Some((data, Some(decoder))))
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode;
+    use poem::Body;
+
+    #[tokio::test]
+    async fn test_decode() {
+        let body = Body::from_vec(
+            b"5\r\nhello\r\n6\r\n world\r\n0\r\nx-amz-checksum-crc32:AAAAAA==\r\n\r\n".to_vec(),
+        );
+        let decoded = decode(body, None).into_string().await.unwrap();
+        assert_eq!(decoded, "hello world");
+
+        let body = Body::from_vec(b"5\r\nhel".to_vec());
+        assert!(decode(body, None).into_string().await.is_err());
+        let body = Body::from_vec(b"zz\r\nhello\r\n0\r\n\r\n".to_vec());
+        assert!(decode(body, None).into_string().await.is_err());
+    }
+}
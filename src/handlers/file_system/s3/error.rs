@@ -0,0 +1,250 @@
+use std::error::Error;
+use std::io;
+
+use bytesize::ByteSize;
+use poem::error::ResponseError;
+use poem::http::StatusCode;
+use poem::web::headers::{ContentRange, HeaderMapExt};
+use poem::{IntoResponse, Response};
+use quick_xml::escape::escape;
+
+use super::chunked::ChunkError;
+use super::xml;
+use crate::reply::ReplyError;
+
+/// The errors of the S3 API, named by their codes
+#[derive(Debug, thiserror::Error)]
+pub enum S3Error {
+    #[error("Access Denied")]
+    AccessDenied,
+
+    #[error("The AWS access key Id you provided does not exist in our records")]
+    InvalidAccessKeyId,
+
+    #[error("The request signature we calculated does not match the signature you provided")]
+    SignatureDoesNotMatch,
+
+    #[error("The difference between the request time and the server's time is too large")]
+    RequestTimeTooSkewed,
+
+    #[error("Request has expired")]
+    ExpiredToken,
+
+    #[error("{0}")]
+    AuthorizationHeaderMalformed(&'static str),
+
+    #[error("The specified bucket does not exist")]
+    NoSuchBucket,
+
+    #[error("The specified key does not exist")]
+    NoSuchKey,
+
+    #[error("The specified multipart upload does not exist")]
+    NoSuchUpload,
+
+    #[error("The bucket you tried to create already exists, and you own it")]
+    BucketAlreadyOwnedByYou,
+
+    #[error("The bucket you tried to delete is not empty")]
+    BucketNotEmpty,
+
+    #[error("The specified bucket is not valid")]
+    InvalidBucketName,
+
+    #[error("{0}")]
+    InvalidArgument(String),
+
+    #[error("The XML you provided was not well-formed")]
+    MalformedXML,
+
+    #[error("One or more of the specified parts could not be found")]
+    InvalidPart,
+
+    #[error("The list of parts was not in ascending order")]
+    InvalidPartOrder,
+
+    #[error("Your proposed upload exceeds the maximum allowed size {0}")]
+    EntityTooLarge(ByteSize),
+
+    #[error("The content did not match the digest you specified")]
+    BadDigest,
+
+    #[error("The chunks of the body are malformed")]
+    IncompleteBody,
+
+    /// With the size of object
+    #[error("The requested range is not satisfiable")]
+    InvalidRange(u64),
+
+    #[error("At least one of the preconditions you specified did not hold")]
+    PreconditionFailed,
+
+    #[error("The storage quota is exceeded")]
+    QuotaExceeded,
+
+    #[error("The key is taken by a directory")]
+    ObjectExistsAsDirectory,
+
+    #[error("A prefix of the key is taken by an object")]
+    ParentIsObject,
+
+    #[error("We encountered an internal error, please try again")]
+    InternalError,
+}
+
+impl S3Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            S3Error::AccessDenied => "AccessDenied",
+            S3Error::InvalidAccessKeyId => "InvalidAccessKeyId",
+            S3Error::SignatureDoesNotMatch => "SignatureDoesNotMatch",
+            S3Error::RequestTimeTooSkewed => "RequestTimeTooSkewed",
+            S3Error::ExpiredToken => "ExpiredToken",
+            S3Error::AuthorizationHeaderMalformed(_) => "AuthorizationHeaderMalformed",
+            S3Error::NoSuchBucket => "NoSuchBucket",
+            S3Error::NoSuchKey => "NoSuchKey",
+            S3Error::NoSuchUpload => "NoSuchUpload",
+            S3Error::BucketAlreadyOwnedByYou => "BucketAlreadyOwnedByYou",
+            S3Error::BucketNotEmpty => "BucketNotEmpty",
+            S3Error::InvalidBucketName => "InvalidBucketName",
+            S3Error::InvalidArgument(_) => "InvalidArgument",
+            S3Error::MalformedXML => "MalformedXML",
+            S3Error::InvalidPart => "InvalidPart",
+            S3Error::InvalidPartOrder => "InvalidPartOrder",
+            S3Error::EntityTooLarge(_) => "EntityTooLarge",
+            S3Error::BadDigest => "BadDigest",
+            S3Error::IncompleteBody => "IncompleteBody",
+            S3Error::InvalidRange(_) => "InvalidRange",
+            S3Error::PreconditionFailed => "PreconditionFailed",
+            S3Error::QuotaExceeded => "QuotaExceeded",
+            S3Error::ObjectExistsAsDirectory => "ObjectExistsAsDirectory",
+            S3Error::ParentIsObject => "ParentIsObject",
+            S3Error::InternalError => "InternalError",
+        }
+    }
+
+    /// Map the statuses of other errors, like those of the workspace guard
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => S3Error::AccessDenied,
+            StatusCode::NOT_FOUND => S3Error::NoSuchKey,
+            StatusCode::BAD_REQUEST => S3Error::InvalidArgument("Invalid request".to_owned()),
+            _ => S3Error::InternalError,
+        }
+    }
+}
+
+impl From<ReplyError> for S3Error {
+    fn from(e: ReplyError) -> Self {
+        match e {
+            ReplyError::WorkspaceRoot
+            | ReplyError::IsAbsolute
+            | ReplyError::OutsideWorkspace
+            | ReplyError::PermissionDenied => S3Error::AccessDenied,
+            ReplyError::NotFound => S3Error::NoSuchKey,
+            ReplyError::AlreadyExists | ReplyError::IsADirectory => {
+                S3Error::ObjectExistsAsDirectory
+            }
+            ReplyError::MissingParent | ReplyError::NotADirectory => S3Error::ParentIsObject,
+            ReplyError::ResourceTooLarge(max) => S3Error::EntityTooLarge(max),
+            ReplyError::QuotaExceeded => S3Error::QuotaExceeded,
+            ReplyError::PreconditionFailed => S3Error::PreconditionFailed,
+            ReplyError::DigestMismatch => S3Error::BadDigest,
+            e @ (ReplyError::InvalidHeader(_)
+            | ReplyError::InvalidParam(_)
+            | ReplyError::IntoItself) => S3Error::InvalidArgument(e.to_string()),
+            ReplyError::Internal(e) => {
+                // the body was cut off or forged on the way
+                match chunk_error(&*e) {
+                    Some(ChunkError::Signature) => S3Error::SignatureDoesNotMatch,
+                    Some(ChunkError::Malformed) => S3Error::IncompleteBody,
+                    None => {
+                        tracing::error!(error = %e, "s3 request failed");
+                        S3Error::InternalError
+                    }
+                }
+            }
+            e => {
+                tracing::error!(error = %e, "s3 request failed");
+                S3Error::InternalError
+            }
+        }
+    }
+}
+
+/// The chunk error wrapped in the IO errors of the body
+fn chunk_error<'e>(e: &'e (dyn Error + 'static)) -> Option<&'e ChunkError> {
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if let Some(chunk) = e.downcast_ref::<ChunkError>() {
+            return Some(chunk);
+        }
+        source = match e.downcast_ref::<io::Error>() {
+            Some(e) => e.get_ref().map(|e| e as &(dyn Error + 'static)),
+            None => e.source(),
+        };
+    }
+
+    None
+}
+
+impl From<io::Error> for S3Error {
+    #[inline]
+    fn from(e: io::Error) -> Self {
+        ReplyError::from(e).into()
+    }
+}
+
+impl ResponseError for S3Error {
+    fn status(&self) -> StatusCode {
+        match self {
+            S3Error::AccessDenied
+            | S3Error::InvalidAccessKeyId
+            | S3Error::SignatureDoesNotMatch
+            | S3Error::RequestTimeTooSkewed
+            | S3Error::ExpiredToken
+            | S3Error::QuotaExceeded => StatusCode::FORBIDDEN,
+            S3Error::NoSuchBucket | S3Error::NoSuchKey | S3Error::NoSuchUpload => {
+                StatusCode::NOT_FOUND
+            }
+            S3Error::BucketAlreadyOwnedByYou
+            | S3Error::BucketNotEmpty
+            | S3Error::ObjectExistsAsDirectory
+            | S3Error::ParentIsObject => StatusCode::CONFLICT,
+            S3Error::AuthorizationHeaderMalformed(_)
+            | S3Error::InvalidBucketName
+            | S3Error::InvalidArgument(_)
+            | S3Error::MalformedXML
+            | S3Error::InvalidPart
+            | S3Error::InvalidPartOrder
+            | S3Error::EntityTooLarge(_)
+            | S3Error::BadDigest
+            | S3Error::IncompleteBody => StatusCode::BAD_REQUEST,
+            S3Error::InvalidRange(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            S3Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            S3Error::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn as_response(&self) -> Response {
+        let mut resp = xml::response(format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>{}</Code><Message>{}</Message></Error>"#,
+            self.code(),
+            escape(&self.to_string())
+        ));
+        resp.set_status(self.status());
+        if let S3Error::InvalidRange(size) = self {
+            resp.headers_mut()
+                .typed_insert(ContentRange::unsatisfied_bytes(*size));
+        }
+
+        resp
+    }
+}
+
+impl IntoResponse for S3Error {
+    #[inline]
+    fn into_response(self) -> Response {
+        self.as_response()
+    }
+}
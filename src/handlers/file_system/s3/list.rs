@@ -0,0 +1,261 @@
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::task;
+
+use crate::utils::atomic;
+
+/// The most keys in a page
+pub const MAX_KEYS: usize = 1000;
+
+/// An object or a common prefix in the order of keys
+#[derive(Debug)]
+pub enum Listed {
+    Object { key: String, md: Metadata },
+    Prefix(String),
+}
+
+impl Listed {
+    pub fn key(&self) -> &str {
+        match self {
+            Listed::Object { key, .. } | Listed::Prefix(key) => key,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Page {
+    pub items: Vec<Listed>,
+    pub truncated: bool,
+}
+
+/// What to list in a bucket
+#[derive(Debug, Clone, Copy)]
+pub struct Query<'a> {
+    pub prefix: &'a str,
+    pub delimiter: Option<&'a str>,
+    /// Only the keys after it
+    pub after: Option<&'a str>,
+    pub max_keys: usize,
+}
+
+/// List the files in the bucket as objects,
+/// the empty directories are listed as the objects ending with `/`.
+pub async fn list(bucket: PathBuf, query: Query<'_>) -> io::Result<Page> {
+    let prefix = query.prefix.to_owned();
+    let delimiter = query.delimiter.map(str::to_owned);
+
+    let mut listed = task::spawn_blocking(move || {
+        // start from the deepest directory in the prefix
+        let base = prefix.rfind('/').map_or("", |i| &prefix[..=i]);
+        let dir = bucket.join(base);
+        if !is_key(base) || !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut listed = Vec::new();
+        match delimiter.as_deref() {
+            // the common case of a directory, which is read alone
+            Some("/") => {
+                for (name, md) in read_dir(&dir)? {
+                    let key = format!("{base}{name}");
+                    if !key.starts_with(&prefix) {
+                        continue;
+                    }
+                    listed.push(if md.is_dir() {
+                        Listed::Prefix(key + "/")
+                    } else {
+                        Listed::Object { key, md }
+                    });
+                }
+            }
+            delimiter => {
+                let mut objects = Vec::new();
+                walk(&dir, base, &mut objects)?;
+                for (key, md) in objects {
+                    let Some(rest) = key.strip_prefix(&prefix) else {
+                        continue;
+                    };
+                    match delimiter
+                        .and_then(|delimiter| rest.find(delimiter).map(|i| i + delimiter.len()))
+                    {
+                        Some(end) => {
+                            listed.push(Listed::Prefix(format!("{prefix}{}", &rest[..end])))
+                        }
+                        None => listed.push(Listed::Object { key, md }),
+                    }
+                }
+            }
+        }
+
+        Ok::<_, io::Error>(listed)
+    })
+    .await
+    .map_err(io::Error::other)??;
+
+    listed.sort_by(|a, b| a.key().cmp(b.key()));
+    listed.dedup_by(|a, b| a.key() == b.key());
+    if let Some(after) = query.after {
+        listed.retain(|item| item.key() > after);
+    }
+
+    let truncated = listed.len() > query.max_keys;
+    listed.truncate(query.max_keys);
+
+    Ok(Page {
+        items: listed,
+        truncated,
+    })
+}
+
+/// The entries which may be objects, the temp files are hidden.
+/// Symlinks are followed to files, but not to directories.
+fn read_dir(dir: &Path) -> io::Result<Vec<(String, Metadata)>> {
+    let mut entries = Vec::new();
+
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if atomic::is_temp(&name) {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        let md = match entry.metadata() {
+            Ok(_) if file_type.is_symlink() => match std::fs::metadata(entry.path()) {
+                Ok(md) if md.is_file() => md,
+                _ => continue,
+            },
+            Ok(md) => md,
+            // removed meanwhile
+            Err(_) => continue,
+        };
+        entries.push((name, md));
+    }
+
+    Ok(entries)
+}
+
+fn walk(dir: &Path, base: &str, objects: &mut Vec<(String, Metadata)>) -> io::Result<()> {
+    let entries = read_dir(dir)?;
+
+    // it's kept as an object, or it would be gone
+    if entries.is_empty() && !base.is_empty() {
+        objects.push((base.to_owned(), dir.metadata()?));
+    }
+
+    for (name, md) in entries {
+        if md.is_dir() {
+            walk(&dir.join(&name), &format!("{base}{name}/"), objects)?;
+        } else {
+            objects.push((format!("{base}{name}"), md));
+        }
+    }
+
+    Ok(())
+}
+
+/// The key maps to a path in the bucket, with a trailing `/` if it's a directory
+pub fn is_key(key: &str) -> bool {
+    let key = key.strip_suffix('/').unwrap_or(key);
+
+    key.is_empty()
+        || key
+            .split('/')
+            .all(|segment| super::super::is_file_name(segment) && !atomic::is_temp(segment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_key, list, Listed, Query};
+    use crate::utils::tests::*;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_list() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let bucket = wk.join("bucket");
+        fs::create_dir_all(bucket.join("a/b")).unwrap();
+        fs::create_dir_all(bucket.join("empty")).unwrap();
+        fs::write(bucket.join("a/b/c.txt"), "c").unwrap();
+        fs::write(bucket.join("a/d.txt"), "d").unwrap();
+        fs::write(bucket.join("a-e.txt"), "e").unwrap();
+        fs::write(bucket.join("a/.sachima-upload.1"), "").unwrap();
+
+        fn keys(listed: &[Listed]) -> Vec<&str> {
+            listed.iter().map(Listed::key).collect()
+        }
+        let query = Query {
+            prefix: "",
+            delimiter: None,
+            after: None,
+            max_keys: 1000,
+        };
+
+        let page = list(bucket.clone(), query).await.unwrap();
+        // `-` is before `/`
+        assert_eq!(
+            keys(&page.items),
+            ["a-e.txt", "a/b/c.txt", "a/d.txt", "empty/"]
+        );
+        assert!(!page.truncated);
+
+        let page = list(
+            bucket.clone(),
+            Query {
+                delimiter: Some("/"),
+                ..query
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(keys(&page.items), ["a-e.txt", "a/", "empty/"]);
+
+        let page = list(
+            bucket.clone(),
+            Query {
+                prefix: "a/",
+                delimiter: Some("/"),
+                max_keys: 1,
+                ..query
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(keys(&page.items), ["a/b/"]);
+        assert!(page.truncated);
+
+        let page = list(
+            bucket.clone(),
+            Query {
+                prefix: "a",
+                after: Some("a/b/"),
+                delimiter: Some("b"),
+                ..query
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(keys(&page.items), ["a/d.txt"]);
+
+        let page = list(
+            bucket.clone(),
+            Query {
+                prefix: "missing/",
+                ..query
+            },
+        )
+        .await
+        .unwrap();
+        assert!(page.items.is_empty());
+
+        assert!(is_key("a/b.txt"));
+        assert!(is_key("a/b/"));
+        assert!(!is_key("a//b"));
+        assert!(!is_key("a/../b"));
+        assert!(!is_key("a/.sachima-upload.1"));
+    }
+}
@@ -0,0 +1,989 @@
+#[cfg(test)]
+mod tests;
+
+mod chunked;
+mod error;
+mod list;
+mod multipart;
+mod xml;
+
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::io;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytesize::ByteSize;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use poem::http::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LOCATION, RANGE};
+use poem::http::{HeaderMap, StatusCode};
+use poem::web::headers::{ETag, HeaderMapExt, IfMatch, IfNoneMatch, LastModified};
+use poem::web::{Path as PathParam, Query};
+use poem::{handler, Body, IntoResponse, Request, Response};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio::task;
+
+use super::conflict::{self, OnConflict};
+use super::digest::Verifier;
+use super::{check_owner, create_dirs, is_file_name, receive, record, remove_created, remove_path};
+use crate::config::Workspace;
+use crate::events::Change;
+use crate::middlewares::Payload;
+use crate::models::permission::User;
+use crate::quota::Quotas;
+use crate::search::SearchIndex;
+use crate::settings::{HomeOwnership, MaxUpload, SharedSettings};
+use crate::utils::atomic;
+use crate::utils::etag::etag;
+use crate::utils::xattr;
+use list::{Listed, MAX_KEYS};
+use xml::Document;
+
+pub use error::S3Error;
+
+/// Where it's served, the signed paths are inside
+pub const MOUNT: &str = "/s3";
+
+/// The content type and user metadata of an object
+const META_XATTR: &str = "user.sachima.s3";
+
+/// Marks the directories created for the keys of objects,
+/// which are removed with the last object in them.
+const IMPLICIT_XATTR: &str = "user.sachima.s3.implicit";
+
+const X_AMZ_META: &str = "x-amz-meta-";
+const X_AMZ_COPY_SOURCE: &str = "x-amz-copy-source";
+const X_AMZ_COPY_SOURCE_RANGE: &str = "x-amz-copy-source-range";
+const X_AMZ_METADATA_DIRECTIVE: &str = "x-amz-metadata-directive";
+
+/// The largest XML body of a request
+const MAX_XML: u64 = 1024 * 1024;
+
+/// The keys are encoded like the paths with `encoding-type=url`
+const KEY_ENCODED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~')
+    .remove(b'/');
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct S3Param {
+    /// `2` for ListObjectsV2
+    list_type: Option<u8>,
+    #[serde(default)]
+    prefix: String,
+    delimiter: Option<String>,
+    max_keys: Option<usize>,
+    /// The last key of the previous page in base64
+    continuation_token: Option<String>,
+    start_after: Option<String>,
+    /// The last key of the previous page of ListObjects V1
+    marker: Option<String>,
+    encoding_type: Option<String>,
+    location: Option<String>,
+    delete: Option<String>,
+    uploads: Option<String>,
+    #[serde(rename = "uploadId")]
+    upload_id: Option<String>,
+    #[serde(rename = "partNumber")]
+    part_number: Option<u32>,
+}
+
+/// What the path names
+enum Resource<'a> {
+    Service,
+    Bucket(&'a str),
+    Object { bucket: &'a str, key: &'a str },
+}
+
+/// A bucket is a top-level directory of the workspace,
+/// and the key is the path inside.
+fn resource(path: &str) -> Result<Resource<'_>, S3Error> {
+    if path.is_empty() {
+        return Ok(Resource::Service);
+    }
+
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    if !is_file_name(bucket) || atomic::is_temp(bucket) {
+        return Err(S3Error::InvalidBucketName);
+    }
+
+    if key.is_empty() {
+        Ok(Resource::Bucket(bucket))
+    } else if list::is_key(key) {
+        Ok(Resource::Object { bucket, key })
+    } else {
+        Err(S3Error::InvalidArgument(format!("Invalid key {key}")))
+    }
+}
+
+/// What the workspace guard and the signature carry
+struct Context<'a> {
+    workspace: &'a Workspace,
+    quotas: &'a Quotas,
+    index: &'a SearchIndex,
+    max_upload: ByteSize,
+    ownership: Option<&'a HomeOwnership>,
+    user: Option<&'a User>,
+    payload: Payload,
+}
+
+impl<'a> Context<'a> {
+    fn new(req: &'a Request) -> Self {
+        const GUARDED: &str = "set by the workspace guard";
+
+        Context {
+            workspace: req.data::<Arc<Workspace>>().expect(GUARDED),
+            quotas: req.data::<Arc<Quotas>>().expect(GUARDED),
+            index: req.data::<Arc<SearchIndex>>().expect(GUARDED),
+            max_upload: req.data::<MaxUpload>().expect(GUARDED).0,
+            ownership: req.data::<HomeOwnership>(),
+            user: req.data::<User>(),
+            payload: req.data::<Payload>().cloned().unwrap_or(Payload::Unsigned),
+        }
+    }
+
+    /// The directory of an existing bucket
+    async fn bucket(&self, bucket: &str) -> Result<PathBuf, S3Error> {
+        let dir = self.workspace.join(bucket);
+
+        match fs::metadata(&dir).await {
+            Ok(md) if md.is_dir() => Ok(dir),
+            Ok(_) => Err(S3Error::NoSuchBucket),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(S3Error::NoSuchBucket),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The body decoded, and the digests to verify it
+    fn body(&self, headers: &HeaderMap, body: Body) -> Result<(Body, Verifier), S3Error> {
+        let mut verifier = Verifier::from_headers(headers)?;
+
+        let body = match &self.payload {
+            Payload::Unsigned => body,
+            Payload::Sha256(hash) => {
+                verifier.expect("sha-256", hash.clone());
+                body
+            }
+            Payload::Chunked(signer) => chunked::decode(body, signer.clone()),
+        };
+
+        Ok((body, verifier))
+    }
+
+    /// Read the XML body of a request
+    async fn xml(&self, headers: &HeaderMap, body: Body) -> Result<xml::Element, S3Error> {
+        let (body, mut verifier) = self.body(headers, body)?;
+
+        let mut buf = Vec::new();
+        body.into_async_read()
+            .take(MAX_XML + 1)
+            .read_to_end(&mut buf)
+            .await?;
+        if buf.len() as u64 > MAX_XML {
+            return Err(S3Error::MalformedXML);
+        }
+        verifier.update(&buf);
+        verifier.verify()?;
+
+        std::str::from_utf8(&buf)
+            .ok()
+            .and_then(xml::parse)
+            .ok_or(S3Error::MalformedXML)
+    }
+
+    /// Receive the body as the object at the path,
+    /// which replaces the existing one.
+    async fn store(
+        &self,
+        path: &Path,
+        body: Body,
+        mut verifier: Verifier,
+        meta: &Meta,
+        if_match: Option<&IfMatch>,
+    ) -> Result<Metadata, S3Error> {
+        let parent = path.parent().unwrap();
+        let created = create_dirs(self.workspace, parent).await?;
+        mark_implicit(self.workspace, &created).await;
+
+        let placed = async {
+            let (file, size) = receive(parent, body, self.max_upload, &mut verifier).await?;
+            verifier.verify()?;
+            meta.save(file.path()).await;
+
+            let replaced = fs::try_exists(path).await?;
+            conflict::place(
+                file,
+                path,
+                size,
+                OnConflict::Overwrite,
+                if_match,
+                self.quotas,
+            )
+            .await?;
+            Ok::<_, S3Error>(replaced)
+        }
+        .await;
+        let replaced = match placed {
+            Ok(replaced) => replaced,
+            Err(e) => {
+                remove_created(self.workspace, &created).await;
+                return Err(e);
+            }
+        };
+
+        let change = match created.first() {
+            Some(dir) => Change::Created(self.workspace.join(dir)),
+            None if replaced => Change::Modified(path.to_owned()),
+            None => Change::Created(path.to_owned()),
+        };
+        record(self.index, change).await;
+
+        Ok(fs::metadata(path).await?)
+    }
+}
+
+/// The content type and user metadata set on upload
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Meta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    /// `x-amz-meta-*` without the prefix
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    user: BTreeMap<String, String>,
+}
+
+impl Meta {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let text = |value: &poem::http::HeaderValue| value.to_str().ok().map(str::to_owned);
+
+        Meta {
+            content_type: headers.get(CONTENT_TYPE).and_then(text),
+            user: headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    let name = name.as_str().strip_prefix(X_AMZ_META)?;
+                    Some((name.to_owned(), text(value)?))
+                })
+                .collect(),
+        }
+    }
+
+    /// Empty if it isn't set or cannot be read
+    async fn load(path: &Path) -> Self {
+        let path = path.to_owned();
+        let value = task::spawn_blocking(move || xattr::get(&path, META_XATTR))
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .flatten();
+
+        value
+            .and_then(|value| serde_json::from_slice(&value).ok())
+            .unwrap_or_default()
+    }
+
+    /// Keep it with the file if the file system supports the extended attributes
+    async fn save(&self, path: &Path) {
+        if self.content_type.is_none() && self.user.is_empty() {
+            return;
+        }
+
+        let Ok(value) = serde_json::to_vec(self) else {
+            return;
+        };
+        let saved = {
+            let path = path.to_owned();
+            task::spawn_blocking(move || xattr::set(&path, META_XATTR, &value)).await
+        };
+        if let Ok(Err(e)) = saved {
+            tracing::warn!(path = %path.display(), error = %e, "cannot save the object metadata");
+        }
+    }
+
+    /// The headers of the object
+    fn headers(&self, path: &Path, headers: &mut HeaderMap) {
+        let content_type = self.content_type.clone().unwrap_or_else(|| {
+            mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string()
+        });
+        if let Ok(value) = content_type.parse() {
+            headers.insert(CONTENT_TYPE, value);
+        }
+
+        for (name, value) in &self.user {
+            if let (Ok(name), Ok(value)) = (
+                format!("{X_AMZ_META}{name}").parse::<poem::http::HeaderName>(),
+                value.parse(),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// **Read the service, a bucket or an object**
+/// - the service => ListBuckets, the buckets of others' homes are hidden
+/// - a bucket => ListObjects, V2 with `list-type=2`
+/// - an object => GetObject, a single range is supported
+#[handler]
+pub async fn get(
+    req: &Request,
+    PathParam(path): PathParam<String>,
+    Query(param): Query<S3Param>,
+) -> Result<Response, S3Error> {
+    let cx = Context::new(req);
+
+    match resource(&path)? {
+        Resource::Service => list_buckets(&cx).await,
+        Resource::Bucket(bucket) if param.location.is_some() => {
+            cx.bucket(bucket).await?;
+            let region = req
+                .data::<SharedSettings>()
+                .and_then(|settings| settings.load().s3.as_ref().map(|s3| s3.region.clone()))
+                .unwrap_or_default();
+            Ok(xml::location(&region))
+        }
+        Resource::Bucket(bucket) => list_objects(&cx, bucket, &param).await,
+        Resource::Object { bucket, key } => {
+            if let Some(id) = &param.upload_id {
+                let upload = multipart::open(&cx.bucket(bucket).await?, key, id).await?;
+                return multipart::list_parts(&upload, bucket, key).await;
+            }
+            get_object(&cx, req.headers(), bucket, key, false).await
+        }
+    }
+}
+
+/// **Check a bucket or an object**
+/// The headers are the same as those of GET.
+#[handler]
+pub async fn head(req: &Request, PathParam(path): PathParam<String>) -> Result<Response, S3Error> {
+    let cx = Context::new(req);
+
+    match resource(&path)? {
+        Resource::Service => Ok(Response::default()),
+        Resource::Bucket(bucket) => {
+            cx.bucket(bucket).await?;
+            Ok(Response::default())
+        }
+        Resource::Object { bucket, key } => get_object(&cx, req.headers(), bucket, key, true).await,
+    }
+}
+
+/// **Write a bucket or an object**
+/// - a bucket => CreateBucket, a top-level directory
+/// - an object with `partNumber` and `uploadId` => UploadPart, or UploadPartCopy
+/// - an object with `x-amz-copy-source` => CopyObject
+/// - an object => PutObject, the missing directories of the key are created,
+///   and a key ending with `/` is a directory
+#[handler]
+pub async fn put(
+    req: &Request,
+    PathParam(path): PathParam<String>,
+    Query(param): Query<S3Param>,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let cx = Context::new(req);
+    let headers = req.headers();
+
+    let (bucket, key) = match resource(&path)? {
+        Resource::Service => {
+            return Err(S3Error::InvalidArgument(
+                "Cannot put the service".to_owned(),
+            ))
+        }
+        Resource::Bucket(bucket) => return create_bucket(&cx, bucket).await,
+        Resource::Object { bucket, key } => (bucket, key),
+    };
+    let dir = cx.bucket(bucket).await?;
+    let copy_source = headers
+        .get(X_AMZ_COPY_SOURCE)
+        .map(|value| copy_source(&cx, value.to_str().unwrap_or_default()))
+        .transpose()?;
+
+    if let (Some(number), Some(id)) = (param.part_number, &param.upload_id) {
+        let upload = multipart::open(&dir, key, id).await?;
+        let copy = copy_source.is_some();
+        let (body, verifier) = match copy_source {
+            Some(src) => {
+                let md = object_metadata(&src, false).await?;
+                let range = match headers.get(X_AMZ_COPY_SOURCE_RANGE) {
+                    Some(value) => range(value.to_str().unwrap_or_default(), md.len())?
+                        .ok_or_else(|| {
+                            S3Error::InvalidArgument(format!("Invalid {X_AMZ_COPY_SOURCE_RANGE}"))
+                        })?,
+                    None => (0, md.len()),
+                };
+                (read_object(&src, range).await?, Verifier::default())
+            }
+            None => cx.body(headers, body)?,
+        };
+        return multipart::upload_part(&cx, &dir.join(key), &upload, number, body, verifier, copy)
+            .await;
+    }
+
+    if let Some(src) = copy_source {
+        return copy_object(&cx, headers, &src, &dir.join(key)).await;
+    }
+
+    put_object(&cx, headers, &dir.join(key), key.ends_with('/'), body).await
+}
+
+/// **Operate on many objects or a multipart upload**
+/// - a bucket with `delete` => DeleteObjects
+/// - an object with `uploads` => CreateMultipartUpload
+/// - an object with `uploadId` => CompleteMultipartUpload
+#[handler]
+pub async fn post(
+    req: &Request,
+    PathParam(path): PathParam<String>,
+    Query(param): Query<S3Param>,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let cx = Context::new(req);
+    let headers = req.headers();
+
+    match resource(&path)? {
+        Resource::Bucket(bucket) if param.delete.is_some() => {
+            let dir = cx.bucket(bucket).await?;
+            let root = cx.xml(headers, body).await?;
+            delete_objects(&cx, &dir, &root).await
+        }
+        Resource::Object { bucket, key } if param.uploads.is_some() => {
+            let dir = cx.bucket(bucket).await?;
+            multipart::create(&dir, bucket, key, &Meta::from_headers(headers)).await
+        }
+        Resource::Object { bucket, key } if param.upload_id.is_some() => {
+            let dir = cx.bucket(bucket).await?;
+            let id = param.upload_id.as_deref().unwrap_or_default();
+            let upload = multipart::open(&dir, key, id).await?;
+            let root = cx.xml(headers, body).await?;
+            multipart::complete(&cx, bucket, key, &dir.join(key), &upload, &root).await
+        }
+        _ => Err(S3Error::InvalidArgument("Unsupported POST".to_owned())),
+    }
+}
+
+/// **Delete a bucket or an object**
+/// - a bucket => DeleteBucket, which must be empty
+/// - an object with `uploadId` => AbortMultipartUpload
+/// - an object => DeleteObject, the directories emptied by it are removed,
+///   and it's done even if the object doesn't exist
+#[handler]
+pub async fn delete(
+    req: &Request,
+    PathParam(path): PathParam<String>,
+    Query(param): Query<S3Param>,
+) -> Result<Response, S3Error> {
+    let cx = Context::new(req);
+
+    match resource(&path)? {
+        Resource::Service => Err(S3Error::InvalidArgument(
+            "Cannot delete the service".to_owned(),
+        )),
+        Resource::Bucket(bucket) => delete_bucket(&cx, bucket).await,
+        Resource::Object { bucket, key } => {
+            let dir = cx.bucket(bucket).await?;
+            match &param.upload_id {
+                Some(id) => {
+                    let upload = multipart::open(&dir, key, id).await?;
+                    multipart::abort(&cx, &dir.join(key), &upload).await?;
+                }
+                None => delete_object(&cx, &dir, key).await?,
+            }
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+    }
+}
+
+async fn list_buckets(cx: &Context<'_>) -> Result<Response, S3Error> {
+    let mut buckets = Vec::new();
+    let mut dir = fs::read_dir(cx.workspace.path()).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if atomic::is_temp(&name) || !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let owned = cx
+            .ownership
+            .is_none_or(|ownership| check_owner(Path::new(&name), ownership, cx.user).is_ok());
+        if owned {
+            let md = entry.metadata().await?;
+            buckets.push((name, md.created().or_else(|_| md.modified())?));
+        }
+    }
+    buckets.sort();
+
+    let owner = cx.user.map_or("anonymous", |user| user.name.as_str());
+    let mut doc = Document::new("ListAllMyBucketsResult");
+    doc.open("Owner")
+        .text("ID", owner)
+        .text("DisplayName", owner)
+        .close("Owner")
+        .open("Buckets");
+    for (name, created) in buckets {
+        doc.open("Bucket")
+            .text("Name", name)
+            .text("CreationDate", xml::timestamp(created))
+            .close("Bucket");
+    }
+    doc.close("Buckets");
+
+    Ok(doc.finish("ListAllMyBucketsResult"))
+}
+
+async fn list_objects(
+    cx: &Context<'_>,
+    bucket: &str,
+    param: &S3Param,
+) -> Result<Response, S3Error> {
+    let dir = cx.bucket(bucket).await?;
+    let v2 = param.list_type == Some(2);
+    let max_keys = param.max_keys.unwrap_or(MAX_KEYS).min(MAX_KEYS);
+    let delimiter = param.delimiter.as_deref().filter(|d| !d.is_empty());
+    let token = param
+        .continuation_token
+        .as_deref()
+        .map(|token| {
+            BASE64
+                .decode(token)
+                .ok()
+                .and_then(|key| String::from_utf8(key).ok())
+                .ok_or_else(|| S3Error::InvalidArgument("Invalid continuation token".to_owned()))
+        })
+        .transpose()?;
+    let after = if v2 {
+        token.as_deref().or(param.start_after.as_deref())
+    } else {
+        param.marker.as_deref()
+    };
+
+    let page = list::list(
+        dir,
+        list::Query {
+            prefix: &param.prefix,
+            delimiter,
+            after,
+            max_keys,
+        },
+    )
+    .await?;
+
+    let url = param.encoding_type.as_deref() == Some("url");
+    let encode = |key: &str| {
+        if url {
+            utf8_percent_encode(key, KEY_ENCODED).to_string()
+        } else {
+            key.to_owned()
+        }
+    };
+
+    let mut doc = Document::new("ListBucketResult");
+    doc.text("Name", bucket)
+        .text("Prefix", encode(&param.prefix))
+        .text("MaxKeys", max_keys)
+        .text("IsTruncated", page.truncated);
+    if let Some(delimiter) = delimiter {
+        doc.text("Delimiter", encode(delimiter));
+    }
+    if url {
+        doc.text("EncodingType", "url");
+    }
+
+    let last = page.items.last().map(Listed::key);
+    if v2 {
+        doc.text("KeyCount", page.items.len());
+        if let Some(token) = &param.continuation_token {
+            doc.text("ContinuationToken", token);
+        }
+        if let Some(start_after) = &param.start_after {
+            doc.text("StartAfter", encode(start_after));
+        }
+        if let (true, Some(last)) = (page.truncated, last) {
+            doc.text("NextContinuationToken", BASE64.encode(last));
+        }
+    } else {
+        doc.text(
+            "Marker",
+            encode(param.marker.as_deref().unwrap_or_default()),
+        );
+        if let (true, Some(last)) = (page.truncated, last) {
+            doc.text("NextMarker", encode(last));
+        }
+    }
+
+    for item in &page.items {
+        match item {
+            Listed::Object { key, md } => {
+                doc.open("Contents")
+                    .text("Key", encode(key))
+                    .text("LastModified", xml::timestamp(md.modified()?))
+                    .text("ETag", etag(md))
+                    .text("Size", if md.is_dir() { 0 } else { md.len() })
+                    .text("StorageClass", "STANDARD")
+                    .close("Contents");
+            }
+            Listed::Prefix(prefix) => {
+                doc.open("CommonPrefixes")
+                    .text("Prefix", encode(prefix))
+                    .close("CommonPrefixes");
+            }
+        }
+    }
+
+    Ok(doc.finish("ListBucketResult"))
+}
+
+/// The metadata of an object, a key ending with `/` is a directory
+async fn object_metadata(path: &Path, dir: bool) -> Result<Metadata, S3Error> {
+    match fs::metadata(path).await {
+        Ok(md) if md.is_dir() == dir => Ok(md),
+        Ok(_) => Err(S3Error::NoSuchKey),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(S3Error::NoSuchKey),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn get_object(
+    cx: &Context<'_>,
+    headers: &HeaderMap,
+    bucket: &str,
+    key: &str,
+    only_headers: bool,
+) -> Result<Response, S3Error> {
+    let path = cx.bucket(bucket).await?.join(key);
+    let md = object_metadata(&path, key.ends_with('/')).await?;
+    let tag = etag(&md);
+
+    if let Some(if_match) = headers.typed_get::<IfMatch>() {
+        if !tag
+            .parse::<ETag>()
+            .is_ok_and(|tag| if_match.precondition_passes(&tag))
+        {
+            return Err(S3Error::PreconditionFailed);
+        }
+    }
+    if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+        if tag
+            .parse::<ETag>()
+            .is_ok_and(|tag| !if_none_match.precondition_passes(&tag))
+        {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(ETAG, tag)
+                .finish());
+        }
+    }
+
+    let size = if md.is_dir() { 0 } else { md.len() };
+    let range = match headers.get(RANGE).and_then(|value| value.to_str().ok()) {
+        Some(value) => range(value, size)?,
+        None => None,
+    };
+
+    let mut resp = Response::builder()
+        .header(ETAG, tag)
+        .header(ACCEPT_RANGES, "bytes")
+        .typed_header(LastModified::from(md.modified()?));
+    let (start, end) = match range {
+        Some((start, end)) => {
+            resp = resp
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header("content-range", format!("bytes {start}-{}/{size}", end - 1));
+            (start, end)
+        }
+        None => (0, size),
+    };
+    resp = resp.header(CONTENT_LENGTH, end - start);
+
+    let body = if only_headers || md.is_dir() {
+        Body::empty()
+    } else {
+        read_object(&path, (start, end)).await?
+    };
+    let mut resp = resp.body(body);
+    Meta::load(&path).await.headers(&path, resp.headers_mut());
+
+    Ok(resp)
+}
+
+/// The bytes `start..end` of the file
+async fn read_object(path: &Path, (start, end): (u64, u64)) -> Result<Body, S3Error> {
+    let mut fd = File::open(path).await?;
+    fd.seek(SeekFrom::Start(start)).await?;
+
+    Ok(Body::from_async_read(BufReader::new(fd.take(end - start))))
+}
+
+/// `bytes=0-9`, `bytes=10-` or `bytes=-10` => `start..end`,
+/// which is `None` if the range isn't a single one of bytes.
+fn range(value: &str, size: u64) -> Result<Option<(u64, u64)>, S3Error> {
+    let Some((first, last)) = value
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|range| !range.contains(','))
+        .and_then(|range| range.split_once('-'))
+    else {
+        return Ok(None);
+    };
+
+    let (start, end) = match (first.trim(), last.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return Err(S3Error::InvalidRange(size)),
+            Ok(suffix) => (size.saturating_sub(suffix), size),
+            Err(_) => return Ok(None),
+        },
+        (first, last) => {
+            let Ok(start) = first.parse::<u64>() else {
+                return Ok(None);
+            };
+            let end = match last {
+                "" => size,
+                last => match last.parse::<u64>() {
+                    Ok(last) if last >= start => (last + 1).min(size),
+                    _ => return Ok(None),
+                },
+            };
+            (start, end)
+        }
+    };
+
+    if start >= size {
+        return Err(S3Error::InvalidRange(size));
+    }
+
+    Ok(Some((start, end)))
+}
+
+async fn create_bucket(cx: &Context<'_>, bucket: &str) -> Result<Response, S3Error> {
+    let dir = cx.workspace.join(bucket);
+
+    match fs::create_dir(&dir).await {
+        Ok(()) => record(cx.index, Change::Created(dir)).await,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => {
+            return Err(S3Error::BucketAlreadyOwnedByYou)
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(S3Error::InvalidBucketName)
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(Response::builder()
+        .header(LOCATION, format!("/{bucket}"))
+        .finish())
+}
+
+async fn delete_bucket(cx: &Context<'_>, bucket: &str) -> Result<Response, S3Error> {
+    let dir = cx.bucket(bucket).await?;
+
+    // the multipart uploads in progress are aborted with it
+    let mut entries = fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !atomic::is_temp(&entry.file_name().to_string_lossy()) {
+            return Err(S3Error::BucketNotEmpty);
+        }
+    }
+
+    remove_path(&dir, cx.quotas).await?;
+    record(cx.index, Change::Removed(dir)).await;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn put_object(
+    cx: &Context<'_>,
+    headers: &HeaderMap,
+    path: &Path,
+    dir: bool,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let md = if dir {
+        let created = create_dirs(cx.workspace, path).await?;
+        mark_implicit(cx.workspace, &created).await;
+        // the directory itself is an object
+        let explicit = {
+            let path = path.to_owned();
+            task::spawn_blocking(move || xattr::remove(&path, IMPLICIT_XATTR)).await
+        };
+        if let Ok(Err(e)) = explicit {
+            tracing::warn!(path = %path.display(), error = %e, "cannot unmark the directory");
+        }
+        if let Some(dir) = created.first() {
+            record(cx.index, Change::Created(cx.workspace.join(dir))).await;
+        }
+        fs::metadata(path).await?
+    } else {
+        let (body, verifier) = cx.body(headers, body)?;
+        let if_match = headers.typed_get::<IfMatch>();
+        cx.store(
+            path,
+            body,
+            verifier,
+            &Meta::from_headers(headers),
+            if_match.as_ref(),
+        )
+        .await?
+    };
+
+    Ok(Response::builder().header(ETAG, etag(&md)).finish())
+}
+
+/// The object to copy in `x-amz-copy-source`, like `/bucket/key?versionId=...`
+fn copy_source(cx: &Context<'_>, value: &str) -> Result<PathBuf, S3Error> {
+    let invalid = || S3Error::InvalidArgument(format!("Invalid {X_AMZ_COPY_SOURCE}"));
+
+    let value = value.split_once('?').map_or(value, |(path, _)| path);
+    let path = percent_decode_str(value)
+        .decode_utf8()
+        .map_err(|_| invalid())?;
+    let path = path.strip_prefix('/').unwrap_or(&path);
+
+    match resource(path)? {
+        Resource::Object { bucket, key } => {
+            if let Some(ownership) = cx.ownership {
+                check_owner(Path::new(bucket), ownership, cx.user)?;
+            }
+            Ok(cx.workspace.join(bucket).join(key))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+async fn copy_object(
+    cx: &Context<'_>,
+    headers: &HeaderMap,
+    src: &Path,
+    dest: &Path,
+) -> Result<Response, S3Error> {
+    let md = object_metadata(src, false).await?;
+
+    let meta = match headers.get(X_AMZ_METADATA_DIRECTIVE) {
+        Some(directive) if directive == "REPLACE" => Meta::from_headers(headers),
+        _ => Meta::load(src).await,
+    };
+    let body = read_object(src, (0, md.len())).await?;
+    let md = cx
+        .store(dest, body, Verifier::default(), &meta, None)
+        .await?;
+
+    let mut doc = Document::new("CopyObjectResult");
+    doc.text("LastModified", xml::timestamp(md.modified()?))
+        .text("ETag", etag(&md));
+
+    Ok(doc.finish("CopyObjectResult"))
+}
+
+/// Delete the object and the directories emptied by it in the bucket,
+/// up to the first one which wasn't created for the keys
+async fn delete_object(cx: &Context<'_>, bucket: &Path, key: &str) -> Result<(), S3Error> {
+    let path = bucket.join(key);
+
+    let removed = if key.ends_with('/') {
+        // only an empty directory is an object
+        match fs::remove_dir(&path).await {
+            Ok(()) => Some(path.clone()),
+            Err(_) => None,
+        }
+    } else {
+        match fs::metadata(&path).await {
+            Ok(md) if md.is_file() => {
+                remove_path(&path, cx.quotas).await?;
+                Some(path.clone())
+            }
+            Ok(_) => None,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    if let Some(mut removed) = removed {
+        let mut dir = path.parent();
+        while let Some(parent) = dir.filter(|parent| *parent != bucket) {
+            if !is_implicit(parent).await || fs::remove_dir(parent).await.is_err() {
+                break;
+            }
+            removed = parent.to_owned();
+            dir = parent.parent();
+        }
+        record(cx.index, Change::Removed(removed)).await;
+    }
+
+    Ok(())
+}
+
+/// Mark the directories created for the key of an object
+async fn mark_implicit(workspace: &Workspace, created: &[String]) {
+    if created.is_empty() {
+        return;
+    }
+
+    let dirs: Vec<_> = created.iter().map(|dir| workspace.join(dir)).collect();
+    let marked = task::spawn_blocking(move || {
+        dirs.iter()
+            .try_for_each(|dir| xattr::set(dir, IMPLICIT_XATTR, b""))
+    })
+    .await;
+    if let Ok(Err(e)) = marked {
+        tracing::warn!(error = %e, "cannot mark the directories created for the key");
+    }
+}
+
+/// Whether the directory was created for the keys, and isn't an object itself
+async fn is_implicit(dir: &Path) -> bool {
+    let dir = dir.to_owned();
+    task::spawn_blocking(move || xattr::get(&dir, IMPLICIT_XATTR))
+        .await
+        .is_ok_and(|marked| matches!(marked, Ok(Some(_))))
+}
+
+/// `<Delete><Quiet>true</Quiet><Object><Key>a.txt</Key></Object>...</Delete>`
+async fn delete_objects(
+    cx: &Context<'_>,
+    bucket: &Path,
+    root: &xml::Element,
+) -> Result<Response, S3Error> {
+    let quiet = xml::child_text(root, "Quiet").is_some_and(|quiet| quiet == "true");
+    let keys: Vec<_> = xml::children(root, "Object")
+        .map(|object| xml::child_text(object, "Key").ok_or(S3Error::MalformedXML))
+        .collect::<Result<_, _>>()?;
+    if root.name != "Delete" || keys.len() > MAX_KEYS {
+        return Err(S3Error::MalformedXML);
+    }
+
+    let mut doc = Document::new("DeleteResult");
+    for key in keys {
+        let deleted = if list::is_key(&key) {
+            delete_object(cx, bucket, &key).await
+        } else {
+            Err(S3Error::InvalidArgument(format!("Invalid key {key}")))
+        };
+
+        match deleted {
+            Ok(()) if quiet => (),
+            Ok(()) => {
+                doc.open("Deleted").text("Key", &key).close("Deleted");
+            }
+            Err(e) => {
+                doc.open("Error")
+                    .text("Key", &key)
+                    .text("Code", e.code())
+                    .text("Message", &e)
+                    .close("Error");
+            }
+        }
+    }
+
+    Ok(doc.finish("DeleteResult"))
+}
@@ -0,0 +1,304 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use futures_util::stream;
+use poem::http::header::ETAG;
+use poem::{Body, Response};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use super::xml::{self, Document, Element};
+use super::{receive, Context, Meta, S3Error, Verifier, MOUNT};
+use crate::quota::Usage;
+use crate::utils::atomic::TEMP_PREFIX;
+use crate::utils::etag::etag;
+
+const MANIFEST: &str = "upload.json";
+
+/// The part numbers are `1..=MAX_PARTS`
+const MAX_PARTS: u32 = 10_000;
+
+/// A multipart upload in progress, which is a hidden directory in the bucket
+/// holding the parts uploaded, so it's gone on restart like the other temp files.
+#[derive(Debug)]
+pub struct Upload {
+    dir: PathBuf,
+    id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    key: String,
+    #[serde(default)]
+    meta: Meta,
+}
+
+fn upload_dir(bucket: &Path, id: &str) -> PathBuf {
+    bucket.join(format!("{TEMP_PREFIX}s3-{id}"))
+}
+
+fn part_name(number: u32) -> String {
+    format!("part-{number:05}")
+}
+
+/// The parts uploaded in the order of their numbers
+async fn parts(dir: &Path) -> io::Result<Vec<(u32, PathBuf, std::fs::Metadata)>> {
+    let mut parts = Vec::new();
+
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let Some(number) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("part-"))
+            .and_then(|number| number.parse().ok())
+        else {
+            continue;
+        };
+        parts.push((number, entry.path(), entry.metadata().await?));
+    }
+    parts.sort_by_key(|(number, _, _)| *number);
+
+    Ok(parts)
+}
+
+/// The bytes reserved in the quotas for the parts
+fn reserved(parts: &[(u32, PathBuf, std::fs::Metadata)]) -> Usage {
+    Usage {
+        bytes: parts.iter().map(|(_, _, md)| md.len()).sum(),
+        files: 0,
+    }
+}
+
+/// Find the upload of the key
+pub async fn open(bucket: &Path, key: &str, id: &str) -> Result<Upload, S3Error> {
+    if id.len() != 32 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(S3Error::NoSuchUpload);
+    }
+
+    let dir = upload_dir(bucket, id);
+    let manifest = match fs::read(dir.join(MANIFEST)).await {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(S3Error::NoSuchUpload),
+        Err(e) => return Err(e.into()),
+    };
+    let manifest: Manifest = serde_json::from_slice(&manifest).map_err(io::Error::other)?;
+    if manifest.key != key {
+        return Err(S3Error::NoSuchUpload);
+    }
+
+    Ok(Upload {
+        dir,
+        id: id.to_owned(),
+    })
+}
+
+/// **CreateMultipartUpload**, the metadata is set when it completes
+pub async fn create(
+    bucket: &Path,
+    bucket_name: &str,
+    key: &str,
+    meta: &Meta,
+) -> Result<Response, S3Error> {
+    let id = Uuid::new_v4().simple().to_string();
+    let dir = upload_dir(bucket, &id);
+
+    fs::create_dir(&dir).await?;
+    let manifest = Manifest {
+        key: key.to_owned(),
+        meta: meta.clone(),
+    };
+    let manifest = serde_json::to_vec(&manifest).map_err(io::Error::other)?;
+    fs::write(dir.join(MANIFEST), manifest).await?;
+
+    let mut doc = Document::new("InitiateMultipartUploadResult");
+    doc.text("Bucket", bucket_name)
+        .text("Key", key)
+        .text("UploadId", id);
+
+    Ok(doc.finish("InitiateMultipartUploadResult"))
+}
+
+/// **UploadPart** and **UploadPartCopy**,
+/// the part is accounted in the quotas of the destination.
+pub async fn upload_part(
+    cx: &Context<'_>,
+    dest: &Path,
+    upload: &Upload,
+    number: u32,
+    body: Body,
+    mut verifier: Verifier,
+    copy: bool,
+) -> Result<Response, S3Error> {
+    if !(1..=MAX_PARTS).contains(&number) {
+        return Err(S3Error::InvalidArgument(format!(
+            "Part number must be an integer between 1 and {MAX_PARTS}"
+        )));
+    }
+
+    let (file, size) = receive(&upload.dir, body, cx.max_upload, &mut verifier).await?;
+    verifier.verify()?;
+
+    let part = upload.dir.join(part_name(number));
+    let added = Usage {
+        bytes: size,
+        files: 0,
+    };
+    cx.quotas.reserve(dest, added).await?;
+    let replaced = fs::metadata(&part).await.ok();
+    if let Err(e) = file.persist_overwrite(&part).await {
+        cx.quotas.release(dest, added);
+        return Err(e.into());
+    }
+    if let Some(md) = replaced {
+        cx.quotas.release(
+            dest,
+            Usage {
+                bytes: md.len(),
+                files: 0,
+            },
+        );
+    }
+
+    let md = fs::metadata(&part).await?;
+    if copy {
+        let mut doc = Document::new("CopyPartResult");
+        doc.text("LastModified", xml::timestamp(md.modified()?))
+            .text("ETag", etag(&md));
+        Ok(doc.finish("CopyPartResult"))
+    } else {
+        Ok(Response::builder().header(ETAG, etag(&md)).finish())
+    }
+}
+
+/// **CompleteMultipartUpload**, the parts listed are concatenated as the object
+pub async fn complete(
+    cx: &Context<'_>,
+    bucket_name: &str,
+    key: &str,
+    dest: &Path,
+    upload: &Upload,
+    root: &Element,
+) -> Result<Response, S3Error> {
+    // `<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>"..."</ETag></Part>...`
+    let listed: Vec<(u32, String)> = xml::children(root, "Part")
+        .map(|part| {
+            let number = xml::child_text(part, "PartNumber")?.trim().parse().ok()?;
+            Some((number, xml::child_text(part, "ETag")?))
+        })
+        .collect::<Option<_>>()
+        .ok_or(S3Error::MalformedXML)?;
+    if root.name != "CompleteMultipartUpload" || listed.is_empty() {
+        return Err(S3Error::MalformedXML);
+    }
+    if listed.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+        return Err(S3Error::InvalidPartOrder);
+    }
+
+    let uploaded = parts(&upload.dir).await?;
+    let mut paths = Vec::new();
+    for (number, tag) in &listed {
+        let matched = uploaded.iter().find(|(uploaded, _, md)| {
+            uploaded == number && etag(md).trim_matches('"') == tag.trim().trim_matches('"')
+        });
+        match matched {
+            Some((_, path, _)) => paths.push(path.clone()),
+            None => return Err(S3Error::InvalidPart),
+        }
+    }
+
+    let manifest: Manifest = serde_json::from_slice(&fs::read(upload.dir.join(MANIFEST)).await?)
+        .map_err(io::Error::other)?;
+
+    // the object takes the place of the parts
+    let reserved = reserved(&uploaded);
+    cx.quotas.release(dest, reserved);
+    let stored = cx
+        .store(
+            dest,
+            concat(paths),
+            Verifier::default(),
+            &manifest.meta,
+            None,
+        )
+        .await;
+    let md = match stored {
+        Ok(md) => md,
+        Err(e) => {
+            cx.quotas.reserve(dest, reserved).await.ok();
+            return Err(e);
+        }
+    };
+    fs::remove_dir_all(&upload.dir).await?;
+
+    let mut doc = Document::new("CompleteMultipartUploadResult");
+    doc.text("Location", format!("{MOUNT}/{bucket_name}/{key}"))
+        .text("Bucket", bucket_name)
+        .text("Key", key)
+        .text("ETag", etag(&md));
+
+    Ok(doc.finish("CompleteMultipartUploadResult"))
+}
+
+/// **AbortMultipartUpload**
+pub async fn abort(cx: &Context<'_>, dest: &Path, upload: &Upload) -> Result<(), S3Error> {
+    let reserved = reserved(&parts(&upload.dir).await?);
+    fs::remove_dir_all(&upload.dir).await?;
+    cx.quotas.release(dest, reserved);
+
+    Ok(())
+}
+
+/// **ListParts**, all in a page
+pub async fn list_parts(
+    upload: &Upload,
+    bucket_name: &str,
+    key: &str,
+) -> Result<Response, S3Error> {
+    let mut doc = Document::new("ListPartsResult");
+    doc.text("Bucket", bucket_name)
+        .text("Key", key)
+        .text("UploadId", &upload.id)
+        .text("MaxParts", MAX_PARTS)
+        .text("IsTruncated", false);
+    for (number, _, md) in parts(&upload.dir).await? {
+        doc.open("Part")
+            .text("PartNumber", number)
+            .text("LastModified", xml::timestamp(md.modified()?))
+            .text("ETag", etag(&md))
+            .text("Size", md.len())
+            .close("Part");
+    }
+
+    Ok(doc.finish("ListPartsResult"))
+}
+
+/// The files one after another
+fn concat(paths: Vec<PathBuf>) -> Body {
+    Body::from_bytes_stream(stream::try_unfold(
+        (paths.into_iter(), None::<File>),
+        |(mut paths, mut current)| async move {
+            loop {
+                if current.is_none() {
+                    match paths.next() {
+                        Some(path) => current = Some(File::open(path).await?),
+                        None => return Ok(None),
+                    }
+                }
+
+                let mut buf = vec![0; 64 * 1024];
+                let n = current.as_mut().unwrap().read(&mut buf).await?;
+                if n == 0 {
+                    current = None;
+                    continue;
+                }
+                buf.truncate(n);
+
+                return Ok::<_, io::Error>(Some((Bytes::from(buf), (paths, current))));
+            }
+        },
+    ))
+}
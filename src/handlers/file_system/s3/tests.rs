@@ -0,0 +1,492 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use bytesize::ByteSize;
+use poem::http::{HeaderMap, HeaderName, Method, StatusCode};
+use poem::test::{TestClient, TestResponse};
+use poem::{Endpoint, EndpointExt, Route, RouteMethod};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tokio::fs;
+
+use crate::checksum::hex;
+use crate::config::s3::AccessKey;
+use crate::config::{Policy, Search, S3};
+use crate::middlewares::{
+    canonical_request, hmac, signing_key, string_to_sign, SigV4Auth, EMPTY_SHA256,
+};
+use crate::quota::Quotas;
+use crate::router::s3_error;
+use crate::search::SearchIndex;
+use crate::settings::{MaxUpload, Settings, WorkspaceSettings};
+use crate::utils::tests::*;
+use crate::utils::xattr;
+
+const ACCESS_KEY: &str = "AKIDSACHIMA";
+const SECRET: &str = "sachima/s3/secret";
+const REGION: &str = "us-east-1";
+
+fn s3_app(wk: crate::config::Workspace) -> impl Endpoint {
+    let index = Arc::new(SearchIndex::new(&wk, &Search::default()));
+    let wk = Arc::new(wk);
+    let settings = Settings {
+        workspace: WorkspaceSettings {
+            root: wk.clone(),
+            max_upload: ByteSize::mib(1),
+            read: Policy::Public,
+            write: Policy::Authenticated,
+            quotas: Default::default(),
+            index: index.clone(),
+        },
+        workspaces: HashMap::new(),
+        homes: false,
+        admins: Default::default(),
+        client_users: HashMap::new(),
+        s3: Some(S3 {
+            region: REGION.to_owned(),
+            keys: HashMap::from([(
+                ACCESS_KEY.to_owned(),
+                AccessKey {
+                    secret: SECRET.to_owned(),
+                    user: "alice".to_owned(),
+                },
+            )]),
+        }),
+    };
+
+    let methods = RouteMethod::new()
+        .get(super::get)
+        .head(super::head)
+        .put(super::put)
+        .post(super::post)
+        .delete(super::delete);
+
+    Route::new()
+        .nest(
+            super::MOUNT,
+            Route::new()
+                .at("/*path", methods)
+                .catch_all_error(s3_error)
+                .with(SigV4Auth::new(Arc::new(ArcSwap::from_pointee(settings)))),
+        )
+        .data(index)
+        .data(wk)
+        .data(Arc::new(Quotas::default()))
+        .data(MaxUpload(ByteSize::mib(1)))
+}
+
+/// Sign the requests like the clients with the access key
+struct Signer {
+    datetime: String,
+    key: Vec<u8>,
+}
+
+impl Signer {
+    fn new() -> Self {
+        let now = OffsetDateTime::now_utc();
+        Signer {
+            datetime: format!(
+                "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+                now.year(),
+                u8::from(now.month()),
+                now.day(),
+                now.hour(),
+                now.minute(),
+                now.second()
+            ),
+            key: signing_key(SECRET, &now_date(now), REGION),
+        }
+    }
+
+    fn scope(&self) -> String {
+        format!("{}/{REGION}/s3/aws4_request", &self.datetime[..8])
+    }
+
+    /// The headers of a request and its signature
+    fn sign(
+        &self,
+        method: &Method,
+        uri: &str,
+        extra: &[(&str, &str)],
+        payload: &str,
+    ) -> (HeaderMap, String) {
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "localhost".parse().unwrap());
+        headers.insert("x-amz-date", self.datetime.parse().unwrap());
+        headers.insert("x-amz-content-sha256", payload.parse().unwrap());
+        for (name, value) in extra {
+            headers.insert(name.parse::<HeaderName>().unwrap(), value.parse().unwrap());
+        }
+        let mut signed: Vec<_> = headers.keys().map(|name| name.to_string()).collect();
+        signed.sort();
+
+        let canonical = canonical_request(method.as_str(), path, query, &headers, &signed, payload);
+        let to_sign = string_to_sign(&self.datetime, &self.scope(), &canonical);
+        let signature = hex(&hmac(&self.key, to_sign.as_bytes()));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={ACCESS_KEY}/{}, SignedHeaders={}, Signature={signature}",
+            self.scope(),
+            signed.join(";")
+        );
+        headers.insert("authorization", authorization.parse().unwrap());
+
+        (headers, signature)
+    }
+
+    fn chunk(&self, previous: &str, data: &[u8]) -> String {
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256-PAYLOAD\n{}\n{}\n{previous}\n{EMPTY_SHA256}\n{}",
+            self.datetime,
+            self.scope(),
+            hex(&Sha256::digest(data))
+        );
+        hex(&hmac(&self.key, to_sign.as_bytes()))
+    }
+}
+
+fn now_date(now: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}",
+        now.year(),
+        u8::from(now.month()),
+        now.day()
+    )
+}
+
+async fn send(
+    client: &TestClient<impl Endpoint>,
+    method: Method,
+    uri: &str,
+    extra: &[(&str, &str)],
+    body: &[u8],
+) -> TestResponse {
+    let payload = hex(&Sha256::digest(body));
+    let (headers, _) = Signer::new().sign(&method, uri, extra, &payload);
+
+    let mut req = client.request(method, uri);
+    for (name, value) in &headers {
+        req = req.header(name, value);
+    }
+    req.body(body.to_vec()).send().await
+}
+
+async fn text(resp: TestResponse) -> String {
+    resp.0.into_body().into_string().await.unwrap()
+}
+
+fn between<'t>(text: &'t str, start: &str, end: &str) -> &'t str {
+    let from = text.find(start).unwrap() + start.len();
+    &text[from..from + text[from..].find(end).unwrap()]
+}
+
+#[tokio::test]
+async fn test_objects() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    let client = TestClient::new(s3_app(wk));
+
+    send(&client, Method::PUT, "/s3/photos", &[], b"")
+        .await
+        .assert_status_is_ok();
+    let resp = send(&client, Method::PUT, "/s3/photos", &[], b"").await;
+    resp.assert_status(StatusCode::CONFLICT);
+    assert!(text(resp)
+        .await
+        .contains("<Code>BucketAlreadyOwnedByYou</Code>"));
+
+    let resp = send(
+        &client,
+        Method::PUT,
+        "/s3/photos/2023/a%20b.txt",
+        &[("content-type", "text/plain")],
+        b"hello world",
+    )
+    .await;
+    resp.assert_status_is_ok();
+    assert_eq!(
+        fs::read_to_string(root.join("photos/2023/a b.txt")).await?,
+        "hello world"
+    );
+
+    let resp = send(
+        &client,
+        Method::GET,
+        "/s3/photos/2023/a%20b.txt",
+        &[("range", "bytes=6-")],
+        b"",
+    )
+    .await;
+    resp.assert_status(StatusCode::PARTIAL_CONTENT);
+    resp.assert_header("content-range", "bytes 6-10/11");
+    assert_eq!(text(resp).await, "world");
+
+    let resp = send(
+        &client,
+        Method::GET,
+        "/s3/photos/2023/a%20b.txt",
+        &[("range", "bytes=20-")],
+        b"",
+    )
+    .await;
+    resp.assert_status(StatusCode::RANGE_NOT_SATISFIABLE);
+
+    let resp = send(&client, Method::HEAD, "/s3/photos/2023/a%20b.txt", &[], b"").await;
+    resp.assert_status_is_ok();
+    resp.assert_header("content-length", "11");
+    send(&client, Method::HEAD, "/s3/photos/2023/missing", &[], b"")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let resp = send(
+        &client,
+        Method::PUT,
+        "/s3/photos/b.txt",
+        &[("x-amz-copy-source", "/photos/2023/a%20b.txt")],
+        b"",
+    )
+    .await;
+    resp.assert_status_is_ok();
+    assert!(text(resp).await.contains("<CopyObjectResult"));
+    assert_eq!(
+        fs::read_to_string(root.join("photos/b.txt")).await?,
+        "hello world"
+    );
+
+    let resp = send(
+        &client,
+        Method::GET,
+        "/s3/photos?list-type=2&delimiter=%2F",
+        &[],
+        b"",
+    )
+    .await;
+    resp.assert_status_is_ok();
+    let listed = text(resp).await;
+    assert!(listed.contains("<Key>b.txt</Key>"));
+    assert!(listed.contains("<CommonPrefixes><Prefix>2023/</Prefix></CommonPrefixes>"));
+    assert!(listed.contains("<KeyCount>2</KeyCount>"));
+
+    // the clients sign the service without the trailing slash
+    for path in ["/s3/", "/s3"] {
+        let resp = send(&client, Method::GET, path, &[], b"").await;
+        assert!(text(resp).await.contains("<Name>photos</Name>"));
+    }
+
+    // the emptied directories are gone with the object,
+    // unless they have been there before it
+    fs::create_dir(root.join("photos/kept")).await?;
+    send(
+        &client,
+        Method::PUT,
+        "/s3/photos/kept/2023/c.txt",
+        &[],
+        b"c",
+    )
+    .await
+    .assert_status_is_ok();
+    send(
+        &client,
+        Method::DELETE,
+        "/s3/photos/kept/2023/c.txt",
+        &[],
+        b"",
+    )
+    .await
+    .assert_status(StatusCode::NO_CONTENT);
+    assert!(fs::try_exists(root.join("photos/kept")).await?);
+    fs::remove_dir_all(root.join("photos/kept")).await?;
+
+    // unless the file system doesn't support the extended attributes
+    let marked = xattr::get(&root.join("photos/2023"), super::IMPLICIT_XATTR)?.is_some();
+    send(
+        &client,
+        Method::DELETE,
+        "/s3/photos/2023/a%20b.txt",
+        &[],
+        b"",
+    )
+    .await
+    .assert_status(StatusCode::NO_CONTENT);
+    assert_eq!(fs::try_exists(root.join("photos/2023")).await?, !marked);
+
+    let resp = send(&client, Method::DELETE, "/s3/photos", &[], b"").await;
+    resp.assert_status(StatusCode::CONFLICT);
+    assert!(text(resp).await.contains("<Code>BucketNotEmpty</Code>"));
+
+    let resp = send(
+        &client,
+        Method::POST,
+        "/s3/photos?delete",
+        &[],
+        br#"<Delete xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Object><Key>b.txt</Key></Object></Delete>"#,
+    )
+    .await;
+    resp.assert_status_is_ok();
+    assert!(text(resp)
+        .await
+        .contains("<Deleted><Key>b.txt</Key></Deleted>"));
+
+    send(&client, Method::DELETE, "/s3/photos", &[], b"")
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    assert!(!fs::try_exists(root.join("photos")).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multipart() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    fs::create_dir(root.join("photos")).await?;
+    let client = TestClient::new(s3_app(wk));
+
+    let resp = send(
+        &client,
+        Method::POST,
+        "/s3/photos/big.bin?uploads",
+        &[],
+        b"",
+    )
+    .await;
+    resp.assert_status_is_ok();
+    let created = text(resp).await;
+    let id = between(&created, "<UploadId>", "</UploadId>").to_owned();
+
+    let mut etags = Vec::new();
+    for (number, part) in [(1, "part one, "), (2, "part two")] {
+        let uri = format!("/s3/photos/big.bin?partNumber={number}&uploadId={id}");
+        let resp = send(&client, Method::PUT, &uri, &[], part.as_bytes()).await;
+        resp.assert_status_is_ok();
+        etags.push(resp.0.headers()["etag"].to_str().unwrap().to_owned());
+    }
+
+    let complete = |parts: &[(usize, &str)]| {
+        let parts: String = parts
+            .iter()
+            .map(|(number, etag)| {
+                format!("<Part><PartNumber>{number}</PartNumber><ETag>{etag}</ETag></Part>")
+            })
+            .collect();
+        format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>")
+    };
+    let uri = format!("/s3/photos/big.bin?uploadId={id}");
+
+    let body = complete(&[(2, &etags[1]), (1, &etags[0])]);
+    let resp = send(&client, Method::POST, &uri, &[], body.as_bytes()).await;
+    resp.assert_status(StatusCode::BAD_REQUEST);
+    assert!(text(resp).await.contains("<Code>InvalidPartOrder</Code>"));
+
+    let body = complete(&[(1, &etags[0]), (2, "\"wrong\"")]);
+    let resp = send(&client, Method::POST, &uri, &[], body.as_bytes()).await;
+    assert!(text(resp).await.contains("<Code>InvalidPart</Code>"));
+
+    let body = complete(&[(1, &etags[0]), (2, &etags[1])]);
+    let resp = send(&client, Method::POST, &uri, &[], body.as_bytes()).await;
+    resp.assert_status_is_ok();
+    assert!(text(resp).await.contains("<Key>big.bin</Key>"));
+    assert_eq!(
+        fs::read_to_string(root.join("photos/big.bin")).await?,
+        "part one, part two"
+    );
+    // nothing of the upload is left
+    assert_eq!(root.join("photos").read_dir()?.count(), 1);
+
+    let resp = send(&client, Method::POST, &uri, &[], body.as_bytes()).await;
+    resp.assert_status(StatusCode::NOT_FOUND);
+    assert!(text(resp).await.contains("<Code>NoSuchUpload</Code>"));
+
+    let resp = send(
+        &client,
+        Method::POST,
+        "/s3/photos/big.bin?uploads",
+        &[],
+        b"",
+    )
+    .await;
+    let created = text(resp).await;
+    let id = between(&created, "<UploadId>", "</UploadId>").to_owned();
+    let uri = format!("/s3/photos/big.bin?uploadId={id}");
+    send(&client, Method::DELETE, &uri, &[], b"")
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    assert_eq!(root.join("photos").read_dir()?.count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_signature() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    fs::create_dir(root.join("photos")).await?;
+    let client = TestClient::new(s3_app(wk));
+
+    // the anonymous requests are left to the workspace guard
+    client.get("/s3/photos").send().await.assert_status_is_ok();
+
+    let signer = Signer::new();
+    let (headers, _) = signer.sign(&Method::PUT, "/s3/photos/a.txt", &[], EMPTY_SHA256);
+    let mut req = client.put("/s3/photos/b.txt");
+    for (name, value) in &headers {
+        req = req.header(name, value);
+    }
+    let resp = req.send().await;
+    resp.assert_status(StatusCode::FORBIDDEN);
+    assert!(text(resp)
+        .await
+        .contains("<Code>SignatureDoesNotMatch</Code>"));
+
+    // the body doesn't match the signed hash
+    let (headers, _) = signer.sign(&Method::PUT, "/s3/photos/a.txt", &[], EMPTY_SHA256);
+    let mut req = client.put("/s3/photos/a.txt");
+    for (name, value) in &headers {
+        req = req.header(name, value);
+    }
+    req.body("forged")
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    assert!(!fs::try_exists(root.join("photos/a.txt")).await?);
+
+    // the chunks are signed one after another
+    for forged in [false, true] {
+        let (headers, seed) = signer.sign(
+            &Method::PUT,
+            "/s3/photos/a.txt",
+            &[("content-encoding", "aws-chunked")],
+            "STREAMING-AWS4-HMAC-SHA256-PAYLOAD",
+        );
+        let first = signer.chunk(&seed, b"hello ");
+        let second = signer.chunk(&first, if forged { b"world?" } else { b"world!" });
+        let last = signer.chunk(&second, b"");
+        let body = format!(
+            "6;chunk-signature={first}\r\nhello \r\n6;chunk-signature={second}\r\nworld!\r\n0;chunk-signature={last}\r\n\r\n"
+        );
+
+        let mut req = client.put("/s3/photos/a.txt");
+        for (name, value) in &headers {
+            req = req.header(name, value);
+        }
+        let resp = req.body(body).send().await;
+        if forged {
+            resp.assert_status(StatusCode::FORBIDDEN);
+            assert!(text(resp)
+                .await
+                .contains("<Code>SignatureDoesNotMatch</Code>"));
+        } else {
+            resp.assert_status_is_ok();
+            assert_eq!(
+                fs::read_to_string(root.join("photos/a.txt")).await?,
+                "hello world!"
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use poem::http::header::CONTENT_TYPE;
+use poem::{Body, Response};
+use quick_xml::escape::{escape, unescape};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+pub use super::super::dav::xml::{parse, Element};
+
+const XML_TYPE: &str = "application/xml";
+
+pub const S3_NS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+/// A document written element by element
+#[derive(Debug)]
+pub struct Document(String);
+
+impl Document {
+    /// Open the root element in the namespace of S3
+    pub fn new(root: &str) -> Self {
+        Document(format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><{root} xmlns="{S3_NS}">"#
+        ))
+    }
+
+    pub fn open(&mut self, name: &str) -> &mut Self {
+        write!(self.0, "<{name}>").unwrap();
+        self
+    }
+
+    pub fn close(&mut self, name: &str) -> &mut Self {
+        write!(self.0, "</{name}>").unwrap();
+        self
+    }
+
+    /// An element with the escaped text
+    pub fn text(&mut self, name: &str, text: impl ToString) -> &mut Self {
+        write!(self.0, "<{name}>{}</{name}>", escape(&text.to_string())).unwrap();
+        self
+    }
+
+    pub fn finish(mut self, root: &str) -> Response {
+        self.close(root);
+        response(self.0)
+    }
+}
+
+/// The region of a bucket, which is empty for `us-east-1`
+pub fn location(region: &str) -> Response {
+    let region = if region == "us-east-1" { "" } else { region };
+    response(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><LocationConstraint xmlns="{S3_NS}">{}</LocationConstraint>"#,
+        escape(region)
+    ))
+}
+
+/// The children named so, the namespace is ignored
+pub fn children<'e>(element: &'e Element, name: &'e str) -> impl Iterator<Item = &'e Element> {
+    element
+        .children
+        .iter()
+        .filter(move |child| child.name == name)
+}
+
+/// The unescaped text of the first child named so
+pub fn child_text(element: &Element, name: &str) -> Option<String> {
+    let child = children(element, name).next()?;
+    unescape(&child.inner).ok().map(|text| text.into_owned())
+}
+
+pub fn response(body: String) -> Response {
+    Response::builder()
+        .header(CONTENT_TYPE, XML_TYPE)
+        .body(Body::from_string(body))
+}
+
+/// Like `2009-10-12T17:50:30.000Z`
+pub fn timestamp(time: SystemTime) -> String {
+    let time = OffsetDateTime::from(time);
+    time.replace_nanosecond(time.millisecond() as u32 * 1_000_000)
+        .unwrap_or(time)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
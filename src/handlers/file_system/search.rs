@@ -0,0 +1,161 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine;
+use poem::handler;
+use poem::web::{Data, Query};
+use serde::Deserialize;
+use tokio::fs;
+
+use super::listing::Kind;
+use super::{check_owner, check_relative, MAX_LIMIT};
+use crate::config::Workspace;
+use crate::models::fs::{Found, FoundEntry, FsEntry};
+use crate::models::permission::User;
+use crate::reply::{ReplyData, ReplyError};
+use crate::search::{DocKind, Matcher, SearchIndex};
+use crate::settings::HomeOwnership;
+use crate::utils::time::TimeFormat;
+
+impl From<Kind> for DocKind {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Dir => DocKind::Dir,
+            Kind::File => DocKind::File,
+            Kind::Symlink => DocKind::Symlink,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SearchParam {
+    /// A glob or substring of the names, or the words in the files with `text=true`
+    q: String,
+    #[serde(default)]
+    text: bool,
+    /// The directory searched, the whole workspace if absent
+    #[serde(default)]
+    path: PathBuf,
+    kind: Option<Kind>,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    /// Where the page starts, from `next_cursor` of the previous page
+    cursor: Option<String>,
+    #[serde(default)]
+    time_format: TimeFormat,
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+/// **Search the workspace by names or the words in the files**
+/// - Ok: return a page of the matched entries with their paths,
+///   the home directories of others are left out
+/// - Err:
+///   - path contains `..` => ReplyError::OutsideWorkspace
+///   - path is inside the home of another user => ReplyError::PermissionDenied
+///   - empty `q`, malformed glob, `limit` or `cursor` => ReplyError::InvalidParam
+///   - `text=true` without the full-text index => ReplyError::InvalidParam
+#[handler]
+pub async fn search(
+    Data(workspace): Data<&Arc<Workspace>>,
+    Data(index): Data<&Arc<SearchIndex>>,
+    ownership: Option<Data<&HomeOwnership>>,
+    user: Option<Data<&User>>,
+    Query(param): Query<SearchParam>,
+) -> Result<ReplyData<Found>, ReplyError> {
+    let invalid = |name: &str| ReplyError::InvalidParam(name.to_owned());
+    let ownership = ownership.map(|Data(ownership)| ownership);
+    let user = user.map(|Data(user)| user);
+
+    check_relative(&param.path)?;
+    if let Some(ownership) = ownership {
+        check_owner(&param.path, ownership, user)?;
+    }
+
+    if param.q.is_empty() {
+        return Err(invalid("q"));
+    }
+    if param.text && !index.full_text() {
+        return Err(invalid("text"));
+    }
+    if param.limit == 0 {
+        return Err(invalid("limit"));
+    }
+    let matcher = Matcher::new(&param.q, param.text).ok_or_else(|| invalid("q"))?;
+
+    // the home served at `/wk/home` is a directory of the indexed workspace
+    let base = index
+        .key(workspace.path())
+        .ok_or(ReplyError::OutsideWorkspace)?;
+    let under = index
+        .key(&workspace.join(&param.path))
+        .ok_or(ReplyError::OutsideWorkspace)?;
+
+    let after = param
+        .cursor
+        .map(|cursor| {
+            BASE64
+                .decode(cursor)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or_else(|| invalid("cursor"))
+        })
+        .transpose()?;
+
+    index.build().await?;
+    // the subtree is scanned off the executor
+    let (ownership, user) = (ownership.cloned(), user.cloned());
+    let (keys, more) = index
+        .search(
+            matcher,
+            under,
+            param.kind.map(Into::into),
+            after,
+            param.limit.min(MAX_LIMIT),
+            move |key| {
+                ownership.as_ref().is_none_or(|ownership| {
+                    check_owner(Path::new(key), ownership, user.as_ref()).is_ok()
+                })
+            },
+        )
+        .await?;
+
+    let mut entries = Vec::with_capacity(keys.len());
+    for key in &keys {
+        let path = index.root().join(key);
+        // removed after being indexed
+        let md = match fs::symlink_metadata(&path).await {
+            Ok(md) => md,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        entries.push(FoundEntry {
+            path: relative_key(key, &base).to_owned(),
+            entry: FsEntry::new(&path, &md, param.time_format).await?,
+        });
+    }
+
+    Ok(ReplyData(Found {
+        entries,
+        next_cursor: more
+            .then(|| keys.last().map(|last| BASE64.encode(last)))
+            .flatten(),
+    }))
+}
+
+/// The key relative to the workspace whose key is `base`
+fn relative_key<'k>(key: &'k str, base: &str) -> &'k str {
+    if base.is_empty() {
+        key
+    } else {
+        key.strip_prefix(base)
+            .and_then(|key| key.strip_prefix('/'))
+            .unwrap_or(key)
+    }
+}
@@ -1,19 +1,35 @@
 use std::io;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::Bytes;
+use bytesize::ByteSize;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use poem::http::header::{
+    CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH, LAST_MODIFIED,
+};
 use poem::http::StatusCode;
-use poem::test::{TestClient, TestForm, TestFormField};
+use poem::test::{TestClient, TestForm, TestFormField, TestJson};
 use poem::{delete, post, put};
 use poem::{get, Route};
-use poem::{Endpoint, EndpointExt};
+use poem::{Body, Endpoint, EndpointExt};
 use tempdir::TempDir;
 use tokio::fs;
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncWriteExt, BufWriter};
 
+use crate::config::{Quota, Search};
+use crate::quota::Quotas;
 use crate::reply::status::*;
 use crate::router::{http_error, reply_error};
+use crate::search::SearchIndex;
+use crate::settings::MaxUpload;
+use crate::utils::etag::etag;
 use crate::utils::tests::*;
 
 async fn create_txt(path: PathBuf, content: &str) -> io::Result<()> {
@@ -41,7 +57,10 @@ where
     let app = Route::new()
         .at(path, ep)
         .catch_error(reply_error)
-        .data(Arc::new(wk));
+        .data(Arc::new(SearchIndex::new(&wk, &Search::default())))
+        .data(Arc::new(wk))
+        .data(Arc::new(Quotas::default()))
+        .data(MaxUpload(ByteSize::mib(1)));
 
     (tmp_dir, TestClient::new(app))
 }
@@ -85,6 +104,492 @@ async fn test_download() -> io::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_head_file() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    let app = Route::new()
+        .at("/*path", get(super::download).head(super::head_file))
+        .catch_error(http_error)
+        .data(Arc::new(wk));
+    let client = TestClient::new(app);
+
+    client
+        .head("/not-found")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    create_txt(root.join("head.txt"), "the content").await?;
+    let resp = client.head("/head.txt").send().await;
+    resp.assert_status_is_ok();
+    resp.assert_header(CONTENT_LENGTH, "11");
+    resp.assert_header(CONTENT_TYPE, "text/plain");
+    resp.assert_header(ETAG, etag(&fs::metadata(root.join("head.txt")).await?));
+    resp.assert_header_exist(LAST_MODIFIED);
+    resp.assert_header(CONTENT_DISPOSITION, r#"attachment; filename="head.txt""#);
+
+    // the headers of the download
+    let got = client.get("/head.txt").send().await;
+    assert_eq!(resp.0.headers(), got.0.headers());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stat() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/*path", get(super::stat));
+    let root = tmp_dir.path();
+
+    assert_buss_status(
+        NOT_FOUND,
+        client.get("/not-found").send().await.json().await,
+    );
+    assert_buss_status(WORKSPACE_ROOT, client.get("/").send().await.json().await);
+
+    create_txt(root.join("stat.txt"), "abc").await?;
+    let reply = client
+        .get("/stat.txt")
+        .query("checksum", &"md5,sha-256")
+        .send()
+        .await
+        .json()
+        .await;
+    let data = reply.value().object().get("data").object();
+    data.get("name").assert_string("stat.txt");
+    data.get("kind").assert_string("File");
+    data.get("etag")
+        .assert_string(&etag(&fs::metadata(root.join("stat.txt")).await?));
+    let checksums = data.get("checksums").object();
+    checksums
+        .get("md5")
+        .assert_string("900150983cd24fb0d6963f7d28e17f72");
+    checksums
+        .get("sha-256")
+        .assert_string("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+    assert_buss_status(
+        INVALID_PARAM,
+        client
+            .get("/stat.txt")
+            .query("checksum", &"rot13")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    fs::create_dir(root.join("dir")).await?;
+    assert_buss_status(
+        IS_A_DIRECTORY,
+        client
+            .get("/dir")
+            .query("checksum", &"md5")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    let reply = client.get("/dir").send().await.json().await;
+    let data = reply.value().object().get("data").object();
+    data.get("kind").assert_string("Dir");
+    assert!(data.get_opt("checksums").is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_checksum() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    let app = Route::new()
+        .at("/checksum/*path", get(super::checksum))
+        .at("/file/*path", get(super::download).head(super::head_file))
+        .catch_error(reply_error)
+        .data(Arc::new(wk));
+    let client = TestClient::new(app);
+
+    assert_buss_status(
+        NOT_FOUND,
+        client
+            .get("/checksum/not-found")
+            .query("algo", &"sha256")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    create_txt(root.join("abc.txt"), "abc").await?;
+    let resp = client.get("/file/abc.txt").send().await;
+    resp.assert_status_is_ok();
+    resp.assert_header_is_not_exist("repr-digest");
+
+    let reply = client
+        .get("/checksum/abc.txt")
+        .query("algo", &"crc32c")
+        .send()
+        .await
+        .json()
+        .await;
+    let data = reply.value().object().get("data").object();
+    data.get("algo").assert_string("crc32c");
+    data.get("digest").assert_string("364b3fb7");
+
+    let reply = client
+        .get("/checksum/abc.txt")
+        .query("algo", &"sha256")
+        .send()
+        .await
+        .json()
+        .await;
+    reply
+        .value()
+        .object()
+        .get("data")
+        .object()
+        .get("digest")
+        .assert_string("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+    // the cached digests are kept in extended attributes if supported
+    let resp = client.get("/file/abc.txt").send().await;
+    if resp.0.headers().contains_key("repr-digest") {
+        resp.assert_header(
+            "repr-digest",
+            "sha-256=:ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=:, crc32c=:Nks/tw==:",
+        );
+        // and in the headers of HEAD
+        let head = client.head("/file/abc.txt").send().await;
+        assert_eq!(head.0.headers(), resp.0.headers());
+    }
+
+    assert!(client
+        .get("/checksum/abc.txt")
+        .query("algo", &"rot13")
+        .send()
+        .await
+        .0
+        .status()
+        .is_client_error());
+
+    fs::create_dir(root.join("dir")).await?;
+    assert_buss_status(
+        IS_A_DIRECTORY,
+        client
+            .get("/checksum/dir")
+            .query("algo", &"md5")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    let search = Search {
+        full_text: true,
+        ..Default::default()
+    };
+    let app = Route::new()
+        .at("/search", get(super::search::search))
+        .at("/put/*path", put(super::put_file))
+        .at("/rename/*path", put(super::rename))
+        .at("/remove/*path", delete(super::remove))
+        .catch_error(reply_error)
+        .data(Arc::new(SearchIndex::new(&wk, &search)))
+        .data(Arc::new(wk))
+        .data(Arc::new(Quotas::default()))
+        .data(MaxUpload(ByteSize::mib(1)));
+    let client = TestClient::new(app);
+    let paths = |reply: TestJson| -> Vec<String> {
+        reply
+            .value()
+            .object()
+            .get("data")
+            .object()
+            .get("entries")
+            .array()
+            .iter()
+            .map(|entry| entry.object().get("path").string().to_owned())
+            .collect()
+    };
+
+    fs::create_dir(root.join("notes")).await?;
+    create_txt(root.join("notes/report.txt"), "quarterly numbers").await?;
+
+    let reply = client
+        .get("/search")
+        .query("q", &"report")
+        .send()
+        .await
+        .json()
+        .await;
+    assert_eq!(paths(reply), ["notes/report.txt"]);
+
+    // indexed by the handlers
+    client
+        .put("/put/notes/todo/list.md")
+        .query("parents", &true)
+        .body("buy milk")
+        .send()
+        .await
+        .assert_status_is_ok();
+    let reply = client
+        .get("/search")
+        .query("q", &"MILK")
+        .query("text", &true)
+        .send()
+        .await
+        .json()
+        .await;
+    assert_eq!(paths(reply), ["notes/todo/list.md"]);
+
+    client
+        .put("/rename/notes")
+        .query("name", &"archive")
+        .send()
+        .await
+        .assert_status_is_ok();
+    let reply = client
+        .get("/search")
+        .query("q", &"*.??")
+        .send()
+        .await
+        .json()
+        .await;
+    assert_eq!(paths(reply), ["archive/todo/list.md"]);
+
+    client
+        .delete("/remove/archive/todo")
+        .send()
+        .await
+        .assert_status_is_ok();
+    let reply = client
+        .get("/search")
+        .query("q", &"t")
+        .query("path", &"archive")
+        .query("kind", &"file")
+        .query("limit", &1)
+        .send()
+        .await
+        .json()
+        .await;
+    let data = reply.value().object().get("data").object();
+    data.get("next_cursor").assert_null();
+    let entry = data.get("entries").array().get(0).object();
+    entry.get("path").assert_string("archive/report.txt");
+    entry.get("name").assert_string("report.txt");
+
+    for (key, value) in [("q", ""), ("q", "[a"), ("limit", "0"), ("cursor", "%%")] {
+        let mut req = client.get("/search");
+        if key != "q" {
+            req = req.query("q", &"a");
+        }
+        assert_buss_status(
+            INVALID_PARAM,
+            req.query(key, &value).send().await.json().await,
+        );
+    }
+    assert_buss_status(
+        OUTSIDE_WORKSPACE,
+        client
+            .get("/search")
+            .query("q", &"a")
+            .query("path", &"..")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    Ok(())
+}
+
+/// Read the server-sent events as `(id, event, data)`
+async fn next_events(
+    body: &mut (impl futures_util::Stream<Item = io::Result<bytes::Bytes>> + Unpin),
+    n: usize,
+) -> Vec<(String, String, serde_json::Value)> {
+    use futures_util::StreamExt;
+
+    let mut text = String::new();
+    let mut events = Vec::new();
+    while events.len() < n {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+            .await
+            .expect("no more events")
+            .unwrap()
+            .unwrap();
+        text.push_str(std::str::from_utf8(&chunk).unwrap());
+
+        while let Some(end) = text.find("\n\n") {
+            let block: String = text.drain(..end + 2).collect();
+            let field = |name: &str| {
+                block
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .map(|value| value.trim().to_owned())
+            };
+            // the keep-alive comments have no data
+            if let Some(data) = field("data:") {
+                events.push((
+                    field("id:").unwrap(),
+                    field("event:").unwrap(),
+                    serde_json::from_str(&data).unwrap(),
+                ));
+            }
+        }
+    }
+
+    events
+}
+
+#[tokio::test]
+async fn test_watch() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    let app = Route::new()
+        .at("/watch/*path", get(super::watch::watch))
+        .at("/put/*path", put(super::put_file))
+        .at("/rename/*path", put(super::rename))
+        .at("/remove/*path", delete(super::remove))
+        .catch_error(reply_error)
+        .data(Arc::new(SearchIndex::new(&wk, &Search::default())))
+        .data(Arc::new(wk))
+        .data(Arc::new(Quotas::default()))
+        .data(MaxUpload(ByteSize::mib(1)));
+    let client = TestClient::new(app);
+
+    fs::create_dir(root.join("notes")).await?;
+    create_txt(root.join("notes.txt"), "").await?;
+
+    let resp = client.get("/watch/notes").send().await;
+    resp.assert_status_is_ok();
+    resp.assert_content_type("text/event-stream");
+    let mut body = resp.0.into_body().into_bytes_stream();
+
+    let put = |path: &'static str| {
+        client
+            .put(format!("/put/{path}"))
+            .query("parents", &true)
+            .body("content")
+            .send()
+    };
+    put("notes/a.txt").await.assert_status_is_ok();
+    put("notes/sub/b.txt").await.assert_status_is_ok();
+    // neither a direct child
+    put("notes/sub/c.txt").await.assert_status_is_ok();
+    put("other/d.txt").await.assert_status_is_ok();
+    client
+        .put("/rename/notes/a.txt")
+        .query("name", &"e.txt")
+        .send()
+        .await
+        .assert_status_is_ok();
+    client
+        .delete("/remove/notes/sub")
+        .send()
+        .await
+        .assert_status_is_ok();
+
+    let events = next_events(&mut body, 4).await;
+    let names: Vec<_> = events.iter().map(|(_, name, _)| name.as_str()).collect();
+    assert_eq!(names, ["created", "created", "renamed", "removed"]);
+    assert_eq!(events[0].2["path"], "notes/a.txt");
+    assert_eq!(events[1].2["path"], "notes/sub");
+    assert_eq!(events[2].2["from"], "notes/a.txt");
+    assert_eq!(events[2].2["to"], "notes/e.txt");
+    assert_eq!(events[3].2["type"], "removed");
+    assert_eq!(events[3].2["path"], "notes/sub");
+
+    // resume after the first event, recursively
+    let resp = client
+        .get("/watch/notes")
+        .header("Last-Event-ID", &events[0].0)
+        .query("recursive", &true)
+        .send()
+        .await;
+    resp.assert_status_is_ok();
+    let mut body = resp.0.into_body().into_bytes_stream();
+    let resumed = next_events(&mut body, 4).await;
+    let paths: Vec<_> = resumed
+        .iter()
+        .map(|(_, _, data)| data.get("path").unwrap_or(&data["to"]).as_str().unwrap())
+        .collect();
+    assert_eq!(
+        paths,
+        ["notes/sub", "notes/sub/c.txt", "notes/e.txt", "notes/sub"]
+    );
+    assert_eq!(resumed[0].0, events[1].0);
+
+    // from a previous run
+    let resp = client.get("/watch/").query("since", &0).send().await;
+    let mut body = resp.0.into_body().into_bytes_stream();
+    let reset = next_events(&mut body, 1).await;
+    assert_eq!(reset[0].1, "reset");
+
+    assert_buss_status(
+        NOT_FOUND,
+        client.get("/watch/missing").send().await.json().await,
+    );
+    assert_buss_status(
+        NOT_A_DIRECTORY,
+        client.get("/watch/notes.txt").send().await.json().await,
+    );
+    let reply = client
+        .get("/watch/notes")
+        .header("Last-Event-ID", "latest")
+        .send()
+        .await
+        .json()
+        .await;
+    assert_buss_status(INVALID_PARAM, reply);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_watch_websocket() -> io::Result<()> {
+    use crate::events::{self, Change};
+    use futures_util::StreamExt;
+    use poem::listener::{Acceptor, Listener, TcpListener};
+    use poem::Server;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path().to_owned();
+    fs::create_dir(root.join("notes")).await?;
+    let app = Route::new()
+        .at("/watch/*path", get(super::watch::watch))
+        .catch_error(reply_error)
+        .data(Arc::new(wk));
+
+    let acceptor = TcpListener::bind("127.0.0.1:0").into_acceptor().await?;
+    let addr = *acceptor.local_addr()[0].as_socket_addr().unwrap();
+    tokio::spawn(Server::new_with_acceptor(acceptor).run(app));
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/watch/notes"))
+        .await
+        .unwrap();
+
+    events::publish(Change::Created(root.join("other.txt")));
+    events::publish(Change::Created(root.join("notes/a.txt")));
+    let Some(Ok(Message::Text(text))) = socket.next().await else {
+        panic!("no event is received");
+    };
+    let event: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(event["type"], "created");
+    assert_eq!(event["path"], "notes/a.txt");
+    assert!(event["id"].is_u64());
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_upload_file() -> io::Result<()> {
     fn file_form(filename: &str) -> TestForm {
@@ -155,56 +660,92 @@ async fn test_upload_file() -> io::Result<()> {
             .await,
     );
 
+    // a truncated body
+    assert_buss_status(
+        INVALID_PARAM,
+        client
+            .post("/")
+            .content_type("multipart/form-data; boundary=sachima")
+            .body("--sachima\r\nContent-Disposition: form-data; name=\"file\"; filename=\"cut.txt\"\r\n\r\ncut")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert!(!tmp_dir.path().join("cut.txt").exists());
+
     Ok(())
 }
 
 #[tokio::test]
-async fn test_rename() -> io::Result<()> {
-    let (tmp_dir, client) = setup("/*path", put(super::rename));
+async fn test_upload_limit() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
     let root = tmp_dir.path();
+    let app = Route::new()
+        .at("/*path", post(super::upload.before(super::limit_size)))
+        .catch_error(reply_error)
+        .data(Arc::new(SearchIndex::new(&wk, &Search::default())))
+        .data(Arc::new(wk))
+        .data(Arc::new(Quotas::default()))
+        .data(MaxUpload(ByteSize::b(16)));
+    let client = TestClient::new(app);
+
+    // a chunked body has no Content-Length, so the limit holds while reading it
+    let chunked = |filename: &str, content: &str| {
+        let body = format!(
+            "--sachima\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\r\n{content}\r\n--sachima--\r\n"
+        );
+        Body::from_bytes_stream(futures_util::stream::once(async move {
+            Ok::<_, io::Error>(Bytes::from(body))
+        }))
+    };
 
     assert_buss_status(
-        NOT_FOUND,
+        OK,
         client
-            .put("/not-found")
-            .query("name", &"rename-not-found")
+            .post("/")
+            .content_type("multipart/form-data; boundary=sachima")
+            .body(chunked("small.txt", "small"))
             .send()
             .await
             .json()
             .await,
     );
+    assert_eq!(fs::read_to_string(root.join("small.txt")).await?, "small");
 
-    create_txt(root.join("rename-to-already-exists"), "").await?;
-    create_txt(root.join("already-exists"), "").await?;
     assert_buss_status(
-        ALREADY_EXISTS,
+        RESOURCE_TOO_LARGE,
         client
-            .put("/rename-to-already-exists")
-            .query("name", &"already-exists")
+            .post("/")
+            .content_type("multipart/form-data; boundary=sachima")
+            .body(chunked("large.txt", "more than sixteen bytes"))
             .send()
             .await
             .json()
             .await,
     );
+    assert!(!root.join("large.txt").exists());
 
-    fs::create_dir(root.join("rename-old-dir")).await?;
     assert_buss_status(
-        OK,
+        RESOURCE_TOO_LARGE,
         client
-            .put("/rename-old-dir")
-            .query("name", &"rename-new-dir")
+            .post("/")
+            .multipart(
+                TestForm::new()
+                    .field(TestFormField::bytes("more than sixteen bytes").filename("large.txt")),
+            )
             .send()
             .await
             .json()
             .await,
     );
 
-    fs::create_dir(root.join("rename-old-file")).await?;
     assert_buss_status(
-        OK,
+        INVALID_HEADER,
         client
-            .put("/rename-old-file")
-            .query("name", &"rename-new-file")
+            .post("/")
+            .header(CONTENT_LENGTH, "many")
+            .multipart(TestForm::new().field(TestFormField::bytes("x").filename("x.txt")))
             .send()
             .await
             .json()
@@ -215,30 +756,333 @@ async fn test_rename() -> io::Result<()> {
 }
 
 #[tokio::test]
-async fn test_remove_entity() -> io::Result<()> {
-    let (tmp_dir, client) = setup("/*path", delete(super::remove));
-    let root = tmp_dir.path();
-
-    assert_buss_status(
-        NOT_FOUND,
-        client.delete("/not-found").send().await.json().await,
-    );
-
-    fs::create_dir(root.join("remove-dir")).await?;
-    assert_buss_status(OK, client.delete("/remove-dir").send().await.json().await);
+async fn test_upload_conflict() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/*path", post(super::upload));
+    let path = tmp_dir.path().join("conflict.txt");
+    create_txt(path.clone(), "the existing content").await?;
 
-    create_txt(root.join("remove-file"), "").await?;
-    assert_buss_status(OK, client.delete("/remove-file").send().await.json().await);
+    let file_form = |content: &'static str| {
+        TestForm::new().field(TestFormField::text(content).filename("conflict.txt"))
+    };
 
-    Ok(())
-}
+    let reply = client
+        .post("/")
+        .query("on-conflict", &"rename")
+        .multipart(file_form("renamed"))
+        .send()
+        .await
+        .json()
+        .await;
+    let data = reply.value().object().get("data").object();
+    data.get("name").assert_string("conflict (1).txt");
+    data.get("skipped").assert_bool(false);
 
-#[tokio::test]
-async fn test_read_dir() -> io::Result<()> {
-    let (tmp_dir, client) = setup("/*path", get(super::read_dir));
+    let reply = client
+        .post("/")
+        .query("on-conflict", &"skip")
+        .multipart(file_form("skipped"))
+        .send()
+        .await
+        .json()
+        .await;
+    reply
+        .value()
+        .object()
+        .get("data")
+        .object()
+        .get("skipped")
+        .assert_bool(true);
+    assert_eq!(fs::read_to_string(&path).await?, "the existing content");
 
     assert_buss_status(
-        NOT_FOUND,
+        PRECONDITION_FAILED,
+        client
+            .post("/")
+            .query("on-conflict", &"overwrite")
+            .header(IF_MATCH, r#""stale""#)
+            .multipart(file_form("clobbered"))
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    let current = etag(&fs::metadata(&path).await?);
+    assert_buss_status(
+        OK,
+        client
+            .post("/")
+            .query("on-conflict", &"overwrite")
+            .header(IF_MATCH, current)
+            .multipart(file_form("overwritten"))
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert_eq!(fs::read_to_string(&path).await?, "overwritten");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upload_quota() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let quota: Quota = toml::from_str("files = 1").unwrap();
+    let quotas = Quotas::new(tmp_dir.path(), Some(&quota), false);
+    let app = Route::new()
+        .at("/upload/*path", post(super::upload))
+        .at("/remove/*path", delete(super::remove))
+        .catch_error(reply_error)
+        .data(Arc::new(SearchIndex::new(&wk, &Search::default())))
+        .data(Arc::new(wk))
+        .data(Arc::new(quotas))
+        .data(MaxUpload(ByteSize::mib(1)));
+    let client = TestClient::new(app);
+
+    let file_form =
+        |name: &str| TestForm::new().field(TestFormField::bytes("the content").filename(name));
+
+    assert_buss_status(
+        OK,
+        client
+            .post("/upload/")
+            .multipart(file_form("first.txt"))
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    assert_buss_status(
+        QUOTA_EXCEEDED,
+        client
+            .post("/upload/")
+            .multipart(file_form("second.txt"))
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert!(!fs::try_exists(tmp_dir.path().join("second.txt")).await?);
+
+    assert_buss_status(
+        OK,
+        client.delete("/remove/first.txt").send().await.json().await,
+    );
+    assert_buss_status(
+        OK,
+        client
+            .post("/upload/")
+            .multipart(file_form("second.txt"))
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_put_file() -> io::Result<()> {
+    let (tmp_dir, wk) = setup_workspace();
+    let root = tmp_dir.path();
+    let app = Route::new()
+        .at("/*path", put(super::put_file))
+        .catch_error(reply_error)
+        .data(Arc::new(SearchIndex::new(&wk, &Search::default())))
+        .data(Arc::new(wk))
+        .data(Arc::new(Quotas::default()))
+        .data(MaxUpload(ByteSize::b(16)));
+    let client = TestClient::new(app);
+
+    assert_buss_status(
+        OK,
+        client
+            .put("/put.txt")
+            .header("X-Mtime", "1000000000")
+            .body("the put content")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("put.txt")).await?,
+        "the put content"
+    );
+    assert_eq!(
+        fs::metadata(root.join("put.txt")).await?.modified()?,
+        UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+    );
+
+    assert_buss_status(
+        ALREADY_EXISTS,
+        client.put("/put.txt").body("").send().await.json().await,
+    );
+
+    assert_buss_status(
+        MISSING_PARENT,
+        client
+            .put("/missing-parent/put.txt")
+            .body("")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    // the parents made for a rejected body are removed
+    assert_buss_status(
+        RESOURCE_TOO_LARGE,
+        client
+            .put("/x/y/large.txt")
+            .query("parents", &true)
+            .body("more than sixteen bytes")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert!(!root.join("x").exists());
+
+    assert_buss_status(
+        RESOURCE_TOO_LARGE,
+        client
+            .put("/large.txt")
+            .body("more than sixteen bytes")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert!(!fs::try_exists(root.join("large.txt")).await?);
+
+    let md5 = BASE64.encode(Md5::digest("verified"));
+    let sha256 = BASE64.encode(Sha256::digest("verified"));
+    assert_buss_status(
+        DIGEST_MISMATCH,
+        client
+            .put("/corrupted.txt")
+            .header("Content-MD5", &md5)
+            .body("corrupted")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert!(!fs::try_exists(root.join("corrupted.txt")).await?);
+
+    assert_buss_status(
+        OK,
+        client
+            .put("/verified.txt")
+            .header("Content-MD5", &md5)
+            .header("Digest", format!("sha-256={sha256}, unknown=abc"))
+            .body("verified")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    assert_buss_status(
+        INVALID_HEADER,
+        client
+            .put("/invalid.txt")
+            .header("X-Mtime", "yesterday")
+            .body("")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rename() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/*path", put(super::rename));
+    let root = tmp_dir.path();
+
+    assert_buss_status(
+        NOT_FOUND,
+        client
+            .put("/not-found")
+            .query("name", &"rename-not-found")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    create_txt(root.join("rename-to-already-exists"), "").await?;
+    create_txt(root.join("already-exists"), "").await?;
+    assert_buss_status(
+        ALREADY_EXISTS,
+        client
+            .put("/rename-to-already-exists")
+            .query("name", &"already-exists")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    fs::create_dir(root.join("rename-old-dir")).await?;
+    assert_buss_status(
+        OK,
+        client
+            .put("/rename-old-dir")
+            .query("name", &"rename-new-dir")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    fs::create_dir(root.join("rename-old-file")).await?;
+    assert_buss_status(
+        OK,
+        client
+            .put("/rename-old-file")
+            .query("name", &"rename-new-file")
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_remove_entity() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/*path", delete(super::remove));
+    let root = tmp_dir.path();
+
+    assert_buss_status(
+        NOT_FOUND,
+        client.delete("/not-found").send().await.json().await,
+    );
+
+    fs::create_dir(root.join("remove-dir")).await?;
+    assert_buss_status(OK, client.delete("/remove-dir").send().await.json().await);
+
+    create_txt(root.join("remove-file"), "").await?;
+    assert_buss_status(OK, client.delete("/remove-file").send().await.json().await);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_dir() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/*path", get(super::read_dir));
+
+    assert_buss_status(
+        NOT_FOUND,
         client.get("/not-found").send().await.json().await,
     );
 
@@ -265,6 +1109,279 @@ async fn test_read_dir() -> io::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_read_dir_page() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/*path", get(super::read_dir));
+    let root = tmp_dir.path();
+    for i in 1..=12 {
+        create_txt(root.join(format!("{i}.txt")), &"x".repeat(i)).await?;
+    }
+    fs::create_dir(root.join("dir")).await?;
+    create_txt(root.join(".hidden"), "").await?;
+
+    let names = |reply: &poem::test::TestJson| -> (Vec<String>, Option<String>) {
+        let data = reply.value().object().get("data").object();
+        let names = data
+            .get("entries")
+            .array()
+            .iter()
+            .map(|entry| entry.object().get("name").string().to_owned())
+            .collect();
+        let cursor = data.get("next_cursor").deserialize();
+        (names, cursor)
+    };
+
+    // natural order, directories first
+    let reply = client.get("/").query("limit", &5).send().await.json().await;
+    let (page, cursor) = names(&reply);
+    assert_eq!(page, ["dir", ".hidden", "1.txt", "2.txt", "3.txt"]);
+
+    let mut all = page;
+    let mut cursor = cursor;
+    while let Some(after) = cursor {
+        let reply = client
+            .get("/")
+            .query("limit", &5)
+            .query("cursor", &after)
+            .send()
+            .await
+            .json()
+            .await;
+        let (page, next) = names(&reply);
+        all.extend(page);
+        cursor = next;
+    }
+    assert_eq!(all.len(), 14);
+    assert_eq!(all.last().unwrap(), "12.txt");
+
+    let reply = client
+        .get("/")
+        .query("sort", &"size")
+        .query("order", &"desc")
+        .query("filter", &"1*.txt")
+        .query("limit", &2)
+        .send()
+        .await
+        .json()
+        .await;
+    let (page, cursor) = names(&reply);
+    assert_eq!(page, ["12.txt", "11.txt"]);
+    assert!(cursor.is_some());
+
+    let reply = client
+        .get("/")
+        .query("filter", &r"^\d\.txt$")
+        .query("regex", &true)
+        .query("kind", &"file")
+        .send()
+        .await
+        .json()
+        .await;
+    assert_eq!(names(&reply).0.len(), 9);
+
+    let reply = client
+        .get("/")
+        .query("hidden", &false)
+        .query("kind", &"dir")
+        .send()
+        .await
+        .json()
+        .await;
+    assert_eq!(names(&reply), (vec!["dir".to_owned()], None));
+
+    assert_buss_status(
+        INVALID_PARAM,
+        client
+            .get("/")
+            .query("sort", &"modified")
+            .query("cursor", &cursor.unwrap())
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert_buss_status(
+        INVALID_PARAM,
+        client
+            .get("/")
+            .query("filter", &"(")
+            .query("regex", &true)
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    let big = root.join("big");
+    fs::create_dir(&big).await?;
+    for i in 0..=super::DEFAULT_LIMIT {
+        create_txt(big.join(i.to_string()), "").await?;
+    }
+
+    // v1 lists the whole directory without `limit`
+    let reply = client.get("/big").send().await.json().await;
+    let (page, cursor) = names(&reply);
+    assert_eq!(page.len(), super::DEFAULT_LIMIT + 1);
+    assert!(cursor.is_none());
+
+    // v2 is paged without `limit`
+    let reply = client
+        .get("/big")
+        .header("X-Api-Version", "2")
+        .send()
+        .await
+        .json()
+        .await;
+    let (page, cursor) = names(&reply);
+    assert_eq!(page.len(), super::DEFAULT_LIMIT);
+    let reply = client
+        .get("/big")
+        .query("cursor", &cursor.unwrap())
+        .send()
+        .await
+        .json()
+        .await;
+    let (page, cursor) = names(&reply);
+    assert_eq!(page, [super::DEFAULT_LIMIT.to_string()]);
+    assert!(cursor.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_dir_metadata() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/*path", get(super::read_dir));
+    let root = tmp_dir.path();
+    create_txt(root.join("a.txt"), "a").await?;
+    fs::symlink("a.txt", root.join("link")).await?;
+
+    let reply = client.get("/").send().await.json().await;
+    let entries = reply
+        .value()
+        .object()
+        .get("data")
+        .object()
+        .get("entries")
+        .array();
+    let file = entries.get(0).object();
+    file.get("kind").assert_string("File");
+    file.get("mime").assert_string("text/plain");
+    file.get("nlink").assert_i64(1);
+    // the old form
+    file.get("modified").string().parse::<u64>().unwrap();
+    let link = entries.get(1).object();
+    link.get("kind").assert_string("Symlink");
+    link.get("target").assert_string("a.txt");
+    link.get("size").assert_null();
+
+    let reply = client
+        .get("/")
+        .query("kind", &"symlink")
+        .query("time-format", &"unix")
+        .send()
+        .await
+        .json()
+        .await;
+    let entries = reply
+        .value()
+        .object()
+        .get("data")
+        .object()
+        .get("entries")
+        .array();
+    entries.assert_len(1);
+    entries.get(0).object().get("accessed").i64();
+
+    let reply = client
+        .get("/")
+        .query("time-format", &"rfc3339")
+        .send()
+        .await
+        .json()
+        .await;
+    assert!(reply
+        .value()
+        .object()
+        .get("data")
+        .object()
+        .get("entries")
+        .array()
+        .get(0)
+        .object()
+        .get("modified")
+        .string()
+        .ends_with('Z'));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_tree() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/*path", get(super::read_dir));
+    let root = tmp_dir.path();
+    fs::create_dir_all(root.join("a/b/c")).await?;
+    create_txt(root.join("a/1.txt"), "12345").await?;
+    create_txt(root.join("a/b/2.txt"), "123").await?;
+
+    let reply = client
+        .get("/")
+        .query("depth", &2)
+        .query("with-size", &true)
+        .send()
+        .await
+        .json()
+        .await;
+    let entries = reply
+        .value()
+        .object()
+        .get("data")
+        .object()
+        .get("entries")
+        .array();
+    let a = entries.get(0).object();
+    a.get("name").assert_string("a");
+    a.get("size").assert_string(&ByteSize::b(8).to_string());
+    a.get("items").assert_i64(4);
+
+    // `b` is at the second level, so its children aren't listed
+    let b = a.get("children").array().get(0).object();
+    b.get("name").assert_string("b");
+    b.get("items").assert_i64(2);
+    assert!(b.get_opt("children").is_none());
+
+    let reply = client
+        .get("/a")
+        .query("depth", &"unlimited")
+        .send()
+        .await
+        .json()
+        .await;
+    let b = reply
+        .value()
+        .object()
+        .get("data")
+        .object()
+        .get("entries")
+        .array()
+        .get(0)
+        .object();
+    b.get("size").assert_null();
+    b.get("children")
+        .array()
+        .get(0)
+        .object()
+        .get("name")
+        .assert_string("c");
+
+    client
+        .get("/")
+        .query("depth", &0)
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_mkdir() -> io::Result<()> {
     let (tmp_dir, client) = setup("/*path", post(super::mkdir));
@@ -287,5 +1404,207 @@ async fn test_mkdir() -> io::Result<()> {
 
     assert_buss_status(OK, client.post("/make-dir").send().await.json().await);
 
+    let reply = client
+        .post("/a/b/c")
+        .query("parents", &true)
+        .send()
+        .await
+        .json()
+        .await;
+    reply
+        .value()
+        .object()
+        .get("data")
+        .assert_string_array(&["a", "a/b", "a/b/c"]);
+
+    // existing directories are fine like `mkdir -p`
+    let reply = client
+        .post("/a/b")
+        .query("parents", &true)
+        .send()
+        .await
+        .json()
+        .await;
+    reply.value().object().get("data").array().assert_is_empty();
+
+    create_txt(tmp_dir.path().join("file.txt"), "").await?;
+    assert_buss_status(
+        NOT_A_DIRECTORY,
+        client
+            .post("/file.txt/dir")
+            .query("parents", &true)
+            .send()
+            .await
+            .json()
+            .await,
+    );
+
+    // symlinks are descended only if they link inside the workspace
+    let outside = TempDir::new("outside")?;
+    fs::symlink(outside.path(), tmp_dir.path().join("escape")).await?;
+    assert_buss_status(
+        OUTSIDE_WORKSPACE,
+        client
+            .post("/escape/dir")
+            .query("parents", &true)
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert!(!outside.path().join("dir").exists());
+
+    fs::symlink("a", tmp_dir.path().join("alias")).await?;
+    assert_buss_status(
+        OK,
+        client
+            .post("/alias/d")
+            .query("parents", &true)
+            .send()
+            .await
+            .json()
+            .await,
+    );
+    assert!(tmp_dir.path().join("a/d").is_dir());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upload_parents() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/*path", post(super::upload));
+    fs::create_dir(tmp_dir.path().join("a")).await?;
+
+    let reply = client
+        .post("/a/b/c")
+        .query("parents", &true)
+        .multipart(TestForm::new().field(TestFormField::text("nested").filename("nested.txt")))
+        .send()
+        .await
+        .json()
+        .await;
+    reply
+        .value()
+        .object()
+        .get("data")
+        .object()
+        .get("created")
+        .assert_string_array(&["a/b", "a/b/c"]);
+    assert_eq!(
+        fs::read_to_string(tmp_dir.path().join("a/b/c/nested.txt")).await?,
+        "nested"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_batch() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/batch", post(super::batch::batch));
+    let root = tmp_dir.path();
+    create_txt(root.join("a.txt"), "a").await?;
+    create_txt(root.join("b.txt"), "b").await?;
+
+    let reply = client
+        .post("/batch")
+        .content_type("application/json")
+        .body(
+            r#"{"ops": [
+                {"op": "mkdir", "path": "dir/nested", "parents": true},
+                {"op": "copy", "path": "a.txt", "to": "copied.txt"},
+                {"op": "rename", "path": "b.txt", "name": "renamed.txt"},
+                {"op": "remove", "path": "missing.txt"},
+                {"op": "move", "path": "../a.txt", "to": "a.txt"},
+                {"op": "copy", "path": "", "to": "root"}
+            ]}"#,
+        )
+        .send()
+        .await
+        .json()
+        .await;
+    let data = reply.value().object().get("data").object();
+    data.get("rolled_back").assert_bool(false);
+
+    let results = data.get("results").array();
+    results.assert_len(6);
+    let status = |i: usize| results.get(i).object().get("status").i64();
+    assert_eq!(status(0), OK as i64);
+    results
+        .get(0)
+        .object()
+        .get("data")
+        .assert_string_array(&["dir", "dir/nested"]);
+    assert_eq!(status(1), OK as i64);
+    results.get(1).object().get("data").assert_null();
+    assert_eq!(status(2), OK as i64);
+    assert_eq!(status(3), NOT_FOUND as i64);
+    assert_eq!(status(4), OUTSIDE_WORKSPACE as i64);
+    assert_eq!(status(5), WORKSPACE_ROOT as i64);
+
+    assert!(root.join("dir/nested").is_dir());
+    assert_eq!(fs::read_to_string(root.join("copied.txt")).await?, "a");
+    assert_eq!(fs::read_to_string(root.join("a.txt")).await?, "a");
+    assert_eq!(fs::read_to_string(root.join("renamed.txt")).await?, "b");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_batch_transactional() -> io::Result<()> {
+    let (tmp_dir, client) = setup("/batch", post(super::batch::batch));
+    let root = tmp_dir.path();
+    fs::create_dir(root.join("dir")).await?;
+    create_txt(root.join("dir/a.txt"), "a").await?;
+    create_txt(root.join("b.txt"), "b").await?;
+
+    let reply = client
+        .post("/batch")
+        .content_type("application/json")
+        .body(
+            r#"{"transactional": true, "ops": [
+                {"op": "remove", "path": "b.txt"},
+                {"op": "copy", "path": "dir", "to": "copied"},
+                {"op": "move", "path": "dir", "to": "moved"},
+                {"op": "mkdir", "path": "x/y", "parents": true},
+                {"op": "move", "path": "moved", "to": "moved/inner"},
+                {"op": "remove", "path": "never-run.txt"}
+            ]}"#,
+        )
+        .send()
+        .await
+        .json()
+        .await;
+    let data = reply.value().object().get("data").object();
+    data.get("rolled_back").assert_bool(true);
+
+    // stopped at the first failure
+    let results = data.get("results").array();
+    results.assert_len(5);
+    results
+        .get(4)
+        .object()
+        .get("status")
+        .assert_i64(INTO_ITSELF as i64);
+
+    assert_eq!(fs::read_to_string(root.join("b.txt")).await?, "b");
+    assert_eq!(fs::read_to_string(root.join("dir/a.txt")).await?, "a");
+    for undone in ["copied", "moved", "x"] {
+        assert!(!fs::try_exists(root.join(undone)).await?);
+    }
+    // nothing staged is left
+    assert_eq!(std::fs::read_dir(root)?.count(), 2);
+
+    let reply = client
+        .post("/batch")
+        .content_type("application/json")
+        .body(r#"{"transactional": true, "ops": [{"op": "remove", "path": "b.txt"}]}"#)
+        .send()
+        .await
+        .json()
+        .await;
+    let data = reply.value().object().get("data").object();
+    data.get("rolled_back").assert_bool(false);
+    assert_eq!(std::fs::read_dir(root)?.count(), 1);
+
     Ok(())
 }
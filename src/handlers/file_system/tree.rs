@@ -0,0 +1,201 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use futures_util::future::BoxFuture;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use tokio::fs;
+use tokio_stream::wrappers::ReadDirStream;
+use tokio_stream::StreamExt;
+
+use super::check_owner;
+use super::listing::{Filter, Key, Order, Sort};
+use crate::config::Workspace;
+use crate::models::fs::FsEntry;
+use crate::models::permission::User;
+use crate::settings::HomeOwnership;
+use crate::utils::atomic;
+use crate::utils::dir_size::dir_size;
+use crate::utils::time::TimeFormat;
+
+/// The deepest tree served, even if it's `unlimited`
+pub const MAX_DEPTH: usize = 32;
+
+/// A positive level count or `unlimited`
+pub fn depth<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let depth = String::deserialize(deserializer)?;
+    if depth == "unlimited" {
+        return Ok(MAX_DEPTH);
+    }
+
+    match depth.parse::<usize>() {
+        Ok(0) | Err(_) => Err(D::Error::custom(
+            "depth should be a positive integer or unlimited",
+        )),
+        Ok(depth) => Ok(depth.min(MAX_DEPTH)),
+    }
+}
+
+/// List a directory as a tree
+pub struct Lister<'a> {
+    pub workspace: &'a Workspace,
+    pub with_size: bool,
+    pub ownership: Option<&'a HomeOwnership>,
+    pub user: Option<&'a User>,
+    pub order: Order,
+    /// Applied to every level
+    pub filter: Filter,
+    pub time_format: TimeFormat,
+}
+
+/// An entry ranked by the order of listing,
+/// made into an `FsEntry` only if it stays in the page
+struct Ranked<'a> {
+    key: Key,
+    path: PathBuf,
+    md: Metadata,
+    order: &'a Order,
+}
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.order.cmp(&self.key, &other.key)
+    }
+}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Ranked<'_> {}
+
+impl Lister<'_> {
+    /// The entries of the directory after the cursor, at most `limit` ones if given,
+    /// and the key of the last one if there are more.
+    pub async fn page(
+        &self,
+        dir: &Path,
+        depth: usize,
+        after: Option<&Key>,
+        limit: Option<usize>,
+    ) -> io::Result<(Vec<FsEntry>, Option<Key>)> {
+        let (ranked, more) = self.scan(dir, after, limit).await?;
+        let next = ranked.last().filter(|_| more).map(|last| last.key.clone());
+
+        Ok((self.expand(ranked, depth).await?, next))
+    }
+
+    /// The entries of the directory,
+    /// and those nested in the directories within `depth` levels.
+    fn read(&self, dir: PathBuf, depth: usize) -> BoxFuture<'_, io::Result<Vec<FsEntry>>> {
+        Box::pin(async move {
+            let (ranked, _) = self.scan(&dir, None, None).await?;
+            self.expand(ranked, depth).await
+        })
+    }
+
+    /// Stream the directory and keep only the entries of the page,
+    /// so a huge directory isn't collected as a whole.
+    async fn scan(
+        &self,
+        dir: &Path,
+        after: Option<&Key>,
+        limit: Option<usize>,
+    ) -> io::Result<(Vec<Ranked<'_>>, bool)> {
+        let mut rd = ReadDirStream::new(fs::read_dir(dir).await?);
+        // the last ones are popped first
+        let mut page = BinaryHeap::new();
+        let mut more = false;
+
+        while let Some(entry) = rd.next().await {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if atomic::is_temp(&name) || !self.filter.passes(&name, entry.file_type().await?.into())
+            {
+                continue;
+            }
+
+            let md = match entry.metadata().await {
+                Ok(md) => md,
+                // removed after listing
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let dir_bytes = if md.is_dir() && self.with_size && self.order.sort == Sort::Size {
+                self.size_of(&entry.path()).await?
+            } else {
+                None
+            };
+
+            let key = self.order.key(&name, &md, dir_bytes);
+            if after.is_some_and(|after| self.order.cmp(&key, after).is_le()) {
+                continue;
+            }
+
+            page.push(Ranked {
+                key,
+                path: entry.path(),
+                md,
+                order: &self.order,
+            });
+            if limit.is_some_and(|limit| page.len() > limit) {
+                page.pop();
+                more = true;
+            }
+        }
+
+        Ok((page.into_sorted_vec(), more))
+    }
+
+    /// Fill the sizes and children of the directories
+    async fn expand(&self, ranked: Vec<Ranked<'_>>, depth: usize) -> io::Result<Vec<FsEntry>> {
+        let mut entries = Vec::with_capacity(ranked.len());
+
+        for Ranked { path, md, .. } in ranked {
+            let mut entry = FsEntry::new(&path, &md, self.time_format).await?;
+            // the home directories of others are listed, but not looked into
+            if !entry.is_dir() || self.is_private(&path) {
+                entries.push(entry);
+                continue;
+            }
+
+            if self.with_size {
+                entry = entry.with_size(dir_size(&path).await?);
+            }
+            if depth > 1 {
+                entry = entry.with_children(self.read(path, depth - 1).await?);
+            }
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    async fn size_of(&self, dir: &Path) -> io::Result<Option<u64>> {
+        if self.is_private(dir) {
+            return Ok(None);
+        }
+
+        Ok(Some(dir_size(dir).await?.bytes))
+    }
+
+    fn is_private(&self, path: &Path) -> bool {
+        let Some(ownership) = self.ownership else {
+            return false;
+        };
+
+        let path = path.strip_prefix(self.workspace.path()).unwrap_or(path);
+        check_owner(path, ownership, self.user).is_err()
+    }
+}
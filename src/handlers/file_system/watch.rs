@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{stream, SinkExt, Stream, StreamExt};
+use poem::http::HeaderMap;
+use poem::web::sse::{Event as SseEvent, SSE};
+use poem::web::websocket::{Message, WebSocket};
+use poem::web::{Data, Path as PathParam, Query};
+use poem::{handler, IntoResponse, Response};
+use serde::Deserialize;
+use tokio::fs;
+use tokio::sync::broadcast::error::RecvError;
+
+use super::check_owner;
+use crate::config::Workspace;
+use crate::events::{self, Change, Event};
+use crate::models::fs::{WatchChange, WatchEvent};
+use crate::models::permission::User;
+use crate::reply::ReplyError;
+use crate::settings::HomeOwnership;
+
+/// Keep the idle connections through the proxies
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+const LAST_EVENT_ID: &str = "last-event-id";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WatchParam {
+    /// Watch the whole subtree instead of the direct children
+    #[serde(default)]
+    recursive: bool,
+    /// Resume after the event id, `Last-Event-ID` takes precedence
+    since: Option<u64>,
+}
+
+/// **Watch the changes inside a directory**
+/// It streams the events over WebSocket if the request upgrades,
+/// otherwise as server-sent events named by the change types.
+///
+/// - Ok: stream the created, modified, renamed and removed paths,
+///   including those changed by other programs,
+///   a `reset` event asks to read the directory again when some events are lost
+/// - Err:
+///   - directory doesn't exist => ReplyError::NotFound
+///   - path isn't directory => ReplyError::NotADirectory
+///   - malformed `since` or `Last-Event-ID` => ReplyError::InvalidParam
+#[handler]
+pub async fn watch(
+    Data(workspace): Data<&Arc<Workspace>>,
+    ownership: Option<Data<&HomeOwnership>>,
+    user: Option<Data<&User>>,
+    PathParam(org): PathParam<PathBuf>,
+    Query(param): Query<WatchParam>,
+    headers: &HeaderMap,
+    ws: Option<WebSocket>,
+) -> Result<Response, ReplyError> {
+    let dir = workspace.join(&org);
+
+    if !fs::try_exists(&dir).await? {
+        return Err(ReplyError::NotFound);
+    }
+
+    if !dir.is_dir() {
+        return Err(ReplyError::NotADirectory);
+    }
+
+    let since = match headers.get(LAST_EVENT_ID) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| ReplyError::InvalidParam(LAST_EVENT_ID.to_owned()))?,
+        ),
+        None => param.since,
+    };
+
+    let scope = Scope {
+        root: workspace.path().to_owned(),
+        dir,
+        recursive: param.recursive,
+        ownership: ownership.map(|Data(ownership)| ownership.clone()),
+        user: user.map(|Data(user)| user.clone()),
+    };
+    let events = watched(scope, since);
+
+    let resp = match ws {
+        Some(ws) => ws
+            .on_upgrade(|mut socket| async move {
+                let mut events = events.boxed();
+                loop {
+                    tokio::select! {
+                        event = events.next() => {
+                            let Some(event) = event else { break };
+                            let text = serde_json::to_string(&event).expect("serializable");
+                            if socket.send(Message::Text(text)).await.is_err() {
+                                break;
+                            }
+                        }
+                        msg = socket.next() => match msg {
+                            Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                            // nothing is expected from the client
+                            Some(Ok(_)) => (),
+                        },
+                    }
+                }
+            })
+            .into_response(),
+        None => SSE::new(events.map(|event| {
+            let data = serde_json::to_string(&event).expect("serializable");
+            SseEvent::message(data)
+                .event_type(event.change.name())
+                .id(event.id.to_string())
+        }))
+        .keep_alive(KEEP_ALIVE)
+        .into_response(),
+    };
+
+    Ok(resp)
+}
+
+/// What the client can see
+struct Scope {
+    root: PathBuf,
+    dir: PathBuf,
+    recursive: bool,
+    ownership: Option<HomeOwnership>,
+    user: Option<User>,
+}
+
+impl Scope {
+    /// The path relative to the workspace root if it's visible
+    fn relative(&self, path: &Path) -> Option<String> {
+        let inside = if self.recursive {
+            path.starts_with(&self.dir)
+        } else {
+            path == self.dir || path.parent() == Some(&self.dir)
+        };
+        if !inside {
+            return None;
+        }
+
+        let relative = path.strip_prefix(&self.root).ok()?;
+        if let Some(ownership) = &self.ownership {
+            check_owner(relative, ownership, self.user.as_ref()).ok()?;
+        }
+
+        Some(relative.to_string_lossy().into_owned())
+    }
+
+    /// A rename across the scope is a creation or removal for the client
+    fn event(&self, event: &Event) -> Option<WatchEvent> {
+        let change = match &event.change {
+            Change::Created(path) => WatchChange::Created {
+                path: self.relative(path)?,
+            },
+            Change::Modified(path) => WatchChange::Modified {
+                path: self.relative(path)?,
+            },
+            Change::Removed(path) => WatchChange::Removed {
+                path: self.relative(path)?,
+            },
+            Change::Renamed { from, to } => match (self.relative(from), self.relative(to)) {
+                (Some(from), Some(to)) => WatchChange::Renamed { from, to },
+                (Some(path), None) => WatchChange::Removed { path },
+                (None, Some(path)) => WatchChange::Created { path },
+                (None, None) => return None,
+            },
+        };
+
+        Some(WatchEvent {
+            id: event.id,
+            change,
+        })
+    }
+}
+
+/// The missed events after `since`, then the new ones
+fn watched(scope: Scope, since: Option<u64>) -> impl Stream<Item = WatchEvent> + Send + 'static {
+    let sub = events::subscribe(since);
+    let reset = sub.reset.map(|id| WatchEvent {
+        id,
+        change: WatchChange::Reset,
+    });
+    let missed: Vec<_> = sub
+        .missed
+        .iter()
+        .filter_map(|event| scope.event(event))
+        .collect();
+
+    let live = stream::unfold(
+        (sub.receiver, scope, 0),
+        |(mut receiver, scope, floor)| async move {
+            loop {
+                match receiver.recv().await {
+                    // the client has read the directory after them
+                    Ok(event) if event.id <= floor => (),
+                    Ok(event) => {
+                        if let Some(event) = scope.event(&event) {
+                            return Some((event, (receiver, scope, floor)));
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {
+                        let id = events::latest();
+                        let reset = WatchEvent {
+                            id,
+                            change: WatchChange::Reset,
+                        };
+                        return Some((reset, (receiver, scope, id)));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        },
+    );
+
+    stream::iter(reset.into_iter().chain(missed)).chain(live)
+}
@@ -1,2 +1,3 @@
+pub mod docs;
 pub mod file_system;
 pub mod permission;
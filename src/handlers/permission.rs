@@ -16,9 +16,11 @@ use crate::db;
 use crate::entity::prelude::Registry;
 use crate::entity::registry;
 use crate::entity::registry::InsertModel as UserModel;
+use crate::home;
 use crate::models::permission::*;
 use crate::reply::ReplyData;
 use crate::reply::ReplyError;
+use crate::settings::SharedSettings;
 use crate::utils::pswd;
 
 #[handler]
@@ -32,6 +34,7 @@ pub async fn register(Json(mut user): Json<UserModel>) -> Result<ReplyData<()>,
 pub async fn login(
     Json(login_form): Json<UserModel>,
     Data(codec): Data<&Arc<Codec<Hs256>>>,
+    Data(settings): Data<&SharedSettings>,
 ) -> Result<ReplyData<Token>, ReplyError> {
     let Some(user) = Registry::find()
         .filter(registry::Column::Username.eq(&login_form.username))
@@ -42,6 +45,14 @@ pub async fn login(
     };
 
     if pswd::verify(&login_form.password, &user.password) {
+        // the home is created on the first login
+        let settings = settings.load();
+        if settings.homes {
+            if let Err(e) = home::ensure_home(&settings.workspace.root, &user.username).await {
+                tracing::warn!(user = user.username, error = %e, "cannot create the home directory");
+            }
+        }
+
         let claims = Claims::new(User {
             name: login_form.username,
         })
@@ -60,3 +71,26 @@ pub fn info(Data(user): Data<&User>) -> ReplyData<UserInfo> {
         roles: ["admin"],
     })
 }
+
+/// **The storage quotas of the user**
+/// - Ok: the default workspace and the home directory,
+///   either is `null` if it isn't limited
+#[handler]
+pub async fn quota(
+    Data(user): Data<&User>,
+    Data(settings): Data<&SharedSettings>,
+) -> Result<ReplyData<UserQuota>, ReplyError> {
+    let settings = settings.load();
+    let wk = &settings.workspace;
+
+    let workspace = wk.quotas.get(&wk.root).await?;
+    let home = match home::home_dir(&wk.root, &user.name) {
+        Some(home) if settings.homes => wk.quotas.get(&home).await?,
+        _ => None,
+    };
+
+    Ok(ReplyData(UserQuota {
+        workspace: workspace.map(QuotaInfo::from),
+        home: home.map(QuotaInfo::from),
+    }))
+}
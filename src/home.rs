@@ -0,0 +1,61 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use tokio::fs;
+
+/// The home of user `alice` is the directory `~alice` in the default workspace
+pub const HOME_PREFIX: char = '~';
+
+/// The home directory of the user,
+/// `None` if the username cannot be a file name.
+pub fn home_dir(root: &Path, username: &str) -> Option<PathBuf> {
+    if username.is_empty() || username.contains(['/', '\\', '\0']) {
+        return None;
+    }
+
+    Some(root.join(format!("{HOME_PREFIX}{username}")))
+}
+
+/// Create the home directory of the user if it doesn't exist
+pub async fn ensure_home(root: &Path, username: &str) -> io::Result<PathBuf> {
+    let Some(home) = home_dir(root, username) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("username {username:?} cannot name a home directory"),
+        ));
+    };
+
+    fs::create_dir_all(&home).await?;
+
+    Ok(home)
+}
+
+/// The owner of the home which the path relative to workspace root is inside
+pub fn owner(path: &Path) -> Option<&str> {
+    match path.components().find(|c| *c != Component::CurDir)? {
+        Component::Normal(name) => name.to_str()?.strip_prefix(HOME_PREFIX),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{home_dir, owner};
+    use std::path::Path;
+
+    #[test]
+    fn test_owner() {
+        assert_eq!(owner(Path::new("~alice")), Some("alice"));
+        assert_eq!(owner(Path::new("./~alice/notes.md")), Some("alice"));
+        assert_eq!(owner(Path::new("shared/~alice")), None);
+        assert_eq!(owner(Path::new("")), None);
+
+        let root = Path::new("/srv/sachima");
+        assert_eq!(
+            home_dir(root, "alice"),
+            Some(Path::new("/srv/sachima/~alice").to_owned())
+        );
+        assert_eq!(home_dir(root, "../alice"), None);
+        assert_eq!(home_dir(root, ""), None);
+    }
+}
@@ -1,41 +1,132 @@
+mod checksum;
 mod config;
-pub use config::Config;
+pub use config::check::{check_config, Problem, Report};
+pub use config::{Config, ConfigError, ConfigSource, Overrides};
 
 mod db;
 mod entity;
 mod error;
+mod events;
 mod handlers;
+mod home;
 mod middlewares;
 mod models;
+mod openapi;
+mod quota;
+mod reload;
 mod reply;
 mod router;
+mod search;
+mod settings;
+mod shutdown;
+mod tls;
 mod utils;
+mod watcher;
+use middlewares::ClientCertAuth;
+use settings::{Settings, SharedSettings};
 use time::format_description::well_known::Rfc3339;
+use tls::TlsListener;
 use tracing_subscriber::fmt::time::OffsetTime;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use utils::pswd;
 
-use poem::listener::TcpListener;
+use entity::registry::InsertModel as UserModel;
+use sea_orm::{ActiveModelTrait, IntoActiveModel};
+
+use arc_swap::ArcSwap;
+use poem::listener::{Listener, TcpListener};
 use poem::middleware::Tracing;
 use poem::EndpointExt;
 use poem::Server;
 use time::UtcOffset;
 
-use std::env;
+use std::error::Error;
 use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub async fn run(config: Config, source: ConfigSource, local_offset: UtcOffset) -> io::Result<()> {
+    let (filter, log) = tracing_subscriber::reload::Layer::new(reload::log_filter(&config));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_timer(OffsetTime::new(local_offset, Rfc3339)))
+        .init();
+    db::init(&config.database_url).await;
+    pswd::init(&config.password_salt);
+    // before any upload is accepted, which would be taken as orphaned
+    sweep_uploads(&config).await;
+
+    let settings: SharedSettings = Arc::new(ArcSwap::from_pointee(Settings::from(&config)));
+    tokio::spawn(reload::on_sighup(
+        source,
+        config.clone(),
+        settings.clone(),
+        log,
+    ));
+    tokio::spawn(quota::reconcile(settings.clone()));
+    tokio::spawn(search::maintain(settings.clone()));
+    tokio::spawn(watcher::watch(settings.clone()));
+
+    let listener = TcpListener::bind(("127.0.0.1", config.port));
+    let listener = match config.tls.clone() {
+        Some(tls) => TlsListener::new(listener, tls).boxed(),
+        None => listener.boxed(),
+    };
+
+    let drain_timeout = Duration::from_secs(config.drain_timeout);
+    let server = Server::new(listener).name("sachima");
+    let app = router::new(config, settings.clone())
+        .with(ClientCertAuth::new(settings.clone()))
+        .with(Tracing);
+
+    server
+        .run_with_graceful_shutdown(app, shutdown::signal_received(), Some(drain_timeout))
+        .await?;
+    shutdown::clean_up();
+    search::save_all(&settings).await;
 
-pub async fn run(mut config: Config, local_offset: UtcOffset) -> io::Result<()> {
-    if let Some(level) = config.poem_log_level.take() {
-        env::set_var("RUST_LOG", format!("poem={level}"));
+    Ok(())
+}
+
+/// Remove the temp files of the uploads interrupted by a crash
+async fn sweep_uploads(config: &Config) {
+    let dirs =
+        std::iter::once(&config.workspace).chain(config.workspaces.iter().map(|wk| &wk.path));
+
+    for dir in dirs {
+        match utils::atomic::sweep(dir).await {
+            Ok(0) => (),
+            Ok(swept) => {
+                tracing::info!(dir = %dir.display(), swept, "orphaned upload files removed")
+            }
+            Err(e) => {
+                tracing::warn!(dir = %dir.display(), error = %e, "cannot sweep orphaned upload files")
+            }
+        }
     }
+}
 
-    tracing_subscriber::fmt()
-        .with_timer(OffsetTime::new(local_offset, Rfc3339))
-        .init();
+/// Register a user, and create the home directory in the homes mode
+pub async fn add_user(
+    config: &Config,
+    username: String,
+    password: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     db::init(&config.database_url).await;
     pswd::init(&config.password_salt);
 
-    let server = Server::new(TcpListener::bind(("127.0.0.1", config.port))).name("sachima");
-    let app = router::new(config).with(Tracing);
+    if config.homes {
+        home::ensure_home(&config.workspace, &username).await?;
+    }
+
+    UserModel {
+        username,
+        password: pswd::hash(password),
+    }
+    .into_active_model()
+    .insert(db::hdr())
+    .await?;
 
-    server.run(app).await
+    Ok(())
 }
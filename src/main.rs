@@ -1,24 +1,85 @@
-use std::fs::File;
 use std::io;
-use std::io::Read;
 use std::path::PathBuf;
+use std::process;
 
-use clap::Parser;
-use sachima::Config;
+use clap::{Parser, Subcommand};
+use sachima::{ConfigSource, Overrides};
 use time::UtcOffset;
 
+/// A simple file server
 #[derive(Parser)]
 struct Cli {
-    #[arg(long, short)]
-    config: PathBuf,
+    /// The TOML config file
+    #[arg(long, short, global = true)]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    overrides: Overrides,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Report every problem of the config and print the effective one
+    CheckConfig,
+
+    /// Manage the users
+    #[command(subcommand)]
+    User(UserCommand),
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Register a user whose password is read from stdin
+    Add { username: String },
 }
 
 fn main() -> io::Result<()> {
     let cli = Cli::parse();
 
-    let mut config = String::new();
-    File::open(cli.config)?.read_to_string(&mut config)?;
-    let config: Config = toml::from_str(&config).unwrap();
+    if let Some(Command::CheckConfig) = &cli.command {
+        let report =
+            runtime().block_on(sachima::check_config(cli.config.as_deref(), &cli.overrides));
+
+        println!("{}", toml::to_string_pretty(&report.effective).unwrap());
+        for problem in &report.problems {
+            eprintln!("error: {problem}");
+        }
+
+        if !report.problems.is_empty() {
+            eprintln!("{} problem(s) found", report.problems.len());
+            process::exit(1);
+        }
+
+        return Ok(());
+    }
+
+    let source = ConfigSource {
+        file: cli.config,
+        overrides: cli.overrides,
+    };
+    let config = match source.load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid config: {e}");
+            process::exit(1);
+        }
+    };
+
+    if let Some(Command::User(UserCommand::Add { username })) = cli.command {
+        let mut password = String::new();
+        io::stdin().read_line(&mut password)?;
+        let password = password.trim_end_matches(['\r', '\n']);
+
+        if let Err(e) = runtime().block_on(sachima::add_user(&config, username, password)) {
+            eprintln!("cannot add the user: {e}");
+            process::exit(1);
+        }
+
+        return Ok(());
+    }
 
     // `time` cannot get the current local offset
     // in multithreaded context.
@@ -26,9 +87,12 @@ fn main() -> io::Result<()> {
     // refer to <https://github.com/time-rs/time/discussions/421>
     let local_offset = UtcOffset::current_local_offset().unwrap();
 
+    runtime().block_on(sachima::run(config, source, local_offset))
+}
+
+fn runtime() -> tokio::runtime::Runtime {
     tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .unwrap()
-        .block_on(sachima::run(config, local_offset))
 }
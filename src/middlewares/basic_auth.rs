@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use jwt_codec::prelude::VerifyingAlgorithm;
+use jwt_codec::Codec;
+use poem::async_trait;
+use poem::http::header::WWW_AUTHENTICATE;
+use poem::http::StatusCode;
+use poem::web::headers::authorization::Basic;
+use poem::web::headers::{Authorization, HeaderMapExt};
+use poem::Endpoint;
+use poem::Middleware;
+use poem::Request;
+use poem::{IntoResponse, Response};
+use sea_orm::{ColumnTrait, DbErr, EntityTrait, QueryFilter};
+
+use super::jwt::bearer_user;
+use crate::db;
+use crate::entity::prelude::Registry;
+use crate::entity::registry;
+use crate::models::permission::User;
+use crate::utils::pswd;
+
+const CHALLENGE: &str = r#"Basic realm="sachima", charset="UTF-8""#;
+
+/// Authenticate the client by the basic auth against the registry,
+/// or by the bearer token, for the clients which cannot log in like WebDAV.
+/// The request without credentials passes through unauthenticated,
+/// and it's asked for them if it's unauthorized.
+#[derive(Debug)]
+pub struct BasicAuth<H> {
+    codec: Arc<Codec<H>>,
+}
+
+#[derive(Debug)]
+pub struct BasicAuthEndpoint<H, E> {
+    codec: Arc<Codec<H>>,
+    ep: E,
+}
+
+impl<H, E> Middleware<E> for BasicAuth<H>
+where
+    H: VerifyingAlgorithm + Send + Sync,
+    E: Endpoint,
+{
+    type Output = BasicAuthEndpoint<H, E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        BasicAuthEndpoint {
+            codec: Arc::clone(&self.codec),
+            ep,
+        }
+    }
+}
+
+#[async_trait]
+impl<H, E> Endpoint for BasicAuthEndpoint<H, E>
+where
+    H: VerifyingAlgorithm + Send + Sync,
+    E: Endpoint,
+{
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> poem::Result<Self::Output> {
+        // authenticated by the client certificate
+        if req.data::<User>().is_none() {
+            if let Some(Authorization(basic)) = req.headers().typed_get::<Authorization<Basic>>() {
+                match registered(basic.username(), basic.password()).await {
+                    Ok(true) => req.set_data(User {
+                        name: basic.username().to_owned(),
+                    }),
+                    Ok(false) => return Ok(challenge()),
+                    Err(e) => return Err(poem::Error::new(e, StatusCode::INTERNAL_SERVER_ERROR)),
+                }
+            } else if let Some(user) = bearer_user(&self.codec, &req) {
+                req.set_data(user);
+            }
+        }
+
+        match self.ep.call(req).await.map(IntoResponse::into_response) {
+            Ok(resp) if resp.status() == StatusCode::UNAUTHORIZED => Ok(challenge()),
+            Err(e) if e.status() == StatusCode::UNAUTHORIZED => Ok(challenge()),
+            res => res,
+        }
+    }
+}
+
+/// Whether the user is registered with the password
+async fn registered(username: &str, password: &str) -> Result<bool, DbErr> {
+    let user = Registry::find()
+        .filter(registry::Column::Username.eq(username))
+        .one(db::hdr())
+        .await?;
+
+    Ok(user.is_some_and(|user| pswd::verify(password, &user.password)))
+}
+
+fn challenge() -> Response {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(WWW_AUTHENTICATE, CHALLENGE)
+        .finish()
+}
+
+impl<H> BasicAuth<H> {
+    #[inline]
+    pub fn new(codec: Arc<Codec<H>>) -> Self {
+        Self { codec }
+    }
+}
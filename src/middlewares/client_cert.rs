@@ -0,0 +1,56 @@
+use poem::async_trait;
+use poem::Addr;
+use poem::Endpoint;
+use poem::Middleware;
+use poem::Request;
+
+use crate::models::permission::User;
+use crate::settings::SharedSettings;
+use crate::tls::CLIENT_CERT_SCHEME;
+
+/// Authenticate the client by its certificate subject,
+/// the request from an unknown subject passes through unauthenticated.
+#[derive(Debug)]
+pub struct ClientCertAuth {
+    settings: SharedSettings,
+}
+
+#[derive(Debug)]
+pub struct ClientCertAuthEndpoint<E> {
+    settings: SharedSettings,
+    ep: E,
+}
+
+impl<E: Endpoint> Middleware<E> for ClientCertAuth {
+    type Output = ClientCertAuthEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ClientCertAuthEndpoint {
+            settings: self.settings.clone(),
+            ep,
+        }
+    }
+}
+
+#[async_trait]
+impl<E: Endpoint> Endpoint for ClientCertAuthEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, mut req: Request) -> poem::Result<Self::Output> {
+        if let Addr::Custom(CLIENT_CERT_SCHEME, subject) = &req.remote_addr().0 {
+            if let Some(name) = self.settings.load().client_users.get(subject.as_ref()) {
+                let user = User { name: name.clone() };
+                req.set_data(user);
+            }
+        }
+
+        self.ep.call(req).await
+    }
+}
+
+impl ClientCertAuth {
+    #[inline]
+    pub fn new(settings: SharedSettings) -> Self {
+        Self { settings }
+    }
+}
@@ -48,15 +48,12 @@ where
     type Output = Response;
 
     async fn call(&self, mut req: Request) -> poem::Result<Self::Output> {
-        let Some(claims) = req
-            .headers()
-            .typed_get::<Authorization<Bearer>>()
-            .and_then(|Authorization(bear)| self
-                .codec
-                .parse_token::<User>(bear.token())
-                .ok()
-            )
-        else {
+        // authenticated by the client certificate
+        if req.data::<User>().is_some() {
+            return self.ep.call(req).await.map(IntoResponse::into_response);
+        }
+
+        let Some(claims) = bearer_user(&self.codec, &req) else {
             return Err(poem::Error::from_status(StatusCode::UNAUTHORIZED));
         };
 
@@ -65,6 +62,13 @@ where
     }
 }
 
+/// The user claimed by the bearer token
+pub fn bearer_user<H: VerifyingAlgorithm>(codec: &Codec<H>, req: &Request) -> Option<User> {
+    req.headers()
+        .typed_get::<Authorization<Bearer>>()
+        .and_then(|Authorization(bear)| codec.parse_token::<User>(bear.token()).ok())
+}
+
 impl<H> JwtVerifier<H> {
     #[inline]
     pub fn new(codec: Arc<Codec<H>>) -> Self {
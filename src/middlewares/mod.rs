@@ -1,2 +1,16 @@
 mod jwt;
 pub use jwt::JwtVerifier;
+
+mod basic_auth;
+pub use basic_auth::BasicAuth;
+
+mod client_cert;
+pub use client_cert::ClientCertAuth;
+
+mod workspace_guard;
+pub use workspace_guard::{Access, Target, WorkspaceGuard};
+
+mod sigv4;
+#[cfg(test)]
+pub(crate) use sigv4::{canonical_request, hmac, signing_key, string_to_sign, EMPTY_SHA256};
+pub use sigv4::{ChunkSigner, Payload, SigV4Auth};
@@ -0,0 +1,534 @@
+use std::fmt::Write;
+
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use poem::async_trait;
+use poem::http::header::AUTHORIZATION;
+use poem::http::{HeaderMap, StatusCode};
+use poem::Endpoint;
+use poem::Middleware;
+use poem::Request;
+use poem::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+use time::{Date, Duration, Month, OffsetDateTime, Time};
+
+use crate::checksum::hex;
+use crate::config::S3;
+use crate::handlers::file_system::s3::{S3Error, MOUNT};
+use crate::models::permission::User;
+use crate::settings::SharedSettings;
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const SERVICE: &str = "s3";
+const TERMINATOR: &str = "aws4_request";
+
+const X_AMZ_DATE: &str = "x-amz-date";
+const X_AMZ_CONTENT_SHA256: &str = "x-amz-content-sha256";
+const X_AMZ_SIGNATURE: &str = "X-Amz-Signature";
+
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+const STREAMING_PAYLOAD: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD";
+const STREAMING_PAYLOAD_TRAILER: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD-TRAILER";
+const STREAMING_UNSIGNED_TRAILER: &str = "STREAMING-UNSIGNED-PAYLOAD-TRAILER";
+
+/// The SHA-256 of nothing in hex
+pub(crate) const EMPTY_SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// How far the signing time may be from now
+const MAX_SKEW: Duration = Duration::minutes(15);
+
+/// The longest a presigned URL lives
+const MAX_EXPIRES: i64 = 7 * 24 * 3600;
+
+/// Everything except the unreserved characters is encoded
+const URI_ENCODED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// How the body of a signed request is verified
+#[derive(Debug, Clone)]
+pub enum Payload {
+    Unsigned,
+    /// The SHA-256 of the whole body
+    Sha256(Vec<u8>),
+    /// In `aws-chunked`, whose chunks are signed if there's a signer
+    Chunked(Option<ChunkSigner>),
+}
+
+/// Sign the chunks following the seed signature
+#[derive(Debug, Clone)]
+pub struct ChunkSigner {
+    key: Vec<u8>,
+    datetime: String,
+    scope: String,
+    pub seed: String,
+}
+
+impl ChunkSigner {
+    /// The signature of a chunk after the previous one
+    pub fn sign(&self, previous: &str, chunk: &[u8]) -> String {
+        let to_sign = format!(
+            "{ALGORITHM}-PAYLOAD\n{}\n{}\n{previous}\n{EMPTY_SHA256}\n{}",
+            self.datetime,
+            self.scope,
+            hex(&Sha256::digest(chunk))
+        );
+        hex(&hmac(&self.key, to_sign.as_bytes()))
+    }
+}
+
+/// Authenticate the requests signed by the S3 access keys with Signature Version 4,
+/// in the `Authorization` header or the query of a presigned URL.
+/// The request without a signature passes through anonymously.
+#[derive(Debug)]
+pub struct SigV4Auth {
+    settings: SharedSettings,
+}
+
+#[derive(Debug)]
+pub struct SigV4AuthEndpoint<E> {
+    settings: SharedSettings,
+    ep: E,
+}
+
+impl<E: Endpoint> Middleware<E> for SigV4Auth {
+    type Output = SigV4AuthEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        SigV4AuthEndpoint {
+            settings: self.settings.clone(),
+            ep,
+        }
+    }
+}
+
+#[async_trait]
+impl<E: Endpoint> Endpoint for SigV4AuthEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> poem::Result<Self::Output> {
+        let settings = self.settings.load();
+        let Some(s3) = &settings.s3 else {
+            return Err(poem::Error::from_status(StatusCode::NOT_FOUND));
+        };
+
+        let signed = if let Some(value) = req.headers().get(AUTHORIZATION) {
+            let value = value
+                .to_str()
+                .map_err(|_| S3Error::AuthorizationHeaderMalformed("Invalid Authorization"))?;
+            let fields =
+                value
+                    .strip_prefix(ALGORITHM)
+                    .ok_or(S3Error::AuthorizationHeaderMalformed(
+                        "Only AWS4-HMAC-SHA256 is supported",
+                    ))?;
+            Some(Signed::from_header(fields, req.headers())?)
+        } else if req
+            .uri()
+            .query()
+            .is_some_and(|q| q.contains(X_AMZ_SIGNATURE))
+        {
+            Some(Signed::from_query(req.uri().query().unwrap_or_default())?)
+        } else {
+            None
+        };
+
+        if let Some(signed) = signed {
+            let (user, payload) = signed.verify(&req, s3, OffsetDateTime::now_utc())?;
+            req.set_data(user);
+            req.set_data(payload);
+        }
+
+        self.ep.call(req).await.map(IntoResponse::into_response)
+    }
+}
+
+impl SigV4Auth {
+    #[inline]
+    pub fn new(settings: SharedSettings) -> Self {
+        Self { settings }
+    }
+}
+
+/// The signature and what it claims
+#[derive(Debug)]
+struct Signed {
+    access_key: String,
+    /// `YYYYMMDD`
+    date: String,
+    region: String,
+    /// `YYYYMMDD'T'HHMMSS'Z'`
+    datetime: String,
+    signed_headers: Vec<String>,
+    signature: String,
+    /// `x-amz-content-sha256`, or `UNSIGNED-PAYLOAD` of a presigned URL
+    payload: String,
+    /// Seconds a presigned URL lives
+    expires: Option<i64>,
+}
+
+impl Signed {
+    /// `Credential=AKID/20130524/us-east-1/s3/aws4_request, SignedHeaders=host;x-amz-date, Signature=...`
+    fn from_header(fields: &str, headers: &HeaderMap) -> Result<Self, S3Error> {
+        let (mut credential, mut signed_headers, mut signature) = (None, None, None);
+        for field in fields.split(',') {
+            match field.trim().split_once('=') {
+                Some(("Credential", value)) => credential = Some(value),
+                Some(("SignedHeaders", value)) => signed_headers = Some(value),
+                Some(("Signature", value)) => signature = Some(value),
+                _ => (),
+            }
+        }
+
+        let malformed = || S3Error::AuthorizationHeaderMalformed("Invalid Authorization");
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+        Self::new(
+            credential.ok_or_else(malformed)?,
+            header(X_AMZ_DATE)
+                .ok_or(S3Error::AuthorizationHeaderMalformed("Missing x-amz-date"))?,
+            signed_headers.ok_or_else(malformed)?,
+            signature.ok_or_else(malformed)?,
+            header(X_AMZ_CONTENT_SHA256).ok_or(S3Error::AuthorizationHeaderMalformed(
+                "Missing x-amz-content-sha256",
+            ))?,
+            None,
+        )
+    }
+
+    /// `X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential=...&X-Amz-Signature=...`
+    fn from_query(query: &str) -> Result<Self, S3Error> {
+        let params = query_params(query);
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+                .ok_or(S3Error::AuthorizationHeaderMalformed(
+                    "Missing the parameters of a presigned URL",
+                ))
+        };
+
+        if param("X-Amz-Algorithm")? != ALGORITHM {
+            return Err(S3Error::AuthorizationHeaderMalformed(
+                "Only AWS4-HMAC-SHA256 is supported",
+            ));
+        }
+        let expires = param("X-Amz-Expires")?
+            .parse()
+            .ok()
+            .filter(|expires| (0..=MAX_EXPIRES).contains(expires))
+            .ok_or(S3Error::AuthorizationHeaderMalformed(
+                "X-Amz-Expires must be less than a week",
+            ))?;
+
+        Self::new(
+            param("X-Amz-Credential")?,
+            param("X-Amz-Date")?,
+            param("X-Amz-SignedHeaders")?,
+            param(X_AMZ_SIGNATURE)?,
+            UNSIGNED_PAYLOAD,
+            Some(expires),
+        )
+    }
+
+    fn new(
+        credential: &str,
+        datetime: &str,
+        signed_headers: &str,
+        signature: &str,
+        payload: &str,
+        expires: Option<i64>,
+    ) -> Result<Self, S3Error> {
+        let mut scope = credential.split('/');
+        let (Some(access_key), Some(date), Some(region), Some(SERVICE), Some(TERMINATOR), None) = (
+            scope.next(),
+            scope.next(),
+            scope.next(),
+            scope.next(),
+            scope.next(),
+            scope.next(),
+        ) else {
+            return Err(S3Error::AuthorizationHeaderMalformed("Invalid credential"));
+        };
+
+        if !datetime.starts_with(date) {
+            return Err(S3Error::AuthorizationHeaderMalformed(
+                "The credential date doesn't match the request date",
+            ));
+        }
+
+        let signed_headers: Vec<_> = signed_headers.split(';').map(str::to_owned).collect();
+        if !signed_headers.iter().any(|name| name == "host") {
+            return Err(S3Error::AuthorizationHeaderMalformed("Host must be signed"));
+        }
+
+        Ok(Signed {
+            access_key: access_key.to_owned(),
+            date: date.to_owned(),
+            region: region.to_owned(),
+            datetime: datetime.to_owned(),
+            signed_headers,
+            signature: signature.to_owned(),
+            payload: payload.to_owned(),
+            expires,
+        })
+    }
+
+    fn scope(&self) -> String {
+        format!("{}/{}/{SERVICE}/{TERMINATOR}", self.date, self.region)
+    }
+
+    fn verify(
+        self,
+        req: &Request,
+        s3: &S3,
+        now: OffsetDateTime,
+    ) -> Result<(User, Payload), S3Error> {
+        let key = s3
+            .keys
+            .get(&self.access_key)
+            .ok_or(S3Error::InvalidAccessKeyId)?;
+
+        if self.region != s3.region {
+            return Err(S3Error::AuthorizationHeaderMalformed("The region is wrong"));
+        }
+
+        let signed_at = parse_datetime(&self.datetime)
+            .ok_or(S3Error::AuthorizationHeaderMalformed("Invalid x-amz-date"))?;
+        match self.expires {
+            Some(_) if signed_at - now > MAX_SKEW => return Err(S3Error::RequestTimeTooSkewed),
+            Some(expires) if now > signed_at + Duration::seconds(expires) => {
+                return Err(S3Error::ExpiredToken)
+            }
+            None if (signed_at - now).abs() > MAX_SKEW => {
+                return Err(S3Error::RequestTimeTooSkewed)
+            }
+            _ => (),
+        }
+
+        let key_bytes = signing_key(&key.secret, &self.date, &self.region);
+        let signature = unhex(&self.signature).ok_or(S3Error::SignatureDoesNotMatch)?;
+        let path = format!("{MOUNT}{}", req.uri().path());
+        // the clients sign the service as `/s3` without the trailing slash
+        let paths = match path.strip_suffix('/') {
+            Some(mount) if mount == MOUNT => vec![path.as_str(), mount],
+            _ => vec![path.as_str()],
+        };
+        let verified = paths.into_iter().any(|path| {
+            let canonical = canonical_request(
+                req.method().as_str(),
+                path,
+                req.uri().query().unwrap_or_default(),
+                req.headers(),
+                &self.signed_headers,
+                &self.payload,
+            );
+            let to_sign = string_to_sign(&self.datetime, &self.scope(), &canonical);
+
+            let mut mac = Hmac::<Sha256>::new_from_slice(&key_bytes).expect("any key length");
+            mac.update(to_sign.as_bytes());
+            mac.verify_slice(&signature).is_ok()
+        });
+        if !verified {
+            return Err(S3Error::SignatureDoesNotMatch);
+        }
+
+        let payload = match self.payload.as_str() {
+            UNSIGNED_PAYLOAD => Payload::Unsigned,
+            STREAMING_UNSIGNED_TRAILER => Payload::Chunked(None),
+            STREAMING_PAYLOAD | STREAMING_PAYLOAD_TRAILER => Payload::Chunked(Some(ChunkSigner {
+                scope: self.scope(),
+                key: key_bytes,
+                datetime: self.datetime,
+                seed: self.signature,
+            })),
+            hash => {
+                Payload::Sha256(unhex(hash).filter(|hash| hash.len() == 32).ok_or_else(|| {
+                    S3Error::InvalidArgument("Invalid x-amz-content-sha256".to_owned())
+                })?)
+            }
+        };
+
+        Ok((
+            User {
+                name: key.user.clone(),
+            },
+            payload,
+        ))
+    }
+}
+
+/// The decoded query parameters in order
+fn query_params(query: &str) -> Vec<(String, String)> {
+    let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+fn uri_encode(s: &str) -> String {
+    utf8_percent_encode(s, URI_ENCODED).to_string()
+}
+
+pub(crate) fn canonical_request(
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &HeaderMap,
+    signed_headers: &[String],
+    payload: &str,
+) -> String {
+    let path = path
+        .split('/')
+        .map(|segment| uri_encode(&percent_decode_str(segment).decode_utf8_lossy()))
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let mut params: Vec<_> = query_params(query)
+        .into_iter()
+        .filter(|(key, _)| key != X_AMZ_SIGNATURE)
+        .map(|(key, value)| (uri_encode(&key), uri_encode(&value)))
+        .collect();
+    params.sort();
+    let query = params
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let mut canonical = format!("{method}\n{path}\n{query}\n");
+    for name in signed_headers {
+        let values: Vec<_> = headers
+            .get_all(name.as_str())
+            .iter()
+            .map(|value| {
+                String::from_utf8_lossy(value.as_bytes())
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        writeln!(canonical, "{name}:{}", values.join(",")).unwrap();
+    }
+    write!(canonical, "\n{}\n{payload}", signed_headers.join(";")).unwrap();
+
+    canonical
+}
+
+pub(crate) fn string_to_sign(datetime: &str, scope: &str, canonical: &str) -> String {
+    format!(
+        "{ALGORITHM}\n{datetime}\n{scope}\n{}",
+        hex(&Sha256::digest(canonical.as_bytes()))
+    )
+}
+
+pub(crate) fn signing_key(secret: &str, date: &str, region: &str) -> Vec<u8> {
+    [date, region, SERVICE, TERMINATOR]
+        .iter()
+        .fold(format!("AWS4{secret}").into_bytes(), |key, part| {
+            hmac(&key, part.as_bytes())
+        })
+}
+
+pub(crate) fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `20130524T000000Z`
+fn parse_datetime(s: &str) -> Option<OffsetDateTime> {
+    if s.len() != 16 || !s.is_ascii() || &s[8..9] != "T" || &s[15..] != "Z" {
+        return None;
+    }
+    let num = |range: std::ops::Range<usize>| s[range].parse::<u16>().ok();
+
+    let date = Date::from_calendar_date(
+        num(0..4)? as i32,
+        Month::try_from(num(4..6)? as u8).ok()?,
+        num(6..8)? as u8,
+    )
+    .ok()?;
+    let time = Time::from_hms(num(9..11)? as u8, num(11..13)? as u8, num(13..15)? as u8).ok()?;
+
+    Some(date.with_time(time).assume_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        canonical_request, hex, hmac, parse_datetime, signing_key, string_to_sign, ChunkSigner,
+        EMPTY_SHA256,
+    };
+    use poem::http::HeaderMap;
+
+    const SECRET: &str = "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY";
+    const SCOPE: &str = "20130524/us-east-1/s3/aws4_request";
+
+    /// The examples in the documents of AWS
+    #[test]
+    fn test_signature() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "examplebucket.s3.amazonaws.com".parse().unwrap());
+        headers.insert("range", "bytes=0-9".parse().unwrap());
+        headers.insert("x-amz-content-sha256", EMPTY_SHA256.parse().unwrap());
+        headers.insert("x-amz-date", "20130524T000000Z".parse().unwrap());
+        let signed: Vec<_> = ["host", "range", "x-amz-content-sha256", "x-amz-date"]
+            .map(str::to_owned)
+            .into();
+
+        let canonical = canonical_request("GET", "/test.txt", "", &headers, &signed, EMPTY_SHA256);
+        let key = signing_key(SECRET, "20130524", "us-east-1");
+        let to_sign = string_to_sign("20130524T000000Z", SCOPE, &canonical);
+        assert_eq!(
+            hex(&hmac(&key, to_sign.as_bytes())),
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+
+        let canonical = canonical_request(
+            "GET",
+            "/examplebucket",
+            "max-keys=2&prefix=J",
+            &headers,
+            &signed[..1],
+            EMPTY_SHA256,
+        );
+        assert!(canonical.starts_with("GET\n/examplebucket\nmax-keys=2&prefix=J\n"));
+
+        let signer = ChunkSigner {
+            key,
+            datetime: "20130524T000000Z".to_owned(),
+            scope: SCOPE.to_owned(),
+            seed: "4f232c4386841ef735655705268965c44a0e4690baa4adea153f7db9fa80a0a9".to_owned(),
+        };
+        assert_eq!(
+            signer.sign(&signer.seed, &[b'a'; 65536]),
+            "ad80c730a21e5b8d04586a2213dd63b9a0e99e0e2307b0ade35a65485a288648"
+        );
+
+        assert!(parse_datetime("20130524T000000Z").is_some());
+        assert!(parse_datetime("20131324T000000Z").is_none());
+        assert!(parse_datetime("2013-05-24T00:00:00Z").is_none());
+    }
+}
@@ -0,0 +1,162 @@
+use std::io;
+use std::sync::Arc;
+
+use jwt_codec::prelude::VerifyingAlgorithm;
+use jwt_codec::Codec;
+use poem::async_trait;
+use poem::http::StatusCode;
+use poem::Endpoint;
+use poem::Middleware;
+use poem::Request;
+use poem::{IntoResponse, Response};
+
+use super::jwt::bearer_user;
+use crate::config::{Policy, Workspace};
+use crate::home;
+use crate::models::permission::User;
+use crate::settings::{HomeOwnership, MaxUpload, SharedSettings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// The workspace which a route serves
+#[derive(Debug, Clone)]
+pub enum Target {
+    Default,
+    Named(Arc<str>),
+    /// The home directory of the requesting user
+    Home,
+}
+
+/// Enforce the policy of a workspace,
+/// and put its latest settings into the request.
+#[derive(Debug)]
+pub struct WorkspaceGuard<H> {
+    settings: SharedSettings,
+    target: Target,
+    access: Access,
+    codec: Arc<Codec<H>>,
+}
+
+#[derive(Debug)]
+pub struct WorkspaceGuardEndpoint<H, E> {
+    settings: SharedSettings,
+    target: Target,
+    access: Access,
+    codec: Arc<Codec<H>>,
+    ep: E,
+}
+
+impl<H, E> Middleware<E> for WorkspaceGuard<H>
+where
+    H: VerifyingAlgorithm + Send + Sync,
+    E: Endpoint,
+{
+    type Output = WorkspaceGuardEndpoint<H, E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        WorkspaceGuardEndpoint {
+            settings: self.settings.clone(),
+            target: self.target.clone(),
+            access: self.access,
+            codec: Arc::clone(&self.codec),
+            ep,
+        }
+    }
+}
+
+#[async_trait]
+impl<H, E> Endpoint for WorkspaceGuardEndpoint<H, E>
+where
+    H: VerifyingAlgorithm + Send + Sync,
+    E: Endpoint,
+{
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> poem::Result<Self::Output> {
+        let settings = self.settings.load();
+
+        // removed by reloading
+        let wk = match &self.target {
+            Target::Default => &settings.workspace,
+            Target::Named(name) => match settings.workspaces.get(&**name) {
+                Some(wk) => wk,
+                None => return Err(poem::Error::from_status(StatusCode::NOT_FOUND)),
+            },
+            Target::Home if settings.homes => &settings.workspace,
+            Target::Home => return Err(poem::Error::from_status(StatusCode::NOT_FOUND)),
+        };
+
+        let policy = match (&self.target, self.access) {
+            (Target::Home, _) => Policy::Authenticated,
+            (_, Access::Read) => wk.read,
+            (_, Access::Write) => wk.write,
+        };
+
+        match policy {
+            // the home directories need to know who is reading
+            Policy::Public => {
+                if req.data::<User>().is_none() {
+                    if let Some(user) = bearer_user(&self.codec, &req) {
+                        req.set_data(user);
+                    }
+                }
+            }
+            Policy::Authenticated => {
+                if req.data::<User>().is_none() {
+                    let Some(user) = bearer_user(&self.codec, &req) else {
+                        return Err(poem::Error::from_status(StatusCode::UNAUTHORIZED));
+                    };
+                    req.set_data(user);
+                }
+            }
+            Policy::Nobody => return Err(poem::Error::from_status(StatusCode::FORBIDDEN)),
+        }
+
+        match &self.target {
+            Target::Home => {
+                let user = req.data::<User>().expect("authenticated above");
+                let home = home::ensure_home(&wk.root, &user.name).await.map_err(|e| {
+                    let status = match e.kind() {
+                        io::ErrorKind::InvalidInput => StatusCode::FORBIDDEN,
+                        _ => StatusCode::INTERNAL_SERVER_ERROR,
+                    };
+                    poem::Error::new(e, status)
+                })?;
+                req.set_data(Arc::new(Workspace::new_unchecked(home)));
+            }
+            Target::Default if settings.homes => {
+                req.set_data(wk.root.clone());
+                req.set_data(HomeOwnership {
+                    admins: settings.admins.clone(),
+                });
+            }
+            _ => req.set_data(wk.root.clone()),
+        }
+        req.set_data(MaxUpload(wk.max_upload));
+        req.set_data(wk.quotas.clone());
+        req.set_data(wk.index.clone());
+
+        self.ep.call(req).await.map(IntoResponse::into_response)
+    }
+}
+
+impl<H> WorkspaceGuard<H> {
+    #[inline]
+    pub fn new(
+        settings: SharedSettings,
+        target: Target,
+        access: Access,
+        codec: Arc<Codec<H>>,
+    ) -> Self {
+        Self {
+            settings,
+            target,
+            access,
+            codec,
+        }
+    }
+}
@@ -1,79 +1,246 @@
 use bytesize::ByteSize;
 use serde::Serialize;
-use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::Metadata;
 use std::io;
-use tokio::fs::DirEntry;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use tokio::fs;
 
-use crate::utils::time::to_unix_timestamp;
+use crate::reply::ReplyItem;
+use crate::utils::dir_size::DirSize;
+use crate::utils::time::{TimeFormat, Timestamp};
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
 pub struct Directory {
     pub parent: Option<String>,
     pub entries: Vec<FsEntry>,
+    /// Pass it as `cursor` for the next page, absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// A page of the search results in the order of paths
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Found {
+    pub entries: Vec<FoundEntry>,
+    /// Pass it as `cursor` for the next page, absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct FoundEntry {
+    /// Relative to the workspace root
+    pub path: String,
+    #[serde(flatten)]
+    pub entry: FsEntry,
+}
+
+/// A change inside the watched directory
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// Pass it as `since` or `Last-Event-ID` to resume
+    pub id: u64,
+    #[serde(flatten)]
+    pub change: WatchChange,
+}
+
+/// The paths are relative to the workspace root
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WatchChange {
+    Created {
+        path: String,
+    },
+    Modified {
+        path: String,
+    },
+    Renamed {
+        from: String,
+        to: String,
+    },
+    Removed {
+        path: String,
+    },
+    /// Some events are lost, the directory should be read again
+    Reset,
+}
+
+impl WatchChange {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WatchChange::Created { .. } => "created",
+            WatchChange::Modified { .. } => "modified",
+            WatchChange::Renamed { .. } => "renamed",
+            WatchChange::Removed { .. } => "removed",
+            WatchChange::Reset => "reset",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, PartialEq)]
 pub struct FsEntry {
     kind: FsEntryKind,
     name: String,
+    /// The recursive total of a directory with `with-size`
     size: Option<ByteSize>,
-    modified: String,
+    modified: Timestamp,
+    /// Absent if the file system doesn't record it
+    created: Option<Timestamp>,
+    accessed: Timestamp,
+    /// Where a symlink points to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    /// The permission bits like `0o755`
+    mode: u32,
+    /// The uid
+    owner: u32,
+    /// The gid
+    group: u32,
+    inode: u64,
+    nlink: u64,
+    /// Guessed by the extension of a file
+    mime: Option<String>,
+    /// The recursive item count of a directory with `with-size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items: Option<u64>,
+    /// The entries of a directory within `depth`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<FsEntry>>,
 }
 
 #[derive(Debug, Serialize, PartialEq)]
 pub enum FsEntryKind {
     Dir,
     File,
+    Symlink,
 }
 
-impl FsEntry {
-    pub async fn dir(entry: &DirEntry) -> io::Result<Self> {
-        Ok(Self {
-            kind: FsEntryKind::Dir,
-            name: entry.file_name().to_string_lossy().into_owned(),
-            size: None,
-            modified: to_unix_timestamp(entry.metadata().await?.modified()?).to_string(),
-        })
-    }
+/// The metadata of a single path
+#[derive(Debug, Serialize)]
+pub struct Stat {
+    #[serde(flatten)]
+    pub entry: FsEntry,
+    pub etag: String,
+    /// Algorithm => lowercase hex digest, of the requested ones
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+}
 
-    pub async fn file(entry: &DirEntry) -> io::Result<Self> {
-        let md = entry.metadata().await?;
+/// The digest of a file
+#[derive(Debug, Serialize)]
+pub struct Checksum {
+    pub algo: &'static str,
+    /// In lowercase hex
+    pub digest: String,
+}
 
-        Ok(Self {
-            kind: FsEntryKind::File,
-            name: entry.file_name().to_string_lossy().into_owned(),
-            size: Some(ByteSize(md.len())),
-            modified: to_unix_timestamp(md.modified()?).to_string(),
-        })
-    }
+/// The file placed by an upload
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct Uploaded {
+    /// Differs from the uploaded name if it's renamed for a conflict
+    pub name: String,
+    /// The existing file is kept for a conflict
+    pub skipped: bool,
+    /// The missing parent directories created with `parents=true`
+    pub created: Vec<String>,
 }
 
-impl Eq for FsEntry {}
+impl Uploaded {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            name: file_name(path),
+            skipped: false,
+            created: Vec::new(),
+        }
+    }
 
-impl PartialOrd for FsEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.kind
-            .partial_cmp(&other.kind)
-            .or_else(|| self.name.partial_cmp(&other.name))
+    pub fn skipped(path: &Path) -> Self {
+        Self {
+            name: file_name(path),
+            skipped: true,
+            created: Vec::new(),
+        }
     }
 }
 
-impl Ord for FsEntry {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
-    }
+/// The replies of a batch
+#[derive(Debug, Serialize)]
+pub struct Batched {
+    /// In the order of the operations,
+    /// a transactional batch stops at the first failure.
+    pub results: Vec<ReplyItem<Done>>,
+    /// The done operations are undone for a failure in a transactional batch
+    pub rolled_back: bool,
+}
+
+/// The data of a done operation in a batch
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Done {
+    Nothing,
+    /// The directories made by `mkdir`
+    Created(Vec<String>),
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
 }
 
-impl PartialOrd for FsEntryKind {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.eq(other) {
-            return None;
+impl FsEntry {
+    /// The symlinks aren't followed
+    pub async fn new(path: &Path, md: &Metadata, format: TimeFormat) -> io::Result<Self> {
+        let name = file_name(path);
+        let (kind, size, target, mime) = if md.is_dir() {
+            (FsEntryKind::Dir, None, None, None)
+        } else if md.is_symlink() {
+            let target = fs::read_link(path).await?;
+            let target = target.to_string_lossy().into_owned();
+            (FsEntryKind::Symlink, None, Some(target), None)
+        } else {
+            let mime = mime_guess::from_path(&name).first_raw().map(str::to_owned);
+            (FsEntryKind::File, Some(ByteSize(md.len())), None, mime)
+        };
+
+        Ok(Self {
+            kind,
+            name,
+            size,
+            modified: format.format(md.modified()?),
+            created: md.created().ok().map(|time| format.format(time)),
+            accessed: format.format(md.accessed()?),
+            target,
+            mode: md.mode() & 0o7777,
+            owner: md.uid(),
+            group: md.gid(),
+            inode: md.ino(),
+            nlink: md.nlink(),
+            mime,
+            items: None,
+            children: None,
+        })
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FsEntryKind::Dir
+    }
+
+    pub fn with_size(self, DirSize { bytes, items }: DirSize) -> Self {
+        Self {
+            size: Some(ByteSize(bytes)),
+            items: Some(items),
+            ..self
         }
+    }
 
-        match (self, other) {
-            (Self::Dir, Self::File) => Some(Ordering::Less),
-            (Self::File, Self::Dir) => Some(Ordering::Greater),
-            _ => unreachable!(),
+    pub fn with_children(self, children: Vec<FsEntry>) -> Self {
+        Self {
+            children: Some(children),
+            ..self
         }
     }
 }
+
+impl Eq for FsEntry {}
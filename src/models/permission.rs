@@ -1,7 +1,10 @@
 use serde::Deserialize;
 use serde::{Serialize, Serializer};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::config::quota::Limit;
+use crate::quota::Usage;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     #[serde(rename = "username")]
     pub name: String,
@@ -13,6 +16,22 @@ pub struct UserInfo {
     pub roles: [&'static str; 1],
 }
 
+/// The storage taken and the upper limits
+#[derive(Debug, Serialize)]
+pub struct QuotaInfo {
+    pub used: Usage,
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserQuota {
+    /// Of the default workspace
+    pub workspace: Option<QuotaInfo>,
+    /// Of the home directory in the homes mode
+    pub home: Option<QuotaInfo>,
+}
+
 #[derive(Debug)]
 pub struct Token(pub String);
 
@@ -29,3 +48,13 @@ impl Serialize for Token {
         TokenObject { token: &self.0 }.serialize(serializer)
     }
 }
+
+impl From<(Usage, Limit)> for QuotaInfo {
+    fn from((used, limit): (Usage, Limit)) -> Self {
+        Self {
+            used,
+            max_bytes: limit.bytes.map(|bytes| bytes.as_u64()),
+            max_files: limit.files,
+        }
+    }
+}
@@ -0,0 +1,861 @@
+use once_cell::sync::Lazy;
+use serde_json::{json, Map, Value};
+
+use crate::reply::status::{self, *};
+
+/// The OpenAPI 3 document of the routes, served at `/openapi.json`
+pub static DOCUMENT: Lazy<Value> = Lazy::new(document);
+
+/// The statuses of the paths checked by the hooks under `/wk`
+const PATH_ERRORS: &[u16] = &[IS_ABSOLUTE, OUTSIDE_WORKSPACE, PERMISSION_DENIED];
+
+/// An operation of a route
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    description: Option<&'static str>,
+    params: Vec<Value>,
+    body: Option<Value>,
+    responses: Value,
+    security: Security,
+}
+
+/// Who may request an operation
+enum Security {
+    /// Nothing is required
+    None,
+    /// Decided by the policies of the workspace
+    Policy,
+    /// A token is required
+    Bearer,
+    /// HTTP Basic or a token
+    Basic,
+    /// AWS Signature V4, or anonymous
+    SigV4,
+}
+
+impl Operation {
+    fn new(method: &'static str, path: &'static str, tag: &'static str) -> Self {
+        Self {
+            method,
+            path,
+            tag,
+            summary: "",
+            description: None,
+            params: Vec::new(),
+            body: None,
+            responses: json!({}),
+            security: Security::None,
+        }
+    }
+
+    fn summary(self, summary: &'static str) -> Self {
+        Self { summary, ..self }
+    }
+
+    fn description(self, description: &'static str) -> Self {
+        Self {
+            description: Some(description),
+            ..self
+        }
+    }
+
+    fn param(mut self, param: Value) -> Self {
+        self.params.push(param);
+        self
+    }
+
+    fn body(self, body: Value) -> Self {
+        Self {
+            body: Some(body),
+            ..self
+        }
+    }
+
+    fn responses(self, responses: Value) -> Self {
+        Self { responses, ..self }
+    }
+
+    fn security(self, security: Security) -> Self {
+        Self { security, ..self }
+    }
+
+    fn into_value(self) -> Value {
+        let mut op = json!({
+            "tags": [self.tag],
+            "summary": self.summary,
+            "responses": self.responses,
+        });
+        if let Some(description) = self.description {
+            op["description"] = description.into();
+        }
+        if !self.params.is_empty() {
+            op["parameters"] = self.params.into();
+        }
+        if let Some(body) = self.body {
+            op["requestBody"] = body;
+        }
+
+        let security = match self.security {
+            Security::None => None,
+            Security::Policy => Some(json!([{}, { "bearer": [] }])),
+            Security::Bearer => Some(json!([{ "bearer": [] }])),
+            Security::Basic => Some(json!([{ "basic": [] }, { "bearer": [] }])),
+            Security::SigV4 => Some(json!([{}, { "sigv4": [] }])),
+        };
+        if let Some(security) = security {
+            op["security"] = security;
+        }
+
+        op
+    }
+}
+
+fn schema(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+/// The path inside the workspace, which may contain `/`
+fn path_param(name: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": "Relative to the workspace root, and may contain `/`",
+        "schema": { "type": "string" },
+    })
+}
+
+fn query(name: &str, schema: Value, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "description": description,
+        "schema": schema,
+    })
+}
+
+fn required_query(name: &str, schema: Value, description: &str) -> Value {
+    let mut query = query(name, schema, description);
+    query["required"] = true.into();
+    query
+}
+
+fn header(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "header",
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn time_format() -> Value {
+    query(
+        "time-format",
+        schema("TimeFormat"),
+        "How the timestamps are replied",
+    )
+}
+
+fn on_conflict() -> Value {
+    query(
+        "on-conflict",
+        json!({ "type": "string", "enum": ["fail", "overwrite", "rename", "skip"], "default": "fail" }),
+        "What to do if the file has already existed",
+    )
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+/// The reply envelope in HTTP 200 with the business statuses in the API v1,
+/// the data alone in the API v2, and the HTTP statuses of the hooks and the failures.
+fn reply(data: Value, errors: &[u16]) -> Value {
+    let names: Vec<&str> = errors
+        .iter()
+        .filter_map(|code| status::ALL.iter().find(|(_, c)| c == code))
+        .map(|(name, _)| *name)
+        .collect();
+
+    // nothing is replied as 204 in the API v2
+    let unit = data == json!({ "nullable": true });
+    let envelope = json!({
+        "oneOf": [
+            {
+                "type": "object",
+                "required": ["status", "data"],
+                "properties": {
+                    "status": { "type": "integer", "enum": [OK] },
+                    "data": data,
+                },
+            },
+            schema("ReplyErrorObject"),
+        ],
+    });
+    let (description, schema) = if unit {
+        (
+            "`status` is `0`, or one of the business statuses with `msg` in the API v1",
+            envelope,
+        )
+    } else {
+        (
+            "`status` is `0` with `data`, or one of the business statuses with `msg` in the API v1, and `data` alone in the API v2",
+            json!({ "anyOf": [envelope, data] }),
+        )
+    };
+
+    let mut responses = json!({
+        "200": {
+            "description": description,
+            "content": { "application/json": { "schema": schema } },
+            "x-reply-statuses": errors,
+            "x-reply-status-names": names,
+        },
+        "4XX": { "$ref": "#/components/responses/Problem" },
+        "500": { "$ref": "#/components/responses/Internal" },
+    });
+    if unit {
+        responses["204"] = json!({ "description": "Done in the API v2" });
+    }
+    responses
+}
+
+/// The routes of the API are served under the prefixes of the versions,
+/// and those of the API v1 are also served without the prefix for now.
+fn api_servers() -> Value {
+    json!([
+        { "url": "/api/v2", "description": "The API v2" },
+        { "url": "/api/v1", "description": "The API v1" },
+        { "url": "/", "description": "The deprecated aliases of the API v1" },
+    ])
+}
+
+/// The HTTP statuses of the workspace guard, unless they're described
+fn guarded(mut responses: Value) -> Value {
+    for (code, description) in [
+        (
+            "401",
+            "The policy requires a user but none is authenticated",
+        ),
+        ("403", "The policy is `nobody`"),
+        (
+            "404",
+            "No such named workspace, or no home outside the homes mode",
+        ),
+    ] {
+        if responses.get(code).is_none() {
+            responses[code] = json!({ "description": description });
+        }
+    }
+    responses
+}
+
+fn statuses(base: &[u16], extra: &[u16]) -> Vec<u16> {
+    base.iter().chain(extra).copied().collect()
+}
+
+fn operations() -> Vec<Operation> {
+    vec![
+        // read the workspace
+        Operation::new("get", "/wk/r/file/{path}", "Workspace")
+            .summary("Download a file")
+            .description("The cached digests are carried in `Digest` and `Repr-Digest`.")
+            .param(path_param("path"))
+            .security(Security::Policy)
+            .responses(guarded(json!({
+                "200": {
+                    "description": "The bytes of the file, whose `Content-Type` is guessed by the extension",
+                    "headers": {
+                        "ETag": { "schema": { "type": "string" } },
+                        "Last-Modified": { "schema": { "type": "string" } },
+                        "Content-Disposition": { "schema": { "type": "string" } },
+                    },
+                    "content": { "*/*": { "schema": { "type": "string", "format": "binary" } } },
+                },
+                "403": { "description": "The workspace root, or the path escapes the workspace" },
+                "404": { "description": "No such file" },
+                "415": { "description": "The path is a directory" },
+                "500": { "$ref": "#/components/responses/Internal" },
+            }))),
+        Operation::new("head", "/wk/r/file/{path}", "Workspace")
+            .summary("Get the headers of a file download without the content")
+            .param(path_param("path"))
+            .security(Security::Policy)
+            .responses(guarded(json!({
+                "200": {
+                    "description": "The same headers as the download",
+                },
+                "403": { "description": "The workspace root, or the path escapes the workspace" },
+                "404": { "description": "No such file" },
+                "415": { "description": "The path is a directory" },
+                "500": { "$ref": "#/components/responses/Internal" },
+            }))),
+        Operation::new("get", "/wk/r/dir/{path}", "Workspace")
+            .summary("Read a directory")
+            .param(path_param("path"))
+            .param(query(
+                "depth",
+                json!({ "oneOf": [{ "type": "integer", "minimum": 1 }, { "type": "string", "enum": ["unlimited"] }], "default": 1 }),
+                "Levels of the nested tree, or `unlimited` up to 32",
+            ))
+            .param(query("with-size", json!({ "type": "boolean", "default": false }), "Sum up the bytes and items of the directories recursively"))
+            .param(query("sort", json!({ "type": "string", "enum": ["name", "size", "modified"], "default": "name" }), "Directories come first"))
+            .param(query("order", json!({ "type": "string", "enum": ["asc", "desc"], "default": "asc" }), ""))
+            .param(query("filter", json!({ "type": "string" }), "A glob of the names, or a regex with `regex=true`"))
+            .param(query("regex", json!({ "type": "boolean", "default": false }), ""))
+            .param(query("kind", schema("Kind"), "Only the entries of the kind"))
+            .param(query("hidden", json!({ "type": "boolean", "default": true }), "List the hidden entries"))
+            .param(query("limit", json!({ "type": "integer", "minimum": 1, "maximum": 10000 }), "Entries per page, 1000 by default in v2 or with `cursor`; v1 lists the whole directory without it"))
+            .param(query("cursor", json!({ "type": "string" }), "Where the page starts, from `next_cursor` of the previous page"))
+            .param(time_format())
+            .security(Security::Policy)
+            .responses(guarded(reply(
+                schema("Directory"),
+                &statuses(PATH_ERRORS, &[NOT_FOUND, NOT_A_DIRECTORY, INVALID_PARAM]),
+            ))),
+        Operation::new("get", "/wk/r/stat/{path}", "Workspace")
+            .summary("Get the metadata of a file or directory")
+            .param(path_param("path"))
+            .param(query("checksum", json!({ "type": "string" }), "Comma-separated algorithms like `md5,sha-256`"))
+            .param(time_format())
+            .security(Security::Policy)
+            .responses(guarded(reply(
+                schema("Stat"),
+                &statuses(PATH_ERRORS, &[WORKSPACE_ROOT, NOT_FOUND, IS_A_DIRECTORY, INVALID_PARAM]),
+            ))),
+        Operation::new("get", "/wk/r/checksum/{path}", "Workspace")
+            .summary("Get the checksum of a file")
+            .description("It's cached until the file changes.")
+            .param(path_param("path"))
+            .param(required_query("algo", schema("Algo"), "The names in `Digest` are accepted too, like `sha-256`"))
+            .security(Security::Policy)
+            .responses(guarded(reply(
+                schema("Checksum"),
+                &statuses(PATH_ERRORS, &[WORKSPACE_ROOT, NOT_FOUND, IS_A_DIRECTORY]),
+            ))),
+        Operation::new("get", "/wk/r/watch/{path}", "Workspace")
+            .summary("Watch the changes inside a directory")
+            .description("The events are streamed over WebSocket if the request upgrades, otherwise as server-sent events named by the change types.")
+            .param(path_param("path"))
+            .param(query("recursive", json!({ "type": "boolean", "default": false }), "Watch the whole subtree instead of the direct children"))
+            .param(query("since", json!({ "type": "integer", "format": "int64" }), "Resume after the event id, `Last-Event-ID` takes precedence"))
+            .param(header("Last-Event-ID", "Resume after the event id"))
+            .security(Security::Policy)
+            .responses({
+                let mut responses = guarded(reply(
+                    schema("WatchEvent"),
+                    &statuses(PATH_ERRORS, &[NOT_FOUND, NOT_A_DIRECTORY, INVALID_PARAM]),
+                ));
+                responses["200"]["description"] =
+                    "The stream of events, or the reply envelope if it cannot be watched".into();
+                responses["200"]["content"]["text/event-stream"] =
+                    json!({ "schema": schema("WatchEvent") });
+                responses["101"] = json!({ "description": "The events in WebSocket text messages" });
+                responses
+            }),
+        Operation::new("get", "/wk/r/search", "Workspace")
+            .summary("Search the workspace by names or the words in the files")
+            .param(required_query("q", json!({ "type": "string" }), "A glob or substring of the names, or the words in the files with `text=true`"))
+            .param(query("text", json!({ "type": "boolean", "default": false }), "Search the full-text index"))
+            .param(query("path", json!({ "type": "string" }), "The directory searched, the whole workspace if absent"))
+            .param(query("kind", schema("Kind"), "Only the entries of the kind"))
+            .param(query("limit", json!({ "type": "integer", "minimum": 1, "default": 100 }), "Entries per page"))
+            .param(query("cursor", json!({ "type": "string" }), "Where the page starts, from `next_cursor` of the previous page"))
+            .param(time_format())
+            .security(Security::Policy)
+            .responses(guarded(reply(
+                schema("Found"),
+                &statuses(PATH_ERRORS, &[INVALID_PARAM]),
+            ))),
+        // write the workspace
+        Operation::new("post", "/wk/w/upload/{parent}", "Workspace")
+            .summary("Upload a file to the parent directory")
+            .param(path_param("parent"))
+            .param(on_conflict())
+            .param(query("parents", json!({ "type": "boolean", "default": false }), "Create the missing parent directories"))
+            .param(header("If-Match", "The ETag of the existing file, checked whatever `on-conflict` is"))
+            .body(json!({
+                "required": true,
+                "content": {
+                    "multipart/form-data": {
+                        "schema": {
+                            "type": "object",
+                            "properties": { "file": { "type": "string", "format": "binary" } },
+                        },
+                    },
+                },
+            }))
+            .security(Security::Policy)
+            .responses(guarded(reply(
+                schema("Uploaded"),
+                &statuses(
+                    PATH_ERRORS,
+                    &[MISSING_PARENT, NOT_A_DIRECTORY, ALREADY_EXISTS, FILE_EXPECTED, MISSING_FILE_NAME, RESOURCE_TOO_LARGE, QUOTA_EXCEEDED, PRECONDITION_FAILED, INVALID_HEADER, INVALID_PARAM],
+                ),
+            ))),
+        Operation::new("put", "/wk/w/put/{path}", "Workspace")
+            .summary("Upload the request body as the file at the path")
+            .param(path_param("path"))
+            .param(on_conflict())
+            .param(query("parents", json!({ "type": "boolean", "default": false }), "Create the missing parent directories"))
+            .param(header("If-Match", "The ETag of the existing file, checked whatever `on-conflict` is"))
+            .param(header("Content-MD5", "Verify the body by its MD5 in base64"))
+            .param(header("Digest", "Verify the body by the digests like `sha-256=...`"))
+            .param(header("X-Mtime", "Set the modified time in Unix seconds"))
+            .body(json!({
+                "required": true,
+                "content": { "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } },
+            }))
+            .security(Security::Policy)
+            .responses(guarded(reply(
+                schema("Uploaded"),
+                &statuses(
+                    PATH_ERRORS,
+                    &[WORKSPACE_ROOT, MISSING_PARENT, NOT_A_DIRECTORY, ALREADY_EXISTS, RESOURCE_TOO_LARGE, DIGEST_MISMATCH, INVALID_HEADER, QUOTA_EXCEEDED, PRECONDITION_FAILED],
+                ),
+            ))),
+        Operation::new("put", "/wk/w/rename/{path}", "Workspace")
+            .summary("Rename a file or directory")
+            .param(path_param("path"))
+            .param(required_query("name", json!({ "type": "string" }), "The new file name"))
+            .security(Security::Policy)
+            .responses(guarded(reply(
+                json!({ "nullable": true }),
+                &statuses(PATH_ERRORS, &[WORKSPACE_ROOT, NOT_FOUND, ALREADY_EXISTS, QUOTA_EXCEEDED]),
+            ))),
+        Operation::new("delete", "/wk/w/remove/{path}", "Workspace")
+            .summary("Remove a file or directory")
+            .param(path_param("path"))
+            .security(Security::Policy)
+            .responses(guarded(reply(
+                json!({ "nullable": true }),
+                &statuses(PATH_ERRORS, &[WORKSPACE_ROOT, NOT_FOUND]),
+            ))),
+        Operation::new("post", "/wk/w/mkdir/{path}", "Workspace")
+            .summary("Make a directory")
+            .param(path_param("path"))
+            .param(query("parents", json!({ "type": "boolean", "default": false }), "Create the missing parent directories like `mkdir -p`"))
+            .security(Security::Policy)
+            .responses(guarded(reply(
+                json!({ "type": "array", "items": { "type": "string" }, "description": "The directories created, relative to the workspace root" }),
+                &statuses(PATH_ERRORS, &[WORKSPACE_ROOT, MISSING_PARENT, ALREADY_EXISTS, NOT_A_DIRECTORY]),
+            ))),
+        Operation::new("post", "/wk/w/batch", "Workspace")
+            .summary("Run the file operations in a batch")
+            .description("The operations run concurrently unless it's transactional, so they shouldn't depend on each other.")
+            .body(json_body(schema("BatchParam")))
+            .security(Security::Policy)
+            .responses(guarded(reply(schema("Batched"), &[]))),
+        // the users
+        Operation::new("post", "/user/register", "User")
+            .summary("Register a user")
+            .body(json_body(schema("Credentials")))
+            .responses(reply(json!({ "nullable": true }), &[])),
+        Operation::new("post", "/user/login", "User")
+            .summary("Log in for a token valid in 3 days")
+            .body(json_body(schema("Credentials")))
+            .responses(reply(schema("Token"), &[USER_NOT_FOUND, INCORRECT_PASSWORD])),
+        Operation::new("get", "/user/info", "User")
+            .summary("The requesting user")
+            .security(Security::Bearer)
+            .responses({
+                let mut responses = reply(schema("UserInfo"), &[]);
+                responses["401"] = json!({ "description": "No valid token" });
+                responses
+            }),
+        Operation::new("get", "/user/quota", "User")
+            .summary("The storage quotas of the user")
+            .security(Security::Bearer)
+            .responses({
+                let mut responses = reply(schema("UserQuota"), &[]);
+                responses["401"] = json!({ "description": "No valid token" });
+                responses
+            }),
+        // the protocols over the default workspace
+        Operation::new("get", "/dav/{path}", "WebDAV")
+            .summary("WebDAV class 1 and 2 over the default workspace")
+            .description("Besides the methods here, `PROPFIND`, `PROPPATCH`, `MKCOL`, `COPY`, `MOVE`, `LOCK` and `UNLOCK` follow RFC 4918.")
+            .param(path_param("path"))
+            .security(Security::Basic)
+            .responses(json!({ "200": { "description": "The bytes of the file" }, "401": { "description": "Not authenticated" } })),
+        Operation::new("head", "/dav/{path}", "WebDAV")
+            .summary("The headers of the file")
+            .param(path_param("path"))
+            .security(Security::Basic)
+            .responses(json!({ "200": { "description": "The same headers as the download" } })),
+        Operation::new("put", "/dav/{path}", "WebDAV")
+            .summary("Write the request body as the file")
+            .param(path_param("path"))
+            .security(Security::Basic)
+            .responses(json!({ "201": { "description": "Created" }, "204": { "description": "Overwritten" } })),
+        Operation::new("delete", "/dav/{path}", "WebDAV")
+            .summary("Remove a file or directory")
+            .param(path_param("path"))
+            .security(Security::Basic)
+            .responses(json!({ "204": { "description": "Removed" } })),
+        Operation::new("options", "/dav/{path}", "WebDAV")
+            .summary("The DAV classes and the methods supported")
+            .param(path_param("path"))
+            .responses(json!({ "200": { "description": "`DAV: 1, 2` and `Allow`" } })),
+        Operation::new("get", "/s3/{path}", "S3")
+            .summary("ListBuckets, ListObjects(V2), ListParts, GetBucketLocation and GetObject")
+            .description("Mounted if `s3` is configured, the buckets are the top-level directories of the default workspace. The errors are in the XML of S3.")
+            .param(path_param("path"))
+            .security(Security::SigV4)
+            .responses(json!({ "200": { "description": "The XML result or the bytes of the object" } })),
+        Operation::new("head", "/s3/{path}", "S3")
+            .summary("HeadBucket and HeadObject")
+            .param(path_param("path"))
+            .security(Security::SigV4)
+            .responses(json!({ "200": { "description": "The headers of the object" } })),
+        Operation::new("put", "/s3/{path}", "S3")
+            .summary("CreateBucket, PutObject, CopyObject, UploadPart and UploadPartCopy")
+            .param(path_param("path"))
+            .security(Security::SigV4)
+            .responses(json!({ "200": { "description": "Done" } })),
+        Operation::new("post", "/s3/{path}", "S3")
+            .summary("DeleteObjects, CreateMultipartUpload and CompleteMultipartUpload")
+            .param(path_param("path"))
+            .security(Security::SigV4)
+            .responses(json!({ "200": { "description": "The XML result" } })),
+        Operation::new("delete", "/s3/{path}", "S3")
+            .summary("DeleteBucket, DeleteObject and AbortMultipartUpload")
+            .param(path_param("path"))
+            .security(Security::SigV4)
+            .responses(json!({ "204": { "description": "Done" } })),
+        // this document
+        Operation::new("get", "/openapi.json", "Docs")
+            .summary("This document")
+            .responses(json!({ "200": { "description": "The OpenAPI 3 document", "content": { "application/json": {} } } })),
+        Operation::new("get", "/docs", "Docs")
+            .summary("The interactive docs of this document")
+            .description("Served if `api-docs` is enabled.")
+            .responses(json!({ "200": { "description": "The page", "content": { "text/html": {} } } })),
+    ]
+}
+
+fn schemas() -> Value {
+    let timestamp = schema("Timestamp");
+
+    json!({
+        "ReplyStatus": {
+            "type": "integer",
+            "description": status::ALL
+                .iter()
+                .map(|(name, code)| format!("- `{code}`: {name}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "enum": status::ALL.iter().map(|(_, code)| code).collect::<Vec<_>>(),
+            "x-enum-varnames": status::ALL.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+        },
+        "ReplyErrorObject": {
+            "type": "object",
+            "required": ["status", "msg"],
+            "properties": {
+                "status": schema("ReplyStatus"),
+                "msg": { "type": "string" },
+                "correlation_id": { "type": "string", "description": "Of an internal error in a batch, found in the logs" },
+            },
+        },
+        "Problem": {
+            "type": "object",
+            "required": ["type", "title", "status", "detail"],
+            "properties": {
+                "type": { "type": "string", "example": "about:blank" },
+                "title": { "type": "string", "description": "The reason phrase of the HTTP status" },
+                "status": { "type": "integer", "description": "The HTTP status" },
+                "detail": { "type": "string" },
+                "code": schema("ReplyStatus"),
+                "correlation_id": { "type": "string", "description": "Of an internal error, found in the logs" },
+            },
+        },
+        "TimeFormat": {
+            "type": "string",
+            "enum": ["string", "unix", "rfc3339"],
+            "default": "string",
+            "description": "Unix seconds in a string, Unix seconds, or RFC 3339 in UTC with nanoseconds",
+        },
+        "Timestamp": {
+            "oneOf": [{ "type": "string" }, { "type": "integer", "format": "int64" }],
+            "description": "Formatted by `time-format`",
+        },
+        "Kind": { "type": "string", "enum": ["dir", "file", "symlink"] },
+        "Algo": {
+            "type": "string",
+            "enum": ["md5", "sha256", "sha512", "blake3", "crc32c"],
+        },
+        "FsEntry": {
+            "type": "object",
+            "required": ["kind", "name", "size", "modified", "created", "accessed", "mode", "owner", "group", "inode", "nlink", "mime"],
+            "properties": {
+                "kind": { "type": "string", "enum": ["Dir", "File", "Symlink"] },
+                "name": { "type": "string" },
+                "size": { "type": "string", "nullable": true, "description": "Like `1.5 KB`, the recursive total of a directory with `with-size`" },
+                "modified": timestamp,
+                "created": { "allOf": [timestamp], "nullable": true, "description": "Absent if the file system doesn't record it" },
+                "accessed": timestamp,
+                "target": { "type": "string", "description": "Where a symlink points to" },
+                "mode": { "type": "integer", "description": "The permission bits like `0o755`" },
+                "owner": { "type": "integer", "description": "The uid" },
+                "group": { "type": "integer", "description": "The gid" },
+                "inode": { "type": "integer", "format": "int64" },
+                "nlink": { "type": "integer", "format": "int64" },
+                "mime": { "type": "string", "nullable": true, "description": "Guessed by the extension of a file" },
+                "items": { "type": "integer", "format": "int64", "description": "The recursive item count of a directory with `with-size`" },
+                "children": { "type": "array", "items": schema("FsEntry"), "description": "The entries of a directory within `depth`" },
+            },
+        },
+        "Directory": {
+            "type": "object",
+            "required": ["parent", "entries", "next_cursor"],
+            "properties": {
+                "parent": { "type": "string", "nullable": true },
+                "entries": { "type": "array", "items": schema("FsEntry") },
+                "next_cursor": { "type": "string", "nullable": true, "description": "Pass it as `cursor` for the next page, absent on the last page" },
+            },
+        },
+        "Found": {
+            "type": "object",
+            "required": ["entries", "next_cursor"],
+            "properties": {
+                "entries": {
+                    "type": "array",
+                    "items": {
+                        "allOf": [
+                            schema("FsEntry"),
+                            { "type": "object", "required": ["path"], "properties": { "path": { "type": "string", "description": "Relative to the workspace root" } } },
+                        ],
+                    },
+                },
+                "next_cursor": { "type": "string", "nullable": true },
+            },
+        },
+        "Stat": {
+            "allOf": [
+                schema("FsEntry"),
+                {
+                    "type": "object",
+                    "required": ["etag"],
+                    "properties": {
+                        "etag": { "type": "string" },
+                        "checksums": { "type": "object", "additionalProperties": { "type": "string" }, "description": "Algorithm => lowercase hex digest, of the requested ones" },
+                    },
+                },
+            ],
+        },
+        "Checksum": {
+            "type": "object",
+            "required": ["algo", "digest"],
+            "properties": {
+                "algo": schema("Algo"),
+                "digest": { "type": "string", "description": "In lowercase hex" },
+            },
+        },
+        "Uploaded": {
+            "type": "object",
+            "required": ["name", "skipped", "created"],
+            "properties": {
+                "name": { "type": "string", "description": "Differs from the uploaded name if it's renamed for a conflict" },
+                "skipped": { "type": "boolean", "description": "The existing file is kept for a conflict" },
+                "created": { "type": "array", "items": { "type": "string" }, "description": "The missing parent directories created with `parents=true`" },
+            },
+        },
+        "WatchEvent": {
+            "type": "object",
+            "required": ["id", "type"],
+            "properties": {
+                "id": { "type": "integer", "format": "int64", "description": "Pass it as `since` or `Last-Event-ID` to resume" },
+                "type": { "type": "string", "enum": ["created", "modified", "renamed", "removed", "reset"], "description": "`reset` asks to read the directory again when some events are lost" },
+                "path": { "type": "string" },
+                "from": { "type": "string", "description": "Of `renamed`" },
+                "to": { "type": "string", "description": "Of `renamed`" },
+            },
+        },
+        "Operation": {
+            "type": "object",
+            "required": ["op", "path"],
+            "properties": {
+                "op": { "type": "string", "enum": ["remove", "rename", "move", "copy", "mkdir"] },
+                "path": { "type": "string", "description": "Relative to the workspace root" },
+                "name": { "type": "string", "description": "The new name of `rename`" },
+                "to": { "type": "string", "description": "The destination of `move` and `copy`" },
+                "parents": { "type": "boolean", "description": "Of `mkdir`" },
+            },
+        },
+        "BatchParam": {
+            "type": "object",
+            "required": ["ops"],
+            "properties": {
+                "ops": { "type": "array", "items": schema("Operation") },
+                "transactional": { "type": "boolean", "default": false, "description": "Run the operations in order and stop at the first failure, which undoes the done ones" },
+            },
+        },
+        "Batched": {
+            "type": "object",
+            "required": ["results", "rolled_back"],
+            "properties": {
+                "results": {
+                    "type": "array",
+                    "items": {
+                        "oneOf": [
+                            {
+                                "type": "object",
+                                "required": ["status", "data"],
+                                "properties": {
+                                    "status": { "type": "integer", "enum": [OK] },
+                                    "data": { "nullable": true, "description": "The directories made by `mkdir`" },
+                                },
+                            },
+                            schema("ReplyErrorObject"),
+                        ],
+                    },
+                    "description": "In the order of the operations, a transactional batch stops at the first failure",
+                },
+                "rolled_back": { "type": "boolean" },
+            },
+        },
+        "Credentials": {
+            "type": "object",
+            "required": ["username", "password"],
+            "properties": {
+                "username": { "type": "string" },
+                "password": { "type": "string", "format": "password" },
+            },
+        },
+        "Token": {
+            "type": "object",
+            "required": ["token"],
+            "properties": { "token": { "type": "string" } },
+        },
+        "UserInfo": {
+            "type": "object",
+            "required": ["username", "roles"],
+            "properties": {
+                "username": { "type": "string" },
+                "roles": { "type": "array", "items": { "type": "string" } },
+            },
+        },
+        "QuotaInfo": {
+            "type": "object",
+            "required": ["used", "max_bytes", "max_files"],
+            "properties": {
+                "used": {
+                    "type": "object",
+                    "required": ["bytes", "files"],
+                    "properties": {
+                        "bytes": { "type": "integer", "format": "int64" },
+                        "files": { "type": "integer", "format": "int64", "description": "Directories aren't counted" },
+                    },
+                },
+                "max_bytes": { "type": "integer", "format": "int64", "nullable": true },
+                "max_files": { "type": "integer", "format": "int64", "nullable": true },
+            },
+        },
+        "UserQuota": {
+            "type": "object",
+            "required": ["workspace", "home"],
+            "properties": {
+                "workspace": { "allOf": [schema("QuotaInfo")], "nullable": true, "description": "Of the default workspace" },
+                "home": { "allOf": [schema("QuotaInfo")], "nullable": true, "description": "Of the home directory in the homes mode" },
+            },
+        },
+    })
+}
+
+fn document() -> Value {
+    let mut paths = Map::new();
+    for op in operations() {
+        let (path, method) = (op.path, op.method);
+        let item = paths.entry(path).or_insert_with(|| {
+            if path.starts_with("/wk/") || path.starts_with("/user/") {
+                json!({ "servers": api_servers() })
+            } else {
+                Value::Object(Map::new())
+            }
+        });
+        item[method] = op.into_value();
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Sachima",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "The default workspace is served under `/wk`, the home of the requesting user under `/wk/home`, and the named workspaces under `/wk/{name}`, which share the routes of `/wk/r` and `/wk/w` documented here.\n\nThe routes of `/wk` and `/user` are served under `/api/v1` and `/api/v2`. In the API v1, the JSON replies are in HTTP 200 with a business `status`, see `ReplyStatus`. In the API v2, the data is replied alone, or nothing in HTTP 204, and the errors are replied in the problem details of RFC 7807 with the HTTP statuses, which keep the business status as `code`.\n\nThe routes without the prefix are the deprecated aliases of the API v1, whose replies carry `Deprecation`, `Sunset` and the `Link` to the successor. There `X-Api-Version: 2` still selects the replies of the API v2.",
+        },
+        "tags": [
+            { "name": "Workspace" },
+            { "name": "User" },
+            { "name": "WebDAV" },
+            { "name": "S3" },
+            { "name": "Docs" },
+        ],
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "responses": {
+                "Problem": {
+                    "description": "The problem details in the API v2",
+                    "content": { "application/problem+json": { "schema": schema("Problem") } },
+                },
+                "Internal": {
+                    "description": "An internal error, whose details are logged with the correlation id",
+                    "headers": { "X-Correlation-Id": { "schema": { "type": "string" } } },
+                    "content": {
+                        "text/plain": {},
+                        "application/problem+json": { "schema": schema("Problem") },
+                    },
+                },
+            },
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT", "description": "From `/user/login`, or a trusted client certificate" },
+                "basic": { "type": "http", "scheme": "basic" },
+                "sigv4": { "type": "apiKey", "in": "header", "name": "Authorization", "description": "AWS Signature V4 by an access key in `s3.keys`, or a presigned URL" },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DOCUMENT;
+    use crate::reply::status;
+    use serde_json::Value;
+
+    /// Every business status is documented, and every schema referred exists
+    #[test]
+    fn test_schemas() {
+        let codes = DOCUMENT["components"]["schemas"]["ReplyStatus"]["enum"]
+            .as_array()
+            .unwrap();
+        for (name, code) in status::ALL {
+            assert!(codes.contains(&(*code).into()), "{name} isn't documented");
+        }
+
+        fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+            match value {
+                Value::Object(map) => {
+                    if let Some(Value::String(r)) = map.get("$ref") {
+                        found.push(r);
+                    }
+                    map.values().for_each(|value| refs(value, found));
+                }
+                Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+                _ => (),
+            }
+        }
+        let mut found = Vec::new();
+        refs(&DOCUMENT, &mut found);
+        for r in found {
+            let pointer = r.strip_prefix('#').unwrap();
+            assert!(DOCUMENT.pointer(pointer).is_some(), "{r} doesn't exist");
+        }
+    }
+}
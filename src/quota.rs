@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::io;
+use std::ops::{Add, AddAssign};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::task;
+use tokio::time;
+
+use crate::config::quota::{Limit, Quota};
+use crate::home;
+use crate::reply::ReplyError;
+use crate::settings::SharedSettings;
+use crate::utils::atomic;
+
+/// How often the tracked usage is corrected by scanning the disk
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The storage taken by a directory tree
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    /// Directories aren't counted
+    pub files: u64,
+}
+
+impl Add for Usage {
+    type Output = Usage;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Usage {
+            bytes: self.bytes + rhs.bytes,
+            files: self.files + rhs.files,
+        }
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Limit {
+    fn exceeded_by(&self, usage: Usage) -> bool {
+        self.bytes.is_some_and(|bytes| usage.bytes > bytes.as_u64())
+            || self.files.is_some_and(|files| usage.files > files)
+    }
+}
+
+/// The quotas of a workspace and the usage tracked incrementally
+#[derive(Debug, Default)]
+pub struct Quotas {
+    /// Absolute directory => its limit
+    dirs: Vec<(PathBuf, Limit)>,
+    /// The workspace root whose home directories are limited
+    homes: Option<(PathBuf, Limit)>,
+    /// Absolute directory => its usage, absent until it's scanned
+    usage: Mutex<HashMap<PathBuf, Usage>>,
+}
+
+impl Quotas {
+    pub fn new(root: &Path, quota: Option<&Quota>, homes: bool) -> Self {
+        let Some(quota) = quota else {
+            return Self::default();
+        };
+
+        let mut dirs: Vec<_> = quota
+            .dirs
+            .iter()
+            .map(|(dir, limit)| (root.join(dir), *limit))
+            .collect();
+        if quota.limit != Limit::default() {
+            dirs.push((root.to_owned(), quota.limit));
+        }
+
+        Self {
+            dirs,
+            homes: quota
+                .home
+                .filter(|_| homes)
+                .map(|limit| (root.to_owned(), limit)),
+            usage: Mutex::default(),
+        }
+    }
+
+    /// Nothing is limited, so nothing needs tracking
+    pub fn is_empty(&self) -> bool {
+        self.dirs.is_empty() && self.homes.is_none()
+    }
+
+    /// The limited directories containing the path
+    fn scopes(&self, path: &Path) -> Vec<(PathBuf, Limit)> {
+        let mut scopes: Vec<_> = self
+            .dirs
+            .iter()
+            .filter(|(dir, _)| path.starts_with(dir))
+            .cloned()
+            .collect();
+
+        if let Some((root, limit)) = &self.homes {
+            if let Some(owner) = path.strip_prefix(root).ok().and_then(home::owner) {
+                if let Some(home) = home::home_dir(root, owner) {
+                    scopes.push((home, *limit));
+                }
+            }
+        }
+
+        scopes
+    }
+
+    /// The usage and limit of a limited directory
+    pub async fn get(&self, dir: &Path) -> io::Result<Option<(Usage, Limit)>> {
+        let Some((dir, limit)) = self.scopes(dir).into_iter().find(|(scope, _)| scope == dir)
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some((self.usage_of(&dir).await?, limit)))
+    }
+
+    /// The tracked usage, scanned if it's unknown
+    async fn usage_of(&self, dir: &Path) -> io::Result<Usage> {
+        if let Some(usage) = self.usage.lock().unwrap().get(dir) {
+            return Ok(*usage);
+        }
+
+        let scanned = measure(dir).await?;
+        Ok(*self
+            .usage
+            .lock()
+            .unwrap()
+            .entry(dir.to_owned())
+            .or_insert(scanned))
+    }
+
+    /// Account the files added at the path,
+    /// unless they exceed any quota containing it.
+    pub async fn reserve(&self, path: &Path, added: Usage) -> Result<(), ReplyError> {
+        self.reserve_except(path, added, None).await
+    }
+
+    /// Account the files moved from one path to another
+    pub async fn relocate(&self, from: &Path, to: &Path, moved: Usage) -> Result<(), ReplyError> {
+        self.reserve_except(to, moved, Some(from)).await?;
+
+        let to_scopes = self.scopes(to);
+        let mut usage = self.usage.lock().unwrap();
+        for (dir, _) in self.scopes(from) {
+            if !to_scopes.iter().any(|(scope, _)| *scope == dir) {
+                release(&mut usage, &dir, moved);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Account the files removed at the path
+    pub fn release(&self, path: &Path, removed: Usage) {
+        let mut usage = self.usage.lock().unwrap();
+        for (dir, _) in self.scopes(path) {
+            release(&mut usage, &dir, removed);
+        }
+    }
+
+    /// The scopes containing `from` have accounted the files already
+    async fn reserve_except(
+        &self,
+        path: &Path,
+        added: Usage,
+        from: Option<&Path>,
+    ) -> Result<(), ReplyError> {
+        let kept = from.map(|from| self.scopes(from)).unwrap_or_default();
+        let scopes: Vec<_> = self
+            .scopes(path)
+            .into_iter()
+            .filter(|(dir, _)| !kept.iter().any(|(scope, _)| scope == dir))
+            .collect();
+
+        for (dir, _) in &scopes {
+            self.usage_of(dir).await?;
+        }
+
+        let mut usage = self.usage.lock().unwrap();
+        for (dir, limit) in &scopes {
+            let used = usage.get(dir).copied().unwrap_or_default();
+            if limit.exceeded_by(used + added) {
+                return Err(ReplyError::QuotaExceeded);
+            }
+        }
+        for (dir, _) in scopes {
+            *usage.entry(dir).or_default() += added;
+        }
+
+        Ok(())
+    }
+
+    /// Correct the tracked usage by scanning the disk
+    pub async fn reconcile(&self) {
+        let dirs: Vec<_> = self.usage.lock().unwrap().keys().cloned().collect();
+
+        for dir in dirs {
+            match measure(&dir).await {
+                Ok(scanned) => {
+                    self.usage.lock().unwrap().insert(dir, scanned);
+                }
+                Err(e) => {
+                    tracing::warn!(dir = %dir.display(), error = %e, "cannot scan the quota usage")
+                }
+            }
+        }
+    }
+}
+
+fn release(usage: &mut HashMap<PathBuf, Usage>, dir: &Path, removed: Usage) {
+    if let Some(used) = usage.get_mut(dir) {
+        used.bytes = used.bytes.saturating_sub(removed.bytes);
+        used.files = used.files.saturating_sub(removed.files);
+    }
+}
+
+/// The storage taken by a file or directory tree,
+/// which is zero if it doesn't exist.
+pub async fn measure(path: &Path) -> io::Result<Usage> {
+    fn walk(path: &Path) -> io::Result<Usage> {
+        let md = match path.symlink_metadata() {
+            Ok(md) => md,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Usage::default()),
+            Err(e) => return Err(e),
+        };
+
+        if !md.is_dir() {
+            return Ok(Usage {
+                bytes: md.len(),
+                files: 1,
+            });
+        }
+
+        let mut usage = Usage::default();
+        for entry in path.read_dir()? {
+            let entry = entry?;
+            // the uploads in progress are accounted once finished
+            if !atomic::is_temp(&entry.file_name().to_string_lossy()) {
+                usage += walk(&entry.path())?;
+            }
+        }
+
+        Ok(usage)
+    }
+
+    let path = path.to_owned();
+    task::spawn_blocking(move || walk(&path))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Reconcile the usage of every workspace periodically
+pub async fn reconcile(settings: SharedSettings) {
+    let mut interval = time::interval(RECONCILE_INTERVAL);
+    // the usage is scanned on demand at first
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let quotas: Vec<Arc<Quotas>> = {
+            let settings = settings.load();
+            std::iter::once(&settings.workspace)
+                .chain(settings.workspaces.values())
+                .map(|wk| wk.quotas.clone())
+                .collect()
+        };
+
+        for quotas in quotas {
+            quotas.reconcile().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{measure, Quotas, Usage};
+    use crate::config::quota::Quota;
+    use crate::reply::ReplyError;
+    use crate::utils::tests::setup_workspace;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_quotas() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let root = wk.path();
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::write(root.join("shared/a.txt"), "12345").unwrap();
+
+        let quota: Quota = toml::from_str(
+            r#"
+            files = 3
+            home = { bytes = "10B" }
+            dirs = { "shared" = { bytes = "8B" } }
+            "#,
+        )
+        .unwrap();
+        let quotas = Quotas::new(root, Some(&quota), true);
+
+        let three = Usage { bytes: 3, files: 1 };
+        quotas
+            .reserve(&root.join("shared/b.txt"), three)
+            .await
+            .unwrap();
+        assert!(matches!(
+            quotas.reserve(&root.join("shared/c.txt"), three).await,
+            Err(ReplyError::QuotaExceeded)
+        ));
+
+        // only the workspace quota covers it
+        quotas.reserve(&root.join("d.txt"), three).await.unwrap();
+        assert!(matches!(
+            quotas.reserve(&root.join("~alice/e.txt"), three).await,
+            Err(ReplyError::QuotaExceeded)
+        ));
+
+        quotas.release(&root.join("shared/b.txt"), three);
+        quotas
+            .reserve(&root.join("~alice/e.txt"), three)
+            .await
+            .unwrap();
+
+        let (used, limit) = quotas.get(&root.join("~alice")).await.unwrap().unwrap();
+        assert_eq!(used, three);
+        assert_eq!(limit.bytes.unwrap().as_u64(), 10);
+
+        assert_eq!(
+            measure(&root.join("shared")).await.unwrap(),
+            Usage { bytes: 5, files: 1 }
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tracing_subscriber::reload::Handle;
+use tracing_subscriber::{EnvFilter, Registry};
+
+use crate::config::ConfigSource;
+use crate::settings::SharedSettings;
+use crate::Config;
+
+pub type LogHandle = Handle<EnvFilter, Registry>;
+
+/// Filter the logs of poem and sachima by the level,
+/// or by `RUST_LOG` if the level is absent.
+pub fn log_filter(config: &Config) -> EnvFilter {
+    match config.poem_log_level {
+        Some(level) => EnvFilter::new(format!("poem={level},sachima={level}")),
+        None => EnvFilter::from_default_env(),
+    }
+}
+
+/// Reload the config on SIGHUP
+pub async fn on_sighup(
+    source: ConfigSource,
+    mut running: Config,
+    settings: SharedSettings,
+    log: LogHandle,
+) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::error!(error = %e, "cannot listen to SIGHUP, reloading is disabled");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        let config = match source.load() {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::error!(error = %e, "config reload failed, the old one is kept");
+                continue;
+            }
+        };
+
+        let previous = running.clone();
+        for key in reload(&mut running, config) {
+            tracing::warn!(key, "changing it requires a restart, ignored");
+        }
+
+        let reloaded = settings.load().reload(&previous, &running);
+        settings.store(Arc::new(reloaded));
+        if let Err(e) = log.reload(log_filter(&running)) {
+            tracing::error!(error = %e, "cannot reload the log filter");
+        }
+
+        tracing::info!("config reloaded");
+    }
+}
+
+/// Take the reloadable keys into the running config,
+/// the others keep the running values and are returned.
+fn reload(running: &mut Config, config: Config) -> Vec<&'static str> {
+    let keys = restart_required(running, &config);
+
+    running.workspace = config.workspace;
+    if running
+        .workspaces
+        .iter()
+        .map(|wk| &wk.name)
+        .eq(config.workspaces.iter().map(|wk| &wk.name))
+    {
+        running.workspaces = config.workspaces;
+    }
+    running.homes = config.homes;
+    running.admins = config.admins;
+    running.quota = config.quota;
+    running.search = config.search;
+    running.max_upload = config.max_upload;
+    running.poem_log_level = config.poem_log_level;
+    if let (Some(running), Some(tls)) = (&mut running.tls, config.tls) {
+        running.client_users = tls.client_users;
+    }
+    if running.s3.is_some() && config.s3.is_some() {
+        running.s3 = config.s3;
+    }
+
+    keys
+}
+
+/// The changed keys which only take effect after a restart
+fn restart_required(running: &Config, reloaded: &Config) -> Vec<&'static str> {
+    let mut keys = Vec::new();
+
+    if running.port != reloaded.port {
+        keys.push("port");
+    }
+    if running.database_url != reloaded.database_url {
+        keys.push("database-url");
+    }
+    if running.jwt_secret_key != reloaded.jwt_secret_key {
+        keys.push("jwt-secret-key");
+    }
+    if running.password_salt != reloaded.password_salt {
+        keys.push("password-salt");
+    }
+    if running.drain_timeout != reloaded.drain_timeout {
+        keys.push("drain-timeout");
+    }
+    if !running
+        .workspaces
+        .iter()
+        .map(|wk| &wk.name)
+        .eq(reloaded.workspaces.iter().map(|wk| &wk.name))
+    {
+        keys.push("workspaces");
+    }
+
+    match (&running.tls, &reloaded.tls) {
+        (None, None) => (),
+        (Some(running), Some(reloaded))
+            if running.cert == reloaded.cert
+                && running.key == reloaded.key
+                && running.client_ca == reloaded.client_ca
+                && running.client_auth_required == reloaded.client_auth_required => {}
+        _ => keys.push("tls"),
+    }
+
+    // the routes are mounted on start
+    if running.s3.is_some() != reloaded.s3.is_some() {
+        keys.push("s3");
+    }
+    if running.api_docs != reloaded.api_docs {
+        keys.push("api-docs");
+    }
+
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reload, restart_required};
+    use crate::config::{Config, Tls, Workspace};
+    use crate::settings::Settings;
+    use crate::utils::tests::setup_workspace;
+    use bytesize::ByteSize;
+    use std::sync::Arc;
+
+    fn config(wk: Workspace) -> Config {
+        Config {
+            port: 8000,
+            database_url: "postgres://localhost/sachima".to_owned(),
+            poem_log_level: None,
+            workspace: wk,
+            workspaces: Default::default(),
+            homes: false,
+            admins: Vec::new(),
+            quota: None,
+            search: Default::default(),
+            max_upload: ByteSize::gib(2),
+            jwt_secret_key: "sachima jwt secret key".to_owned(),
+            password_salt: "sachima password salt".to_owned(),
+            tls: Some(Tls {
+                cert: "cert.pem".into(),
+                key: "key.pem".into(),
+                client_ca: None,
+                client_auth_required: false,
+                client_users: Default::default(),
+            }),
+            s3: None,
+            api_docs: false,
+            drain_timeout: 30,
+        }
+    }
+
+    #[test]
+    fn test_restart_required() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let running = config(wk);
+
+        let mut reloaded = running.clone();
+        reloaded.max_upload = ByteSize::gib(4);
+        assert!(restart_required(&running, &reloaded).is_empty());
+
+        reloaded.port = 9000;
+        reloaded.tls.as_mut().unwrap().cert = "renamed.pem".into();
+        reloaded.s3 = Some(Default::default());
+        assert_eq!(restart_required(&running, &reloaded), ["port", "tls", "s3"]);
+
+        // the running values are kept for those requiring a restart
+        let mut config = running.clone();
+        assert_eq!(reload(&mut config, reloaded), ["port", "tls", "s3"]);
+        assert_eq!(config.port, 8000);
+        assert_eq!(config.tls.as_ref().unwrap().cert, running.tls.unwrap().cert);
+        assert!(config.s3.is_none());
+        assert_eq!(config.max_upload, ByteSize::gib(4));
+    }
+
+    #[test]
+    fn test_keep_indexes() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let running = config(wk);
+        let settings = Settings::from(&running);
+
+        let mut reloaded = running.clone();
+        reloaded.max_upload = ByteSize::gib(4);
+        let kept = settings.reload(&running, &reloaded);
+        assert!(Arc::ptr_eq(
+            &kept.workspace.index,
+            &settings.workspace.index
+        ));
+        assert!(Arc::ptr_eq(
+            &kept.workspace.quotas,
+            &settings.workspace.quotas
+        ));
+        assert_eq!(kept.workspace.max_upload, ByteSize::gib(4));
+
+        reloaded.search.full_text = !running.search.full_text;
+        let rebuilt = settings.reload(&running, &reloaded);
+        assert!(!Arc::ptr_eq(
+            &rebuilt.workspace.index,
+            &settings.workspace.index
+        ));
+        assert!(Arc::ptr_eq(
+            &rebuilt.workspace.quotas,
+            &settings.workspace.quotas
+        ));
+    }
+}
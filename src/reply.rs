@@ -1,13 +1,18 @@
 use std::borrow::Cow;
+use std::fmt;
 use std::io;
 
 use bytesize::ByteSize;
 use poem::error::{ParseMultipartError, ResponseError};
+use poem::http::header::CONTENT_TYPE;
+use poem::http::HeaderValue;
 use poem::http::StatusCode;
 use poem::web::Json;
-use poem::{IntoResponse, Response};
+use poem::{IntoResponse, Request, Response};
 use sea_orm::DbErr;
 use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
 
 use status::*;
 
@@ -57,6 +62,30 @@ pub enum ReplyError {
     #[error("uploaded resource is larger than the upper limit {0}")]
     ResourceTooLarge(ByteSize),
 
+    #[error("path escapes the workspace")]
+    OutsideWorkspace,
+
+    #[error("permission denied")]
+    PermissionDenied,
+
+    #[error("storage quota exceeded")]
+    QuotaExceeded,
+
+    #[error("file doesn't match the precondition")]
+    PreconditionFailed,
+
+    #[error("content doesn't match the digest")]
+    DigestMismatch,
+
+    #[error("invalid header {0}")]
+    InvalidHeader(String),
+
+    #[error("cannot move or copy a directory into itself")]
+    IntoItself,
+
+    #[error("invalid parameter {0}")]
+    InvalidParam(String),
+
     #[error(transparent)]
     Internal(InternalError),
 }
@@ -69,11 +98,81 @@ struct ReplyDataObject<T> {
 
 impl<T: Serialize + Send> IntoResponse for ReplyData<T> {
     fn into_response(self) -> Response {
-        Json(ReplyDataObject {
+        let mut resp = Json(ReplyDataObject {
             status: OK,
             data: self.0,
         })
-        .into_response()
+        .into_response();
+        resp.extensions_mut().insert(Enveloped);
+        resp
+    }
+}
+
+/// Marks the replies of `ReplyData`, whose data is replied alone in the API v2
+#[derive(Debug, Clone, Copy)]
+struct Enveloped;
+
+/// Take the data out of the envelope for the API v2, where nothing is replied as 204
+pub async fn unwrap_data(mut resp: Response) -> Response {
+    if resp.extensions_mut().remove::<Enveloped>().is_none() {
+        return resp;
+    }
+
+    let (parts, body) = resp.into_parts();
+    let data = match body.into_bytes().await {
+        Ok(bytes) => serde_json::from_slice::<Value>(&bytes)
+            .map(|mut reply| reply["data"].take())
+            .map_err(InternalError::from),
+        Err(e) => Err(e.into()),
+    };
+
+    match data {
+        Ok(Value::Null) => StatusCode::NO_CONTENT.into_response(),
+        Ok(data) => {
+            let mut resp = Json(data).into_response();
+            resp.set_status(parts.status);
+            resp
+        }
+        Err(e) => Problem::from(ReplyError::Internal(e)).into_response(),
+    }
+}
+
+/// The reply of an item in a batch, shaped like a whole reply
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct ReplyItem<T>(Item<T>);
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Item<T> {
+    Data(ReplyDataObject<T>),
+    Error {
+        #[serde(flatten)]
+        error: ReplyErrorObject,
+        /// Of an internal error, which is logged with it
+        #[serde(skip_serializing_if = "Option::is_none")]
+        correlation_id: Option<String>,
+    },
+}
+
+impl<T> From<Result<T, ReplyError>> for ReplyItem<T> {
+    fn from(res: Result<T, ReplyError>) -> Self {
+        ReplyItem(match res {
+            Ok(data) => Item::Data(ReplyDataObject { status: OK, data }),
+            Err(e) => match ReplyErrorObject::try_from(e) {
+                Ok(error) => Item::Error {
+                    error,
+                    correlation_id: None,
+                },
+                Err(e) => Item::Error {
+                    error: ReplyErrorObject {
+                        status: INTERNAL_ERROR,
+                        msg: "internal error".into(),
+                    },
+                    correlation_id: Some(log_internal(&e)),
+                },
+            },
+        })
     }
 }
 
@@ -136,6 +235,38 @@ impl TryFrom<ReplyError> for ReplyErrorObject {
                 status: RESOURCE_TOO_LARGE,
                 msg: e.to_string().into(),
             },
+            ReplyError::OutsideWorkspace => Self {
+                status: OUTSIDE_WORKSPACE,
+                msg: "path escapes the workspace".into(),
+            },
+            ReplyError::PermissionDenied => Self {
+                status: PERMISSION_DENIED,
+                msg: "permission denied".into(),
+            },
+            ReplyError::QuotaExceeded => Self {
+                status: QUOTA_EXCEEDED,
+                msg: "storage quota exceeded".into(),
+            },
+            ReplyError::PreconditionFailed => Self {
+                status: PRECONDITION_FAILED,
+                msg: "file doesn't match the precondition".into(),
+            },
+            ReplyError::DigestMismatch => Self {
+                status: DIGEST_MISMATCH,
+                msg: "content doesn't match the digest".into(),
+            },
+            e @ ReplyError::InvalidHeader(_) => Self {
+                status: INVALID_HEADER,
+                msg: e.to_string().into(),
+            },
+            ReplyError::IntoItself => Self {
+                status: INTO_ITSELF,
+                msg: "cannot move or copy a directory into itself".into(),
+            },
+            e @ ReplyError::InvalidParam(_) => Self {
+                status: INVALID_PARAM,
+                msg: e.to_string().into(),
+            },
 
             ReplyError::Internal(e) => return Err(e),
         })
@@ -146,9 +277,13 @@ impl IntoResponse for ReplyError {
     fn into_response(self) -> Response {
         match ReplyErrorObject::try_from(self) {
             Ok(rp) => rp.into_response(),
-            Err(e) => format!("{e:?}")
-                .with_status(StatusCode::INTERNAL_SERVER_ERROR)
-                .into_response(),
+            Err(e) => {
+                let id = log_internal(&e);
+                format!("internal error, correlation id {id}")
+                    .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .with_header(X_CORRELATION_ID, id)
+                    .into_response()
+            }
         }
     }
 }
@@ -170,10 +305,159 @@ impl ResponseError for ReplyError {
     }
 }
 
+/// Carries the correlation id of an internal error, which is found in the logs
+pub const X_CORRELATION_ID: &str = "x-correlation-id";
+
+/// Selects the API version of a request without a versioned prefix
+pub const X_API_VERSION: &str = "x-api-version";
+
+/// The internal details are logged instead of replied
+fn log_internal(e: &dyn fmt::Display) -> String {
+    let id = Uuid::new_v4().simple().to_string();
+    tracing::error!(correlation_id = id, error = %e, "internal error");
+    id
+}
+
+/// How the replies are shaped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// The data in the envelope, and the business status in HTTP 200
+    #[default]
+    V1,
+    /// The bare data, and the problem details with the HTTP status
+    V2,
+}
+
+impl ApiVersion {
+    /// Set on the route, or selected by `X-Api-Version`
+    pub fn of(req: &Request) -> Self {
+        if let Some(version) = req.data::<ApiVersion>() {
+            return *version;
+        }
+
+        match req
+            .headers()
+            .get(X_API_VERSION)
+            .map(|value| value.as_bytes())
+        {
+            Some(b"2") => ApiVersion::V2,
+            _ => ApiVersion::V1,
+        }
+    }
+}
+
+impl ReplyError {
+    /// The HTTP status in the API v2
+    pub fn http_status(&self) -> StatusCode {
+        match self {
+            ReplyError::IsAbsolute
+            | ReplyError::FileExpected
+            | ReplyError::MissingFileName
+            | ReplyError::DigestMismatch
+            | ReplyError::InvalidHeader(_)
+            | ReplyError::IntoItself
+            | ReplyError::InvalidParam(_) => StatusCode::BAD_REQUEST,
+            ReplyError::UserNotFound | ReplyError::IncorrectPassword => StatusCode::UNAUTHORIZED,
+            ReplyError::WorkspaceRoot
+            | ReplyError::OutsideWorkspace
+            | ReplyError::PermissionDenied => StatusCode::FORBIDDEN,
+            ReplyError::NotFound => StatusCode::NOT_FOUND,
+            ReplyError::AlreadyExists
+            | ReplyError::MissingParent
+            | ReplyError::NotADirectory
+            | ReplyError::IsADirectory => StatusCode::CONFLICT,
+            ReplyError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ReplyError::ResourceTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ReplyError::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+            ReplyError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The problem details of RFC 7807 in the API v2,
+/// which keep the business status as `code`.
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: Cow<'static, str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+}
+
+impl Problem {
+    fn new(status: StatusCode, detail: Cow<'static, str>) -> Self {
+        Self {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            detail,
+            code: None,
+            correlation_id: None,
+        }
+    }
+
+    /// The errors other than `ReplyError`, like those of the guards
+    pub fn from_error(e: poem::Error) -> Self {
+        let status = e.status();
+        if status.is_server_error() {
+            let id = log_internal(&e);
+            return Self {
+                correlation_id: Some(id),
+                ..Self::new(status, "internal error".into())
+            };
+        }
+
+        Self::new(status, e.to_string().into())
+    }
+}
+
+impl From<ReplyError> for Problem {
+    fn from(e: ReplyError) -> Self {
+        let status = e.http_status();
+        match ReplyErrorObject::try_from(e) {
+            Ok(ReplyErrorObject { status: code, msg }) => Self {
+                code: Some(code),
+                ..Self::new(status, msg)
+            },
+            Err(e) => Self {
+                code: Some(INTERNAL_ERROR),
+                correlation_id: Some(log_internal(&e)),
+                ..Self::new(status, "internal error".into())
+            },
+        }
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let id = self.correlation_id.clone();
+
+        let mut resp = Json(self).with_status(status).into_response();
+        resp.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        if let Some(id) = id.and_then(|id| id.parse().ok()) {
+            resp.headers_mut().insert(X_CORRELATION_ID, id);
+        }
+
+        resp
+    }
+}
+
 pub mod status {
     macro_rules! reply_status_code {
     ($($status: ident = $code: expr),*,) => {
         $(pub const $status: u16 = $code;)*
+
+        /// The names and codes of every status
+        pub const ALL: &[(&str, u16)] = &[$((stringify!($status), $code)),*];
     };
 }
 
@@ -191,6 +475,17 @@ pub mod status {
         FILE_EXPECTED = 10,
         MISSING_FILE_NAME = 11,
         RESOURCE_TOO_LARGE = 12,
+        OUTSIDE_WORKSPACE = 13,
+        PERMISSION_DENIED = 14,
+        QUOTA_EXCEEDED = 15,
+        PRECONDITION_FAILED = 16,
+        DIGEST_MISMATCH = 17,
+        INVALID_HEADER = 18,
+        INTO_ITSELF = 19,
+        // only in the items of a batch and the problem details,
+        // otherwise it's HTTP 500
+        INTERNAL_ERROR = 20,
+        INVALID_PARAM = 21,
     }
 }
 
@@ -201,10 +496,16 @@ impl From<io::Error> for ReplyError {
     }
 }
 
+/// The multipart body is sent by the client
 impl From<ParseMultipartError> for ReplyError {
-    #[inline]
     fn from(e: ParseMultipartError) -> Self {
-        Self::Internal(Box::new(e))
+        match e {
+            ParseMultipartError::InvalidContentType(_)
+            | ParseMultipartError::ContentTypeRequired => {
+                Self::InvalidHeader("content-type".to_owned())
+            }
+            _ => Self::InvalidParam("body".to_owned()),
+        }
     }
 }
 
@@ -1,27 +1,52 @@
+use std::future::Future;
 use std::sync::Arc;
 
-use bytesize::ByteSize;
 use jwt_codec::prelude::Hs256;
 use jwt_codec::Codec;
-use poem::http::StatusCode;
-use poem::Route;
-use poem::{delete, get, post, put};
+use poem::endpoint::BoxEndpoint;
+use poem::http::header::LINK;
+use poem::http::{HeaderValue, Method, StatusCode};
 use poem::{Endpoint, EndpointExt};
 use poem::{IntoResponse, Response};
+use poem::{Route, RouteMethod};
 
-use crate::config::Workspace;
+use crate::handlers::file_system::dav;
+use crate::handlers::file_system::s3::{self, S3Error};
 use crate::handlers::*;
-use crate::middlewares::JwtVerifier;
-use crate::reply::ReplyError;
+use crate::middlewares::{Access, BasicAuth, JwtVerifier, SigV4Auth, Target, WorkspaceGuard};
+use crate::reply::{unwrap_data, ApiVersion, Problem, ReplyError};
+use crate::settings::SharedSettings;
 use crate::Config;
 
-pub async fn http_error(e: ReplyError) -> StatusCode {
+/// The statuses of downloads in the API v1, which have no body
+pub async fn http_error(e: ReplyError) -> Response {
     match e {
-        ReplyError::WorkspaceRoot | ReplyError::IsAbsolute => StatusCode::FORBIDDEN,
+        ReplyError::IsAbsolute => StatusCode::FORBIDDEN.into_response(),
+        ReplyError::IsADirectory => StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response(),
+        // logged with a correlation id
+        e @ ReplyError::Internal(_) => e.into_response(),
+        e => e.http_status().into_response(),
+    }
+}
+
+/// The statuses defined by WebDAV
+pub async fn dav_error(e: ReplyError) -> StatusCode {
+    match e {
+        ReplyError::WorkspaceRoot
+        | ReplyError::IsAbsolute
+        | ReplyError::OutsideWorkspace
+        | ReplyError::PermissionDenied
+        | ReplyError::IntoItself => StatusCode::FORBIDDEN,
         ReplyError::NotFound => StatusCode::NOT_FOUND,
-        ReplyError::IsADirectory => StatusCode::UNSUPPORTED_MEDIA_TYPE,
-        ReplyError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        _ => unreachable!(),
+        ReplyError::AlreadyExists | ReplyError::IsADirectory => StatusCode::METHOD_NOT_ALLOWED,
+        ReplyError::MissingParent | ReplyError::NotADirectory => StatusCode::CONFLICT,
+        ReplyError::ResourceTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        ReplyError::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+        ReplyError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+        ReplyError::DigestMismatch | ReplyError::InvalidHeader(_) | ReplyError::InvalidParam(_) => {
+            StatusCode::BAD_REQUEST
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
@@ -29,95 +54,431 @@ pub async fn reply_error(e: ReplyError) -> Response {
     e.into_response()
 }
 
-pub fn new(config: Config) -> Route {
+/// Reply in the API version of the request, where the data is taken out of the envelope in the API v2,
+/// and `v1` replies the `ReplyError` in the API v1 and the others are left as they are.
+fn versioned<F, Fut>(ep: impl Endpoint + 'static, v1: F) -> impl Endpoint
+where
+    F: Fn(ReplyError) -> Fut + Copy + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send,
+{
+    ep.around(move |ep, req| async move {
+        let version = ApiVersion::of(&req);
+        let e = match (version, ep.call(req).await) {
+            (ApiVersion::V1, Ok(resp)) => return Ok(resp.into_response()),
+            (ApiVersion::V2, Ok(resp)) => return Ok(unwrap_data(resp.into_response()).await),
+            (_, Err(e)) => e,
+        };
+
+        match (version, e.downcast::<ReplyError>()) {
+            (ApiVersion::V1, Ok(e)) => Ok(v1(e).await),
+            (ApiVersion::V1, Err(e)) => Err(e),
+            (ApiVersion::V2, Ok(e)) => Ok(Problem::from(e).into_response()),
+            (ApiVersion::V2, Err(e)) => Ok(Problem::from_error(e).into_response()),
+        }
+    })
+}
+
+/// The errors of S3 in XML, including those of the workspace guard
+pub async fn s3_error(e: poem::Error) -> Response {
+    if e.is::<S3Error>() {
+        return e.into_response();
+    }
+
+    match e.downcast::<ReplyError>() {
+        Ok(e) => S3Error::from(e).into_response(),
+        Err(e) => S3Error::from_status(e.status()).into_response(),
+    }
+}
+
+const DEPRECATION: &str = "deprecation";
+const SUNSET: &str = "sunset";
+
+/// The unprefixed routes were deprecated at the time, in the structured date of `Deprecation`
+const DEPRECATED_AT: &str = "@1792368000";
+
+/// The unprefixed routes would be removed at the time
+const SUNSET_AT: &str = "Tue, 19 Oct 2027 00:00:00 GMT";
+
+/// The unprefixed routes of the API are the deprecated aliases of `/api/v1`
+fn deprecated(ep: impl Endpoint + 'static) -> impl Endpoint {
+    ep.around(|ep, req| async move {
+        let successor = req
+            .original_uri()
+            .path_and_query()
+            .map(|path| format!(r#"</api/v1{path}>; rel="successor-version""#));
+
+        let mut resp = match ep.call(req).await {
+            Ok(resp) => resp.into_response(),
+            Err(e) => e.into_response(),
+        };
+        let headers = resp.headers_mut();
+        headers.insert(DEPRECATION, HeaderValue::from_static(DEPRECATED_AT));
+        headers.insert(SUNSET, HeaderValue::from_static(SUNSET_AT));
+        if let Some(link) = successor.and_then(|link| link.parse().ok()) {
+            headers.insert(LINK, link);
+        }
+
+        Ok(resp)
+    })
+}
+
+/// Where the API is served, and the version it's pinned to,
+/// the unprefixed one is the deprecated alias of `/api/v1`.
+const API_MOUNTS: [(&str, Option<ApiVersion>); 3] = [
+    ("/api/v1", Some(ApiVersion::V1)),
+    ("/api/v2", Some(ApiVersion::V2)),
+    ("", None),
+];
+
+/// The workspaces inside the API
+const WK: &str = "/wk";
+/// The users inside the API
+const USER: &str = "/user";
+/// Reading and writing inside a workspace
+const READ: &str = "/r";
+const WRITE: &str = "/w";
+
+/// The API is served at `/api/v1` and `/api/v2`, and the same routes of `/api/v1`
+/// are served without the prefix for the existing clients.
+pub fn new(config: Config, settings: SharedSettings) -> Route {
     let codec = Arc::new(Codec::hs256(config.jwt_secret_key.as_bytes()));
 
+    let names: Vec<_> = config
+        .workspaces
+        .iter()
+        .map(|wk| wk.name.as_str())
+        .collect();
+    let wk_api = || workspaces(&names, settings.clone(), codec.clone());
+    let user_api = || user(codec.clone(), settings.clone());
+
+    let route = API_MOUNTS
+        .into_iter()
+        .fold(Route::new(), |route, (prefix, version)| {
+            let (wk, user) = (format!("{prefix}{WK}"), format!("{prefix}{USER}"));
+            match version {
+                Some(version) => route
+                    .nest(wk, wk_api().data(version))
+                    .nest(user, user_api().data(version)),
+                None => route
+                    .nest(wk, deprecated(wk_api()))
+                    .nest(user, deprecated(user_api())),
+            }
+        })
+        .nest(dav::MOUNT, webdav(settings.clone(), codec.clone()));
+
+    let route = if config.s3.is_some() {
+        route.nest(s3::MOUNT, s3(settings.clone(), codec.clone()))
+    } else {
+        route
+    };
+
+    serve(route, docs_routes(config.api_docs))
+}
+
+/// The default workspace is served at the root,
+/// the home of requesting user is served at `/home`,
+/// and the named ones are served at `/{name}`.
+fn workspaces(names: &[&str], settings: SharedSettings, codec: Arc<Codec<Hs256>>) -> impl Endpoint {
+    let route = names.iter().copied().fold(
+        workspace(Target::Default, settings.clone(), codec.clone()).nest(
+            "/home",
+            workspace(Target::Home, settings.clone(), codec.clone()),
+        ),
+        |route, name| {
+            route.nest(
+                format!("/{name}"),
+                workspace(Target::Named(name.into()), settings.clone(), codec.clone()),
+            )
+        },
+    );
+
+    // the errors of the guards are replied in the version too
+    versioned(route, reply_error)
+}
+
+/// Every request loads the latest settings of the workspace,
+/// which would be swapped on reload.
+fn workspace(target: Target, settings: SharedSettings, codec: Arc<Codec<Hs256>>) -> Route {
     Route::new()
         .nest(
-            "/wk",
-            workspace(config.workspace, config.max_upload, codec.clone()),
+            READ,
+            read_wk().with(WorkspaceGuard::new(
+                settings.clone(),
+                target.clone(),
+                Access::Read,
+                codec.clone(),
+            )),
+        )
+        .nest(
+            WRITE,
+            write_wk().with(WorkspaceGuard::new(settings, target, Access::Write, codec)),
         )
-        .nest("/user", user(codec))
 }
 
-fn workspace(wk: Workspace, max_upload: ByteSize, codec: Arc<Codec<Hs256>>) -> impl Endpoint {
-    Route::new()
-        .nest("/r", read_wk())
-        .nest("/w", write_wk(max_upload).with(JwtVerifier::new(codec)))
-        .data(Arc::new(wk))
+/// The routes of a group relative to where it's mounted,
+/// which the OpenAPI document is checked against.
+type Routes = Vec<(&'static str, Method, BoxEndpoint<'static>)>;
+
+fn at(
+    path: &'static str,
+    method: Method,
+    ep: impl Endpoint + 'static,
+) -> (&'static str, Method, BoxEndpoint<'static>) {
+    (path, method, ep.map_to_response().boxed())
 }
 
-fn read_wk() -> impl Endpoint {
-    Route::new()
-        .at(
+/// Serve the routes beside those of the route, the methods at the same path share it
+fn serve(route: Route, routes: Routes) -> Route {
+    let mut paths: Vec<(&str, RouteMethod)> = Vec::new();
+    for (path, method, ep) in routes {
+        match paths.iter_mut().find(|(at, _)| *at == path) {
+            Some((_, methods)) => *methods = std::mem::take(methods).method(method, ep),
+            None => paths.push((path, RouteMethod::new().method(method, ep))),
+        }
+    }
+
+    paths
+        .into_iter()
+        .fold(route, |route, (path, methods)| route.at(path, methods))
+}
+
+fn read_wk() -> Route {
+    serve(Route::new(), read_routes())
+}
+
+fn read_routes() -> Routes {
+    vec![
+        at(
             "/file/*path",
-            get(file_system::download)
-                .before(file_system::ensure_relative)
-                .catch_error(http_error),
-        )
-        .at(
-            "/dir/*path",
-            get(file_system::read_dir)
-                .before(file_system::ensure_relative)
-                .catch_error(reply_error),
-        )
+            Method::GET,
+            versioned(checked(file_system::download), http_error),
+        ),
+        at(
+            "/file/*path",
+            Method::HEAD,
+            versioned(checked(file_system::head_file), http_error),
+        ),
+        at("/dir/*path", Method::GET, checked(file_system::read_dir)),
+        at("/stat/*path", Method::GET, checked(file_system::stat)),
+        at(
+            "/checksum/*path",
+            Method::GET,
+            checked(file_system::checksum),
+        ),
+        at(
+            "/watch/*path",
+            Method::GET,
+            checked(file_system::watch::watch),
+        ),
+        // the path in query is checked like those above
+        at("/search", Method::GET, file_system::search::search),
+    ]
+}
+
+fn write_wk() -> Route {
+    serve(Route::new(), write_routes())
 }
 
 /// Write the file system,
 /// these handlers cannot operate the workspace root.
-#[rustfmt::skip]
-fn write_wk(max_upload: ByteSize) -> impl Endpoint {
-    Route::new()
-        .at(
+fn write_routes() -> Routes {
+    vec![
+        at(
             "/upload/*parent",
-            post(file_system::upload)
-                .before(move |req| async move {file_system::limit_size(req, max_upload)})
-                .before(file_system::ensure_relative),
-        )
-        .at(
-            "/rename/*path",
-            put(file_system::rename)
-                .before(file_system::ensure_relative)
-                .before(file_system::ensure_not_root),
-        )
-        .at(
+            Method::POST,
+            checked(file_system::upload.before(file_system::limit_size)),
+        ),
+        at("/put/*path", Method::PUT, not_root(file_system::put_file)),
+        at("/rename/*path", Method::PUT, not_root(file_system::rename)),
+        at(
             "/remove/*path",
-            delete(file_system::remove)
-                .before(file_system::ensure_relative)
-                .before(file_system::ensure_not_root),
-        )
-        .at(
-            "/mkdir/*path",
-            post(file_system::mkdir)
-                .before(file_system::ensure_relative)
-                .before(file_system::ensure_not_root),
-        )
-        .catch_error(reply_error)
+            Method::DELETE,
+            not_root(file_system::remove),
+        ),
+        at("/mkdir/*path", Method::POST, not_root(file_system::mkdir)),
+        // the paths in body are checked like those above
+        at("/batch", Method::POST, file_system::batch::batch),
+    ]
 }
 
-fn user(codec: Arc<Codec<Hs256>>) -> impl Endpoint {
-    Route::new()
-        .at("/register", post(permission::register))
-        .at("/login", post(permission::login).data(codec.clone()))
-        .at("/info", get(permission::info).with(JwtVerifier::new(codec)))
-        .catch_error(reply_error)
+fn webdav(settings: SharedSettings, codec: Arc<Codec<Hs256>>) -> impl Endpoint {
+    serve(Route::new(), dav_routes(settings, codec.clone()))
+        .catch_error(dav_error)
+        .with(BasicAuth::new(codec))
+}
+
+/// WebDAV over the default workspace, the methods reading and writing it
+/// follow its **read** and **write** policies like `/wk/r` and `/wk/w`.
+fn dav_routes(settings: SharedSettings, codec: Arc<Codec<Hs256>>) -> Routes {
+    let guard =
+        |access| WorkspaceGuard::new(settings.clone(), Target::Default, access, codec.clone());
+    let (read, write) = (|| guard(Access::Read), || guard(Access::Write));
+
+    vec![
+        at("/*path", Method::OPTIONS, dav::options),
+        at(
+            "/*path",
+            Method::GET,
+            checked(file_system::download).with(read()),
+        ),
+        at(
+            "/*path",
+            Method::HEAD,
+            checked(file_system::head_file).with(read()),
+        ),
+        at(
+            "/*path",
+            dav::method(dav::PROPFIND),
+            checked(dav::propfind).with(read()),
+        ),
+        at(
+            "/*path",
+            dav::method(dav::PROPPATCH),
+            checked(dav::proppatch).with(write()),
+        ),
+        at(
+            "/*path",
+            dav::method(dav::MKCOL),
+            checked(dav::mkcol).with(write()),
+        ),
+        at("/*path", Method::PUT, checked(dav::put).with(write())),
+        at("/*path", Method::DELETE, checked(dav::delete).with(write())),
+        // the destinations are checked like the paths
+        at(
+            "/*path",
+            dav::method(dav::COPY),
+            checked(dav::copy).with(write()),
+        ),
+        at(
+            "/*path",
+            dav::method(dav::MOVE),
+            checked(dav::move_to).with(write()),
+        ),
+        at(
+            "/*path",
+            dav::method(dav::LOCK),
+            checked(dav::lock).with(write()),
+        ),
+        at(
+            "/*path",
+            dav::method(dav::UNLOCK),
+            checked(dav::unlock).with(write()),
+        ),
+    ]
+}
+
+fn s3(settings: SharedSettings, codec: Arc<Codec<Hs256>>) -> impl Endpoint {
+    serve(Route::new(), s3_routes(settings.clone(), codec))
+        .catch_all_error(s3_error)
+        .with(SigV4Auth::new(settings.clone()))
+        .data(settings)
+}
+
+/// The S3 compatible API over the default workspace, the buckets are its top-level
+/// directories, and the access keys act as their users in its policies.
+fn s3_routes(settings: SharedSettings, codec: Arc<Codec<Hs256>>) -> Routes {
+    let guard =
+        |access| WorkspaceGuard::new(settings.clone(), Target::Default, access, codec.clone());
+    let (read, write) = (|| guard(Access::Read), || guard(Access::Write));
+
+    vec![
+        at("/*path", Method::GET, checked(s3::get).with(read())),
+        at("/*path", Method::HEAD, checked(s3::head).with(read())),
+        at("/*path", Method::PUT, checked(s3::put).with(write())),
+        at("/*path", Method::POST, checked(s3::post).with(write())),
+        at("/*path", Method::DELETE, checked(s3::delete).with(write())),
+    ]
+}
+
+/// Check the path like `checked`, which mustn't be the workspace root
+fn not_root(ep: impl Endpoint) -> impl Endpoint {
+    checked(ep).before(file_system::ensure_not_root)
+}
+
+/// Check the path like those under `/wk`
+fn checked(ep: impl Endpoint) -> impl Endpoint {
+    ep.before(file_system::ensure_owner)
+        .before(file_system::ensure_relative)
+}
+
+fn user(codec: Arc<Codec<Hs256>>, settings: SharedSettings) -> impl Endpoint {
+    versioned(
+        serve(Route::new(), user_routes(codec, settings)),
+        reply_error,
+    )
+}
+
+fn user_routes(codec: Arc<Codec<Hs256>>, settings: SharedSettings) -> Routes {
+    vec![
+        at("/register", Method::POST, permission::register),
+        at(
+            "/login",
+            Method::POST,
+            permission::login.data(codec.clone()).data(settings.clone()),
+        ),
+        at(
+            "/info",
+            Method::GET,
+            permission::info.with(JwtVerifier::new(codec.clone())),
+        ),
+        at(
+            "/quota",
+            Method::GET,
+            permission::quota
+                .with(JwtVerifier::new(codec))
+                .data(settings),
+        ),
+    ]
+}
+
+/// This document, and its interactive docs if they are enabled
+fn docs_routes(api_docs: bool) -> Routes {
+    let mut routes = vec![at("/openapi.json", Method::GET, docs::openapi)];
+    if api_docs {
+        routes.push(at("/docs", Method::GET, docs::page));
+    }
+
+    routes
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{read_wk, write_wk};
-    use crate::reply::status::{IS_ABSOLUTE, WORKSPACE_ROOT};
+    use super::{
+        dav, dav_routes, deprecated, docs_routes, read_routes, read_wk, reply_error, s3, s3_routes,
+        user_routes, versioned, workspaces, write_routes, write_wk, API_MOUNTS, READ, USER, WK,
+        WRITE,
+    };
+    use crate::config::Policy;
+    use crate::models::permission::User;
+    use crate::openapi::DOCUMENT;
+    use crate::quota::Quotas;
+    use crate::reply::status::{
+        INTERNAL_ERROR, IS_ABSOLUTE, NOT_FOUND, OK, OUTSIDE_WORKSPACE, PERMISSION_DENIED,
+        WORKSPACE_ROOT,
+    };
+    use crate::reply::{ApiVersion, ReplyError, ReplyItem, X_API_VERSION, X_CORRELATION_ID};
+    use crate::search::SearchIndex;
+    use crate::settings::{Settings, WorkspaceSettings};
     use crate::utils::tests::*;
+    use arc_swap::ArcSwap;
     use bytesize::ByteSize;
+    use jwt_codec::Codec;
+    use poem::http::header::{AUTHORIZATION, CONTENT_TYPE, LINK};
     use poem::http::StatusCode;
     use poem::test::TestClient;
-    use poem::EndpointExt;
+    use poem::{get, handler, EndpointExt, Route};
+    use std::collections::{BTreeSet, HashMap};
     use std::sync::Arc;
 
+    /// The methods of the operations in OpenAPI
+    const OPERATIONS: [&str; 8] = [
+        "get", "put", "post", "delete", "options", "head", "patch", "trace",
+    ];
+
     #[tokio::test]
     async fn test_read_workspace() {
         let (_tmp_dir, wk) = setup_workspace();
-        let client = TestClient::new(read_wk().data(Arc::new(wk)));
+        let client = TestClient::new(versioned(read_wk(), reply_error).data(Arc::new(wk)));
 
         client
             .get("/file//")
@@ -126,12 +487,17 @@ mod tests {
             .assert_status(StatusCode::FORBIDDEN);
 
         assert_buss_status(IS_ABSOLUTE, client.get("/dir//").send().await.json().await);
+
+        assert_buss_status(
+            OUTSIDE_WORKSPACE,
+            client.get("/dir/a/../..").send().await.json().await,
+        );
     }
 
     #[tokio::test]
     async fn test_write_workspace() {
         let (_tmp_dir, wk) = setup_workspace();
-        let client = TestClient::new(write_wk(ByteSize::gb(2)).data(Arc::new(wk)));
+        let client = TestClient::new(versioned(write_wk(), reply_error).data(Arc::new(wk)));
 
         assert_buss_status(
             WORKSPACE_ROOT,
@@ -143,4 +509,490 @@ mod tests {
             client.delete("/remove//").send().await.json().await,
         );
     }
+
+    #[tokio::test]
+    async fn test_api_versions() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let client = TestClient::new(versioned(read_wk(), reply_error).data(Arc::new(wk)));
+
+        assert_buss_status(
+            NOT_FOUND,
+            client.get("/dir/missing").send().await.json().await,
+        );
+        client
+            .get("/file/missing")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+
+        let resp = client
+            .get("/dir/missing")
+            .header(X_API_VERSION, "2")
+            .send()
+            .await;
+        resp.assert_status(StatusCode::NOT_FOUND);
+        resp.assert_header(CONTENT_TYPE, "application/problem+json");
+        let problem = resp.json().await;
+        let problem = problem.value().object();
+        problem.get("type").assert_string("about:blank");
+        problem.get("title").assert_string("Not Found");
+        problem.get("status").assert_i64(404);
+        problem.get("code").assert_i64(NOT_FOUND as i64);
+
+        // downloads reply the problems too
+        let resp = client.get("/file/").header(X_API_VERSION, "2").send().await;
+        resp.assert_status(StatusCode::FORBIDDEN);
+        let problem = resp.json().await;
+        problem
+            .value()
+            .object()
+            .get("code")
+            .assert_i64(WORKSPACE_ROOT as i64);
+    }
+
+    #[tokio::test]
+    async fn test_versioned_prefixes() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let api = || {
+            let route = Route::new().nest("/r", read_wk()).nest("/w", write_wk());
+            versioned(route, reply_error)
+        };
+        let client = TestClient::new(
+            Route::new()
+                .nest("/api/v1", api().data(ApiVersion::V1))
+                .nest("/api/v2", api().data(ApiVersion::V2))
+                .nest("/", deprecated(api()))
+                .data(Arc::new(SearchIndex::new(&wk, &Default::default())))
+                .data(Arc::new(Quotas::default()))
+                .data(Arc::new(wk)),
+        );
+
+        assert_buss_status(OK, client.get("/api/v1/r/dir/").send().await.json().await);
+        let resp = client.get("/api/v1/r/dir/").send().await;
+        assert!(resp.0.headers().get(super::DEPRECATION).is_none());
+
+        // the bare data
+        let resp = client.get("/api/v2/r/dir/").send().await;
+        resp.assert_status_is_ok();
+        let data = resp.json().await;
+        data.value().object().get("entries").array().assert_len(0);
+        let resp = client.post("/api/v2/w/mkdir/new-dir").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_json(["new-dir"]).await;
+        client
+            .delete("/api/v2/w/remove/new-dir")
+            .send()
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+
+        // the version is fixed by the prefix
+        let resp = client
+            .get("/api/v1/r/dir/missing")
+            .header(X_API_VERSION, "2")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        assert_buss_status(NOT_FOUND, resp.json().await);
+        let resp = client.get("/api/v2/r/dir/missing").send().await;
+        resp.assert_status(StatusCode::NOT_FOUND);
+        resp.assert_header(CONTENT_TYPE, "application/problem+json");
+
+        // the deprecated aliases of the API v1
+        let resp = client.get("/r/dir/").send().await;
+        resp.assert_header(super::DEPRECATION, super::DEPRECATED_AT);
+        resp.assert_header(super::SUNSET, super::SUNSET_AT);
+        let link = resp.0.headers()[LINK].to_str().unwrap().to_owned();
+        assert!(link.starts_with("</api/v1/"));
+        assert!(link.ends_with(r#">; rel="successor-version""#));
+        assert_buss_status(OK, resp.json().await);
+
+        let resp = client.get("/r/dir/missing").send().await;
+        resp.assert_header(super::SUNSET, super::SUNSET_AT);
+        assert_buss_status(NOT_FOUND, resp.json().await);
+    }
+
+    #[tokio::test]
+    async fn test_internal_errors() {
+        #[handler]
+        fn fail() -> Result<(), ReplyError> {
+            Err(ReplyError::Internal("secret details".into()))
+        }
+
+        #[handler]
+        async fn deny() -> poem::Result<()> {
+            Err(poem::Error::from_status(StatusCode::UNAUTHORIZED))
+        }
+
+        let client = TestClient::new(versioned(
+            Route::new().at("/fail", get(fail)).at("/deny", get(deny)),
+            reply_error,
+        ));
+
+        let resp = client.get("/fail").send().await;
+        resp.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+        let id = resp.0.headers()[X_CORRELATION_ID]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let text = resp.0.into_body().into_string().await.unwrap();
+        assert!(!text.contains("secret"));
+        assert!(text.contains(&id));
+
+        let resp = client.get("/fail").header(X_API_VERSION, "2").send().await;
+        resp.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+        let id = resp.0.headers()[X_CORRELATION_ID]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let problem = resp.json().await;
+        let problem = problem.value().object();
+        problem.get("code").assert_i64(INTERNAL_ERROR as i64);
+        problem.get("correlation_id").assert_string(&id);
+        problem.get("detail").assert_string("internal error");
+
+        // the errors of the guards
+        client
+            .get("/deny")
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        let resp = client.get("/deny").header(X_API_VERSION, "2").send().await;
+        resp.assert_status(StatusCode::UNAUTHORIZED);
+        resp.assert_header(CONTENT_TYPE, "application/problem+json");
+
+        // the items of a batch
+        let item = ReplyItem::<()>::from(Err(ReplyError::Internal("secret details".into())));
+        let item = serde_json::to_value(item).unwrap();
+        assert_eq!(item["status"], INTERNAL_ERROR);
+        assert_eq!(item["msg"], "internal error");
+        assert!(item["correlation_id"].is_string());
+        let item = serde_json::to_value(ReplyItem::<()>::from(Err(ReplyError::NotFound))).unwrap();
+        assert!(item.get("correlation_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_named_workspaces() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let (_photos_dir, photos) = setup_workspace();
+        let settings = Settings {
+            workspace: WorkspaceSettings {
+                index: Arc::new(SearchIndex::new(&wk, &Default::default())),
+                root: Arc::new(wk),
+                max_upload: ByteSize::gb(2),
+                read: Policy::Public,
+                write: Policy::Authenticated,
+                quotas: Default::default(),
+            },
+            workspaces: HashMap::from([(
+                "photos".to_owned(),
+                WorkspaceSettings {
+                    index: Arc::new(SearchIndex::new(&photos, &Default::default())),
+                    root: Arc::new(photos),
+                    max_upload: ByteSize::gb(2),
+                    read: Policy::Authenticated,
+                    write: Policy::Nobody,
+                    quotas: Default::default(),
+                },
+            )]),
+            homes: false,
+            admins: Default::default(),
+            client_users: HashMap::new(),
+            s3: None,
+        };
+        let codec = Arc::new(Codec::hs256(b"sachima jwt secret key"));
+        let client = TestClient::new(workspaces(
+            &["photos"],
+            Arc::new(ArcSwap::from_pointee(settings)),
+            codec.clone(),
+        ));
+        let bearer = format!(
+            "Bearer {}",
+            codec
+                .gen_token(&User {
+                    name: "TD-Sky".to_owned()
+                })
+                .unwrap()
+        );
+
+        assert_buss_status(OK, client.get("/r/dir/").send().await.json().await);
+
+        client
+            .get("/photos/r/dir/")
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        assert_buss_status(
+            OK,
+            client
+                .get("/photos/r/dir/")
+                .header(AUTHORIZATION, &bearer)
+                .send()
+                .await
+                .json()
+                .await,
+        );
+
+        client
+            .post("/photos/w/mkdir/new-dir")
+            .header(AUTHORIZATION, &bearer)
+            .send()
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_homes() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let root = wk.to_path_buf();
+        let settings = Settings {
+            workspace: WorkspaceSettings {
+                index: Arc::new(SearchIndex::new(&wk, &Default::default())),
+                root: Arc::new(wk),
+                max_upload: ByteSize::gb(2),
+                read: Policy::Public,
+                write: Policy::Authenticated,
+                quotas: Default::default(),
+            },
+            workspaces: HashMap::new(),
+            homes: true,
+            admins: Arc::new(["root".to_owned()].into()),
+            client_users: HashMap::new(),
+            s3: None,
+        };
+        let codec = Arc::new(Codec::hs256(b"sachima jwt secret key"));
+        let client = TestClient::new(workspaces(
+            &[],
+            Arc::new(ArcSwap::from_pointee(settings)),
+            codec.clone(),
+        ));
+        let bearer = |name: &str| {
+            format!(
+                "Bearer {}",
+                codec
+                    .gen_token(&User {
+                        name: name.to_owned()
+                    })
+                    .unwrap()
+            )
+        };
+
+        client
+            .get("/home/r/dir/")
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        assert_buss_status(
+            OK,
+            client
+                .post("/home/w/mkdir/notes")
+                .header(AUTHORIZATION, bearer("alice"))
+                .send()
+                .await
+                .json()
+                .await,
+        );
+        assert!(root.join("~alice/notes").is_dir());
+
+        assert_buss_status(
+            OK,
+            client
+                .get("/r/dir/~alice")
+                .header(AUTHORIZATION, bearer("alice"))
+                .send()
+                .await
+                .json()
+                .await,
+        );
+        assert_buss_status(
+            OK,
+            client
+                .get("/r/dir/~alice")
+                .header(AUTHORIZATION, bearer("root"))
+                .send()
+                .await
+                .json()
+                .await,
+        );
+        assert_buss_status(
+            PERMISSION_DENIED,
+            client.get("/r/dir/~alice").send().await.json().await,
+        );
+        assert_buss_status(
+            PERMISSION_DENIED,
+            client
+                .delete("/w/remove/~alice/notes")
+                .header(AUTHORIZATION, bearer("bob"))
+                .send()
+                .await
+                .json()
+                .await,
+        );
+
+        // listed, but not looked into
+        let reply = client
+            .get("/r/dir/")
+            .query("depth", &"unlimited")
+            .header(AUTHORIZATION, bearer("bob"))
+            .send()
+            .await
+            .json()
+            .await;
+        let alice = reply
+            .value()
+            .object()
+            .get("data")
+            .object()
+            .get("entries")
+            .array()
+            .get(0)
+            .object();
+        alice.get("name").assert_string("~alice");
+        assert!(alice.get_opt("children").is_none());
+
+        // the homes of others are left out of the search
+        let search = |name: &str| {
+            client
+                .get("/r/search")
+                .query("q", &"notes")
+                .header(AUTHORIZATION, bearer(name))
+                .send()
+        };
+        let reply = search("bob").await.json().await;
+        let entries = reply.value().object().get("data").object().get("entries");
+        entries.array().assert_len(0);
+        let reply = search("alice").await.json().await;
+        let entries = reply.value().object().get("data").object().get("entries");
+        entries
+            .array()
+            .get(0)
+            .object()
+            .get("path")
+            .assert_string("~alice/notes");
+
+        let reply = client
+            .post("/w/batch")
+            .header(AUTHORIZATION, bearer("bob"))
+            .content_type("application/json")
+            .body(r#"{"ops": [{"op": "move", "path": "~alice/notes", "to": "~bob"}]}"#)
+            .send()
+            .await
+            .json()
+            .await;
+        reply
+            .value()
+            .object()
+            .get("data")
+            .object()
+            .get("results")
+            .array()
+            .get(0)
+            .object()
+            .get("status")
+            .assert_i64(PERMISSION_DENIED as i64);
+        assert!(root.join("~alice/notes").is_dir());
+
+        // shared areas are writable as before
+        assert_buss_status(
+            OK,
+            client
+                .post("/w/mkdir/shared")
+                .header(AUTHORIZATION, bearer("bob"))
+                .send()
+                .await
+                .json()
+                .await,
+        );
+        assert_buss_status(
+            PERMISSION_DENIED,
+            client
+                .put("/w/rename/shared")
+                .query("name", &"~alice")
+                .header(AUTHORIZATION, bearer("bob"))
+                .send()
+                .await
+                .json()
+                .await,
+        );
+    }
+
+    /// Every route served is described in the OpenAPI document at every server of its path,
+    /// and the other way round, except the methods of WebDAV which OpenAPI cannot describe
+    #[test]
+    fn test_documented() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let settings = Arc::new(ArcSwap::from_pointee(Settings {
+            workspace: WorkspaceSettings {
+                index: Arc::new(SearchIndex::new(&wk, &Default::default())),
+                root: Arc::new(wk),
+                max_upload: ByteSize::gb(2),
+                read: Policy::Public,
+                write: Policy::Public,
+                quotas: Default::default(),
+            },
+            workspaces: HashMap::new(),
+            homes: false,
+            admins: Default::default(),
+            client_users: HashMap::new(),
+            s3: None,
+        }));
+        let codec = Arc::new(Codec::hs256(b"sachima jwt secret key"));
+
+        let mut groups = Vec::new();
+        for (prefix, _) in API_MOUNTS {
+            groups.push((format!("{prefix}{WK}{READ}"), read_routes()));
+            groups.push((format!("{prefix}{WK}{WRITE}"), write_routes()));
+            groups.push((
+                format!("{prefix}{USER}"),
+                user_routes(codec.clone(), settings.clone()),
+            ));
+        }
+        groups.push((
+            dav::MOUNT.to_owned(),
+            dav_routes(settings.clone(), codec.clone()),
+        ));
+        groups.push((s3::MOUNT.to_owned(), s3_routes(settings, codec)));
+        groups.push((String::new(), docs_routes(true)));
+
+        let mut served = BTreeSet::new();
+        for (mount, routes) in groups {
+            for (path, method, _) in routes {
+                let path: Vec<_> = path
+                    .split('/')
+                    .map(|segment| match segment.strip_prefix('*') {
+                        Some(param) => format!("{{{param}}}"),
+                        None => segment.to_owned(),
+                    })
+                    .collect();
+                served.insert((
+                    format!("{mount}{}", path.join("/")),
+                    method.as_str().to_lowercase(),
+                ));
+            }
+        }
+        served.retain(|(_, method)| OPERATIONS.contains(&method.as_str()));
+
+        let mut documented = BTreeSet::new();
+        for (path, item) in DOCUMENT["paths"].as_object().unwrap() {
+            let servers: Vec<_> = match item.get("servers") {
+                Some(servers) => servers
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|server| server["url"].as_str().unwrap().trim_end_matches('/'))
+                    .collect(),
+                None => vec![""],
+            };
+            for method in item.as_object().unwrap().keys() {
+                if OPERATIONS.contains(&method.as_str()) {
+                    for server in &servers {
+                        documented.insert((format!("{server}{path}"), method.clone()));
+                    }
+                }
+            }
+        }
+
+        assert_eq!(served, documented);
+    }
 }
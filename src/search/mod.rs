@@ -0,0 +1,639 @@
+mod text;
+
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::io;
+use std::ops::Bound;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::task;
+use tokio::time;
+
+use crate::checksum::hex;
+use crate::config::Search;
+use crate::settings::{Settings, SharedSettings};
+use crate::utils::atomic;
+
+/// How often the changed indexes are saved
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DocKind {
+    Dir,
+    File,
+    Symlink,
+}
+
+/// An indexed entry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Doc {
+    kind: DocKind,
+    len: u64,
+    /// In nanoseconds
+    mtime: u64,
+    /// The sorted words of a text-like file, absent if they aren't indexed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    terms: Option<Vec<String>>,
+}
+
+/// Path relative to the workspace root, separated by `/` => the entry
+type Docs = BTreeMap<String, Doc>;
+
+/// The index file
+#[derive(Serialize, Deserialize)]
+struct Saved<D> {
+    root: PathBuf,
+    docs: D,
+}
+
+/// What the entries are matched by
+#[derive(Debug)]
+pub enum Matcher {
+    /// A glob of the names, case-insensitively
+    Glob(Pattern),
+    /// A substring of the names, case-insensitively
+    Substring(String),
+    /// The words in the content, every one is a prefix of an indexed word
+    Words(Vec<String>),
+}
+
+impl Matcher {
+    /// `None` if it's a malformed glob
+    pub fn new(q: &str, text: bool) -> Option<Self> {
+        if text {
+            return Some(Matcher::Words(text::words(q)));
+        }
+
+        if q.contains(['*', '?', '[']) {
+            Pattern::new(q).ok().map(Matcher::Glob)
+        } else {
+            Some(Matcher::Substring(q.to_lowercase()))
+        }
+    }
+
+    fn matches(&self, key: &str, doc: &Doc) -> bool {
+        let name = key.rsplit('/').next().unwrap_or(key);
+
+        match self {
+            Matcher::Glob(pattern) => pattern.matches_with(
+                name,
+                MatchOptions {
+                    case_sensitive: false,
+                    ..Default::default()
+                },
+            ),
+            Matcher::Substring(sub) => name.to_lowercase().contains(sub.as_str()),
+            Matcher::Words(words) => doc
+                .terms
+                .as_ref()
+                .is_some_and(|terms| words.iter().all(|word| text::contains_prefix(terms, word))),
+        }
+    }
+}
+
+/// The index of a workspace, which is built on first use
+/// and kept up to date by the write handlers and the watcher.
+///
+/// The names which aren't UTF-8 aren't indexed.
+#[derive(Debug)]
+pub struct SearchIndex {
+    root: PathBuf,
+    config: Search,
+    /// Absent until it's built. The searches and saves run off the executor
+    /// on a snapshot, so an update meanwhile copies the docs instead of waiting.
+    docs: Mutex<Option<Arc<Docs>>>,
+    building: tokio::sync::Mutex<()>,
+    /// Changed since it was saved
+    dirty: AtomicBool,
+}
+
+impl SearchIndex {
+    pub fn new(root: &Path, config: &Search) -> Self {
+        Self {
+            root: root.to_owned(),
+            config: config.clone(),
+            docs: Mutex::default(),
+            building: tokio::sync::Mutex::default(),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn full_text(&self) -> bool {
+        self.config.full_text
+    }
+
+    /// The key of an absolute path inside the root
+    pub fn key(&self, path: &Path) -> Option<String> {
+        let mut key = String::new();
+
+        for component in path.strip_prefix(&self.root).ok()?.components() {
+            match component {
+                Component::Normal(name) => {
+                    if !key.is_empty() {
+                        key.push('/');
+                    }
+                    key.push_str(name.to_str()?);
+                }
+                Component::CurDir => (),
+                _ => return None,
+            }
+        }
+
+        Some(key)
+    }
+
+    fn is_built(&self) -> bool {
+        self.docs.lock().unwrap().is_some()
+    }
+
+    /// Load the saved index and bring it up to date by scanning the disk,
+    /// only the changed files are read again.
+    pub async fn build(&self) -> io::Result<()> {
+        if self.is_built() {
+            return Ok(());
+        }
+
+        let _building = self.building.lock().await;
+        if self.is_built() {
+            return Ok(());
+        }
+
+        let saved = self.load().await;
+        let root = self.root.clone();
+        let config = self.config.clone();
+        let docs = task::spawn_blocking(move || {
+            let mut docs = Docs::new();
+            scan(&root, "", &saved, &config, &mut docs);
+            docs
+        })
+        .await
+        .map_err(io::Error::other)?;
+
+        *self.docs.lock().unwrap() = Some(Arc::new(docs));
+        self.dirty.store(true, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Index the path as it's on the disk now, including its subtree,
+    /// it's removed from the index if it doesn't exist.
+    pub async fn refresh(&self, path: &Path) {
+        let Some(key) = self.key(path) else {
+            return;
+        };
+        if !self.is_built() || key.split('/').any(atomic::is_temp) {
+            return;
+        }
+
+        let old = match &*self.docs.lock().unwrap() {
+            Some(docs) => subtree(docs, &key)
+                .map(|(key, doc)| (key.clone(), doc.clone()))
+                .collect(),
+            None => return,
+        };
+        let root = self.root.clone();
+        let config = self.config.clone();
+        let scanned = {
+            let key = key.clone();
+            task::spawn_blocking(move || {
+                let mut docs = Docs::new();
+                scan(&root, &key, &old, &config, &mut docs);
+                docs
+            })
+            .await
+        };
+
+        match scanned {
+            Ok(scanned) => self.update(|docs| {
+                remove_subtree(docs, &key);
+                docs.extend(scanned);
+            }),
+            Err(e) => tracing::warn!(path = %path.display(), error = %e, "cannot index the path"),
+        }
+    }
+
+    /// Move the indexed subtree, the words aren't read again
+    pub fn moved(&self, from: &Path, to: &Path) {
+        let (Some(from), Some(to)) = (self.key(from), self.key(to)) else {
+            return;
+        };
+
+        self.update(|docs| {
+            let moved = remove_subtree(docs, &from);
+            remove_subtree(docs, &to);
+            docs.extend(
+                moved
+                    .into_iter()
+                    .map(|(key, doc)| (format!("{to}{}", &key[from.len()..]), doc)),
+            );
+        });
+    }
+
+    /// Drop the path and its subtree from the index
+    pub fn remove(&self, path: &Path) {
+        if let Some(key) = self.key(path) {
+            self.update(|docs| {
+                remove_subtree(docs, &key);
+            });
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Docs)) {
+        if let Some(docs) = &mut *self.docs.lock().unwrap() {
+            f(Arc::make_mut(docs));
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The matched keys inside the directory `under` in order,
+    /// which are after the key `after`, and whether more are left.
+    ///
+    /// It should be built first.
+    pub async fn search(
+        &self,
+        matcher: Matcher,
+        under: String,
+        kind: Option<DocKind>,
+        after: Option<String>,
+        limit: usize,
+        visible: impl Fn(&str) -> bool + Send + 'static,
+    ) -> io::Result<(Vec<String>, bool)> {
+        let Some(docs) = self.snapshot() else {
+            return Ok((Vec::new(), false));
+        };
+
+        task::spawn_blocking(move || {
+            matched(
+                &docs,
+                &matcher,
+                &under,
+                kind,
+                after.as_deref(),
+                limit,
+                visible,
+            )
+        })
+        .await
+        .map_err(io::Error::other)
+    }
+
+    fn snapshot(&self) -> Option<Arc<Docs>> {
+        self.docs.lock().unwrap().clone()
+    }
+
+    /// The index file, named after the root
+    fn file(&self) -> Option<PathBuf> {
+        let dir = self.config.index_dir.as_ref()?;
+        let hash = Sha256::digest(self.root.as_os_str().as_encoded_bytes());
+
+        Some(dir.join(format!("{}.json", &hex(&hash)[..16])))
+    }
+
+    async fn load(&self) -> Docs {
+        let Some(file) = self.file() else {
+            return Docs::new();
+        };
+
+        let saved = match fs::read(&file).await {
+            Ok(saved) => saved,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Docs::new(),
+            Err(e) => {
+                tracing::warn!(file = %file.display(), error = %e, "cannot read the search index");
+                return Docs::new();
+            }
+        };
+
+        match serde_json::from_slice::<Saved<Docs>>(&saved) {
+            Ok(saved) if saved.root == self.root => saved.docs,
+            Ok(_) => Docs::new(),
+            Err(e) => {
+                tracing::warn!(file = %file.display(), error = %e, "corrupt search index, rebuilt");
+                Docs::new()
+            }
+        }
+    }
+
+    /// Save the index if it's changed
+    pub async fn save(&self) -> io::Result<()> {
+        let Some(file) = self.file() else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let Some(docs) = self.snapshot() else {
+            return Ok(());
+        };
+
+        let root = self.root.clone();
+        let dir = file.parent().unwrap().to_owned();
+        let res = task::spawn_blocking(move || {
+            let saved = serde_json::to_vec(&Saved { root, docs: &*docs })?;
+            std::fs::create_dir_all(&dir)?;
+            let temp = atomic::temp_path(&dir);
+            std::fs::write(&temp, saved)?;
+            std::fs::rename(&temp, &file).inspect_err(|_| {
+                std::fs::remove_file(&temp).ok();
+            })
+        })
+        .await
+        .map_err(io::Error::other)?;
+
+        if res.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+
+        res
+    }
+}
+
+/// The matched keys of the docs inside the directory `under`, see `SearchIndex::search`
+fn matched(
+    docs: &Docs,
+    matcher: &Matcher,
+    under: &str,
+    kind: Option<DocKind>,
+    after: Option<&str>,
+    limit: usize,
+    visible: impl Fn(&str) -> bool,
+) -> (Vec<String>, bool) {
+    // `a/b` sorts after `a b` and `a-b`, so the subtree is contiguous from `a/`
+    let prefix = if under.is_empty() {
+        String::new()
+    } else {
+        format!("{under}/")
+    };
+    let start = match after {
+        Some(after) if *after >= *prefix => Bound::Excluded(after),
+        _ => Bound::Included(prefix.as_str()),
+    };
+
+    let mut found = docs
+        .range::<str, _>((start, Bound::Unbounded))
+        .take_while(|(key, _)| key.starts_with(&prefix))
+        .filter(|(key, doc)| {
+            kind.is_none_or(|kind| doc.kind == kind) && matcher.matches(key, doc) && visible(key)
+        })
+        .map(|(key, _)| key.clone());
+
+    let keys: Vec<_> = found.by_ref().take(limit).collect();
+    let more = found.next().is_some();
+
+    (keys, more)
+}
+
+/// The entries of the key and inside it
+fn subtree<'d>(docs: &'d Docs, key: &str) -> impl Iterator<Item = (&'d String, &'d Doc)> {
+    let prefix = format!("{key}/");
+    docs.get_key_value(key).into_iter().chain(
+        docs.range::<str, _>((Bound::Included(prefix.as_str()), Bound::Unbounded))
+            .take_while(move |(child, _)| child.starts_with(&prefix)),
+    )
+}
+
+fn remove_subtree(docs: &mut Docs, key: &str) -> Vec<(String, Doc)> {
+    let keys: Vec<_> = if key.is_empty() {
+        docs.keys().cloned().collect()
+    } else {
+        subtree(docs, key).map(|(key, _)| key.clone()).collect()
+    };
+
+    keys.into_iter()
+        .filter_map(|key| docs.remove_entry(&key))
+        .collect()
+}
+
+/// Index the entry of the key and its subtree,
+/// the unchanged entries are taken from `old`.
+fn scan(root: &Path, key: &str, old: &Docs, config: &Search, docs: &mut Docs) {
+    let path = root.join(key);
+    let md = match path.symlink_metadata() {
+        Ok(md) => md,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            tracing::debug!(path = %path.display(), error = %e, "cannot index the path");
+            return;
+        }
+    };
+
+    if !key.is_empty() {
+        docs.insert(key.to_owned(), doc(&path, &md, old.get(key), config));
+    }
+
+    if !md.is_dir() {
+        return;
+    }
+
+    let entries = match path.read_dir() {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::debug!(path = %path.display(), error = %e, "cannot index the directory");
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if atomic::is_temp(name) {
+            continue;
+        }
+
+        let child = if key.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{key}/{name}")
+        };
+        scan(root, &child, old, config, docs);
+    }
+}
+
+fn doc(path: &Path, md: &Metadata, old: Option<&Doc>, config: &Search) -> Doc {
+    let kind = if md.is_dir() {
+        DocKind::Dir
+    } else if md.is_symlink() {
+        DocKind::Symlink
+    } else {
+        DocKind::File
+    };
+    let len = md.len();
+    let mtime = md
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos() as u64);
+    let wants_text =
+        config.full_text && kind == DocKind::File && len <= config.max_text_size.as_u64();
+
+    if let Some(old) = old {
+        if old.kind == kind
+            && old.len == len
+            && old.mtime == mtime
+            && (old.terms.is_some() || !wants_text)
+        {
+            return Doc {
+                terms: old.terms.clone().filter(|_| wants_text),
+                ..old.clone()
+            };
+        }
+    }
+
+    Doc {
+        kind,
+        len,
+        mtime,
+        // the binary files are indexed with no words, so they aren't read again
+        terms: wants_text.then(|| text::terms(path).unwrap_or_default()),
+    }
+}
+
+/// The indexes of every workspace
+pub fn indexes(settings: &Settings) -> Vec<Arc<SearchIndex>> {
+    std::iter::once(&settings.workspace)
+        .chain(settings.workspaces.values())
+        .map(|wk| wk.index.clone())
+        .collect()
+}
+
+/// Build the indexes ahead of the first search,
+/// and save the changed ones periodically.
+pub async fn maintain(settings: SharedSettings) {
+    let mut interval = time::interval(SAVE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let indexes = indexes(&settings.load());
+        for index in indexes {
+            if let Err(e) = index.build().await {
+                tracing::warn!(root = %index.root().display(), error = %e, "cannot build the search index");
+            }
+            if let Err(e) = index.save().await {
+                tracing::warn!(root = %index.root().display(), error = %e, "cannot save the search index");
+            }
+        }
+    }
+}
+
+/// Save the changed indexes on shutdown
+pub async fn save_all(settings: &SharedSettings) {
+    for index in indexes(&settings.load()) {
+        if let Err(e) = index.save().await {
+            tracing::warn!(root = %index.root().display(), error = %e, "cannot save the search index");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DocKind, Matcher, SearchIndex};
+    use crate::config::Search;
+    use crate::utils::tests::setup_workspace;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_search_index() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let (_index_dir, index_dir) = setup_workspace();
+        let config = Search {
+            index_dir: Some(index_dir.to_path_buf()),
+            full_text: true,
+            ..Default::default()
+        };
+        fs::create_dir_all(wk.join("notes/old")).unwrap();
+        fs::create_dir(wk.join("notes b")).unwrap();
+        fs::write(wk.join("notes/Todo.md"), "buy milk, fix the printer").unwrap();
+        fs::write(wk.join("notes/old/todo.txt"), "nothing").unwrap();
+        fs::write(wk.join("notes b/todo.md"), "milk").unwrap();
+        fs::write(wk.join("photo.png"), "milk").unwrap();
+
+        let index = SearchIndex::new(&wk, &config);
+        index.build().await.unwrap();
+        let search = |q: &str, text: bool, under: &str, limit: usize| {
+            index.search(
+                Matcher::new(q, text).unwrap(),
+                under.to_owned(),
+                None,
+                None,
+                limit,
+                |_| true,
+            )
+        };
+
+        assert_eq!(
+            search("todo", false, "", 10).await.unwrap().0,
+            ["notes b/todo.md", "notes/Todo.md", "notes/old/todo.txt"]
+        );
+        assert_eq!(
+            search("*.MD", false, "notes", 10).await.unwrap(),
+            (vec!["notes/Todo.md".to_owned()], false)
+        );
+        assert!(search("todo", false, "", 1).await.unwrap().1);
+        // the binary file isn't read
+        assert_eq!(
+            search("MILK", true, "", 10).await.unwrap().0,
+            ["notes b/todo.md", "notes/Todo.md"]
+        );
+        assert_eq!(
+            search("mil prin", true, "", 10).await.unwrap().0,
+            ["notes/Todo.md"]
+        );
+
+        let (dirs, _) = index
+            .search(
+                Matcher::new("notes", false).unwrap(),
+                String::new(),
+                Some(DocKind::Dir),
+                Some("notes".to_owned()),
+                10,
+                |_| true,
+            )
+            .await
+            .unwrap();
+        assert_eq!(dirs, ["notes b"]);
+
+        fs::rename(wk.join("notes"), wk.join("archive")).unwrap();
+        index.moved(&wk.join("notes"), &wk.join("archive"));
+        fs::write(wk.join("archive/old/todo.txt"), "printer").unwrap();
+        index.refresh(&wk.join("archive/old/todo.txt")).await;
+        index.remove(&wk.join("notes b"));
+        assert_eq!(
+            search("printer", true, "", 10).await.unwrap().0,
+            ["archive/Todo.md", "archive/old/todo.txt"]
+        );
+
+        // loaded from the saved one
+        index.save().await.unwrap();
+        let reloaded = SearchIndex::new(&wk, &config);
+        reloaded.build().await.unwrap();
+        assert_eq!(
+            reloaded
+                .search(
+                    Matcher::new("todo", false).unwrap(),
+                    String::new(),
+                    None,
+                    None,
+                    10,
+                    |_| true
+                )
+                .await
+                .unwrap()
+                .0,
+            ["archive/Todo.md", "archive/old/todo.txt", "notes b/todo.md"]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Words longer than it are cut, they're rarely searched in full
+const MAX_WORD_LEN: usize = 64;
+
+/// The rest of a huge file isn't indexed
+const MAX_TERMS: usize = 50_000;
+
+/// The sorted unique words of a text-like file,
+/// `None` if it looks binary.
+pub fn terms(path: &Path) -> Option<Vec<String>> {
+    if let Some(mime) = mime_guess::from_path(path).first() {
+        if !is_textual(&mime) {
+            return None;
+        }
+    }
+
+    let content = fs::read(path).ok()?;
+    if content.contains(&0) {
+        return None;
+    }
+
+    Some(words(&String::from_utf8_lossy(&content)))
+}
+
+fn is_textual(mime: &mime_guess::Mime) -> bool {
+    mime.type_() == mime_guess::mime::TEXT
+        || matches!(
+            mime.subtype().as_str(),
+            "json" | "xml" | "javascript" | "x-sh" | "x-toml" | "x-yaml" | "x-tex" | "csv"
+        )
+        || mime
+            .suffix()
+            .is_some_and(|suffix| suffix == "json" || suffix == "xml")
+}
+
+/// Lowercase alphanumeric runs of at least two characters
+pub fn words(text: &str) -> Vec<String> {
+    let mut words = BTreeSet::new();
+
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().nth(1).is_some())
+    {
+        if words.len() >= MAX_TERMS {
+            break;
+        }
+
+        let word: String = word.chars().take(MAX_WORD_LEN).collect();
+        words.insert(word.to_lowercase());
+    }
+
+    words.into_iter().collect()
+}
+
+/// Whether a sorted term starts with the word
+pub fn contains_prefix(terms: &[String], word: &str) -> bool {
+    let i = terms.partition_point(|term| term.as_str() < word);
+    terms.get(i).is_some_and(|term| term.starts_with(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{contains_prefix, words};
+
+    #[test]
+    fn test_words() {
+        let terms = words("Hello, hello World! a 42 ünïcode_ok");
+        assert_eq!(terms, ["42", "hello", "ok", "world", "ünïcode"]);
+
+        assert!(contains_prefix(&terms, "wor"));
+        assert!(contains_prefix(&terms, "42"));
+        assert!(!contains_prefix(&terms, "x"));
+        assert!(!contains_prefix(&terms, "worlds"));
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use bytesize::ByteSize;
+
+use crate::config::{Policy, Workspace, S3};
+use crate::quota::Quotas;
+use crate::search::SearchIndex;
+use crate::Config;
+
+/// The settings which are swapped atomically on reload
+#[derive(Debug)]
+pub struct Settings {
+    /// The default workspace served at `/wk`
+    pub workspace: WorkspaceSettings,
+    /// Name => the workspace served at `/wk/{name}`
+    pub workspaces: HashMap<String, WorkspaceSettings>,
+    /// Serve the home directories in the default workspace
+    pub homes: bool,
+    /// The users who may access every home directory
+    pub admins: Arc<HashSet<String>>,
+    /// Certificate subject => username
+    pub client_users: HashMap<String, String>,
+    /// The region and access keys of the S3 compatible API
+    pub s3: Option<S3>,
+}
+
+#[derive(Debug)]
+pub struct WorkspaceSettings {
+    pub root: Arc<Workspace>,
+    pub max_upload: ByteSize,
+    pub read: Policy,
+    pub write: Policy,
+    pub quotas: Arc<Quotas>,
+    pub index: Arc<SearchIndex>,
+}
+
+/// Handlers reach the settings through it,
+/// the in-flight requests keep the snapshot they loaded.
+pub type SharedSettings = Arc<ArcSwap<Settings>>;
+
+/// The max data size of single file, carried by the request
+#[derive(Debug, Clone, Copy)]
+pub struct MaxUpload(pub ByteSize);
+
+/// Carried by the requests to the default workspace in the homes mode,
+/// the home directories inside are private to their owners and the admins.
+#[derive(Debug, Clone)]
+pub struct HomeOwnership {
+    pub admins: Arc<HashSet<String>>,
+}
+
+impl Settings {
+    /// The settings of the reloaded config, which keep the search indexes and the quotas
+    /// of the workspaces unchanged, so neither is rebuilt from scratch.
+    pub fn reload(&self, running: &Config, config: &Config) -> Self {
+        let mut settings = Settings::from(config);
+
+        if config.workspace == running.workspace {
+            settings.workspace.keep(
+                &self.workspace,
+                config.search == running.search,
+                config.quota == running.quota && config.homes == running.homes,
+            );
+        }
+
+        for wk in config.workspaces.iter() {
+            let Some(old) = running.workspaces.iter().find(|old| old.name == wk.name) else {
+                continue;
+            };
+            let (Some(new), Some(kept)) = (
+                settings.workspaces.get_mut(&wk.name),
+                self.workspaces.get(&wk.name),
+            ) else {
+                continue;
+            };
+            if wk.path == old.path {
+                new.keep(kept, config.search == running.search, wk.quota == old.quota);
+            }
+        }
+
+        settings
+    }
+}
+
+impl WorkspaceSettings {
+    fn keep(&mut self, running: &WorkspaceSettings, index: bool, quotas: bool) {
+        if index {
+            self.index = running.index.clone();
+        }
+        if quotas {
+            self.quotas = running.quotas.clone();
+        }
+    }
+}
+
+impl From<&Config> for Settings {
+    fn from(config: &Config) -> Self {
+        Self {
+            workspace: WorkspaceSettings {
+                root: Arc::new(config.workspace.clone()),
+                max_upload: config.max_upload,
+                read: Policy::Public,
+                write: Policy::Authenticated,
+                quotas: Arc::new(Quotas::new(
+                    &config.workspace,
+                    config.quota.as_ref(),
+                    config.homes,
+                )),
+                index: Arc::new(SearchIndex::new(&config.workspace, &config.search)),
+            },
+            workspaces: config
+                .workspaces
+                .iter()
+                .map(|wk| {
+                    let settings = WorkspaceSettings {
+                        root: Arc::new(wk.path.clone()),
+                        max_upload: wk.max_upload.unwrap_or(config.max_upload),
+                        read: wk.read,
+                        write: wk.write,
+                        quotas: Arc::new(Quotas::new(&wk.path, wk.quota.as_ref(), false)),
+                        index: Arc::new(SearchIndex::new(&wk.path, &config.search)),
+                    };
+                    (wk.name.clone(), settings)
+                })
+                .collect(),
+            homes: config.homes,
+            admins: Arc::new(config.admins.iter().cloned().collect()),
+            client_users: config
+                .tls
+                .as_ref()
+                .map(|tls| tls.client_users.clone())
+                .unwrap_or_default(),
+            s3: config.s3.clone(),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+/// The files being written, which are removed if they aren't finished
+static WRITING: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// A file being written, removed on drop unless it's finished,
+/// e.g. the client disconnects or the draining times out.
+#[derive(Debug)]
+pub struct Writing {
+    path: PathBuf,
+    finished: bool,
+}
+
+impl Writing {
+    pub fn new(path: &Path) -> Self {
+        WRITING.lock().unwrap().insert(path.to_owned());
+
+        Self {
+            path: path.to_owned(),
+            finished: false,
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for Writing {
+    fn drop(&mut self) {
+        if WRITING.lock().unwrap().remove(&self.path) && !self.finished {
+            remove_partial(&self.path);
+        }
+    }
+}
+
+fn remove_partial(path: &Path) {
+    match fs::remove_file(path) {
+        Ok(()) => tracing::warn!(path = %path.display(), "half-written file removed"),
+        Err(e) => {
+            tracing::error!(path = %path.display(), error = %e, "cannot remove half-written file")
+        }
+    }
+}
+
+/// Remove the files still being written
+pub fn clean_up() {
+    for path in std::mem::take(&mut *WRITING.lock().unwrap()) {
+        remove_partial(&path);
+    }
+}
+
+/// Resolve on the first SIGTERM or SIGINT to start draining,
+/// and the second one exits immediately.
+pub async fn signal_received() {
+    let (mut term, mut int) = match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(term), Ok(int)) => (term, int),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(error = %e, "cannot listen to SIGTERM and SIGINT, graceful shutdown is disabled");
+            return std::future::pending().await;
+        }
+    };
+
+    recv_either(&mut term, &mut int).await;
+    tracing::info!("shutting down, send the signal again to exit immediately");
+
+    tokio::spawn(async move {
+        recv_either(&mut term, &mut int).await;
+        tracing::warn!("forced to exit");
+        clean_up();
+        process::exit(1);
+    });
+}
+
+async fn recv_either(term: &mut Signal, int: &mut Signal) {
+    tokio::select! {
+        _ = term.recv() => (),
+        _ = int.recv() => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Writing;
+    use crate::utils::tests::setup_workspace;
+    use std::fs;
+
+    #[test]
+    fn test_writing() {
+        let (_tmp_dir, wk) = setup_workspace();
+        let finished = wk.join("finished.txt");
+        let partial = wk.join("partial.txt");
+
+        fs::write(&finished, "the whole content").unwrap();
+        Writing::new(&finished).finish();
+        assert!(finished.exists());
+
+        fs::write(&partial, "the half").unwrap();
+        drop(Writing::new(&partial));
+        assert!(!partial.exists());
+    }
+}
@@ -0,0 +1,403 @@
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use poem::async_trait;
+use poem::http::uri::Scheme;
+use poem::listener::{Acceptor, Listener};
+use poem::web::{LocalAddr, RemoteAddr};
+use poem::Addr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient,
+};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::config::Tls;
+use crate::error::FileSysError;
+
+/// The scheme of remote address which carries
+/// the subject of a verified client certificate
+pub const CLIENT_CERT_SCHEME: &str = "x509";
+
+/// How often the certificate files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The connection which doesn't finish handshake in time is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wrap a listener with TLS,
+/// the certificate files are reloaded once they are modified.
+pub struct TlsListener<T> {
+    inner: T,
+    config: Tls,
+}
+
+impl<T> TlsListener<T> {
+    pub fn new(inner: T, config: Tls) -> Self {
+        Self { inner, config }
+    }
+}
+
+#[async_trait]
+impl<T> Listener for TlsListener<T>
+where
+    T: Listener,
+    T::Acceptor: 'static,
+{
+    type Acceptor = TlsAcceptor<<T::Acceptor as Acceptor>::Io>;
+
+    async fn into_acceptor(self) -> io::Result<Self::Acceptor> {
+        let tls = load(&self.config)?;
+        let inner = self.inner.into_acceptor().await?;
+        let local_addr = inner.local_addr();
+        let (tx, rx) = mpsc::channel(64);
+        let worker = tokio::spawn(serve(inner, self.config, tls, tx));
+
+        Ok(TlsAcceptor {
+            local_addr,
+            accepted: rx,
+            worker,
+        })
+    }
+}
+
+type Accepted<I> = (TlsStream<I>, LocalAddr, RemoteAddr);
+
+/// Handshakes run in background,
+/// so a slow client cannot block the others.
+pub struct TlsAcceptor<I> {
+    local_addr: Vec<LocalAddr>,
+    accepted: mpsc::Receiver<Accepted<I>>,
+    worker: JoinHandle<()>,
+}
+
+impl<I> Drop for TlsAcceptor<I> {
+    fn drop(&mut self) {
+        self.worker.abort();
+    }
+}
+
+#[async_trait]
+impl<I> Acceptor for TlsAcceptor<I>
+where
+    I: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Io = TlsStream<I>;
+
+    fn local_addr(&self) -> Vec<LocalAddr> {
+        self.local_addr.clone()
+    }
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, LocalAddr, RemoteAddr, Scheme)> {
+        match self.accepted.recv().await {
+            Some((stream, local_addr, remote_addr)) => {
+                Ok((stream, local_addr, remote_addr, Scheme::HTTPS))
+            }
+            None => Err(io::Error::other("TLS worker stopped")),
+        }
+    }
+}
+
+async fn serve<A: Acceptor>(
+    mut inner: A,
+    config: Tls,
+    mut tls: tokio_rustls::TlsAcceptor,
+    tx: mpsc::Sender<Accepted<A::Io>>,
+) {
+    let mut stamped = stamp(&config);
+    let mut interval = time::interval(WATCH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let latest = stamp(&config);
+                if latest == stamped {
+                    continue;
+                }
+
+                match load(&config) {
+                    Ok(reloaded) => {
+                        tls = reloaded;
+                        stamped = latest;
+                        tracing::info!("TLS certificate reloaded");
+                    }
+                    Err(e) => tracing::error!(error = %e, "cannot reload TLS certificate"),
+                }
+            }
+            res = inner.accept() => {
+                let (stream, local_addr, remote_addr, _) = match res {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "cannot accept connection");
+                        time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+
+                tokio::spawn(handshake(tls.clone(), stream, local_addr, remote_addr, tx.clone()));
+            }
+        }
+    }
+}
+
+async fn handshake<I>(
+    tls: tokio_rustls::TlsAcceptor,
+    stream: I,
+    local_addr: LocalAddr,
+    remote_addr: RemoteAddr,
+    tx: mpsc::Sender<Accepted<I>>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin,
+{
+    let stream = match time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            tracing::debug!(remote_addr = %remote_addr, error = %e, "TLS handshake failed");
+            return;
+        }
+        Err(_) => {
+            tracing::debug!(remote_addr = %remote_addr, "TLS handshake timed out");
+            return;
+        }
+    };
+
+    let remote_addr = match stream.get_ref().1.peer_certificates().and_then(subject) {
+        Some(subject) => RemoteAddr(Addr::Custom(CLIENT_CERT_SCHEME, subject.into())),
+        None => remote_addr,
+    };
+
+    let _ = tx.send((stream, local_addr, remote_addr)).await;
+}
+
+/// The subject of the end-entity certificate, like `CN=alice, O=Example`
+fn subject(chain: &[Certificate]) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(&chain.first()?.0).ok()?;
+    Some(cert.subject().to_string())
+}
+
+type Stamp = Vec<Option<SystemTime>>;
+
+fn stamp(config: &Tls) -> Stamp {
+    [
+        Some(&config.cert),
+        Some(&config.key),
+        config.client_ca.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|path| path.metadata().and_then(|md| md.modified()).ok())
+    .collect()
+}
+
+pub(crate) fn load(config: &Tls) -> io::Result<tokio_rustls::TlsAcceptor> {
+    let certs = read_certs(&config.cert)?;
+    let key = read_key(&config.key)?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+
+    let builder = match &config.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(client_ca)? {
+                roots.add(&cert).map_err(|e| invalid_data(client_ca, e))?;
+            }
+
+            let verifier = if config.client_auth_required {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        // which would accept every client
+        None if config.client_auth_required => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "client-auth-required needs client-ca to verify the clients",
+            ))
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid_data(&config.key, e))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config).into())
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, FileSysError> {
+    let certs = File::open(path)
+        .and_then(|fd| rustls_pemfile::certs(&mut BufReader::new(fd)))
+        .map_err(|e| FileSysError {
+            path: path.to_owned(),
+            source: e,
+        })?;
+
+    if certs.is_empty() {
+        return Err(invalid_data(path, "no certificate found"));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey, FileSysError> {
+    use rustls_pemfile::Item;
+
+    let items = File::open(path)
+        .and_then(|fd| rustls_pemfile::read_all(&mut BufReader::new(fd)))
+        .map_err(|e| FileSysError {
+            path: path.to_owned(),
+            source: e,
+        })?;
+
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid_data(path, "no private key found"))
+}
+
+fn invalid_data<E>(path: &Path, e: E) -> FileSysError
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    FileSysError {
+        path: path.to_owned(),
+        source: io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, TlsListener, CLIENT_CERT_SCHEME};
+    use crate::config::Tls;
+    use poem::listener::{Acceptor, Listener, TcpListener};
+    use poem::Addr;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+    use std::fs;
+    use std::sync::Arc;
+    use tempdir::TempDir;
+    use tokio::net::TcpStream;
+    use tokio_rustls::rustls;
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerName};
+    use tokio_rustls::TlsConnector;
+
+    fn issue(name: &str, ca: Option<&Certificate>) -> Certificate {
+        let mut params = CertificateParams::new(vec![name.to_owned()]);
+        params.distinguished_name.push(DnType::CommonName, name);
+        if ca.is_none() {
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        }
+
+        Certificate::from_params(params).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate() {
+        let tmp_dir = TempDir::new("sachima").unwrap();
+        let root = tmp_dir.path();
+
+        let ca = issue("sachima-ca", None);
+        let server = issue("localhost", Some(&ca));
+        let client = issue("alice", Some(&ca));
+
+        fs::write(root.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+        fs::write(
+            root.join("cert.pem"),
+            server.serialize_pem_with_signer(&ca).unwrap(),
+        )
+        .unwrap();
+        fs::write(root.join("key.pem"), server.serialize_private_key_pem()).unwrap();
+
+        let config = Tls {
+            cert: root.join("cert.pem"),
+            key: root.join("key.pem"),
+            client_ca: Some(root.join("ca.pem")),
+            client_auth_required: false,
+            client_users: Default::default(),
+        };
+        let mut acceptor = TlsListener::new(TcpListener::bind("127.0.0.1:0"), config)
+            .into_acceptor()
+            .await
+            .unwrap();
+        let addr = *acceptor.local_addr()[0].as_socket_addr().unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&rustls::Certificate(ca.serialize_der().unwrap()))
+            .unwrap();
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+
+        let anonymous = TlsConnector::from(Arc::new(builder.clone().with_no_client_auth()));
+        let alice = TlsConnector::from(Arc::new(
+            builder
+                .with_client_auth_cert(
+                    vec![rustls::Certificate(
+                        client.serialize_der_with_signer(&ca).unwrap(),
+                    )],
+                    rustls::PrivateKey(client.serialize_private_key_der()),
+                )
+                .unwrap(),
+        ));
+
+        for (connector, expected) in [
+            (anonymous, None),
+            (
+                alice,
+                Some(Addr::Custom(CLIENT_CERT_SCHEME, "CN=alice".into())),
+            ),
+        ] {
+            let connecting = tokio::spawn(async move {
+                let tcp = TcpStream::connect(addr).await.unwrap();
+                connector
+                    .connect(ServerName::try_from("localhost").unwrap(), tcp)
+                    .await
+                    .unwrap()
+            });
+
+            let (_, _, remote_addr, _) = acceptor.accept().await.unwrap();
+            match expected {
+                Some(addr) => assert_eq!(remote_addr.0, addr),
+                None => assert!(remote_addr.as_socket_addr().is_some()),
+            }
+
+            connecting.await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_client_auth_without_ca() {
+        let tmp_dir = TempDir::new("sachima").unwrap();
+        let root = tmp_dir.path();
+
+        let server = issue("localhost", None);
+        fs::write(root.join("cert.pem"), server.serialize_pem().unwrap()).unwrap();
+        fs::write(root.join("key.pem"), server.serialize_private_key_pem()).unwrap();
+
+        let mut config = Tls {
+            cert: root.join("cert.pem"),
+            key: root.join("key.pem"),
+            client_ca: None,
+            client_auth_required: true,
+            client_users: Default::default(),
+        };
+        assert!(load(&config).is_err());
+
+        config.client_auth_required = false;
+        assert!(load(&config).is_ok());
+    }
+}
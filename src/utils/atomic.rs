@@ -0,0 +1,249 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::fs::{File, OpenOptions};
+use tokio::task;
+
+use crate::shutdown::Writing;
+
+/// The hidden temp files are named `.sachima-upload.{unique}`,
+/// so are the entries staged for removal by a transactional batch.
+pub const TEMP_PREFIX: &str = ".sachima-upload.";
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// A hidden temp file in the destination directory,
+/// which is moved into place after being fully written,
+/// so no one sees a half-written file at the final name.
+///
+/// It's removed on drop unless persisted.
+#[derive(Debug)]
+pub struct AtomicFile {
+    fd: File,
+    temp: PathBuf,
+    writing: Writing,
+}
+
+impl AtomicFile {
+    pub async fn create(dir: &Path) -> io::Result<Self> {
+        loop {
+            let temp = temp_path(dir);
+
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp)
+                .await
+            {
+                Ok(fd) => {
+                    let writing = Writing::new(&temp);
+                    return Ok(Self { fd, temp, writing });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn file(&mut self) -> &mut File {
+        &mut self.fd
+    }
+
+    /// The path of temp file
+    pub fn path(&self) -> &Path {
+        &self.temp
+    }
+
+    /// Move the file to `dest` unless it exists
+    pub async fn persist(self, dest: &Path) -> io::Result<()> {
+        self.try_persist(dest).await.map_err(|(_, e)| e)
+    }
+
+    /// Like `persist`, but the file is given back on failure,
+    /// so it can be moved to another name.
+    pub async fn try_persist(self, dest: &Path) -> Result<(), (Self, io::Error)> {
+        match self.rename_to(dest, rename_noreplace).await {
+            Ok(()) => {
+                self.writing.finish();
+                Ok(())
+            }
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    /// Move the file to `dest`, replacing the existing one
+    pub async fn persist_overwrite(self, dest: &Path) -> io::Result<()> {
+        self.rename_to(dest, |from, to| std::fs::rename(from, to))
+            .await?;
+        self.writing.finish();
+        Ok(())
+    }
+
+    async fn rename_to(
+        &self,
+        dest: &Path,
+        rename: fn(&Path, &Path) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.fd.sync_all().await?;
+
+        let temp = self.temp.clone();
+        let dest = dest.to_owned();
+        task::spawn_blocking(move || {
+            rename(&temp, &dest)?;
+
+            // persist the rename itself
+            if let Some(dir) = dest.parent() {
+                std::fs::File::open(dir)?.sync_all()?;
+            }
+
+            Ok(())
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+/// A unique hidden path in the directory
+pub fn temp_path(dir: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    dir.join(format!(
+        "{TEMP_PREFIX}{}-{}-{nanos}",
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed),
+    ))
+}
+
+/// Fail with `AlreadyExists` if `to` exists
+#[cfg(target_os = "linux")]
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_from = CString::new(from.as_os_str().as_bytes())?;
+    let c_to = CString::new(to.as_os_str().as_bytes())?;
+
+    // SAFETY: both paths are valid C strings
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            c_from.as_ptr(),
+            libc::AT_FDCWD,
+            c_to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if ret == 0 {
+        return Ok(());
+    }
+
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        // the file system doesn't support the flag
+        Some(libc::EINVAL | libc::ENOSYS) => link_noreplace(from, to),
+        _ => Err(e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    link_noreplace(from, to)
+}
+
+/// Linking fails if `to` exists
+fn link_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    std::fs::hard_link(from, to)?;
+    std::fs::remove_file(from)
+}
+
+/// Remove the temp files and staged entries left by a crash under the directory
+pub async fn sweep(dir: &Path) -> io::Result<usize> {
+    fn walk(dir: &Path) -> io::Result<usize> {
+        let mut swept = 0;
+
+        for entry in dir.read_dir()? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let temp = is_temp(&entry.file_name().to_string_lossy());
+
+            if file_type.is_dir() && temp {
+                std::fs::remove_dir_all(entry.path())?;
+                swept += 1;
+            } else if file_type.is_dir() {
+                swept += walk(&entry.path())?;
+            } else if temp {
+                std::fs::remove_file(entry.path())?;
+                swept += 1;
+            }
+        }
+
+        Ok(swept)
+    }
+
+    let dir = dir.to_owned();
+    task::spawn_blocking(move || walk(&dir))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Whether the entry is a temp file, which is hidden from the clients
+pub fn is_temp(name: &str) -> bool {
+    name.starts_with(TEMP_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sweep, AtomicFile};
+    use crate::utils::tests::setup_workspace;
+    use std::io;
+    use tokio::fs;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_atomic_file() -> io::Result<()> {
+        let (_tmp_dir, wk) = setup_workspace();
+        let dest = wk.join("atomic.txt");
+
+        let mut file = AtomicFile::create(&wk).await?;
+        file.file().write_all(b"the content").await?;
+        // invisible before being persisted
+        assert!(!fs::try_exists(&dest).await?);
+        file.persist(&dest).await?;
+        assert_eq!(fs::read_to_string(&dest).await?, "the content");
+
+        let file = AtomicFile::create(&wk).await?;
+        assert_eq!(
+            file.persist(&dest).await.unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        let mut file = AtomicFile::create(&wk).await?;
+        file.file().write_all(b"overwritten").await?;
+        file.persist_overwrite(&dest).await?;
+        assert_eq!(fs::read_to_string(&dest).await?, "overwritten");
+
+        // only the destination is left
+        assert_eq!(wk.read_dir()?.count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sweep() -> io::Result<()> {
+        let (_tmp_dir, wk) = setup_workspace();
+        fs::create_dir(wk.join("nested")).await?;
+        fs::write(wk.join("nested/.sachima-upload.1-0-0"), "orphan").await?;
+        fs::create_dir_all(wk.join(".sachima-upload.1-1-0/staged")).await?;
+        fs::write(wk.join("kept.txt"), "kept").await?;
+
+        assert_eq!(sweep(&wk).await?, 2);
+        assert!(fs::try_exists(wk.join("kept.txt")).await?);
+
+        Ok(())
+    }
+}
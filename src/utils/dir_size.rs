@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use once_cell::sync::Lazy;
+use tokio::task;
+
+use crate::utils::atomic;
+
+/// Forget all the listings once so many directories are cached
+const MAX_CACHED: usize = 100_000;
+
+/// The directories modified so recently aren't cached,
+/// a change in the same tick of a coarse timestamp would leave the mtime as it is.
+const RACY: Duration = Duration::from_secs(2);
+
+/// What a directory holds directly, valid until its mtime changes.
+///
+/// Adding, removing and renaming entries change the mtime of their directory,
+/// and the files changed in place are found by their own sizes and mtimes.
+#[derive(Debug)]
+struct Listing {
+    mtime: SystemTime,
+    size: DirSize,
+    subdirs: Vec<OsString>,
+    /// The direct files with their sizes and mtimes
+    files: Vec<(OsString, u64, SystemTime)>,
+}
+
+/// None of the files has been changed in place
+fn unchanged(dir: &Path, files: &[(OsString, u64, SystemTime)]) -> bool {
+    files.iter().all(|(name, len, mtime)| {
+        dir.join(name)
+            .symlink_metadata()
+            .is_ok_and(|md| md.len() == *len && md.modified().is_ok_and(|m| m == *mtime))
+    })
+}
+
+static CACHE: Lazy<Mutex<HashMap<PathBuf, Listing>>> = Lazy::new(Mutex::default);
+
+/// The recursive size of a directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirSize {
+    pub bytes: u64,
+    /// Files, directories and symlinks inside
+    pub items: u64,
+}
+
+impl AddAssign for DirSize {
+    fn add_assign(&mut self, rhs: Self) {
+        self.bytes += rhs.bytes;
+        self.items += rhs.items;
+    }
+}
+
+/// Sum up the directory tree, which only stats the directories
+/// whose listings are cached.
+pub async fn dir_size(dir: &Path) -> io::Result<DirSize> {
+    let dir = dir.to_owned();
+    task::spawn_blocking(move || walk(&dir))
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn walk(dir: &Path) -> io::Result<DirSize> {
+    let mtime = dir.symlink_metadata()?.modified()?;
+
+    let cached = CACHE
+        .lock()
+        .unwrap()
+        .get(dir)
+        .filter(|listing| listing.mtime == mtime)
+        .map(|listing| (listing.size, listing.subdirs.clone(), listing.files.clone()));
+    // checked without holding the cache
+    let cached = cached.filter(|(_, _, files)| unchanged(dir, files));
+    let (mut size, subdirs) = match cached {
+        Some((size, subdirs, _)) => (size, subdirs),
+        None => {
+            // the mtime is taken before listing, and a listing is cached only
+            // once the mtime is old enough, so a change meanwhile is seen next time
+            let listing = list(dir, mtime)?;
+            let found = (listing.size, listing.subdirs.clone());
+
+            let settled = SystemTime::now()
+                .duration_since(mtime)
+                .is_ok_and(|age| age >= RACY);
+            let mut cache = CACHE.lock().unwrap();
+            if cache.len() >= MAX_CACHED {
+                cache.clear();
+            }
+            if settled {
+                cache.insert(dir.to_owned(), listing);
+            } else {
+                cache.remove(dir);
+            }
+
+            found
+        }
+    };
+
+    for name in subdirs {
+        match walk(&dir.join(name)) {
+            Ok(subdir) => size += subdir,
+            // removed after listing
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(size)
+}
+
+fn list(dir: &Path, mtime: SystemTime) -> io::Result<Listing> {
+    let mut size = DirSize::default();
+    let mut subdirs = Vec::new();
+    let mut files = Vec::new();
+
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if atomic::is_temp(&name.to_string_lossy()) {
+            continue;
+        }
+
+        size.items += 1;
+        // symlinks aren't followed
+        if entry.file_type()?.is_dir() {
+            subdirs.push(name);
+        } else {
+            let md = entry.metadata()?;
+            size.bytes += md.len();
+            files.push((name, md.len(), md.modified()?));
+        }
+    }
+
+    Ok(Listing {
+        mtime,
+        size,
+        subdirs,
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dir_size, DirSize};
+    use crate::utils::tests::setup_workspace;
+    use fs_set_times::SystemTimeSpec;
+    use std::io;
+    use std::time::{Duration, SystemTime};
+    use tokio::fs::{self, OpenOptions};
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_dir_size() -> io::Result<()> {
+        let (_tmp_dir, wk) = setup_workspace();
+        fs::create_dir_all(wk.join("a/b")).await?;
+        fs::write(wk.join("a/1.txt"), "12345").await?;
+        fs::write(wk.join("a/b/2.txt"), "123").await?;
+        fs::write(wk.join("a/b/.sachima-upload.1-0-0"), "uploading").await?;
+
+        assert_eq!(
+            dir_size(&wk.join("a")).await?,
+            DirSize { bytes: 8, items: 3 }
+        );
+
+        // the nested change is seen through the mtime of its directory
+        fs::write(wk.join("a/b/3.txt"), "1").await?;
+        assert_eq!(
+            dir_size(&wk.join("a")).await?,
+            DirSize { bytes: 9, items: 4 }
+        );
+        fs::remove_dir_all(wk.join("a/b")).await?;
+        assert_eq!(
+            dir_size(&wk.join("a")).await?,
+            DirSize { bytes: 5, items: 1 }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_changed_in_place() -> io::Result<()> {
+        let (_tmp_dir, wk) = setup_workspace();
+        fs::create_dir(wk.join("a")).await?;
+        fs::write(wk.join("a/1.txt"), "12345").await?;
+        // old enough to be cached
+        let past = SystemTime::now() - Duration::from_secs(60);
+        fs_set_times::set_mtime(wk.join("a"), SystemTimeSpec::Absolute(past))?;
+
+        assert_eq!(
+            dir_size(&wk.join("a")).await?,
+            DirSize { bytes: 5, items: 1 }
+        );
+
+        // the mtime of the directory is left as it is
+        let mut fd = OpenOptions::new()
+            .append(true)
+            .open(wk.join("a/1.txt"))
+            .await?;
+        fd.write_all(b"678").await?;
+        fd.sync_all().await?;
+        assert_eq!(fs::metadata(wk.join("a")).await?.modified()?, past);
+        assert_eq!(
+            dir_size(&wk.join("a")).await?,
+            DirSize { bytes: 8, items: 1 }
+        );
+
+        Ok(())
+    }
+}
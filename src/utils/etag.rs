@@ -0,0 +1,14 @@
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+
+/// A strong entity tag made of the modified time and size
+pub fn etag(md: &Metadata) -> String {
+    let modified = md
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default()
+        .as_nanos();
+
+    format!(r#""{modified:x}-{:x}""#, md.len())
+}
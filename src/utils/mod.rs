@@ -5,3 +5,13 @@ pub mod tests;
 pub mod time;
 
 pub mod pswd;
+
+pub mod atomic;
+
+pub mod etag;
+
+pub mod dir_size;
+
+pub mod natural;
+
+pub mod xattr;
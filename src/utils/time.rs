@@ -1,6 +1,66 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[inline]
-pub fn to_unix_timestamp(time: SystemTime) -> u64 {
-    time.duration_since(UNIX_EPOCH).unwrap().as_secs()
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// How the timestamps are replied
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeFormat {
+    /// Unix seconds in a string like `"1684900000"`, for compatibility
+    #[default]
+    String,
+    /// Unix seconds
+    Unix,
+    /// In UTC with nanoseconds, like `"2023-05-24T03:46:40.123456789Z"`
+    Rfc3339,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Timestamp {
+    Text(String),
+    Number(u64),
+}
+
+impl TimeFormat {
+    pub fn format(self, time: SystemTime) -> Timestamp {
+        // the times before 1970 are clamped, which are surely wrong
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+
+        match self {
+            TimeFormat::String => Timestamp::Text(secs.to_string()),
+            TimeFormat::Unix => Timestamp::Number(secs),
+            TimeFormat::Rfc3339 => OffsetDateTime::from(time)
+                .format(&Rfc3339)
+                .map_or(Timestamp::Number(secs), Timestamp::Text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TimeFormat, Timestamp};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_time_format() {
+        let time = UNIX_EPOCH + Duration::new(1_684_900_000, 5_000_000);
+
+        assert_eq!(
+            TimeFormat::String.format(time),
+            Timestamp::Text("1684900000".to_owned())
+        );
+        assert_eq!(
+            TimeFormat::Unix.format(time),
+            Timestamp::Number(1_684_900_000)
+        );
+        assert_eq!(
+            TimeFormat::Rfc3339.format(time),
+            Timestamp::Text("2023-05-24T03:46:40.005Z".to_owned())
+        );
+    }
 }
@@ -0,0 +1,96 @@
+use std::io;
+use std::path::Path;
+
+/// Read a user extended attribute of the file, `None` if it's absent
+#[cfg(target_os = "linux")]
+pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let (c_path, c_name) = c_strings(path, name)?;
+    let mut buf = vec![0u8; 256];
+
+    loop {
+        // SAFETY: both strings are valid C strings and the buffer is as large as told
+        let ret = unsafe {
+            libc::getxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+            )
+        };
+        if ret >= 0 {
+            buf.truncate(ret as usize);
+            return Ok(Some(buf));
+        }
+
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::ENODATA) => return Ok(None),
+            Some(libc::ERANGE) if buf.len() < 64 * 1024 => buf.resize(buf.len() * 4, 0),
+            _ => return Err(e),
+        }
+    }
+}
+
+/// Write a user extended attribute of the file,
+/// which fails if the file system doesn't support it.
+#[cfg(target_os = "linux")]
+pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let (c_path, c_name) = c_strings(path, name)?;
+
+    // SAFETY: both strings are valid C strings and the value is as large as told
+    let ret = unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Remove a user extended attribute of the file, which is fine if it's absent
+#[cfg(target_os = "linux")]
+pub fn remove(path: &Path, name: &str) -> io::Result<()> {
+    let (c_path, c_name) = c_strings(path, name)?;
+
+    // SAFETY: both strings are valid C strings
+    let ret = unsafe { libc::removexattr(c_path.as_ptr(), c_name.as_ptr()) };
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        _ if ret == 0 => Ok(()),
+        Some(libc::ENODATA) => Ok(()),
+        _ => Err(e),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn c_strings(path: &Path, name: &str) -> io::Result<(std::ffi::CString, std::ffi::CString)> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok((
+        CString::new(path.as_os_str().as_bytes())?,
+        CString::new(name)?,
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+    Ok(None)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn remove(_path: &Path, _name: &str) -> io::Result<()> {
+    Ok(())
+}
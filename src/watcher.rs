@@ -0,0 +1,243 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::time;
+
+use crate::events::{self, Change};
+use crate::search;
+use crate::settings::SharedSettings;
+use crate::utils::atomic;
+
+/// How often the watched workspaces follow the reloaded settings
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A burst of events, like a large copy, is handled at once
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watch the workspaces for the changes made by other programs,
+/// index the changed paths and publish the changes.
+pub async fn watch(settings: SharedSettings) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(move |res| {
+        tx.send(res).ok();
+    }) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!(error = %e, "cannot watch the workspaces");
+            return;
+        }
+    };
+    let mut watched = HashSet::new();
+    let mut interval = time::interval(SYNC_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => sync(&mut watcher, &mut watched, &settings),
+            Some(res) = rx.recv() => {
+                let mut burst = Burst::default();
+                burst.collect(res);
+
+                time::sleep(DEBOUNCE).await;
+                while let Ok(res) = rx.try_recv() {
+                    burst.collect(res);
+                }
+
+                let (changed, changes) = burst.finish();
+                refresh(changed, &settings).await;
+                changes.into_iter().for_each(events::publish_external);
+            }
+        }
+    }
+}
+
+/// Watch the added workspaces and stop watching the removed ones
+fn sync(
+    watcher: &mut RecommendedWatcher,
+    watched: &mut HashSet<PathBuf>,
+    settings: &SharedSettings,
+) {
+    let roots: HashSet<_> = search::indexes(&settings.load())
+        .iter()
+        .map(|index| index.root().to_owned())
+        .collect();
+
+    for root in watched.difference(&roots) {
+        watcher.unwatch(root).ok();
+    }
+    for root in roots.difference(watched) {
+        if let Err(e) = watcher.watch(root, RecursiveMode::Recursive) {
+            tracing::warn!(root = %root.display(), error = %e, "cannot watch the workspace");
+        }
+    }
+
+    *watched = roots;
+}
+
+/// The events received at once
+#[derive(Debug, Default)]
+struct Burst {
+    /// To be indexed again
+    changed: BTreeSet<PathBuf>,
+    changes: Vec<Change>,
+    /// Tracker => the old path of a rename whose new path isn't reported yet
+    renaming: HashMap<usize, PathBuf>,
+}
+
+impl Burst {
+    fn collect(&mut self, res: notify::Result<Event>) {
+        let event = match res {
+            // reading changes nothing
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => return,
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!(error = %e, "the workspace watcher failed");
+                return;
+            }
+        };
+        self.changed.extend(event.paths.iter().cloned());
+
+        let tracker = event.attrs.tracker();
+        let Some(path) = event.paths.into_iter().next() else {
+            return;
+        };
+        match event.kind {
+            EventKind::Create(_) => self.push(Change::Created(path)),
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => match tracker {
+                Some(tracker) => {
+                    self.renaming.insert(tracker, path);
+                }
+                None => self.push(Change::Removed(path)),
+            },
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                match tracker.and_then(|tracker| self.renaming.remove(&tracker)) {
+                    Some(from) => self.push(Change::Renamed { from, to: path }),
+                    None => self.push(Change::Created(path)),
+                }
+            }
+            // reported with From and To as well
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => (),
+            EventKind::Modify(_) => self.push(Change::Modified(path)),
+            EventKind::Remove(_) => self.push(Change::Removed(path)),
+            _ => (),
+        }
+    }
+
+    /// Hide the temp files, an upload appears when its temp file is renamed
+    fn push(&mut self, change: Change) {
+        let change = match change {
+            Change::Renamed { from, to } => match (is_temp(&from), is_temp(&to)) {
+                (false, false) => Change::Renamed { from, to },
+                (true, false) => Change::Created(to),
+                (false, true) => Change::Removed(from),
+                (true, true) => return,
+            },
+            change if change.paths().any(|path| is_temp(path)) => return,
+            change => change,
+        };
+
+        // a new file is written after it's created
+        let seen = self.changes.iter().any(|seen| {
+            *seen == change
+                || matches!((seen, &change), (Change::Created(a), Change::Modified(b)) if a == b)
+        });
+        if !seen {
+            self.changes.push(change);
+        }
+    }
+
+    /// The paths to index and the changes to publish
+    fn finish(mut self) -> (BTreeSet<PathBuf>, Vec<Change>) {
+        // moved out of the watched workspaces
+        for (_, from) in std::mem::take(&mut self.renaming) {
+            self.push(Change::Removed(from));
+        }
+
+        (self.changed, self.changes)
+    }
+}
+
+fn is_temp(path: &Path) -> bool {
+    path.iter()
+        .any(|name| atomic::is_temp(&name.to_string_lossy()))
+}
+
+async fn refresh(changed: BTreeSet<PathBuf>, settings: &SharedSettings) {
+    let indexes = search::indexes(&settings.load());
+    let mut last: Option<PathBuf> = None;
+
+    for path in changed {
+        // refreshed with its ancestor
+        if last.as_ref().is_some_and(|last| path.starts_with(last)) {
+            continue;
+        }
+
+        let index = indexes
+            .iter()
+            .filter(|index| path.starts_with(index.root()))
+            .max_by_key(|index| index.root().components().count());
+        if let Some(index) = index {
+            index.refresh(&path).await;
+        }
+
+        last = Some(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Burst;
+    use crate::events::Change;
+    use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode};
+    use notify::{Event, EventKind};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_burst() {
+        let path = |path: &str| PathBuf::from("/wk").join(path);
+        let event = |kind, p: &str| Ok(Event::new(kind).add_path(path(p)));
+        let renamed = |mode, p: &str, tracker| {
+            Ok(Event::new(EventKind::Modify(ModifyKind::Name(mode)))
+                .add_path(path(p))
+                .set_tracker(tracker))
+        };
+
+        let mut burst = Burst::default();
+        burst.collect(event(EventKind::Create(CreateKind::File), "new.txt"));
+        burst.collect(event(
+            EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+            "new.txt",
+        ));
+        burst.collect(renamed(RenameMode::From, "a.txt", 1));
+        burst.collect(renamed(RenameMode::To, "b.txt", 1));
+        // an upload
+        burst.collect(event(
+            EventKind::Create(CreateKind::File),
+            ".sachima-upload.1-0-0",
+        ));
+        burst.collect(renamed(RenameMode::From, ".sachima-upload.1-0-0", 2));
+        burst.collect(renamed(RenameMode::To, "up.txt", 2));
+        burst.collect(event(EventKind::Remove(RemoveKind::Any), "old.txt"));
+        // moved out of the workspace
+        burst.collect(renamed(RenameMode::From, "gone.txt", 3));
+
+        let (changed, changes) = burst.finish();
+        assert!(changed.contains(&path("b.txt")));
+        assert_eq!(
+            changes,
+            [
+                Change::Created(path("new.txt")),
+                Change::Renamed {
+                    from: path("a.txt"),
+                    to: path("b.txt")
+                },
+                Change::Created(path("up.txt")),
+                Change::Removed(path("old.txt")),
+                Change::Removed(path("gone.txt")),
+            ]
+        );
+    }
+}